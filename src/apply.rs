@@ -1,46 +1,357 @@
+use std::cmp::Reverse;
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
 
 use crate::cli::{ApplyArgs, ApplyMethodArg};
-use crate::config::{self, ResolvedConfig};
+use crate::config::{
+    self, ApplyStep, ResolvedConfig, ResolvedRepositoryConfig, SideChannelBackend,
+    SideChannelConfig,
+};
 use crate::git;
+use crate::journal::{self, Journal, Phase};
+use crate::prompt;
+use crate::resolve;
+use crate::state::ApplyRecord;
 
-pub fn run(args: &ApplyArgs, config: &ResolvedConfig) -> Result<()> {
+/// What applying a side-channel tip did to one repository.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Applied {
+    /// The method that applied it, e.g. `Auto (rebase)`.
+    pub method: String,
+    /// Set when the apply moved `HEAD`, so `apply --undo` can take it back.
+    pub record: Option<ApplyRecord>,
+}
+
+/// Applies the side channel to one repository and returns the record of an
+/// apply that moved its `HEAD`.
+///
+/// On a terminal, an apply that fails is offered again as a merge that keeps
+/// its conflicts, and conflicts are then resolved file by file.
+pub fn run(args: &ApplyArgs, config: &ResolvedConfig) -> Result<Option<(PathBuf, ApplyRecord)>> {
+    let repo = target_repo(args)?;
+    let side = fetch(&repo, config)?;
+    if !args.paths.is_empty() {
+        let record = apply_paths(&repo, &side, &args.paths, args.commit)?;
+        return Ok(record.map(|record| (repo, record)));
+    }
+    let interactive = prompt::is_interactive(args.non_interactive);
+    let before = git::rev_parse(&repo, "HEAD")?.trim().to_string();
+    let mut method = args
+        .method
+        .unwrap_or_else(|| config::resolve_apply_method(config, &repo));
+    let mut applied = match apply_fetched(&repo, &side, Some(method), config) {
+        Ok(applied) => applied,
+        Err(err) if interactive && method != ApplyMethodArg::MergeWithConflicts => {
+            eprintln!("{err:#}");
+            if !prompt::confirm("Merge the side channel instead and resolve its conflicts here?")? {
+                return Err(err);
+            }
+            method = ApplyMethodArg::MergeWithConflicts;
+            apply_fetched(&repo, &side, Some(method), config)?
+        }
+        Err(err) => return Err(err),
+    };
+
+    let conflicted = git::unmerged_paths(&repo)?;
+    if interactive && !conflicted.is_empty() {
+        if !resolve::resolve(&repo, &conflicted, prompt::ask)? {
+            return Ok(None);
+        }
+        applied = Applied {
+            record: applied_record(&repo, &side, before, &applied.method)?,
+            method: format!("{method:?}, conflicts resolved"),
+        };
+    }
+
+    println!(
+        "Applied side-channel changes to {} using {}",
+        repo.display(),
+        applied.method
+    );
+    Ok(applied.record.map(|record| (repo, record)))
+}
+
+/// The repository `apply` works on: `--repo`, else the current directory.
+pub fn target_repo(args: &ApplyArgs) -> Result<PathBuf> {
     let repo = match &args.repo {
         Some(path) => path.clone(),
         None => std::env::current_dir().context("failed to resolve current directory")?,
     };
+    canonical_repo(&repo)
+}
+
+/// `apply --undo`: resets `repo` to where `record`'s apply found it, keeping
+/// uncommitted changes. Refuses when commits were made on top of the apply
+/// unless `force`, which drops them as well.
+pub fn undo(config: &ResolvedConfig, repo: &Path, record: &ApplyRecord, force: bool) -> Result<()> {
+    ensure_writable(config, repo)?;
+    let head = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    if head == record.before {
+        bail!(
+            "{} is already back at {}, before the last apply",
+            repo.display(),
+            short(&record.before)
+        );
+    }
+    if head != record.after {
+        if !git::is_ancestor(repo, &record.after, "HEAD")? {
+            bail!(
+                "HEAD of {} no longer contains the last apply ({}); undo it by hand",
+                repo.display(),
+                short(&record.after)
+            );
+        }
+        if !force {
+            let later = git::commit_count(repo, &format!("{}..HEAD", record.after))?;
+            bail!(
+                "{later} commit(s) were made in {} after the last apply; pass --force to drop them too",
+                repo.display()
+            );
+        }
+    }
+    git::reset_keep(repo, &record.before).with_context(|| {
+        format!(
+            "failed to reset {} to {}",
+            repo.display(),
+            short(&record.before)
+        )
+    })
+}
 
-    let repo = canonical_repo(&repo)?;
-    let side = config::resolve_apply_side_channel(config, &repo);
+fn short(rev: &str) -> &str {
+    rev.get(..12).unwrap_or(rev)
+}
 
-    git::fetch_side_channel(&repo, &side).with_context(|| {
+/// `apply --paths`: checks out `paths` from the fetched side-channel tip and,
+/// with `commit`, commits just those paths.
+fn apply_paths(
+    repo: &Path,
+    side: &SideChannelConfig,
+    paths: &[String],
+    commit: bool,
+) -> Result<Option<ApplyRecord>> {
+    let before = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    git::checkout_side_channel_paths(repo, side, paths).with_context(|| {
         format!(
-            "failed to fetch side-channel branch {}/{} for {}",
-            side.remote_name,
-            side.branch_name,
+            "failed to check out {} from the side channel in {}",
+            paths.join(", "),
             repo.display()
         )
     })?;
-
-    match args.method {
-        ApplyMethodArg::Merge => git::merge_side_channel_ff(&repo, &side)
-            .with_context(|| format!("failed to ff-merge into {}", repo.display()))?,
-        ApplyMethodArg::CherryPick => git::cherry_pick_side_channel_tip(&repo, &side)
-            .with_context(|| format!("failed to cherry-pick into {}", repo.display()))?,
-        ApplyMethodArg::Squash => git::squash_merge_side_channel(&repo, &side)
-            .with_context(|| format!("failed to squash-merge into {}", repo.display()))?,
+    if commit {
+        let message = format!("Apply {} from side channel", paths.join(", "));
+        git::commit_paths(repo, &message, paths)
+            .with_context(|| format!("failed to commit applied paths in {}", repo.display()))?;
     }
 
     println!(
-        "Applied side-channel changes to {} using {:?}",
+        "Applied {} from the side channel to {}{}",
+        paths.join(", "),
         repo.display(),
-        args.method
+        if commit { ", committed" } else { "" }
     );
+    applied_record(repo, side, before, "Paths")
+}
+
+/// `apply --all`: fetches every repository's side channel, skips those whose
+/// tip is already in `HEAD`, and applies the rest newest snapshot first.
+/// Returns 1 when any repository conflicted or failed, and the records of
+/// the applies that moved `HEAD`.
+pub fn run_all(
+    args: &ApplyArgs,
+    config: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+) -> (i32, Vec<(PathBuf, ApplyRecord)>) {
+    let (mut skipped, mut failed) = (0, 0);
+    let mut pending = Vec::new();
+    for repo in repos {
+        let path = &repo.path;
+        if repo.read_only {
+            skipped += 1;
+            println!("[SKIP] {} :: read-only", path.display());
+            continue;
+        }
+        let side = config::resolve_apply_side_channel(config, path);
+        if !side.enabled {
+            skipped += 1;
+            println!("[SKIP] {} :: side channel disabled", path.display());
+            continue;
+        }
+        let fetched = fetch(path, config).and_then(|side| {
+            let tip = git::rev_parse(path, &git::side_channel_tracking_ref(&side))?;
+            let tip = tip.trim().to_string();
+            if git::is_ancestor(path, &tip, "HEAD")? {
+                return Ok(None);
+            }
+            Ok(Some((git::commit_time(path, &tip)?, side)))
+        });
+        match fetched {
+            Ok(Some((snapshot_at, side))) => pending.push((snapshot_at, path, side)),
+            Ok(None) => {
+                skipped += 1;
+                println!("[SKIP] {} :: already applied", path.display());
+            }
+            Err(err) => {
+                failed += 1;
+                println!("[FAIL] {} :: {err:#}", path.display());
+            }
+        }
+    }
+
+    pending.sort_by_key(|(snapshot_at, _, _)| Reverse(*snapshot_at));
+    let (mut applied, mut conflicted) = (0, 0);
+    let mut records = Vec::new();
+    for (_, path, side) in pending {
+        match apply_fetched(path, &side, args.method, config) {
+            Ok(done) => {
+                applied += 1;
+                println!("[APPLIED] {} :: {}", path.display(), done.method);
+                records.extend(done.record.map(|record| (path.clone(), record)));
+            }
+            Err(err) => {
+                conflicted += 1;
+                println!("[CONFLICT] {} :: {err:#}", path.display());
+            }
+        }
+    }
+
+    let failed_note = if failed > 0 {
+        format!(", {failed} failed")
+    } else {
+        String::new()
+    };
+    println!(
+        "Applied across {} repos: {applied} applied, {skipped} skipped, {conflicted} conflicted{failed_note}",
+        repos.len()
+    );
+    (i32::from(conflicted + failed > 0), records)
+}
+
+/// Fails for repositories configured `read_only`, which `apply` never
+/// writes to.
+pub fn ensure_writable(config: &ResolvedConfig, repo: &Path) -> Result<()> {
+    if config::is_read_only(config, repo) {
+        bail!("{} is read_only; shephard never changes it", repo.display());
+    }
     Ok(())
 }
 
+/// Fetches `repo`'s side-channel branch, which must be stored in git.
+pub fn fetch(repo: &Path, config: &ResolvedConfig) -> Result<SideChannelConfig> {
+    ensure_writable(config, repo)?;
+    let side = config::resolve_apply_side_channel(config, repo);
+    match side.backend {
+        SideChannelBackend::Git => {}
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => bail!(
+            "{} stores snapshots as bundles with {}; download the bundle and `git fetch` it instead",
+            repo.display(),
+            side.backend
+        ),
+    }
+
+    git::fetch_side_channel(repo, &side, &config::resolve_git_auth(config, repo)).with_context(
+        || {
+            format!(
+                "failed to fetch side-channel branch {}/{} for {}",
+                side.remote_name,
+                side.branch_name,
+                repo.display()
+            )
+        },
+    )?;
+    Ok(side)
+}
+
+/// Applies the fetched side-channel tip with `method`, or the repository's
+/// configured method.
+pub fn apply_fetched(
+    repo: &Path,
+    side: &SideChannelConfig,
+    method: Option<ApplyMethodArg>,
+    config: &ResolvedConfig,
+) -> Result<Applied> {
+    ensure_writable(config, repo)?;
+    let before = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    let mut journal = Journal::open(journal::journal_dir().ok().as_deref(), repo);
+    journal.record(Phase::Apply);
+    let method = method.unwrap_or_else(|| config::resolve_apply_method(config, repo));
+    let applied = match method {
+        ApplyMethodArg::Merge => git::merge_side_channel_ff(repo, side)
+            .with_context(|| format!("failed to ff-merge into {}", repo.display()))
+            .map(|()| format!("{method:?}")),
+        ApplyMethodArg::CherryPick => git::cherry_pick_side_channel_tip(repo, side)
+            .with_context(|| format!("failed to cherry-pick into {}", repo.display()))
+            .map(|()| format!("{method:?}")),
+        ApplyMethodArg::Squash => git::squash_merge_side_channel(repo, side)
+            .with_context(|| format!("failed to squash-merge into {}", repo.display()))
+            .map(|()| format!("{method:?}")),
+        ApplyMethodArg::Rebase => git::rebase_onto_side_channel(repo, side)
+            .with_context(|| format!("failed to rebase {}", repo.display()))
+            .map(|()| format!("{method:?}")),
+        ApplyMethodArg::Auto => apply_auto(repo, side, &config.apply.auto_chain)
+            .map(|step| format!("{method:?} ({step})")),
+        ApplyMethodArg::MergeWithConflicts => git::merge_side_channel_keep_conflicts(repo, side)
+            .with_context(|| format!("failed to merge into {}", repo.display()))
+            .map(|conflicted| match conflicted.as_slice() {
+                [] => format!("{method:?}"),
+                paths => format!(
+                    "{method:?}, leaving conflicts in {}; resolve them and commit",
+                    paths.join(", ")
+                ),
+            }),
+    };
+    // The journal marks interrupted applies; a failed one reports itself.
+    journal.finish();
+    let method = applied?;
+    let record = applied_record(repo, side, before, &method)?;
+    Ok(Applied { method, record })
+}
+
+/// The record of an apply that moved `HEAD` away from `before`.
+fn applied_record(
+    repo: &Path,
+    side: &SideChannelConfig,
+    before: String,
+    method: &str,
+) -> Result<Option<ApplyRecord>> {
+    let after = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    if after == before {
+        return Ok(None);
+    }
+    let side_tip = git::rev_parse(repo, &git::side_channel_tracking_ref(side))?;
+    Ok(Some(ApplyRecord {
+        before,
+        after,
+        side_tip: side_tip.trim().to_string(),
+        method: method.to_string(),
+        applied_at: Utc::now().timestamp(),
+    }))
+}
+
+/// Tries each step of `chain` until one applies the side-channel tip. Failed
+/// steps undo themselves, so the next one starts from the same branch.
+fn apply_auto(repo: &Path, side: &SideChannelConfig, chain: &[ApplyStep]) -> Result<ApplyStep> {
+    let mut failures = Vec::new();
+    for step in chain {
+        let attempt = match step {
+            ApplyStep::FastForward => git::merge_side_channel_ff(repo, side),
+            ApplyStep::Rebase => git::rebase_onto_side_channel(repo, side),
+            ApplyStep::Merge => git::merge_side_channel_commit(repo, side),
+        };
+        match attempt {
+            Ok(()) => return Ok(*step),
+            Err(err) => failures.push(format!("{step}: {err:#}")),
+        }
+    }
+    bail!(
+        "no step of apply.auto_chain applied to {}: {}",
+        repo.display(),
+        failures.join("; ")
+    )
+}
+
 fn canonical_repo(path: &Path) -> Result<PathBuf> {
     path.canonicalize()
         .with_context(|| format!("failed to canonicalize {}", path.display()))
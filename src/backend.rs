@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::config::{GitAuth, LastSnapshot, SideChannelBackend, SideChannelConfig};
+use crate::git::{self, SideChannelSyncResult};
+use crate::offload;
+
+/// What to snapshot from a repository's working tree.
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotRequest<'a> {
+    pub include_untracked: bool,
+    pub excludes: &'a [&'a str],
+    /// Ignored-file globs to snapshot anyway.
+    pub force_include: &'a [String],
+    pub message: &'a str,
+    pub auth: &'a GitAuth,
+    /// The last snapshot this machine stored, from state; a working tree
+    /// still matching it is not snapshot again while the side channel it went
+    /// to still has it.
+    pub last_snapshot: Option<&'a LastSnapshot>,
+}
+
+/// Somewhere side-channel snapshots are stored. The workflow only talks to
+/// this trait, so a new storage kind is a new implementation plus a
+/// [`SideChannelBackend`] variant.
+pub trait SnapshotBackend {
+    /// Checks the destination is usable before any snapshot is taken.
+    fn preflight(&self, repo: &Path, auth: &GitAuth) -> Result<()>;
+
+    /// Snapshots the working tree and stores it, leaving `HEAD`, the index and
+    /// the working tree untouched.
+    fn store(&self, repo: &Path, request: &SnapshotRequest<'_>) -> Result<SideChannelSyncResult>;
+
+    /// Past-tense phrase for a stored snapshot in the run summary.
+    fn stored_label(&self) -> &'static str;
+}
+
+/// Returns the backend configured by `side.backend`.
+pub fn for_config(side: &SideChannelConfig) -> Box<dyn SnapshotBackend + '_> {
+    match side.backend {
+        SideChannelBackend::Git => Box::new(GitRemoteBackend { side }),
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => Box::new(BundleBackend { side }),
+    }
+}
+
+/// Pushes snapshot commits to `branch_name` on the `remote_name` git remote.
+pub struct GitRemoteBackend<'a> {
+    pub side: &'a SideChannelConfig,
+}
+
+impl SnapshotBackend for GitRemoteBackend<'_> {
+    fn preflight(&self, repo: &Path, auth: &GitAuth) -> Result<()> {
+        git::side_channel_preflight(repo, self.side, auth)
+    }
+
+    fn store(&self, repo: &Path, request: &SnapshotRequest<'_>) -> Result<SideChannelSyncResult> {
+        git::side_channel_sync(repo, self.side, request)
+    }
+
+    fn stored_label(&self) -> &'static str {
+        "side-channel commit pushed"
+    }
+}
+
+/// Uploads snapshot bundles to `destination` with rclone or restic.
+pub struct BundleBackend<'a> {
+    pub side: &'a SideChannelConfig,
+}
+
+impl SnapshotBackend for BundleBackend<'_> {
+    fn preflight(&self, _repo: &Path, _auth: &GitAuth) -> Result<()> {
+        Ok(())
+    }
+
+    fn store(&self, repo: &Path, request: &SnapshotRequest<'_>) -> Result<SideChannelSyncResult> {
+        offload::offload_snapshot(
+            repo,
+            self.side,
+            request.include_untracked,
+            request.excludes,
+            request.force_include,
+            request.message,
+        )
+    }
+
+    fn stored_label(&self) -> &'static str {
+        "side-channel snapshot uploaded"
+    }
+}
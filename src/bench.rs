@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use crate::cli::{BenchArgs, BenchFormat};
+use crate::config::{self, AuthMode, ResolvedConfig, ResolvedRepositoryConfig};
+use crate::git;
+use crate::probe;
+
+/// The git operations `shephard bench` times.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BenchOp {
+    /// `git status --porcelain`, local only.
+    Status,
+    /// `git fetch --dry-run` from the upstream remote.
+    Fetch,
+    /// `git push --dry-run` to the upstream.
+    Push,
+}
+
+impl BenchOp {
+    pub const ALL: [BenchOp; 3] = [BenchOp::Status, BenchOp::Fetch, BenchOp::Push];
+
+    fn label(self) -> &'static str {
+        match self {
+            BenchOp::Status => "status",
+            BenchOp::Fetch => "fetch",
+            BenchOp::Push => "push",
+        }
+    }
+}
+
+/// Round-trip times of one operation over every iteration that succeeded.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct Timing {
+    pub min_ms: Option<u64>,
+    pub median_ms: Option<u64>,
+    pub max_ms: Option<u64>,
+    pub failures: usize,
+    /// The first failure, when there was one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Timing {
+    fn from_samples(mut samples: Vec<u64>, failures: usize, error: Option<String>) -> Self {
+        samples.sort_unstable();
+        Timing {
+            min_ms: samples.first().copied(),
+            median_ms: median(&samples),
+            max_ms: samples.last().copied(),
+            failures,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct RepoBench {
+    pub repo: PathBuf,
+    /// The upstream's host; `None` for repositories without an upstream or
+    /// whose upstream is on this machine.
+    pub host: Option<String>,
+    pub timings: BTreeMap<BenchOp, Timing>,
+}
+
+/// Times every operation on every repository `args.iterations` times, then
+/// prints a table, or JSON with `--format json`.
+pub fn run(
+    args: &BenchArgs,
+    cfg: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+) -> Result<i32> {
+    if args.iterations == 0 {
+        bail!("--iterations must be at least 1");
+    }
+    let paths = repos
+        .iter()
+        .map(|repo| repo.path.clone())
+        .collect::<Vec<_>>();
+    let upstreams = probe::upstream_hosts(&paths, &cfg.url_rewrites);
+    let benches = repos
+        .iter()
+        .map(|repo| {
+            let host = upstreams
+                .get(&repo.path)
+                .map(|upstream| upstream.host.clone());
+            RepoBench {
+                repo: repo.path.clone(),
+                host,
+                timings: measure(cfg, &repo.path, args.iterations),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match args.format {
+        BenchFormat::Table => print!("{}", render_table(&benches)),
+        BenchFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "iterations": args.iterations,
+                "repos": benches,
+                "hosts": host_medians(&benches),
+            }))?
+        ),
+    }
+    let failed = benches.iter().any(|bench| {
+        bench
+            .timings
+            .values()
+            .any(|timing| timing.median_ms.is_none())
+    });
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// Times each [`BenchOp`] `iterations` times in `repo`, never prompting for
+/// credentials.
+pub fn measure(cfg: &ResolvedConfig, repo: &Path, iterations: usize) -> BTreeMap<BenchOp, Timing> {
+    // A benchmark that stops for a passphrase would time the typing.
+    let mut auth = config::resolve_git_auth(cfg, repo);
+    auth.mode = AuthMode::NoPrompt;
+    let remote = git::upstream_remote(repo).ok().flatten();
+
+    BenchOp::ALL
+        .into_iter()
+        .map(|op| {
+            let mut samples = Vec::new();
+            let mut failures = 0;
+            let mut error = None;
+            for _ in 0..iterations {
+                let started = Instant::now();
+                let outcome = match (op, &remote) {
+                    (BenchOp::Status, _) => git::changed_path_count(repo).map(|_| ()),
+                    (BenchOp::Fetch, Some(remote)) => git::fetch_dry_run(repo, remote, &auth),
+                    (BenchOp::Push, Some(_)) => git::push_dry_run(repo, &auth),
+                    (BenchOp::Fetch | BenchOp::Push, None) => Err(anyhow::anyhow!("no upstream")),
+                };
+                match outcome {
+                    Ok(()) => samples.push(millis(started.elapsed())),
+                    Err(err) => {
+                        failures += 1;
+                        error.get_or_insert_with(|| format!("{err:#}"));
+                    }
+                }
+            }
+            (op, Timing::from_samples(samples, failures, error))
+        })
+        .collect()
+}
+
+/// The median of each operation's per-repository medians, by upstream host.
+pub fn host_medians(benches: &[RepoBench]) -> BTreeMap<String, BTreeMap<BenchOp, u64>> {
+    let mut by_host = BTreeMap::<String, BTreeMap<BenchOp, Vec<u64>>>::new();
+    for bench in benches {
+        let Some(host) = &bench.host else {
+            continue;
+        };
+        for (op, timing) in &bench.timings {
+            if let Some(median) = timing.median_ms {
+                by_host
+                    .entry(host.clone())
+                    .or_default()
+                    .entry(*op)
+                    .or_default()
+                    .push(median);
+            }
+        }
+    }
+    by_host
+        .into_iter()
+        .map(|(host, ops)| {
+            let ops = ops
+                .into_iter()
+                .filter_map(|(op, mut medians)| {
+                    medians.sort_unstable();
+                    median(&medians).map(|median| (op, median))
+                })
+                .collect();
+            (host, ops)
+        })
+        .collect()
+}
+
+/// One row per repository with each operation's median, slowest fetch first,
+/// then one per upstream host.
+pub fn render_table(benches: &[RepoBench]) -> String {
+    let mut rows = benches.iter().collect::<Vec<_>>();
+    rows.sort_by_key(|bench| {
+        std::cmp::Reverse(
+            bench
+                .timings
+                .get(&BenchOp::Fetch)
+                .and_then(|timing| timing.median_ms),
+        )
+    });
+
+    let mut out = String::new();
+    let header = BenchOp::ALL
+        .iter()
+        .map(|op| format!("{:>8}", op.label()))
+        .collect::<String>();
+    out.push_str(&format!("{header}  {:<20}  REPO\n", "HOST"));
+    for bench in rows {
+        let cells = BenchOp::ALL
+            .iter()
+            .map(|op| {
+                let cell = match bench.timings.get(op).and_then(|timing| timing.median_ms) {
+                    Some(ms) => format!("{ms}ms"),
+                    None => "failed".to_string(),
+                };
+                format!("{cell:>8}")
+            })
+            .collect::<String>();
+        out.push_str(&format!(
+            "{cells}  {:<20}  {}\n",
+            bench.host.as_deref().unwrap_or("-"),
+            bench.repo.display()
+        ));
+    }
+
+    let hosts = host_medians(benches);
+    if !hosts.is_empty() {
+        out.push_str("By host (median):\n");
+        for (host, ops) in hosts {
+            let ops = BenchOp::ALL
+                .iter()
+                .filter_map(|op| ops.get(op).map(|ms| format!("{} {ms}ms", op.label())))
+                .collect::<Vec<_>>();
+            out.push_str(&format!("  {host}: {}\n", ops.join(", ")));
+        }
+    }
+    for bench in benches {
+        for (op, timing) in &bench.timings {
+            if let Some(error) = &timing.error {
+                out.push_str(&format!(
+                    "[FAIL] {} {} ({}x): {error}\n",
+                    bench.repo.display(),
+                    op.label(),
+                    timing.failures
+                ));
+            }
+        }
+    }
+    out
+}
+
+fn median(sorted: &[u64]) -> Option<u64> {
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(sorted[len / 2]),
+        len => Some((sorted[len / 2 - 1] + sorted[len / 2]) / 2),
+    }
+}
+
+fn millis(elapsed: Duration) -> u64 {
+    u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn timing(median_ms: Option<u64>) -> Timing {
+        Timing {
+            min_ms: median_ms,
+            median_ms,
+            max_ms: median_ms,
+            failures: usize::from(median_ms.is_none()),
+            error: median_ms.is_none().then(|| "no upstream".to_string()),
+        }
+    }
+
+    fn bench(repo: &str, host: Option<&str>, fetch: Option<u64>) -> RepoBench {
+        RepoBench {
+            repo: PathBuf::from(repo),
+            host: host.map(str::to_string),
+            timings: BTreeMap::from([
+                (BenchOp::Status, timing(Some(5))),
+                (BenchOp::Fetch, timing(fetch)),
+                (BenchOp::Push, timing(fetch.map(|ms| ms + 10))),
+            ]),
+        }
+    }
+
+    #[test]
+    fn table_lists_slowest_fetch_first_then_hosts_and_failures() {
+        let benches = [
+            bench("/srv/fast", Some("git.lan"), Some(40)),
+            bench("/srv/slow", Some("github.com"), Some(300)),
+            bench("/srv/slower", Some("github.com"), Some(500)),
+            bench("/srv/local", None, None),
+        ];
+
+        assert_eq!(
+            render_table(&benches),
+            "  status   fetch    push  HOST                  REPO\n\
+             \x20    5ms   500ms   510ms  github.com            /srv/slower\n\
+             \x20    5ms   300ms   310ms  github.com            /srv/slow\n\
+             \x20    5ms    40ms    50ms  git.lan               /srv/fast\n\
+             \x20    5ms  failed  failed  -                     /srv/local\n\
+             By host (median):\n\
+             \x20 git.lan: status 5ms, fetch 40ms, push 50ms\n\
+             \x20 github.com: status 5ms, fetch 400ms, push 410ms\n\
+             [FAIL] /srv/local fetch (1x): no upstream\n\
+             [FAIL] /srv/local push (1x): no upstream\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::path::Path;
+
+use anyhow::{Result, bail};
+
+use crate::apply;
+use crate::cli::ApplyMethodArg;
+use crate::config::{self, ResolvedConfig, ResolvedRepositoryConfig, SideChannelBackend};
+use crate::discovery;
+use crate::git;
+
+/// How a repository's side channel was brought onto this machine.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Restored {
+    /// The side-channel tip was applied with the named method.
+    Applied(String),
+    /// `HEAD` already contains the side-channel tip.
+    AlreadyApplied,
+    /// The side-channel remote has no side branch yet.
+    NoSnapshots,
+    SideChannelDisabled,
+    /// The repository is `read_only`, so nothing is applied to it.
+    ReadOnly,
+    /// Snapshots are stored as bundles, which `apply` cannot read.
+    Bundles(SideChannelBackend),
+}
+
+/// Sets up a new machine: clones every selected repository that is missing
+/// from disk and has a `clone_url`, adds its side-channel remote (from
+/// `remote_url_template` when missing), and applies the side-channel tip like
+/// `apply`. Returns 1 when any repository failed.
+pub fn run(
+    config: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+    method: Option<ApplyMethodArg>,
+) -> i32 {
+    let (mut cloned, mut applied, mut skipped, mut failed) = (0, 0, 0, 0);
+    for repo in repos {
+        let path = &repo.path;
+        let was_cloned = if path.exists() {
+            false
+        } else {
+            let Some(url) = &repo.clone_url else {
+                skipped += 1;
+                println!("[SKIP] {} :: missing and has no clone_url", path.display());
+                continue;
+            };
+            match git::clone_repo(url, path, &config::resolve_git_auth(config, path)) {
+                Ok(()) => {
+                    cloned += 1;
+                    true
+                }
+                Err(err) => {
+                    failed += 1;
+                    println!("[FAIL] {} :: cloning {url}: {err:#}", path.display());
+                    continue;
+                }
+            }
+        };
+
+        let origin = if was_cloned { "cloned" } else { "present" };
+        match restore(config, path, method) {
+            Ok(restored) => {
+                let note = match restored {
+                    Restored::Applied(method) => {
+                        applied += 1;
+                        format!("applied side channel using {method}")
+                    }
+                    Restored::AlreadyApplied => "side channel already applied".to_string(),
+                    Restored::NoSnapshots => "no side-channel snapshots yet".to_string(),
+                    Restored::SideChannelDisabled => "side channel disabled".to_string(),
+                    Restored::ReadOnly => "read-only, side channel not applied".to_string(),
+                    Restored::Bundles(backend) => {
+                        format!("snapshots are stored with {backend}; restore them by hand")
+                    }
+                };
+                println!("[OK] {} :: {origin}, {note}", path.display());
+            }
+            Err(err) => {
+                failed += 1;
+                println!("[FAIL] {} :: {origin}, {err:#}", path.display());
+            }
+        }
+    }
+
+    let failed_note = if failed > 0 {
+        format!(", {failed} failed")
+    } else {
+        String::new()
+    };
+    println!(
+        "Bootstrapped {} repos: {cloned} cloned, {applied} applied, {skipped} skipped{failed_note}",
+        repos.len()
+    );
+    i32::from(failed > 0)
+}
+
+/// Adds `repo`'s side-channel remote when missing, fetches the side branch and
+/// applies its tip unless `HEAD` already has it.
+pub fn restore(
+    config: &ResolvedConfig,
+    repo: &Path,
+    method: Option<ApplyMethodArg>,
+) -> Result<Restored> {
+    if !discovery::is_git_repository(repo) {
+        bail!("{} is not a git repository", repo.display());
+    }
+    if config::is_read_only(config, repo) {
+        return Ok(Restored::ReadOnly);
+    }
+    let side = config::resolve_apply_side_channel(config, repo);
+    if !side.enabled {
+        return Ok(Restored::SideChannelDisabled);
+    }
+    match side.backend {
+        SideChannelBackend::Git => {}
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => {
+            return Ok(Restored::Bundles(side.backend));
+        }
+    }
+
+    git::ensure_side_channel_remote(repo, &side)?;
+    if !git::side_branch_exists(repo, &side, &config::resolve_git_auth(config, repo))? {
+        return Ok(Restored::NoSnapshots);
+    }
+    let side = apply::fetch(repo, config)?;
+    let tip = git::rev_parse(repo, &git::side_channel_tracking_ref(&side))?;
+    if git::is_ancestor(repo, tip.trim(), "HEAD")? {
+        return Ok(Restored::AlreadyApplied);
+    }
+    apply::apply_fetched(repo, &side, method, config)
+        .map(|applied| Restored::Applied(applied.method))
+}
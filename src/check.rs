@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use crate::cli::CheckArgs;
+use crate::config::{CheckPolicy, ResolvedRepositoryConfig};
+use crate::discovery;
+use crate::git;
+use crate::git_backend::GitBackend;
+
+/// Verifies every repository against the `[check]` policy without syncing and
+/// returns `1` if any repository violates it.
+pub fn run(
+    args: &CheckArgs,
+    policy: &CheckPolicy,
+    repos: &[ResolvedRepositoryConfig],
+    backend: &dyn GitBackend,
+) -> i32 {
+    let mut failed = 0;
+    for repo in repos {
+        let problems = problems(&repo.path, policy, args.refresh, backend);
+        if problems.is_empty() {
+            println!("[OK] {}", repo.path.display());
+        } else {
+            failed += 1;
+            println!("[FAIL] {} :: {}", repo.path.display(), problems.join(", "));
+        }
+    }
+
+    println!(
+        "Checked {} repos: {} ok, {failed} failed",
+        repos.len(),
+        repos.len() - failed
+    );
+    if failed > 0 { 1 } else { 0 }
+}
+
+/// Lists the ways `repo` violates `policy`; an empty list means it passes.
+pub fn problems(
+    repo: &Path,
+    policy: &CheckPolicy,
+    refresh: bool,
+    backend: &dyn GitBackend,
+) -> Vec<String> {
+    if !discovery::is_git_repository(repo) {
+        return vec!["not a git repository".to_string()];
+    }
+
+    let mut problems = Vec::new();
+    if refresh && let Err(err) = git::fetch(repo) {
+        problems.push(format!("fetch failed: {err:#}"));
+    }
+
+    if !policy.allowed_branches.is_empty() {
+        match backend.current_branch(repo) {
+            Ok(Some(branch)) if policy.allowed_branches.contains(&branch) => {}
+            Ok(Some(branch)) => problems.push(format!(
+                "on {branch}, allowed: {}",
+                policy.allowed_branches.join(", ")
+            )),
+            Ok(None) => problems.push("detached HEAD".to_string()),
+            Err(err) => problems.push(format!("branch check failed: {err:#}")),
+        }
+    }
+
+    if !policy.allow_dirty {
+        match backend.changed_path_count(repo) {
+            Ok(0) => {}
+            Ok(count) => problems.push(format!("{count} changed")),
+            Err(err) => problems.push(format!("status check failed: {err:#}")),
+        }
+    }
+
+    if !policy.allow_behind {
+        match backend.upstream_ahead_behind(repo) {
+            Ok(Some((_, 0))) => {}
+            Ok(Some((_, behind))) => problems.push(format!("behind upstream by {behind}")),
+            Ok(None) => problems.push("no upstream".to_string()),
+            Err(err) => problems.push(format!("upstream check failed: {err:#}")),
+        }
+    }
+
+    problems
+}
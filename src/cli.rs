@@ -1,18 +1,90 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+
+use crate::config::{ExitCodePolicy, OutputFormat};
 
 #[derive(Debug, Parser)]
 #[command(name = "shephard", about = "Sync many git repositories from one place")]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Command>,
+    /// Log more to stderr: `-v` each repository's result, `-vv` every git
+    /// command, `-vvv` what the commands printed.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
     Run(RunArgs),
     Apply(ApplyArgs),
+    Status(StatusArgs),
+    /// Verify repositories are clean, on allowed branches, and up to date without syncing.
+    Check(CheckArgs),
+    #[command(subcommand)]
+    SideChannel(SideChannelCommand),
+    /// Prune stale and unconfigured repository records from the state file.
+    GcState(GcStateArgs),
+    /// Write unpushed commits and working-tree snapshots of repositories to a tarball.
+    Export(ExportArgs),
+    /// Recreate commits and working trees from a tarball written by `export`.
+    Import(ImportArgs),
+    /// Remove or rename remotes listed under `[prune_remotes]`, after showing the plan.
+    PruneRemotes(PruneRemotesArgs),
+    #[command(subcommand)]
+    Remotes(RemotesCommand),
+    /// Point config and state records of a repository that moved on disk at its new path.
+    Move(MoveArgs),
+    /// Add, remove, enable or disable `[[repositories]]` entries in the config file.
+    #[command(subcommand)]
+    Repo(RepoCommand),
+    /// Check that the environment has what unattended syncs need.
+    Doctor(DoctorArgs),
+    /// Create the config file, asking for roots, side-channel and commit settings.
+    Init(InitArgs),
+    /// Print data for shell completions, pickers and editor integrations.
+    Completions(CompletionsArgs),
+    /// Print repositories one per line, or choose some with a picker such as fzf.
+    Pick(PickArgs),
+    /// Install or remove git hooks that mark repositories as changed.
+    Hook(HookArgs),
+    /// Time status, fetch and push round trips per repository and upstream host.
+    Bench(BenchArgs),
+    /// Stay running and sync repositories shortly after their files change.
+    Watch(WatchArgs),
+    /// Clone missing repositories and apply their side-channel snapshots, for a new machine.
+    Bootstrap(BootstrapArgs),
+    /// Replace this binary with the latest GitHub release, checking only its SHA256SUMS digest (no signature).
+    #[cfg(feature = "self-update")]
+    SelfUpdate(SelfUpdateArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SideChannelCommand {
+    /// Prune unreachable snapshot objects on side-channel remotes.
+    Gc(SideChannelGcArgs),
+    /// Create missing bare side-channel remotes and seed the side branch.
+    Provision(SideChannelProvisionArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RepoCommand {
+    /// Add a `[[repositories]]` entry for a git repository.
+    Add(RepoAddArgs),
+    /// Remove the repository's `[[repositories]]` entries.
+    Remove(RepoPathArgs),
+    /// Let the repository sync again.
+    Enable(RepoPathArgs),
+    /// Keep the repository configured but leave it out of runs.
+    Disable(RepoPathArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RemotesCommand {
+    /// Rewrite remote URLs by `[url_rewrites]`, after showing the plan.
+    Migrate(RemotesMigrateArgs),
 }
 
 #[derive(Debug, Clone, Default, Parser)]
@@ -21,6 +93,12 @@ pub struct RunArgs {
     pub non_interactive: bool,
     #[arg(long, value_name = "PATH")]
     pub repos: Vec<PathBuf>,
+    /// Add the repositories listed one per line in FILE (`-` for stdin), such
+    /// as the output of `shephard pick`.
+    #[arg(long, value_name = "FILE", conflicts_with = "plan_file")]
+    pub repos_from: Option<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
     #[arg(long)]
     pub pull_only: bool,
     #[arg(long)]
@@ -33,19 +111,345 @@ pub struct RunArgs {
     pub side_channel: bool,
     #[arg(long)]
     pub no_side_channel: bool,
+    #[arg(long, value_name = "REMOTE")]
+    pub side_remote: Option<String>,
+    #[arg(long, value_name = "BRANCH")]
+    pub side_branch: Option<String>,
+    /// Fast-forward onto side-channel changes from other machines before
+    /// snapshotting, when it is safe (overrides `side_channel.auto_apply`).
+    #[arg(long)]
+    pub auto_apply: bool,
+    /// Skip the pull phase (overrides `phases.pull`).
+    #[arg(long)]
+    pub skip_pull: bool,
+    /// Skip the upstream push phase, committing locally only (overrides
+    /// `phases.push`).
+    #[arg(long)]
+    pub no_push: bool,
+    /// Print only the summary line and repositories that did not succeed.
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// How to print the run report (overrides `display.format`).
+    #[arg(long, value_enum, value_name = "FORMAT", conflicts_with = "quiet")]
+    pub format: Option<OutputFormat>,
+    /// Which results count as failure for the exit code (overrides `exit_code`).
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub exit_code: Option<ExitCodePolicy>,
+    /// Write what this run would do to a JSON plan instead of syncing.
+    #[arg(long, value_name = "FILE", conflicts_with = "plan_file")]
+    pub write_plan: Option<PathBuf>,
+    /// Sync exactly the repositories in a plan written by `--write-plan`, as it says.
+    #[arg(long, value_name = "FILE", conflicts_with = "repos")]
+    pub plan_file: Option<PathBuf>,
+    /// Never prompt for credentials during the run; afterwards, retry the
+    /// repositories that needed them one at a time with git on the terminal.
+    #[arg(long, conflicts_with = "non_interactive")]
+    pub interactive_auth: bool,
 }
 
 #[derive(Debug, Clone, Parser)]
 pub struct ApplyArgs {
     #[arg(long, value_name = "PATH")]
     pub repo: Option<PathBuf>,
-    #[arg(long, value_enum, default_value_t = ApplyMethodArg::Merge)]
-    pub method: ApplyMethodArg,
+    /// Apply to every configured and discovered repository, newest
+    /// side-channel snapshot first, skipping those already applied.
+    #[arg(long, conflicts_with = "repo")]
+    pub all: bool,
+    /// Defaults to the repository's `apply.method`, then the global one, then
+    /// `merge`.
+    #[arg(long, value_enum)]
+    pub method: Option<ApplyMethodArg>,
+    /// Check out only these paths (relative to the repository root) from the
+    /// side-channel tip, leaving the rest of the working tree alone.
+    #[arg(long, value_name = "PATH", num_args = 1.., conflicts_with_all = ["all", "method"])]
+    pub paths: Vec<String>,
+    /// Commit the paths checked out by `--paths`.
+    #[arg(long, requires = "paths")]
+    pub commit: bool,
+    /// Reset the repository to where the last recorded apply found it,
+    /// keeping uncommitted changes.
+    #[arg(long, conflicts_with_all = ["all", "method", "paths"])]
+    pub undo: bool,
+    /// With `--undo`, also drop commits made after the apply.
+    #[arg(long, requires = "undo")]
+    pub force: bool,
+    /// Never prompt; a conflicted apply fails or is left for resolving by
+    /// hand instead of offering to resolve it file by file.
+    #[arg(long)]
+    pub non_interactive: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct StatusArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Print a single compact summary line for status bars.
+    #[arg(long)]
+    pub short: bool,
+    /// Fetch remotes before checking instead of relying on cached data.
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct CheckArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Fetch remotes first so "behind upstream" reflects the server.
+    #[arg(long)]
+    pub refresh: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct WatchArgs {
+    /// Seconds a repository must go without changes before it is synced.
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub debounce_secs: u64,
+    /// Settings for each sync, as for `run`; syncs never prompt.
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct BenchArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// How many times to run each operation; the table shows the median.
+    #[arg(long, short = 'n', default_value_t = 3)]
+    pub iterations: usize,
+    #[arg(long, value_enum, default_value_t = BenchFormat::Table)]
+    pub format: BenchFormat,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct BootstrapArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    /// Defaults to each repository's `apply.method`, then the global one,
+    /// then `merge`.
+    #[arg(long, value_enum)]
+    pub method: Option<ApplyMethodArg>,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum BenchFormat {
+    /// Medians per repository and per upstream host, slowest fetch first.
+    #[default]
+    Table,
+    /// Minimum, median and maximum of every operation, plus failures.
+    Json,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct SideChannelGcArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct GcStateArgs {
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Report what would be pruned without rewriting the state file.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct ExportArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Archive to write.
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct ImportArgs {
+    /// Archive written by `shephard export`.
+    #[arg(value_name = "FILE")]
+    pub archive: PathBuf,
+    /// Import repositories exported under the path prefix OLD at NEW instead.
+    #[arg(long, value_name = "OLD=NEW")]
+    pub map: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct PruneRemotesArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Apply the plan without asking.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct RemotesMigrateArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Rewrite the URLs without asking.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct RepoAddArgs {
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+    /// Name shown for the repository instead of its path.
+    #[arg(long)]
+    pub alias: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct RepoPathArgs {
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct MoveArgs {
+    /// Where the repository used to be.
+    #[arg(value_name = "OLD")]
+    pub old: PathBuf,
+    /// Where the repository is now.
+    #[arg(value_name = "NEW")]
+    pub new: PathBuf,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct HookArgs {
+    #[command(subcommand)]
+    pub command: HookCommand,
+    #[arg(long, value_name = "PATH", global = true)]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH", global = true)]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Subcommand)]
+pub enum HookCommand {
+    /// Write post-commit, post-checkout and post-merge hooks that record the
+    /// time of the last change in the git directory, for `watch` to notice.
+    Install,
+    /// Remove the hooks `install` wrote, leaving other hooks alone.
+    Uninstall,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct PickArgs {
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Command that reads paths on stdin and prints the chosen ones, e.g.
+    /// `fzf --multi` (overrides `picker`).
+    #[arg(long, value_name = "COMMAND")]
+    pub picker: Option<String>,
+    /// Only print the list, even when `picker` is configured.
+    #[arg(long, conflicts_with = "picker")]
+    pub no_picker: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct CompletionsArgs {
+    /// List configured repositories, one per line or as JSON.
+    #[arg(long, required = true)]
+    pub list_repos: bool,
+    /// Also list repositories discovered under workspace roots.
+    #[arg(long)]
+    pub discovered: bool,
+    #[arg(long, value_enum, default_value_t = ListFormat::Tsv)]
+    pub format: ListFormat,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum ListFormat {
+    /// Tab-separated columns: path, alias, enabled, source, root.
+    #[default]
+    Tsv,
+    Json,
+}
+
+#[cfg(feature = "self-update")]
+#[derive(Debug, Clone, Default, Parser)]
+pub struct SelfUpdateArgs {
+    /// Only report whether a newer release exists; exits 1 when one does.
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct DoctorArgs {
+    /// Check the minimal environment cron would run shephard in instead of
+    /// this shell's.
+    #[arg(long)]
+    pub cron: bool,
+    /// Also time `git status` in each repository and offer to turn on
+    /// `core.untrackedCache` where listing untracked files is slow.
+    #[arg(long)]
+    pub untracked_cache: bool,
+    /// Turn on `core.untrackedCache` where it helps without asking.
+    #[arg(short, long, requires = "untracked_cache")]
+    pub yes: bool,
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct InitArgs {
+    /// Workspace roots to write; the suggestion when asking.
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// List the repositories found under the roots as `[[repositories]]`
+    /// entries without asking.
+    #[arg(long)]
+    pub discover: bool,
+    /// Replace an existing config file.
+    #[arg(long)]
+    pub force: bool,
+    /// Write the flags and defaults without asking.
+    #[arg(long)]
+    pub non_interactive: bool,
+}
+
+#[derive(Debug, Clone, Default, Parser)]
+pub struct SideChannelProvisionArgs {
+    #[arg(long, value_name = "PATH")]
+    pub repos: Vec<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    pub roots: Vec<PathBuf>,
+    /// Also create ssh remotes by running `git init --bare` on the host.
+    #[arg(long)]
+    pub ssh: bool,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ApplyMethodArg {
     Merge,
     CherryPick,
     Squash,
+    /// Replay the branch's own commits on top of the side-channel tip.
+    Rebase,
+    /// Try each step of `apply.auto_chain` (fast-forward, rebase, merge
+    /// commit by default) until one succeeds.
+    Auto,
+    /// Merge with a merge commit; on conflict, leave the merge in progress
+    /// with conflict markers in the working tree to resolve by hand.
+    MergeWithConflicts,
 }
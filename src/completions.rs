@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+
+use crate::cli::ListFormat;
+use crate::config::{self, ResolvedRepositoryConfig, ResolvedWorkspaceRoot};
+
+/// Renders `repos` for scripts, sorted by path. TSV has one line per
+/// repository with the columns path, alias, enabled, source (`configured` or
+/// `discovered`) and root name, empty when unset; JSON is an array of objects
+/// with the same keys. Columns are only ever appended, so scripts can rely on
+/// their positions.
+pub fn list_repos(
+    configured: &[ResolvedRepositoryConfig],
+    repos: &[ResolvedRepositoryConfig],
+    roots: &[ResolvedWorkspaceRoot],
+    format: ListFormat,
+) -> String {
+    let configured_keys = configured
+        .iter()
+        .map(|repo| config::canonical_repo_key(&repo.path))
+        .collect::<BTreeSet<_>>();
+    let mut repos = repos.iter().collect::<Vec<_>>();
+    repos.sort_by(|left, right| left.path.cmp(&right.path));
+    let rows = repos.into_iter().map(|repo| {
+        let source = if configured_keys.contains(&config::canonical_repo_key(&repo.path)) {
+            "configured"
+        } else {
+            "discovered"
+        };
+        let root = config::root_for(roots, &repo.path).map(|root| root.name.as_str());
+        (repo, source, root)
+    });
+
+    match format {
+        ListFormat::Tsv => rows
+            .map(|(repo, source, root)| {
+                let field = |value: &str| value.replace(['\t', '\n'], " ");
+                format!(
+                    "{}\t{}\t{}\t{source}\t{}\n",
+                    field(&repo.path.to_string_lossy()),
+                    field(repo.alias.as_deref().unwrap_or_default()),
+                    repo.enabled,
+                    field(root.unwrap_or_default())
+                )
+            })
+            .collect(),
+        ListFormat::Json => {
+            let entries = rows
+                .map(|(repo, source, root)| {
+                    serde_json::json!({
+                        "path": repo.path,
+                        "alias": repo.alias,
+                        "enabled": repo.enabled,
+                        "source": source,
+                        "root": root,
+                    })
+                })
+                .collect::<Vec<_>>();
+            format!(
+                "{}\n",
+                serde_json::to_string_pretty(&entries).unwrap_or_default()
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::config::ResolvedRepositorySideChannelConfig;
+
+    #[test]
+    fn list_repos_marks_source_alias_and_root() {
+        let roots = [ResolvedWorkspaceRoot {
+            path: PathBuf::from("/srv/work"),
+            name: "work".to_string(),
+            include_untracked: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            notify: None,
+        }];
+        let defaults = config::merge_discovered_repositories(
+            &[],
+            &[PathBuf::from("/srv/notes"), PathBuf::from("/srv/old")],
+            &[],
+        );
+        let configured = vec![
+            ResolvedRepositoryConfig {
+                alias: Some("notes".to_string()),
+                ..defaults[0].clone()
+            },
+            ResolvedRepositoryConfig {
+                enabled: false,
+                ..defaults[1].clone()
+            },
+        ];
+        let known = config::merge_discovered_repositories(
+            &configured,
+            &[PathBuf::from("/srv/work/api")],
+            &roots,
+        );
+
+        assert_eq!(
+            list_repos(&configured, &known, &roots, ListFormat::Tsv),
+            "/srv/notes\tnotes\ttrue\tconfigured\t\n\
+             /srv/old\t\tfalse\tconfigured\t\n\
+             /srv/work/api\t\ttrue\tdiscovered\twork\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&list_repos(&configured, &known, &roots, ListFormat::Json))
+                .expect("json should parse");
+        assert_eq!(
+            json[2],
+            serde_json::json!({
+                "path": "/srv/work/api",
+                "alias": null,
+                "enabled": true,
+                "source": "discovered",
+                "root": "work",
+            })
+        );
+    }
+}
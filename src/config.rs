@@ -1,30 +1,227 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, bail};
-use serde::Deserialize;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{FixedOffset, Local, Utc};
+use serde::{Deserialize, Serialize};
 
-use crate::cli::RunArgs;
+use crate::cli::{ApplyMethodArg, RunArgs};
+use crate::remote;
 
-#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum RunMode {
     SyncAll,
     PullOnly,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum FailurePolicy {
     Continue,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Which results make `shephard run` exit non-zero.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitCodePolicy {
+    /// Failed and busy repositories.
+    Standard,
+    /// Also skipped repositories (offline hosts, colocated jj), for CI.
+    Strict,
+    /// Only failures other than authentication errors; busy repositories and
+    /// rejected credentials are reported but tolerated.
+    Lenient,
+}
+
+/// What to do with repositories that jj (Jujutsu) also manages, detected by
+/// a `.jj` directory next to `.git`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JjPolicy {
+    /// Report the repository as skipped without touching it.
+    Skip,
+    /// Sync through `commands.pull`/`commands.push`, defaulting to
+    /// `jj git fetch` and `jj git push`.
+    Commands,
+}
+
+/// Where side-channel snapshots are stored.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SideChannelBackend {
+    /// Commits pushed to `branch_name` on `remote_name`.
+    Git,
+    /// A git bundle per repository and host, copied with `rclone copyto`.
+    Rclone,
+    /// A git bundle per repository and host, stored with `restic backup --stdin`.
+    Restic,
+}
+
+impl fmt::Display for SideChannelBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SideChannelBackend::Git => "git",
+            SideChannelBackend::Rclone => "rclone",
+            SideChannelBackend::Restic => "restic",
+        })
+    }
+}
+
+/// How shephard answers read-only repository queries such as `status`'s.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitBackendKind {
+    /// Run the `git` binary.
+    #[default]
+    Subprocess,
+    /// Read repositories in-process with gitoxide; needs the `gix` feature.
+    Gix,
+}
+
+/// How network git commands (fetch, pull, push) may ask for credentials.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMode {
+    /// Leave prompting to git and ssh.
+    #[default]
+    Default,
+    /// Fail instead of prompting, so a batch never waits on a credential.
+    NoPrompt,
+    /// Let git and ssh prompt on the terminal, for one-time passwords and
+    /// passphrases.
+    Terminal,
+}
+
+/// Credential sources shephard hands to git for fetches, pulls and pushes,
+/// so unattended runs can use a keychain or password manager.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GitCredentials {
+    /// Program git and ssh run to ask for a password or passphrase, set as
+    /// `GIT_ASKPASS` and `SSH_ASKPASS`.
+    pub askpass: Option<String>,
+    /// Credential helper used instead of the ones in git config, e.g.
+    /// `!op plugin run -- gh auth git-credential`.
+    pub credential_helper: Option<String>,
+}
+
+/// How a network git command may get credentials.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GitAuth {
+    pub mode: AuthMode,
+    pub credentials: GitCredentials,
+}
+
+/// The clock `{timestamp}` reads: `"local"`, `"utc"` or a fixed offset such
+/// as `"+05:30"`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimestampZone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl TryFrom<String> for TimestampZone {
+    type Error = String;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        match raw.to_ascii_lowercase().as_str() {
+            "local" => Ok(TimestampZone::Local),
+            "utc" => Ok(TimestampZone::Utc),
+            _ => raw.parse::<FixedOffset>().map(TimestampZone::Fixed).map_err(|_| {
+                format!("unknown timezone {raw:?}; expected \"local\", \"utc\" or an offset such as \"+05:30\"")
+            }),
+        }
+    }
+}
+
+impl From<TimestampZone> for String {
+    fn from(zone: TimestampZone) -> Self {
+        match zone {
+            TimestampZone::Local => "local".to_string(),
+            TimestampZone::Utc => "utc".to_string(),
+            TimestampZone::Fixed(offset) => offset.to_string(),
+        }
+    }
+}
+
+/// How `{timestamp}` is rendered in commit messages and trailers.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TimestampStyle {
+    /// A chrono `strftime` format.
+    pub format: String,
+    pub zone: TimestampZone,
+}
+
+impl Default for TimestampStyle {
+    fn default() -> Self {
+        Self {
+            format: "%Y-%m-%d %H:%M:%S %z".to_string(),
+            zone: TimestampZone::Local,
+        }
+    }
+}
+
+impl TimestampStyle {
+    /// The current time in this style.
+    pub fn now(&self) -> String {
+        match self.zone {
+            TimestampZone::Local => Local::now().format(&self.format).to_string(),
+            TimestampZone::Utc => Utc::now().format(&self.format).to_string(),
+            TimestampZone::Fixed(offset) => Utc::now()
+                .with_timezone(&offset)
+                .format(&self.format)
+                .to_string(),
+        }
+    }
+}
+
+/// Whether untracked files are committed or snapshot alongside tracked changes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UntrackedPolicy {
+    Exclude,
+    Include,
+    /// List the untracked files and ask before each interactive run.
+    Ask,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SideChannelConfig {
     pub enabled: bool,
     pub remote_name: String,
     pub branch_name: String,
+    /// Split snapshots touching more files than this into several pushes.
+    pub push_chunk_files: Option<usize>,
+    /// Skip snapshots changing fewer lines than this since the previous one,
+    /// ignoring whitespace. With `min_changed_files` too, reaching either wins.
+    pub min_changed_lines: Option<usize>,
+    /// Skip snapshots changing fewer files than this since the previous one.
+    pub min_changed_files: Option<usize>,
+    /// Git merge driver per attribute pattern (e.g. `"*.md" = "union"`) used
+    /// when combining a snapshot with the current side-channel tip.
+    pub merge_drivers: BTreeMap<String, String>,
+    /// URL for adding `remote_name` when a repository lacks it, e.g.
+    /// `git@backup:shephard/{repo_name}.git`.
+    pub remote_url_template: Option<String>,
+    /// Snapshot files matched by ignore rules along with other untracked
+    /// files. Off by default: only `force_include` globs reach ignored files.
+    pub snapshot_ignored: bool,
+    /// Before snapshotting, fast-forward onto a side-channel tip holding
+    /// changes this checkout lacks, when the tree is clean.
+    pub auto_apply: bool,
+    /// Track the side channel under `refs/shephard/remotes/` instead of
+    /// `refs/remotes/`, keeping it out of `git branch -r`.
+    pub hide_refs: bool,
+    pub backend: SideChannelBackend,
+    /// rclone remote path (`b2:bucket/shephard`) or restic repository for the
+    /// `rclone` and `restic` backends.
+    pub destination: Option<String>,
 }
 
 #[derive(Debug, Clone, Default, Eq, PartialEq)]
@@ -32,53 +229,461 @@ pub struct ResolvedRepositorySideChannelConfig {
     pub enabled: Option<bool>,
     pub remote_name: Option<String>,
     pub branch_name: Option<String>,
+    pub push_chunk_files: Option<usize>,
+    pub min_changed_lines: Option<usize>,
+    pub min_changed_files: Option<usize>,
+    pub merge_drivers: Option<BTreeMap<String, String>>,
+    pub remote_url_template: Option<String>,
+    pub snapshot_ignored: Option<bool>,
+    pub auto_apply: Option<bool>,
+    pub hide_refs: Option<bool>,
+    pub backend: Option<SideChannelBackend>,
+    pub destination: Option<String>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ResolvedRepositoryConfig {
     pub path: PathBuf,
     pub enabled: bool,
-    pub include_untracked: Option<bool>,
+    /// Replaces `default_mode`; `pull_only` also wins over `--push`.
+    pub mode: Option<RunMode>,
+    pub include_untracked: Option<UntrackedPolicy>,
+    pub builtin_excludes: Option<bool>,
+    /// Upstream push override; side-channel snapshots are unaffected.
+    pub push_enabled: Option<bool>,
+    pub side_channel: ResolvedRepositorySideChannelConfig,
+    /// Glob pathspecs of ignored files to force into side-channel snapshots.
+    pub force_include: Vec<String>,
+    pub commands: RepoCommands,
+    pub colocated_jj: Option<JjPolicy>,
+    /// Stable name for state records, for repositories whose root commit
+    /// does not identify them (or that have none yet).
+    pub id: Option<String>,
+    /// Replaces the global `lockfiles` list.
+    pub lockfiles: Option<Vec<String>>,
+    /// Higher priorities are synced and reported first; ties go by path.
+    pub priority: i32,
+    /// Replaces the global `askpass` and `credential_helper` settings that are
+    /// set here.
+    pub credentials: GitCredentials,
+    /// Name shown for this repository in reports and `status` instead of its
+    /// path.
+    pub alias: Option<String>,
+    /// Replaces `apply.method` when `apply --method` is not given.
+    pub apply_method: Option<ApplyMethodArg>,
+    /// Where `shephard bootstrap` clones the repository from when `path`
+    /// does not exist yet.
+    pub clone_url: Option<String>,
+    /// Replaces the global `pre_repo` and `post_repo` hooks that are set here.
+    pub hooks: RepoHooks,
+    /// Never commit, push, or create refs here, whatever the flags: runs only
+    /// pull, the side channel is off, and `apply` refuses the repository.
+    pub read_only: bool,
+}
+
+/// Shell commands that replace shephard's built-in git operations for one
+/// repository, e.g. for jj checkouts or repositories with their own sync
+/// script. Each runs with `sh -c` in the repository directory.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RepoCommands {
+    /// Replaces `git pull --ff-only`.
+    pub pull: Option<String>,
+    /// Replaces staging, committing, and pushing upstream.
+    pub push: Option<String>,
+}
+
+/// Shell commands run around a sync with `sh -c`. `pre_run` and `post_run`
+/// run once per `shephard run`; `pre_repo` and `post_repo` run in each
+/// repository's directory, and a repository's own `hooks` replace them.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HooksConfig {
+    pub pre_run: Option<String>,
+    pub post_run: Option<String>,
+    pub pre_repo: Option<String>,
+    pub post_repo: Option<String>,
+}
+
+/// The per-repository half of [`HooksConfig`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoHooks {
+    /// Runs before the repository syncs; failing fails the repository without
+    /// syncing it.
+    pub pre_repo: Option<String>,
+    /// Runs after the repository synced, whatever the result.
+    pub post_repo: Option<String>,
+}
+
+/// What `shephard check` requires of every repository.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CheckPolicy {
+    /// Branches repositories must be on; empty allows any branch.
+    pub allowed_branches: Vec<String>,
+    pub allow_dirty: bool,
+    pub allow_behind: bool,
+}
+
+/// Which remotes `shephard prune-remotes` removes or renames, e.g. after
+/// moving the backup server.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct PruneRemotesPolicy {
+    /// Remotes whose URL points at one of these hosts are removed.
+    pub dead_hosts: Vec<String>,
+    /// Remote names that are removed outright, such as old side-channel names.
+    pub remove: Vec<String>,
+    /// Old remote name to new name.
+    pub renames: BTreeMap<String, String>,
+}
+
+/// How reports and `status` print repository paths. Repositories with an
+/// `alias` always show it; plans and state keep absolute paths.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PathStyle {
+    /// Canonical absolute paths.
+    #[default]
+    Absolute,
+    /// Paths under the home directory as `~/...`.
+    Home,
+    /// Paths under a workspace root as `<root name>/...`, others as with `Home`.
+    Root,
+}
+
+/// How `shephard run` prints its report.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Eq, PartialEq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One line per repository.
+    #[default]
+    Plain,
+    /// Plain with colored status labels.
+    Color,
+    /// Only repositories that did not succeed.
+    Quiet,
+    /// One JSON document with absolute paths.
+    Json,
+    /// A Markdown table.
+    Markdown,
+}
+
+/// One way `apply --method auto` may bring in side-channel changes.
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyStep {
+    /// `git merge --ff-only`.
+    FastForward,
+    /// Replay the branch's own commits on top of the side-channel tip.
+    Rebase,
+    /// A merge commit joining the branch and the side-channel tip.
+    Merge,
+}
+
+impl fmt::Display for ApplyStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ApplyStep::FastForward => "fast-forward",
+            ApplyStep::Rebase => "rebase",
+            ApplyStep::Merge => "merge commit",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ApplyConfig {
+    /// Used when `apply --method` is not given and the repository sets none.
+    pub method: ApplyMethodArg,
+    /// Steps `apply --method auto` tries in order until one succeeds; a step
+    /// that fails is undone before the next one starts.
+    pub auto_chain: Vec<ApplyStep>,
+}
+
+impl Default for ApplyConfig {
+    fn default() -> Self {
+        ApplyConfig {
+            method: ApplyMethodArg::Merge,
+            auto_chain: vec![ApplyStep::FastForward, ApplyStep::Rebase, ApplyStep::Merge],
+        }
+    }
+}
+
+/// Which phases of a sync run; `post` always runs. See
+/// [`crate::workflow::RunPhase`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PhasesConfig {
+    /// Side-channel remote setup and fetch. The conflict guard runs whenever
+    /// the commit phase does.
+    pub preflight: bool,
+    /// Upstream pull, plus side-channel catch-up.
+    pub pull: bool,
+    /// Staging and committing, or taking and storing the side-channel
+    /// snapshot.
+    pub commit: bool,
+    /// Upstream push.
+    pub push: bool,
+}
+
+impl Default for PhasesConfig {
+    fn default() -> Self {
+        PhasesConfig {
+            preflight: true,
+            pull: true,
+            commit: true,
+            push: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DisplayConfig {
+    pub paths: PathStyle,
+    pub format: OutputFormat,
+}
+
+/// Fallbacks for what a minimal environment such as cron's leaves out,
+/// applied before any git command runs.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct EnvironmentConfig {
+    /// Agent socket to use when `SSH_AUTH_SOCK` is unset or stale, e.g. a
+    /// stable symlink kept up to date by the login shell.
+    pub ssh_auth_sock_path: Option<PathBuf>,
+    /// Directories appended to `PATH` when missing from it, e.g. where git,
+    /// rclone or an askpass program is installed.
+    pub path: Vec<PathBuf>,
+}
+
+/// Where run reports are sent besides stdout.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct NotifyConfig {
+    pub email: Option<EmailNotifyConfig>,
+    pub webhooks: Vec<WebhookNotifyConfig>,
+    /// healthchecks.io-style ping URL; `/start` is pinged when a run begins,
+    /// the URL itself on success and `/fail` on failure.
+    pub healthcheck_url: Option<String>,
+}
+
+/// `[notify.email]`: mails the run report through an SMTP server using curl.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct EmailNotifyConfig {
+    /// curl SMTP URL, e.g. `smtps://smtp.example.com` or `smtp://localhost:25`.
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub username: Option<String>,
+    /// Shell command printing the SMTP password, e.g. `pass show smtp`.
+    pub password_command: Option<String>,
+    #[serde(default)]
+    pub only_on_failure: bool,
+}
+
+/// `[[notify.webhooks]]`: posts the run report to an HTTP endpoint.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct WebhookNotifyConfig {
+    pub url: String,
+    #[serde(default)]
+    pub kind: WebhookKind,
+    /// Shell command printing a bearer token (Matrix access tokens).
+    pub token_command: Option<String>,
+    #[serde(default)]
+    pub only_on_failure: bool,
+}
+
+/// Payload format a webhook endpoint expects.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookKind {
+    /// shephard's own JSON with per-repository results.
+    #[default]
+    Generic,
+    /// Slack incoming webhook with blocks.
+    Slack,
+    /// Discord webhook with an embed.
+    Discord,
+    /// Matrix client-server `m.room.message` send URL, without the
+    /// transaction id.
+    Matrix,
+}
+
+/// A directory walked for repositories, with defaults applied to discovered
+/// repositories that have no explicit `[[repositories]]` entry.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResolvedWorkspaceRoot {
+    pub path: PathBuf,
+    /// Label in the per-root run summary; defaults to the directory name.
+    pub name: String,
+    pub include_untracked: Option<UntrackedPolicy>,
     pub side_channel: ResolvedRepositorySideChannelConfig,
+    /// Replaces `[notify]` for repositories under this root, configured or
+    /// discovered, so their reports only reach this root's targets.
+    pub notify: Option<NotifyConfig>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ResolvedConfig {
     pub default_mode: RunMode,
     pub push_enabled: bool,
-    pub include_untracked: bool,
+    pub include_untracked: UntrackedPolicy,
+    /// Keep well-known build and dependency directories (`target/`,
+    /// `node_modules/`, ...) out of untracked staging.
+    pub builtin_excludes: bool,
     pub side_channel: SideChannelConfig,
     pub commit_template: String,
+    /// Body rendered under the subject from `commit_template`, with the same
+    /// placeholders plus `{changed_files}`; `None` writes no body.
+    pub commit_body_template: Option<String>,
+    /// Column the body is wrapped at; `0` leaves its lines as rendered.
+    pub commit_wrap_width: usize,
+    pub commit_timestamp: TimestampStyle,
+    /// `Key: value` trailers appended to every commit shephard creates.
+    pub commit_trailers: BTreeMap<String, String>,
     pub failure_policy: FailurePolicy,
+    pub exit_code: ExitCodePolicy,
+    /// How long to wait for another process to release `index.lock`.
+    pub lock_timeout_secs: u64,
+    /// How long to wait for each distinct upstream host to answer before
+    /// skipping its repositories as offline; `0` disables the probe.
+    pub probe_timeout_secs: u64,
     pub repositories: Vec<ResolvedRepositoryConfig>,
+    pub workspace_roots: Vec<ResolvedWorkspaceRoot>,
+    pub descend_hidden_dirs: bool,
+    /// Globs for directories discovery never enters, such as `node_modules`
+    /// or `~/scratch/**`; `~` and environment variables are expanded.
+    pub discovery_excludes: Vec<String>,
+    /// Drop state records whose last run is older than this; `0` keeps them.
+    pub state_retention_days: u64,
+    pub check: CheckPolicy,
+    pub colocated_jj: JjPolicy,
+    pub notify: NotifyConfig,
+    pub prune_remotes: PruneRemotesPolicy,
+    /// Old URL prefix to the prefix that replaces it, applied like git's
+    /// `url.<base>.insteadOf` to the URLs shephard adds remotes and clones
+    /// from, to reported hosts, and by `shephard remotes migrate`.
+    pub url_rewrites: BTreeMap<String, String>,
+    pub environment: EnvironmentConfig,
+    pub display: DisplayConfig,
+    pub apply: ApplyConfig,
+    pub phases: PhasesConfig,
+    /// Command `shephard pick` pipes repository paths through, e.g.
+    /// `fzf --multi`.
+    pub picker: Option<String>,
+    pub hooks: HooksConfig,
+    pub git_backend: GitBackendKind,
+    /// Globs of lockfiles whose changes alone do not produce a sync commit or
+    /// snapshot, e.g. `**/Cargo.lock`.
+    pub lockfiles: Vec<String>,
+    pub credentials: GitCredentials,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// Everything one repository's sync runs with, after config, per-repository
+/// overrides, flags and plan entries. Run history records it per repository.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ResolvedRunConfig {
+    pub mode: RunMode,
     pub push_enabled: bool,
-    pub include_untracked: bool,
+    pub include_untracked: UntrackedPolicy,
+    pub builtin_excludes: bool,
     pub side_channel: SideChannelConfig,
     pub commit_template: String,
+    #[serde(default)]
+    pub commit_body_template: Option<String>,
+    #[serde(default)]
+    pub commit_wrap_width: usize,
+    #[serde(default)]
+    pub commit_timestamp: TimestampStyle,
+    pub commit_trailers: BTreeMap<String, String>,
     pub failure_policy: FailurePolicy,
+    pub lock_timeout_secs: u64,
+    pub force_include: Vec<String>,
+    pub commands: RepoCommands,
+    pub colocated_jj: JjPolicy,
+    /// Glob pathspecs kept out of staging on top of `builtin_excludes`, such
+    /// as shephard's own state directory when it lives inside the repository.
+    pub excludes: Vec<String>,
+    pub lockfiles: Vec<String>,
+    pub auth: GitAuth,
+    pub phases: PhasesConfig,
+    #[serde(default)]
+    pub hooks: RepoHooks,
+    /// Set for `read_only` repositories; no phase past the pull runs,
+    /// whatever the mode, plan or flags say.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Where each mutating phase is journaled so an interrupted run can be
+    /// reported; `None` turns journaling off.
+    #[serde(skip)]
+    pub journal_dir: Option<PathBuf>,
+    /// The last snapshot stored for this repository, from state.
+    #[serde(skip)]
+    pub last_snapshot: Option<LastSnapshot>,
+}
+
+/// The last snapshot this machine stored for a repository, so a working tree
+/// that already reached the same place is not snapshot again.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LastSnapshot {
+    /// Where it was stored: the side-channel remote, its URL and the branch
+    /// ref, or the bundle backend and destination.
+    pub target: String,
+    /// The working tree as snapshot, before the side-channel tip was merged in.
+    pub tree: String,
+    /// The side-channel commit that carried it; `None` for bundle backends.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tip: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Default)]
 struct PartialConfig {
     default_mode: Option<RunMode>,
     push_enabled: Option<bool>,
-    include_untracked: Option<bool>,
+    include_untracked: Option<PartialUntrackedPolicy>,
+    builtin_excludes: Option<bool>,
     side_channel: Option<PartialSideChannelConfig>,
     commit: Option<PartialCommitConfig>,
     failure_policy: Option<FailurePolicy>,
+    exit_code: Option<ExitCodePolicy>,
+    lock_timeout_secs: Option<u64>,
+    probe_timeout_secs: Option<u64>,
     repositories: Option<Vec<PartialRepositoryConfig>>,
+    discovery: Option<PartialDiscoveryConfig>,
+    state: Option<PartialStateConfig>,
+    check: Option<PartialCheckConfig>,
+    colocated_jj: Option<JjPolicy>,
+    notify: Option<PartialNotifyConfig>,
+    prune_remotes: Option<PartialPruneRemotesConfig>,
+    url_rewrites: Option<BTreeMap<String, String>>,
+    environment: Option<PartialEnvironmentConfig>,
+    display: Option<PartialDisplayConfig>,
+    apply: Option<PartialApplyConfig>,
+    phases: Option<PartialPhasesConfig>,
+    picker: Option<String>,
+    hooks: Option<HooksConfig>,
+    git_backend: Option<GitBackendKind>,
+    lockfiles: Option<Vec<String>>,
+    #[serde(flatten)]
+    credentials: GitCredentials,
 }
 
 #[derive(Debug, Deserialize, Default)]
 struct PartialRepositoryConfig {
     path: PathBuf,
     enabled: Option<bool>,
-    include_untracked: Option<bool>,
+    mode: Option<RunMode>,
+    include_untracked: Option<PartialUntrackedPolicy>,
+    builtin_excludes: Option<bool>,
+    push_enabled: Option<bool>,
     side_channel: Option<PartialSideChannelConfig>,
+    force_include: Option<Vec<String>>,
+    commands: Option<RepoCommands>,
+    colocated_jj: Option<JjPolicy>,
+    id: Option<String>,
+    lockfiles: Option<Vec<String>>,
+    priority: Option<i32>,
+    alias: Option<String>,
+    apply: Option<PartialRepositoryApplyConfig>,
+    clone_url: Option<String>,
+    hooks: Option<RepoHooks>,
+    read_only: Option<bool>,
+    #[serde(flatten)]
+    credentials: GitCredentials,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -86,11 +691,133 @@ struct PartialSideChannelConfig {
     enabled: Option<bool>,
     remote_name: Option<String>,
     branch_name: Option<String>,
+    push_chunk_files: Option<usize>,
+    min_changed_lines: Option<usize>,
+    min_changed_files: Option<usize>,
+    merge_drivers: Option<BTreeMap<String, String>>,
+    /// `remote_url` reads better for one repository's literal URL; setting
+    /// both keys is a duplicate-field error.
+    #[serde(alias = "remote_url")]
+    remote_url_template: Option<String>,
+    snapshot_ignored: Option<bool>,
+    auto_apply: Option<bool>,
+    hide_refs: Option<bool>,
+    backend: Option<SideChannelBackend>,
+    destination: Option<String>,
+}
+
+/// `include_untracked` accepts `true`, `false`, or `"ask"`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum PartialUntrackedPolicy {
+    Flag(bool),
+    Keyword(UntrackedKeyword),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UntrackedKeyword {
+    Ask,
+}
+
+impl From<PartialUntrackedPolicy> for UntrackedPolicy {
+    fn from(partial: PartialUntrackedPolicy) -> Self {
+        match partial {
+            PartialUntrackedPolicy::Flag(true) => UntrackedPolicy::Include,
+            PartialUntrackedPolicy::Flag(false) => UntrackedPolicy::Exclude,
+            PartialUntrackedPolicy::Keyword(UntrackedKeyword::Ask) => UntrackedPolicy::Ask,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Default)]
 struct PartialCommitConfig {
     message_template: Option<String>,
+    body_template: Option<String>,
+    wrap_width: Option<usize>,
+    timestamp_format: Option<String>,
+    timezone: Option<TimestampZone>,
+    trailers: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialDiscoveryConfig {
+    roots: Option<Vec<PartialWorkspaceRoot>>,
+    descend_hidden: Option<bool>,
+    exclude: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialNotifyConfig {
+    email: Option<EmailNotifyConfig>,
+    webhooks: Option<Vec<WebhookNotifyConfig>>,
+    healthcheck_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialCheckConfig {
+    allowed_branches: Option<Vec<String>>,
+    allow_dirty: Option<bool>,
+    allow_behind: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialPruneRemotesConfig {
+    dead_hosts: Option<Vec<String>>,
+    remove: Option<Vec<String>>,
+    renames: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialDisplayConfig {
+    paths: Option<PathStyle>,
+    format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialApplyConfig {
+    method: Option<ApplyMethodArg>,
+    auto_chain: Option<Vec<ApplyStep>>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialPhasesConfig {
+    preflight: Option<bool>,
+    pull: Option<bool>,
+    commit: Option<bool>,
+    push: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialRepositoryApplyConfig {
+    method: Option<ApplyMethodArg>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialEnvironmentConfig {
+    ssh_auth_sock_path: Option<PathBuf>,
+    path: Option<Vec<PathBuf>>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct PartialStateConfig {
+    retention_days: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PartialWorkspaceRoot {
+    Path(PathBuf),
+    Table(Box<PartialWorkspaceRootConfig>),
+}
+
+#[derive(Debug, Deserialize)]
+struct PartialWorkspaceRootConfig {
+    path: PathBuf,
+    name: Option<String>,
+    include_untracked: Option<PartialUntrackedPolicy>,
+    side_channel: Option<PartialSideChannelConfig>,
+    notify: Option<PartialNotifyConfig>,
 }
 
 pub fn config_path() -> Result<PathBuf> {
@@ -99,16 +826,180 @@ pub fn config_path() -> Result<PathBuf> {
 }
 
 pub fn load() -> Result<ResolvedConfig> {
-    let mut cfg = defaults();
     let path = config_path()?;
     if !path.exists() {
-        return Ok(cfg);
+        return Ok(defaults());
     }
 
     let raw = fs::read_to_string(&path)
         .with_context(|| format!("failed reading config file at {}", path.display()))?;
-    let parsed: PartialConfig = toml::from_str(&raw)
-        .with_context(|| format!("failed parsing config file at {}", path.display()))?;
+    let config_dir = path
+        .parent()
+        .context("unable to determine parent directory for config file")?;
+    parse(&raw, config_dir)
+        .with_context(|| format!("failed parsing config file at {}", path.display()))
+}
+
+/// Points `[[repositories]]` entries of `raw` that resolve to `old` at `new`,
+/// editing only their `path` values so comments and layout survive. Returns
+/// the new text and how many entries changed.
+pub fn rewrite_repository_path(
+    raw: &str,
+    config_dir: &Path,
+    old: &Path,
+    new: &Path,
+) -> Result<(String, usize)> {
+    let mut document: toml_edit::DocumentMut = raw.parse()?;
+    let key = canonical_repo_key(old);
+    let Some(entries) = repository_tables(&mut document)? else {
+        return Ok((raw.to_string(), 0));
+    };
+    let mut changed = 0;
+    for entry in entries.iter_mut() {
+        if !entry_resolves_to(entry, config_dir, &key)? {
+            continue;
+        }
+        if let Some(value) = entry
+            .get_mut("path")
+            .and_then(toml_edit::Item::as_value_mut)
+        {
+            let decor = value.decor().clone();
+            *value = toml_edit::Value::from(new.to_string_lossy().to_string());
+            *value.decor_mut() = decor;
+            changed += 1;
+        }
+    }
+    Ok((document.to_string(), changed))
+}
+
+/// Appends a `[[repositories]]` entry for `path`, with `alias` when given, to
+/// `raw`, leaving the rest of the file as written. Fails when an entry
+/// already resolves to `path`.
+pub fn add_repository(
+    raw: &str,
+    config_dir: &Path,
+    path: &Path,
+    alias: Option<&str>,
+) -> Result<String> {
+    let mut document: toml_edit::DocumentMut = raw.parse()?;
+    let key = canonical_repo_key(path);
+    if let Some(entries) = repository_tables(&mut document)? {
+        for entry in entries.iter() {
+            if entry_resolves_to(entry, config_dir, &key)? {
+                bail!("{} is already configured", path.display());
+            }
+        }
+    }
+
+    let mut entry = toml_edit::Table::new();
+    entry["path"] = toml_edit::value(path.to_string_lossy().to_string());
+    if let Some(alias) = alias {
+        entry["alias"] = toml_edit::value(alias);
+    }
+    match repository_tables(&mut document)? {
+        Some(entries) => entries.push(entry),
+        None => {
+            let mut entries = toml_edit::ArrayOfTables::new();
+            entries.push(entry);
+            document.insert("repositories", toml_edit::Item::ArrayOfTables(entries));
+        }
+    }
+    Ok(document.to_string())
+}
+
+/// Removes the `[[repositories]]` entries of `raw` that resolve to `path`,
+/// comments above them included. Returns the new text and how many went.
+pub fn remove_repository(raw: &str, config_dir: &Path, path: &Path) -> Result<(String, usize)> {
+    let mut document: toml_edit::DocumentMut = raw.parse()?;
+    let key = canonical_repo_key(path);
+    let Some(entries) = repository_tables(&mut document)? else {
+        return Ok((raw.to_string(), 0));
+    };
+    let mut matching = Vec::new();
+    for (idx, entry) in entries.iter().enumerate() {
+        if entry_resolves_to(entry, config_dir, &key)? {
+            matching.push(idx);
+        }
+    }
+    for idx in matching.iter().rev() {
+        entries.remove(*idx);
+    }
+    Ok((document.to_string(), matching.len()))
+}
+
+/// Sets `enabled` on the `[[repositories]]` entries of `raw` that resolve to
+/// `path`; enabling drops the key, since entries are enabled by default.
+/// Returns the new text and how many entries matched.
+pub fn set_repository_enabled(
+    raw: &str,
+    config_dir: &Path,
+    path: &Path,
+    enabled: bool,
+) -> Result<(String, usize)> {
+    let mut document: toml_edit::DocumentMut = raw.parse()?;
+    let key = canonical_repo_key(path);
+    let Some(entries) = repository_tables(&mut document)? else {
+        return Ok((raw.to_string(), 0));
+    };
+    let mut matched = 0;
+    for entry in entries.iter_mut() {
+        if !entry_resolves_to(entry, config_dir, &key)? {
+            continue;
+        }
+        matched += 1;
+        if enabled {
+            entry.remove("enabled");
+        } else {
+            entry["enabled"] = toml_edit::value(false);
+        }
+    }
+    Ok((document.to_string(), matched))
+}
+
+/// The `[[repositories]]` tables of `document`, or `None` when it has none.
+fn repository_tables(
+    document: &mut toml_edit::DocumentMut,
+) -> Result<Option<&mut toml_edit::ArrayOfTables>> {
+    match document.get_mut("repositories") {
+        None => Ok(None),
+        Some(item) => item
+            .as_array_of_tables_mut()
+            .map(Some)
+            .context("repositories is not written as [[repositories]] tables; edit it by hand"),
+    }
+}
+
+/// Whether a `[[repositories]]` entry's `path` resolves to the repository
+/// with [`canonical_repo_key`] `key`.
+fn entry_resolves_to(entry: &toml_edit::Table, config_dir: &Path, key: &str) -> Result<bool> {
+    let Some(written) = entry.get("path").and_then(|path| path.as_str()) else {
+        return Ok(false);
+    };
+    let expanded = expand_path(Path::new(written))?;
+    let resolved = if expanded.is_absolute() {
+        expanded
+    } else {
+        config_dir.join(expanded)
+    };
+    Ok(canonical_repo_key(&resolved) == key)
+}
+
+/// Parses and validates config file contents; relative paths resolve against
+/// `config_dir`.
+pub fn parse(raw: &str, config_dir: &Path) -> Result<ResolvedConfig> {
+    let host = hostname::get()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    parse_for_host(raw, config_dir, &host)
+}
+
+fn parse_for_host(raw: &str, config_dir: &Path, host: &str) -> Result<ResolvedConfig> {
+    let mut cfg = defaults();
+    let mut document: toml::Table = toml::from_str(raw)?;
+    apply_host_overlay(&mut document, host)?;
+    apply_repository_templates(&mut document)?;
+    let parsed: PartialConfig = toml::Value::Table(document).try_into()?;
 
     if let Some(mode) = parsed.default_mode {
         cfg.default_mode = mode;
@@ -117,8 +1008,18 @@ pub fn load() -> Result<ResolvedConfig> {
         cfg.push_enabled = enabled;
     }
     if let Some(include_untracked) = parsed.include_untracked {
-        cfg.include_untracked = include_untracked;
+        cfg.include_untracked = include_untracked.into();
+    }
+    if let Some(builtin_excludes) = parsed.builtin_excludes {
+        cfg.builtin_excludes = builtin_excludes;
+    }
+    if let Some(colocated_jj) = parsed.colocated_jj {
+        cfg.colocated_jj = colocated_jj;
     }
+    if let Some(lockfiles) = parsed.lockfiles {
+        cfg.lockfiles = lockfiles;
+    }
+    cfg.credentials = resolve_credentials(parsed.credentials, "")?;
     if let Some(side_channel) = parsed.side_channel {
         if let Some(enabled) = side_channel.enabled {
             cfg.side_channel.enabled = enabled;
@@ -129,63 +1030,333 @@ pub fn load() -> Result<ResolvedConfig> {
         if let Some(branch_name) = side_channel.branch_name {
             cfg.side_channel.branch_name = branch_name;
         }
+        if let Some(push_chunk_files) = side_channel.push_chunk_files {
+            cfg.side_channel.push_chunk_files = Some(push_chunk_files);
+        }
+        if let Some(min_changed_lines) = side_channel.min_changed_lines {
+            cfg.side_channel.min_changed_lines = Some(min_changed_lines);
+        }
+        if let Some(min_changed_files) = side_channel.min_changed_files {
+            cfg.side_channel.min_changed_files = Some(min_changed_files);
+        }
+        if let Some(merge_drivers) = side_channel.merge_drivers {
+            cfg.side_channel.merge_drivers = merge_drivers;
+        }
+        if let Some(remote_url_template) = side_channel.remote_url_template {
+            cfg.side_channel.remote_url_template = Some(remote_url_template);
+        }
+        if let Some(snapshot_ignored) = side_channel.snapshot_ignored {
+            cfg.side_channel.snapshot_ignored = snapshot_ignored;
+        }
+        if let Some(auto_apply) = side_channel.auto_apply {
+            cfg.side_channel.auto_apply = auto_apply;
+        }
+        if let Some(hide_refs) = side_channel.hide_refs {
+            cfg.side_channel.hide_refs = hide_refs;
+        }
+        if let Some(backend) = side_channel.backend {
+            cfg.side_channel.backend = backend;
+        }
+        if let Some(destination) = side_channel.destination {
+            cfg.side_channel.destination = Some(destination);
+        }
     }
-    if let Some(template) = parsed.commit.and_then(|commit| commit.message_template) {
-        cfg.commit_template = template;
+    if let Some(commit) = parsed.commit {
+        if let Some(template) = commit.message_template {
+            cfg.commit_template = template;
+        }
+        if let Some(template) = commit.body_template {
+            cfg.commit_body_template = Some(template);
+        }
+        if let Some(width) = commit.wrap_width {
+            cfg.commit_wrap_width = width;
+        }
+        if let Some(format) = commit.timestamp_format {
+            cfg.commit_timestamp.format = format;
+        }
+        if let Some(zone) = commit.timezone {
+            cfg.commit_timestamp.zone = zone;
+        }
+        if let Some(trailers) = commit.trailers {
+            cfg.commit_trailers = trailers;
+        }
     }
     if let Some(policy) = parsed.failure_policy {
         cfg.failure_policy = policy;
     }
+    if let Some(policy) = parsed.exit_code {
+        cfg.exit_code = policy;
+    }
+    if let Some(lock_timeout_secs) = parsed.lock_timeout_secs {
+        cfg.lock_timeout_secs = lock_timeout_secs;
+    }
+    if let Some(probe_timeout_secs) = parsed.probe_timeout_secs {
+        cfg.probe_timeout_secs = probe_timeout_secs;
+    }
     if let Some(repositories) = parsed.repositories {
-        let config_dir = path
-            .parent()
-            .context("unable to determine parent directory for config file")?;
         cfg.repositories = resolve_repositories(repositories, config_dir)?;
     }
-
-    validate(&cfg)?;
-    Ok(cfg)
-}
-
-pub fn resolve_run_config(base: &ResolvedConfig, args: &RunArgs) -> Result<ResolvedRunConfig> {
-    validate_run_args(args)?;
-
-    let mut mode = base.default_mode;
-    if args.pull_only {
-        mode = RunMode::PullOnly;
+    if let Some(discovery) = parsed.discovery {
+        if let Some(roots) = discovery.roots {
+            cfg.workspace_roots = resolve_roots(roots, config_dir)?;
+        }
+        if let Some(descend_hidden) = discovery.descend_hidden {
+            cfg.descend_hidden_dirs = descend_hidden;
+        }
+        if let Some(exclude) = discovery.exclude {
+            cfg.discovery_excludes = exclude
+                .iter()
+                .enumerate()
+                .map(|(idx, pattern)| {
+                    expand_path(Path::new(pattern))
+                        .map(|expanded| expanded.to_string_lossy().into_owned())
+                        .with_context(|| format!("discovery.exclude[{idx}]"))
+                })
+                .collect::<Result<_>>()?;
+        }
     }
-    if args.push {
-        mode = RunMode::SyncAll;
+    if let Some(retention_days) = parsed.state.and_then(|state| state.retention_days) {
+        cfg.state_retention_days = retention_days;
     }
-
-    let push_enabled = match mode {
-        RunMode::PullOnly => false,
-        RunMode::SyncAll => base.push_enabled,
-    };
-
-    let mut resolved = ResolvedRunConfig {
-        push_enabled,
-        include_untracked: base.include_untracked,
-        side_channel: base.side_channel.clone(),
-        commit_template: base.commit_template.clone(),
-        failure_policy: base.failure_policy,
-    };
-    apply_cli_overrides(&mut resolved, args);
-
-    Ok(resolved)
-}
-
-pub fn resolve_repo_run_config(
-    base: &ResolvedRunConfig,
-    args: &RunArgs,
-    repo: &ResolvedRepositoryConfig,
-) -> ResolvedRunConfig {
+    if let Some(check) = parsed.check {
+        if let Some(allowed_branches) = check.allowed_branches {
+            cfg.check.allowed_branches = allowed_branches;
+        }
+        if let Some(allow_dirty) = check.allow_dirty {
+            cfg.check.allow_dirty = allow_dirty;
+        }
+        if let Some(allow_behind) = check.allow_behind {
+            cfg.check.allow_behind = allow_behind;
+        }
+    }
+    if let Some(notify) = parsed.notify {
+        cfg.notify = resolve_notify(notify);
+    }
+    if let Some(prune_remotes) = parsed.prune_remotes {
+        if let Some(dead_hosts) = prune_remotes.dead_hosts {
+            cfg.prune_remotes.dead_hosts = dead_hosts;
+        }
+        if let Some(remove) = prune_remotes.remove {
+            cfg.prune_remotes.remove = remove;
+        }
+        if let Some(renames) = prune_remotes.renames {
+            cfg.prune_remotes.renames = renames;
+        }
+    }
+    if let Some(url_rewrites) = parsed.url_rewrites {
+        cfg.url_rewrites = url_rewrites;
+    }
+    if let Some(picker) = parsed.picker {
+        if picker.trim().is_empty() {
+            bail!("picker cannot be empty");
+        }
+        cfg.picker = Some(picker);
+    }
+    if let Some(hooks) = parsed.hooks {
+        cfg.hooks = hooks;
+    }
+    if let Some(git_backend) = parsed.git_backend {
+        if cfg!(not(feature = "gix")) && git_backend == GitBackendKind::Gix {
+            bail!("git_backend = \"gix\" needs shephard built with the `gix` feature");
+        }
+        cfg.git_backend = git_backend;
+    }
+    if let Some(display) = parsed.display {
+        if let Some(paths) = display.paths {
+            cfg.display.paths = paths;
+        }
+        if let Some(format) = display.format {
+            cfg.display.format = format;
+        }
+    }
+    if let Some(apply) = parsed.apply {
+        if let Some(method) = apply.method {
+            cfg.apply.method = method;
+        }
+        if let Some(auto_chain) = apply.auto_chain {
+            if auto_chain.is_empty() {
+                bail!("apply.auto_chain needs at least one step");
+            }
+            cfg.apply.auto_chain = auto_chain;
+        }
+    }
+    if let Some(phases) = parsed.phases {
+        if let Some(preflight) = phases.preflight {
+            cfg.phases.preflight = preflight;
+        }
+        if let Some(pull) = phases.pull {
+            cfg.phases.pull = pull;
+        }
+        if let Some(commit) = phases.commit {
+            cfg.phases.commit = commit;
+        }
+        if let Some(push) = phases.push {
+            cfg.phases.push = push;
+        }
+    }
+    if let Some(environment) = parsed.environment {
+        if let Some(sock) = environment.ssh_auth_sock_path {
+            cfg.environment.ssh_auth_sock_path =
+                Some(expand_path(&sock).context("environment.ssh_auth_sock_path")?);
+        }
+        if let Some(path) = environment.path {
+            cfg.environment.path = path
+                .iter()
+                .map(|dir| expand_path(dir).context("environment.path"))
+                .collect::<Result<_>>()?;
+        }
+    }
+
+    validate(&cfg)?;
+    apply_url_rewrites(&mut cfg);
+    Ok(cfg)
+}
+
+pub fn resolve_run_config(base: &ResolvedConfig, args: &RunArgs) -> Result<ResolvedRunConfig> {
+    validate_run_args(args)?;
+
+    let mut resolved = ResolvedRunConfig {
+        mode: base.default_mode,
+        push_enabled: base.push_enabled,
+        include_untracked: base.include_untracked,
+        builtin_excludes: base.builtin_excludes,
+        side_channel: base.side_channel.clone(),
+        commit_template: base.commit_template.clone(),
+        commit_body_template: base.commit_body_template.clone(),
+        commit_wrap_width: base.commit_wrap_width,
+        commit_timestamp: base.commit_timestamp.clone(),
+        commit_trailers: base.commit_trailers.clone(),
+        failure_policy: base.failure_policy,
+        lock_timeout_secs: base.lock_timeout_secs,
+        force_include: Vec::new(),
+        commands: RepoCommands::default(),
+        colocated_jj: base.colocated_jj,
+        excludes: Vec::new(),
+        lockfiles: base.lockfiles.clone(),
+        auth: GitAuth {
+            mode: AuthMode::Default,
+            credentials: base.credentials.clone(),
+        },
+        phases: base.phases,
+        hooks: RepoHooks {
+            pre_repo: base.hooks.pre_repo.clone(),
+            post_repo: base.hooks.post_repo.clone(),
+        },
+        read_only: false,
+        journal_dir: None,
+        last_snapshot: None,
+    };
+    apply_cli_overrides(&mut resolved, args);
+
+    Ok(resolved)
+}
+
+pub fn resolve_repo_run_config(
+    base: &ResolvedRunConfig,
+    args: &RunArgs,
+    repo: &ResolvedRepositoryConfig,
+) -> ResolvedRunConfig {
     let mut resolved = base.clone();
     apply_repo_overrides(&mut resolved, repo);
     apply_cli_overrides(&mut resolved, args);
+    // Mirrors marked pull-only must never be committed to or pushed, even
+    // when a run asks for `--push`.
+    if repo.mode == Some(RunMode::PullOnly) || repo.read_only {
+        resolved.mode = RunMode::PullOnly;
+    }
+    if repo.read_only {
+        resolved.read_only = true;
+        resolved.side_channel.enabled = false;
+    }
     resolved
 }
 
+/// Returns the workspace roots for a run: configured roots followed by any
+/// `--roots` passed on the command line, without duplicates.
+pub fn resolve_workspace_roots(
+    config: &ResolvedConfig,
+    cli_roots: &[PathBuf],
+) -> Vec<ResolvedWorkspaceRoot> {
+    let mut seen = BTreeSet::new();
+    let cli_roots = cli_roots.iter().map(|root| {
+        let path = canonicalize_repo_path(root);
+        ResolvedWorkspaceRoot {
+            name: default_root_name(&path),
+            path,
+            include_untracked: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            notify: None,
+        }
+    });
+    config
+        .workspace_roots
+        .iter()
+        .cloned()
+        .chain(cli_roots)
+        .filter(|root| seen.insert(canonical_repo_key(&root.path)))
+        .collect()
+}
+
+/// The most specific workspace root containing `path`.
+pub fn root_for<'a>(
+    roots: &'a [ResolvedWorkspaceRoot],
+    path: &Path,
+) -> Option<&'a ResolvedWorkspaceRoot> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(&root.path))
+        .max_by_key(|root| root.path.components().count())
+}
+
+/// Combines configured repositories with repositories discovered under the
+/// workspace roots. Configured entries win, so a discovered repository that is
+/// disabled in config stays disabled. Other discovered repositories inherit the
+/// defaults of the most specific root containing them.
+pub fn merge_discovered_repositories(
+    configured: &[ResolvedRepositoryConfig],
+    discovered: &[PathBuf],
+    roots: &[ResolvedWorkspaceRoot],
+) -> Vec<ResolvedRepositoryConfig> {
+    let mut seen: BTreeSet<String> = configured
+        .iter()
+        .map(|repo| canonical_repo_key(&repo.path))
+        .collect();
+    let mut merged = configured.to_vec();
+
+    for path in discovered {
+        if !seen.insert(canonical_repo_key(path)) {
+            continue;
+        }
+
+        let root = root_for(roots, path);
+        merged.push(ResolvedRepositoryConfig {
+            path: path.clone(),
+            enabled: true,
+            mode: None,
+            include_untracked: root.and_then(|root| root.include_untracked),
+            builtin_excludes: None,
+            push_enabled: None,
+            side_channel: root
+                .map(|root| root.side_channel.clone())
+                .unwrap_or_default(),
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
+        });
+    }
+
+    merged
+}
+
 pub fn enabled_repositories(config: &ResolvedConfig) -> Vec<ResolvedRepositoryConfig> {
     config
         .repositories
@@ -195,18 +1366,70 @@ pub fn enabled_repositories(config: &ResolvedConfig) -> Vec<ResolvedRepositoryCo
         .collect()
 }
 
-pub fn resolve_apply_side_channel(config: &ResolvedConfig, repo: &Path) -> SideChannelConfig {
+/// Resolves the side-channel settings for one repository from global config and
+/// its per-repository (or per-root) overrides.
+pub fn resolve_repo_side_channel(
+    config: &ResolvedConfig,
+    repo: &ResolvedRepositoryConfig,
+) -> SideChannelConfig {
+    let mut side_channel = config.side_channel.clone();
+    apply_repo_side_channel_overrides(&mut side_channel, &repo.side_channel);
+    if repo.read_only {
+        side_channel.enabled = false;
+    }
+    side_channel
+}
+
+/// Credentials for git commands `shephard apply` runs against `repo`.
+pub fn resolve_git_auth(config: &ResolvedConfig, repo: &Path) -> GitAuth {
     let repo_key = canonical_repo_key(repo);
+    let mut credentials = config.credentials.clone();
+    if let Some(configured) = config
+        .repositories
+        .iter()
+        .find(|configured| canonical_repo_key(&configured.path) == repo_key)
+    {
+        apply_repo_credential_overrides(&mut credentials, &configured.credentials);
+    }
+    GitAuth {
+        mode: AuthMode::Default,
+        credentials,
+    }
+}
 
-    for configured in &config.repositories {
-        if canonical_repo_key(&configured.path) == repo_key {
-            let mut side_channel = config.side_channel.clone();
-            apply_repo_side_channel_overrides(&mut side_channel, &configured.side_channel);
-            return side_channel;
+pub fn resolve_apply_side_channel(config: &ResolvedConfig, repo: &Path) -> SideChannelConfig {
+    let mut side_channel = config.side_channel.clone();
+    if let Some(configured) = configured_repo(config, repo) {
+        apply_repo_side_channel_overrides(&mut side_channel, &configured.side_channel);
+        if configured.read_only {
+            side_channel.enabled = false;
         }
     }
+    side_channel
+}
+
+/// Whether `repo` is configured `read_only`.
+pub fn is_read_only(config: &ResolvedConfig, repo: &Path) -> bool {
+    configured_repo(config, repo).is_some_and(|configured| configured.read_only)
+}
+
+/// The method `apply` uses for `repo` without `--method`: the repository's
+/// `apply.method`, else the global one.
+pub fn resolve_apply_method(config: &ResolvedConfig, repo: &Path) -> ApplyMethodArg {
+    configured_repo(config, repo)
+        .and_then(|configured| configured.apply_method)
+        .unwrap_or(config.apply.method)
+}
 
-    config.side_channel.clone()
+fn configured_repo<'a>(
+    config: &'a ResolvedConfig,
+    repo: &Path,
+) -> Option<&'a ResolvedRepositoryConfig> {
+    let repo_key = canonical_repo_key(repo);
+    config
+        .repositories
+        .iter()
+        .find(|configured| canonical_repo_key(&configured.path) == repo_key)
 }
 
 pub fn canonical_repo_key(path: &Path) -> String {
@@ -223,6 +1446,20 @@ fn validate_run_args(args: &RunArgs) -> Result<()> {
     if args.side_channel && args.no_side_channel {
         bail!("--side-channel and --no-side-channel cannot be used together");
     }
+    if args
+        .side_remote
+        .as_ref()
+        .is_some_and(|remote_name| remote_name.trim().is_empty())
+    {
+        bail!("--side-remote cannot be empty");
+    }
+    if args
+        .side_branch
+        .as_ref()
+        .is_some_and(|branch_name| branch_name.trim().is_empty())
+    {
+        bail!("--side-branch cannot be empty");
+    }
     Ok(())
 }
 
@@ -230,7 +1467,66 @@ fn apply_repo_overrides(config: &mut ResolvedRunConfig, repo: &ResolvedRepositor
     if let Some(include_untracked) = repo.include_untracked {
         config.include_untracked = include_untracked;
     }
+    if let Some(builtin_excludes) = repo.builtin_excludes {
+        config.builtin_excludes = builtin_excludes;
+    }
+    if let Some(mode) = repo.mode {
+        config.mode = mode;
+    }
+    if let Some(push_enabled) = repo.push_enabled {
+        config.push_enabled = push_enabled;
+    }
     apply_repo_side_channel_overrides(&mut config.side_channel, &repo.side_channel);
+    config.force_include = repo.force_include.clone();
+    config.commands = repo.commands.clone();
+    if let Some(colocated_jj) = repo.colocated_jj {
+        config.colocated_jj = colocated_jj;
+    }
+    if let Some(lockfiles) = &repo.lockfiles {
+        config.lockfiles = lockfiles.clone();
+    }
+    apply_repo_credential_overrides(&mut config.auth.credentials, &repo.credentials);
+    if let Some(pre_repo) = &repo.hooks.pre_repo {
+        config.hooks.pre_repo = Some(pre_repo.clone());
+    }
+    if let Some(post_repo) = &repo.hooks.post_repo {
+        config.hooks.post_repo = Some(post_repo.clone());
+    }
+}
+
+fn apply_repo_credential_overrides(credentials: &mut GitCredentials, overrides: &GitCredentials) {
+    if let Some(askpass) = &overrides.askpass {
+        credentials.askpass = Some(askpass.clone());
+    }
+    if let Some(credential_helper) = &overrides.credential_helper {
+        credentials.credential_helper = Some(credential_helper.clone());
+    }
+}
+
+/// Checks that configured credential commands are non-empty and expands `~`
+/// and environment variables in the askpass program path.
+fn resolve_credentials(credentials: GitCredentials, prefix: &str) -> Result<GitCredentials> {
+    let askpass = match credentials.askpass {
+        Some(askpass) if askpass.trim().is_empty() => bail!("{prefix}askpass cannot be empty"),
+        Some(askpass) => Some(
+            expand_path(Path::new(&askpass))
+                .with_context(|| format!("{prefix}askpass"))?
+                .to_string_lossy()
+                .to_string(),
+        ),
+        None => None,
+    };
+    if credentials
+        .credential_helper
+        .as_ref()
+        .is_some_and(|helper| helper.trim().is_empty())
+    {
+        bail!("{prefix}credential_helper cannot be empty");
+    }
+    Ok(GitCredentials {
+        askpass,
+        credential_helper: credentials.credential_helper,
+    })
 }
 
 fn apply_repo_side_channel_overrides(
@@ -246,14 +1542,50 @@ fn apply_repo_side_channel_overrides(
     if let Some(branch_name) = &overrides.branch_name {
         side_channel.branch_name = branch_name.clone();
     }
+    if let Some(push_chunk_files) = overrides.push_chunk_files {
+        side_channel.push_chunk_files = Some(push_chunk_files);
+    }
+    if let Some(min_changed_lines) = overrides.min_changed_lines {
+        side_channel.min_changed_lines = Some(min_changed_lines);
+    }
+    if let Some(min_changed_files) = overrides.min_changed_files {
+        side_channel.min_changed_files = Some(min_changed_files);
+    }
+    if let Some(merge_drivers) = &overrides.merge_drivers {
+        side_channel.merge_drivers = merge_drivers.clone();
+    }
+    if let Some(remote_url_template) = &overrides.remote_url_template {
+        side_channel.remote_url_template = Some(remote_url_template.clone());
+    }
+    if let Some(snapshot_ignored) = overrides.snapshot_ignored {
+        side_channel.snapshot_ignored = snapshot_ignored;
+    }
+    if let Some(auto_apply) = overrides.auto_apply {
+        side_channel.auto_apply = auto_apply;
+    }
+    if let Some(hide_refs) = overrides.hide_refs {
+        side_channel.hide_refs = hide_refs;
+    }
+    if let Some(backend) = overrides.backend {
+        side_channel.backend = backend;
+    }
+    if let Some(destination) = &overrides.destination {
+        side_channel.destination = Some(destination.clone());
+    }
 }
 
 fn apply_cli_overrides(config: &mut ResolvedRunConfig, args: &RunArgs) {
+    if args.pull_only {
+        config.mode = RunMode::PullOnly;
+    }
+    if args.push {
+        config.mode = RunMode::SyncAll;
+    }
     if args.include_untracked {
-        config.include_untracked = true;
+        config.include_untracked = UntrackedPolicy::Include;
     }
     if args.tracked_only {
-        config.include_untracked = false;
+        config.include_untracked = UntrackedPolicy::Exclude;
     }
     if args.side_channel {
         config.side_channel.enabled = true;
@@ -261,6 +1593,24 @@ fn apply_cli_overrides(config: &mut ResolvedRunConfig, args: &RunArgs) {
     if args.no_side_channel {
         config.side_channel.enabled = false;
     }
+    if let Some(remote_name) = &args.side_remote {
+        config.side_channel.remote_name = remote_name.clone();
+    }
+    if let Some(branch_name) = &args.side_branch {
+        config.side_channel.branch_name = branch_name.clone();
+    }
+    if args.auto_apply {
+        config.side_channel.auto_apply = true;
+    }
+    if args.skip_pull {
+        config.phases.pull = false;
+    }
+    if args.no_push {
+        config.phases.push = false;
+    }
+    if args.interactive_auth {
+        config.auth.mode = AuthMode::NoPrompt;
+    }
 }
 
 fn resolve_repositories(
@@ -269,16 +1619,19 @@ fn resolve_repositories(
 ) -> Result<Vec<ResolvedRepositoryConfig>> {
     let mut resolved = Vec::new();
     let mut seen_keys = BTreeSet::new();
+    let mut seen_ids = BTreeSet::new();
 
     for (idx, partial) in partials.into_iter().enumerate() {
         if partial.path.as_os_str().is_empty() {
             bail!("repositories[{idx}].path cannot be empty");
         }
 
-        let resolved_path = if partial.path.is_absolute() {
-            partial.path.clone()
+        let expanded =
+            expand_path(&partial.path).with_context(|| format!("repositories[{idx}].path"))?;
+        let resolved_path = if expanded.is_absolute() {
+            expanded
         } else {
-            config_dir.join(&partial.path)
+            config_dir.join(expanded)
         };
         let canonical_path = canonicalize_repo_path(&resolved_path);
         let key = canonical_repo_key(&canonical_path);
@@ -288,45 +1641,333 @@ fn resolve_repositories(
                 partial.path.display()
             );
         }
-
-        let side_channel = if let Some(repo_side_channel) = partial.side_channel {
-            ResolvedRepositorySideChannelConfig {
-                enabled: repo_side_channel.enabled,
-                remote_name: repo_side_channel.remote_name,
-                branch_name: repo_side_channel.branch_name,
+        if partial
+            .alias
+            .as_ref()
+            .is_some_and(|alias| alias.trim().is_empty())
+        {
+            bail!("repositories[{idx}].alias cannot be empty");
+        }
+        if partial
+            .clone_url
+            .as_ref()
+            .is_some_and(|url| url.trim().is_empty())
+        {
+            bail!("repositories[{idx}].clone_url cannot be empty");
+        }
+        if let Some(id) = &partial.id {
+            if id.trim().is_empty() {
+                bail!("repositories[{idx}].id cannot be empty");
             }
-        } else {
-            ResolvedRepositorySideChannelConfig::default()
-        };
+            if !seen_ids.insert(id.clone()) {
+                bail!("repositories[{idx}] duplicates id {id}");
+            }
+        }
 
         resolved.push(ResolvedRepositoryConfig {
             path: canonical_path,
             enabled: partial.enabled.unwrap_or(true),
-            include_untracked: partial.include_untracked,
-            side_channel,
+            mode: partial.mode,
+            include_untracked: partial.include_untracked.map(Into::into),
+            builtin_excludes: partial.builtin_excludes,
+            push_enabled: partial.push_enabled,
+            side_channel: resolve_side_channel_overrides(partial.side_channel),
+            force_include: partial.force_include.unwrap_or_default(),
+            commands: partial.commands.unwrap_or_default(),
+            colocated_jj: partial.colocated_jj,
+            id: partial.id,
+            lockfiles: partial.lockfiles,
+            priority: partial.priority.unwrap_or_default(),
+            credentials: resolve_credentials(
+                partial.credentials,
+                &format!("repositories[{idx}]."),
+            )?,
+            alias: partial.alias,
+            apply_method: partial.apply.and_then(|apply| apply.method),
+            clone_url: partial.clone_url,
+            hooks: partial.hooks.unwrap_or_default(),
+            read_only: partial.read_only.unwrap_or(false),
         });
     }
 
     Ok(resolved)
 }
 
+fn resolve_side_channel_overrides(
+    partial: Option<PartialSideChannelConfig>,
+) -> ResolvedRepositorySideChannelConfig {
+    if let Some(side_channel) = partial {
+        ResolvedRepositorySideChannelConfig {
+            enabled: side_channel.enabled,
+            remote_name: side_channel.remote_name,
+            branch_name: side_channel.branch_name,
+            push_chunk_files: side_channel.push_chunk_files,
+            min_changed_lines: side_channel.min_changed_lines,
+            min_changed_files: side_channel.min_changed_files,
+            merge_drivers: side_channel.merge_drivers,
+            remote_url_template: side_channel.remote_url_template,
+            snapshot_ignored: side_channel.snapshot_ignored,
+            auto_apply: side_channel.auto_apply,
+            hide_refs: side_channel.hide_refs,
+            backend: side_channel.backend,
+            destination: side_channel.destination,
+        }
+    } else {
+        ResolvedRepositorySideChannelConfig::default()
+    }
+}
+
+fn resolve_roots(
+    roots: Vec<PartialWorkspaceRoot>,
+    config_dir: &Path,
+) -> Result<Vec<ResolvedWorkspaceRoot>> {
+    roots
+        .into_iter()
+        .enumerate()
+        .map(|(idx, root)| {
+            let table = match root {
+                PartialWorkspaceRoot::Path(path) => PartialWorkspaceRootConfig {
+                    path,
+                    name: None,
+                    include_untracked: None,
+                    side_channel: None,
+                    notify: None,
+                },
+                PartialWorkspaceRoot::Table(table) => *table,
+            };
+            let path = table.path;
+            let path = expand_path(&path).with_context(|| format!("discovery.roots[{idx}]"))?;
+            let path = if path.is_absolute() || path.as_os_str().is_empty() {
+                path
+            } else {
+                config_dir.join(path)
+            };
+            let path = canonicalize_repo_path(&path);
+            Ok(ResolvedWorkspaceRoot {
+                name: table.name.unwrap_or_else(|| default_root_name(&path)),
+                path,
+                include_untracked: table.include_untracked.map(Into::into),
+                side_channel: resolve_side_channel_overrides(table.side_channel),
+                notify: table.notify.map(resolve_notify),
+            })
+        })
+        .collect()
+}
+
+fn default_root_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().to_string(),
+    )
+}
+
+fn resolve_notify(partial: PartialNotifyConfig) -> NotifyConfig {
+    NotifyConfig {
+        email: partial.email,
+        webhooks: partial.webhooks.unwrap_or_default(),
+        healthcheck_url: partial.healthcheck_url,
+    }
+}
+
+/// Merges the `[hosts."<name>"]` section matching `host` (by full name, after
+/// the section for its short name) over the rest of the document, then drops
+/// `[hosts]`. Tables merge key by key; other values, arrays included, replace.
+fn apply_host_overlay(document: &mut toml::Table, host: &str) -> Result<()> {
+    let Some(hosts) = document.remove("hosts") else {
+        return Ok(());
+    };
+    let toml::Value::Table(mut hosts) = hosts else {
+        bail!("hosts must be a table of per-hostname sections");
+    };
+    let short = host.split('.').next().unwrap_or(host);
+    let names = if short == host {
+        vec![host]
+    } else {
+        vec![short, host]
+    };
+    for name in names {
+        match hosts.remove(name) {
+            Some(toml::Value::Table(overlay)) => merge_tables(document, overlay),
+            Some(_) => bail!("hosts.\"{name}\" must be a table"),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Expands `template = "<name>"` in `[[repositories]]` entries into the
+/// options of `[templates.<name>]`, then drops `[templates]`. The entry's own
+/// options win; tables merge key by key, as host sections do.
+fn apply_repository_templates(document: &mut toml::Table) -> Result<()> {
+    let templates = match document.remove("templates") {
+        Some(toml::Value::Table(templates)) => templates,
+        Some(_) => bail!("templates must be a table of named option sets"),
+        None => toml::Table::new(),
+    };
+    let Some(toml::Value::Array(repositories)) = document.get_mut("repositories") else {
+        return Ok(());
+    };
+    for (idx, entry) in repositories.iter_mut().enumerate() {
+        let toml::Value::Table(entry) = entry else {
+            continue;
+        };
+        let name = match entry.remove("template") {
+            Some(toml::Value::String(name)) => name,
+            Some(_) => bail!("repositories[{idx}].template must be a template name"),
+            None => continue,
+        };
+        let mut expanded = match templates.get(&name) {
+            Some(toml::Value::Table(template)) => template.clone(),
+            Some(_) => bail!("templates.{name} must be a table"),
+            None => bail!("repositories[{idx}].template names unknown template \"{name}\""),
+        };
+        if expanded.contains_key("path") {
+            bail!("templates.{name} cannot set path");
+        }
+        merge_tables(&mut expanded, std::mem::take(entry));
+        *entry = expanded;
+    }
+    Ok(())
+}
+
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(nested)) => {
+                merge_tables(existing, nested);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Expands a leading `~` to the home directory and `$VAR` or `${VAR}` to the
+/// environment variable's value, so one config file works across machines.
+/// An unset variable is an error rather than an empty string.
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    let Some(raw) = path.to_str() else {
+        return Ok(path.to_path_buf());
+    };
+
+    let mut expanded = String::new();
+    let mut remaining = raw;
+    if raw == "~" || raw.starts_with("~/") {
+        let home = dirs::home_dir().context("unable to resolve home directory for `~`")?;
+        expanded.push_str(&home.to_string_lossy());
+        remaining = &raw[1..];
+    }
+    while let Some(idx) = remaining.find('$') {
+        expanded.push_str(&remaining[..idx]);
+        let after = &remaining[idx + 1..];
+        let (name, tail) = match after.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .with_context(|| format!("unterminated `${{` in {raw}"))?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+            remaining = after;
+            continue;
+        }
+        let value = std::env::var(name)
+            .with_context(|| format!("environment variable {name} in {raw} is not set"))?;
+        expanded.push_str(&value);
+        remaining = tail;
+    }
+    expanded.push_str(remaining);
+    Ok(PathBuf::from(expanded))
+}
+
 fn canonicalize_repo_path(path: &Path) -> PathBuf {
     path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
 }
 
-fn defaults() -> ResolvedConfig {
+/// Points every `remote_url_template` and `clone_url` at its `[url_rewrites]`
+/// replacement, so remotes and clones shephard creates use the new URLs.
+fn apply_url_rewrites(cfg: &mut ResolvedConfig) {
+    let rewrites = cfg.url_rewrites.clone();
+    let rewrite = |url: &mut Option<String>| {
+        if let Some(rewritten) = url
+            .as_deref()
+            .and_then(|url| remote::rewrite_url(&rewrites, url))
+        {
+            *url = Some(rewritten);
+        }
+    };
+    rewrite(&mut cfg.side_channel.remote_url_template);
+    for repo in &mut cfg.repositories {
+        rewrite(&mut repo.side_channel.remote_url_template);
+        rewrite(&mut repo.clone_url);
+    }
+    for root in &mut cfg.workspace_roots {
+        rewrite(&mut root.side_channel.remote_url_template);
+    }
+}
+
+/// The configuration used when there is no config file.
+pub fn defaults() -> ResolvedConfig {
     ResolvedConfig {
         default_mode: RunMode::SyncAll,
         push_enabled: true,
-        include_untracked: false,
+        include_untracked: UntrackedPolicy::Exclude,
+        builtin_excludes: true,
         side_channel: SideChannelConfig {
             enabled: false,
             remote_name: "shephard".to_string(),
             branch_name: "shephard/sync".to_string(),
+            push_chunk_files: None,
+            min_changed_lines: None,
+            min_changed_files: None,
+            merge_drivers: BTreeMap::new(),
+            remote_url_template: None,
+            snapshot_ignored: false,
+            auto_apply: false,
+            hide_refs: false,
+            backend: SideChannelBackend::Git,
+            destination: None,
         },
         commit_template: "shephard sync: {timestamp} {hostname} [{scope}]".to_string(),
+        commit_body_template: None,
+        commit_wrap_width: 72,
+        commit_timestamp: TimestampStyle::default(),
+        commit_trailers: BTreeMap::from([
+            ("Sync-host".to_string(), "{hostname}".to_string()),
+            ("Synced-by".to_string(), "shephard {version}".to_string()),
+        ]),
         failure_policy: FailurePolicy::Continue,
+        exit_code: ExitCodePolicy::Standard,
+        lock_timeout_secs: 10,
+        probe_timeout_secs: 5,
         repositories: Vec::new(),
+        workspace_roots: Vec::new(),
+        descend_hidden_dirs: false,
+        discovery_excludes: Vec::new(),
+        state_retention_days: 90,
+        check: CheckPolicy::default(),
+        colocated_jj: JjPolicy::Skip,
+        notify: NotifyConfig::default(),
+        prune_remotes: PruneRemotesPolicy::default(),
+        url_rewrites: BTreeMap::new(),
+        environment: EnvironmentConfig::default(),
+        lockfiles: Vec::new(),
+        credentials: GitCredentials::default(),
+        display: DisplayConfig::default(),
+        apply: ApplyConfig::default(),
+        phases: PhasesConfig::default(),
+        picker: None,
+        hooks: HooksConfig::default(),
+        git_backend: GitBackendKind::Subprocess,
     }
 }
 
@@ -337,9 +1978,97 @@ fn validate(cfg: &ResolvedConfig) -> Result<()> {
     if cfg.side_channel.branch_name.trim().is_empty() {
         bail!("side_channel.branch_name cannot be empty");
     }
+    if cfg.side_channel.push_chunk_files == Some(0) {
+        bail!("side_channel.push_chunk_files must be greater than zero");
+    }
+    validate_merge_drivers("side_channel", &cfg.side_channel.merge_drivers)?;
+    if cfg
+        .side_channel
+        .remote_url_template
+        .as_ref()
+        .is_some_and(|template| template.trim().is_empty())
+    {
+        bail!("side_channel.remote_url_template cannot be empty");
+    }
+    if cfg
+        .side_channel
+        .destination
+        .as_ref()
+        .is_some_and(|destination| destination.trim().is_empty())
+    {
+        bail!("side_channel.destination cannot be empty");
+    }
+    for (from, to) in &cfg.prune_remotes.renames {
+        if to.trim().is_empty() {
+            bail!("prune_remotes.renames.{from} cannot be empty");
+        }
+    }
+    if cfg.url_rewrites.keys().any(|from| from.trim().is_empty()) {
+        bail!("url_rewrites cannot rewrite an empty prefix");
+    }
+    validate_notify(&cfg.notify, "notify")?;
     if cfg.commit_template.trim().is_empty() {
         bail!("commit.message_template cannot be empty");
     }
+    // chrono panics while rendering a format it cannot parse.
+    if StrftimeItems::new(&cfg.commit_timestamp.format).any(|item| matches!(item, Item::Error)) {
+        bail!(
+            "commit.timestamp_format {:?} is not a valid strftime format",
+            cfg.commit_timestamp.format
+        );
+    }
+    for (key, value) in &cfg.commit_trailers {
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("commit.trailers key '{key}' must contain only letters, digits and '-'");
+        }
+        if value.trim().is_empty() || value.contains('\n') {
+            bail!("commit.trailers value for '{key}' must be a single non-empty line");
+        }
+    }
+
+    if cfg
+        .check
+        .allowed_branches
+        .iter()
+        .any(|branch| branch.trim().is_empty())
+    {
+        bail!("check.allowed_branches entries cannot be empty");
+    }
+
+    for (idx, pattern) in cfg.discovery_excludes.iter().enumerate() {
+        if pattern.trim_end_matches('/').trim().is_empty() {
+            bail!("discovery.exclude[{idx}] cannot be empty");
+        }
+        glob::Pattern::new(pattern).with_context(|| format!("discovery.exclude[{idx}]"))?;
+    }
+
+    for (idx, root) in cfg.workspace_roots.iter().enumerate() {
+        if root.path.as_os_str().is_empty() {
+            bail!("discovery.roots[{idx}].path cannot be empty");
+        }
+        if root
+            .side_channel
+            .remote_name
+            .as_ref()
+            .is_some_and(|remote_name| remote_name.trim().is_empty())
+        {
+            bail!("discovery.roots[{idx}].side_channel.remote_name cannot be empty");
+        }
+        if root
+            .side_channel
+            .branch_name
+            .as_ref()
+            .is_some_and(|branch_name| branch_name.trim().is_empty())
+        {
+            bail!("discovery.roots[{idx}].side_channel.branch_name cannot be empty");
+        }
+        if root.name.trim().is_empty() {
+            bail!("discovery.roots[{idx}].name cannot be empty");
+        }
+        if let Some(notify) = &root.notify {
+            validate_notify(notify, &format!("discovery.roots[{idx}].notify"))?;
+        }
+    }
 
     let mut seen_keys = BTreeSet::new();
     for (idx, repo) in cfg.repositories.iter().enumerate() {
@@ -371,15 +2100,77 @@ fn validate(cfg: &ResolvedConfig) -> Result<()> {
         {
             bail!("repositories[{idx}].side_channel.branch_name cannot be empty");
         }
+        if repo.side_channel.push_chunk_files == Some(0) {
+            bail!("repositories[{idx}].side_channel.push_chunk_files must be greater than zero");
+        }
+        if let Some(merge_drivers) = &repo.side_channel.merge_drivers {
+            validate_merge_drivers(&format!("repositories[{idx}].side_channel"), merge_drivers)?;
+        }
+        if repo
+            .force_include
+            .iter()
+            .any(|pattern| pattern.trim().is_empty())
+        {
+            bail!("repositories[{idx}].force_include patterns cannot be empty");
+        }
+        for (name, command) in [("pull", &repo.commands.pull), ("push", &repo.commands.push)] {
+            if let Some(command) = command
+                && command.trim().is_empty()
+            {
+                bail!("repositories[{idx}].commands.{name} cannot be empty");
+            }
+        }
     }
 
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use pretty_assertions::assert_eq;
+fn validate_notify(notify: &NotifyConfig, prefix: &str) -> Result<()> {
+    if let Some(email) = &notify.email {
+        if email.server.trim().is_empty() {
+            bail!("{prefix}.email.server cannot be empty");
+        }
+        if email.to.is_empty() {
+            bail!("{prefix}.email.to must list at least one recipient");
+        }
+    }
+    if notify
+        .healthcheck_url
+        .as_ref()
+        .is_some_and(|url| url.trim().is_empty())
+    {
+        bail!("{prefix}.healthcheck_url cannot be empty");
+    }
+    for (idx, webhook) in notify.webhooks.iter().enumerate() {
+        if webhook.url.trim().is_empty() {
+            bail!("{prefix}.webhooks[{idx}].url cannot be empty");
+        }
+    }
+    Ok(())
+}
+
+fn validate_merge_drivers(prefix: &str, merge_drivers: &BTreeMap<String, String>) -> Result<()> {
+    for (pattern, driver) in merge_drivers {
+        if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+            bail!(
+                "{prefix}.merge_drivers pattern '{pattern}' must be non-empty without whitespace"
+            );
+        }
+        if driver.is_empty()
+            || !driver
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("{prefix}.merge_drivers driver '{driver}' for '{pattern}' is not a valid name");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn pull_only_override_disables_push() {
@@ -390,7 +2181,7 @@ mod tests {
         };
 
         let resolved = resolve_run_config(&base, &args).expect("resolve should succeed");
-        assert_eq!(resolved.push_enabled, false);
+        assert_eq!(resolved.mode, RunMode::PullOnly);
     }
 
     #[test]
@@ -417,12 +2208,37 @@ mod tests {
         let repo = ResolvedRepositoryConfig {
             path: PathBuf::from("/tmp/repo"),
             enabled: true,
-            include_untracked: Some(true),
+            mode: None,
+            include_untracked: Some(UntrackedPolicy::Include),
+            builtin_excludes: None,
+            push_enabled: None,
             side_channel: ResolvedRepositorySideChannelConfig {
                 enabled: Some(true),
                 remote_name: Some("backup".to_string()),
                 branch_name: Some("backup/sync".to_string()),
+                push_chunk_files: None,
+                min_changed_lines: None,
+                min_changed_files: None,
+                merge_drivers: None,
+                remote_url_template: None,
+                snapshot_ignored: None,
+                auto_apply: None,
+                hide_refs: None,
+                backend: None,
+                destination: None,
             },
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
         };
 
         let resolved = resolve_repo_run_config(&global, &args, &repo);
@@ -430,15 +2246,202 @@ mod tests {
         assert_eq!(
             resolved,
             ResolvedRunConfig {
+                mode: RunMode::SyncAll,
                 push_enabled: true,
-                include_untracked: true,
+                include_untracked: UntrackedPolicy::Include,
+                builtin_excludes: true,
                 side_channel: SideChannelConfig {
                     enabled: true,
                     remote_name: "backup".to_string(),
                     branch_name: "backup/sync".to_string(),
+                    push_chunk_files: None,
+                    min_changed_lines: None,
+                    min_changed_files: None,
+                    merge_drivers: BTreeMap::new(),
+                    remote_url_template: None,
+                    snapshot_ignored: false,
+                    auto_apply: false,
+                    hide_refs: false,
+                    backend: SideChannelBackend::Git,
+                    destination: None,
                 },
                 commit_template: "shephard sync: {timestamp} {hostname} [{scope}]".to_string(),
+                commit_body_template: None,
+                commit_wrap_width: 72,
+                commit_timestamp: TimestampStyle::default(),
+                commit_trailers: defaults().commit_trailers,
                 failure_policy: FailurePolicy::Continue,
+                lock_timeout_secs: 10,
+                force_include: Vec::new(),
+                commands: RepoCommands::default(),
+                colocated_jj: JjPolicy::Skip,
+                excludes: Vec::new(),
+                lockfiles: Vec::new(),
+                auth: GitAuth::default(),
+                phases: PhasesConfig::default(),
+                journal_dir: None,
+                last_snapshot: None,
+                hooks: RepoHooks::default(),
+                read_only: false,
+            }
+        );
+    }
+
+    #[test]
+    fn repo_push_override_applies_without_leaving_pull_only_runs() {
+        let mut base = defaults();
+        base.push_enabled = false;
+        let repo = ResolvedRepositoryConfig {
+            push_enabled: Some(true),
+            ..repo_entry("/tmp/repo", None)
+        };
+        let sync_args = RunArgs::default();
+        let pull_args = RunArgs {
+            pull_only: true,
+            ..RunArgs::default()
+        };
+
+        let sync = resolve_run_config(&base, &sync_args).expect("resolve should succeed");
+        let pull = resolve_run_config(&base, &pull_args).expect("resolve should succeed");
+        let resolved = [
+            resolve_repo_run_config(&sync, &sync_args, &repo),
+            resolve_repo_run_config(&pull, &pull_args, &repo),
+        ]
+        .map(|cfg| (cfg.mode, cfg.push_enabled));
+
+        assert_eq!(
+            resolved,
+            [(RunMode::SyncAll, true), (RunMode::PullOnly, true)]
+        );
+    }
+
+    #[test]
+    fn repo_mode_replaces_default_mode_and_pull_only_ignores_push_flag() {
+        let mut base = defaults();
+        base.default_mode = RunMode::PullOnly;
+        let mirror = repo_entry("/tmp/mirror", Some(RunMode::PullOnly));
+        let work = repo_entry("/tmp/work", Some(RunMode::SyncAll));
+        let default_args = RunArgs::default();
+        let push_args = RunArgs {
+            push: true,
+            ..RunArgs::default()
+        };
+        let pull_args = RunArgs {
+            pull_only: true,
+            ..RunArgs::default()
+        };
+
+        let modes = [
+            (&default_args, &mirror),
+            (&default_args, &work),
+            (&push_args, &mirror),
+            (&pull_args, &work),
+        ]
+        .map(|(args, repo)| {
+            let global = resolve_run_config(&base, args).expect("resolve should succeed");
+            resolve_repo_run_config(&global, args, repo).mode
+        });
+
+        assert_eq!(
+            modes,
+            [
+                RunMode::PullOnly,
+                RunMode::SyncAll,
+                RunMode::PullOnly,
+                RunMode::PullOnly,
+            ]
+        );
+    }
+
+    #[test]
+    fn read_only_repositories_stay_pull_only_without_a_side_channel() {
+        let mut base = defaults();
+        base.side_channel.enabled = true;
+        let mut tracked = repo_entry("/tmp/third-party", Some(RunMode::SyncAll));
+        tracked.read_only = true;
+        tracked.push_enabled = Some(true);
+        let args = RunArgs {
+            push: true,
+            ..RunArgs::default()
+        };
+
+        let global = resolve_run_config(&base, &args).expect("resolve should succeed");
+        let resolved = resolve_repo_run_config(&global, &args, &tracked);
+
+        assert_eq!(
+            (
+                resolved.mode,
+                resolved.read_only,
+                resolved.side_channel.enabled,
+                resolve_repo_side_channel(&base, &tracked).enabled,
+            ),
+            (RunMode::PullOnly, true, false, false)
+        );
+    }
+
+    fn repo_entry(path: &str, mode: Option<RunMode>) -> ResolvedRepositoryConfig {
+        ResolvedRepositoryConfig {
+            path: PathBuf::from(path),
+            enabled: true,
+            mode,
+            include_untracked: None,
+            builtin_excludes: None,
+            push_enabled: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
+        }
+    }
+
+    #[test]
+    fn resolved_run_configs_round_trip_through_json() {
+        let base = defaults();
+        let args = RunArgs {
+            pull_only: true,
+            ..RunArgs::default()
+        };
+        let global = resolve_run_config(&base, &args).expect("resolve should succeed");
+        let repo = ResolvedRepositoryConfig {
+            include_untracked: Some(UntrackedPolicy::Include),
+            ..repo_entry("/tmp/repo", None)
+        };
+        let mut resolved = resolve_repo_run_config(&global, &args, &repo);
+        resolved.journal_dir = Some(PathBuf::from("/tmp/journal"));
+
+        let value = serde_json::to_value(&resolved).expect("config should serialize");
+        let parsed: ResolvedRunConfig =
+            serde_json::from_value(value.clone()).expect("config should parse");
+
+        assert_eq!(
+            (
+                &value["mode"],
+                &value["include_untracked"],
+                &value["auth"]["mode"],
+                value.get("journal_dir"),
+            ),
+            (
+                &serde_json::json!("pull_only"),
+                &serde_json::json!("include"),
+                &serde_json::json!("default"),
+                None,
+            )
+        );
+        assert_eq!(
+            parsed,
+            ResolvedRunConfig {
+                journal_dir: None,
+                last_snapshot: None,
+                ..resolved
             }
         );
     }
@@ -455,31 +2458,117 @@ mod tests {
         let repo = ResolvedRepositoryConfig {
             path: PathBuf::from("/tmp/repo"),
             enabled: true,
-            include_untracked: Some(true),
+            mode: None,
+            include_untracked: Some(UntrackedPolicy::Include),
+            builtin_excludes: None,
+            push_enabled: None,
             side_channel: ResolvedRepositorySideChannelConfig {
                 enabled: Some(true),
                 ..ResolvedRepositorySideChannelConfig::default()
             },
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
         };
 
         let resolved = resolve_repo_run_config(&global, &args, &repo);
 
-        assert_eq!(resolved.include_untracked, false);
+        assert_eq!(resolved.include_untracked, UntrackedPolicy::Exclude);
         assert_eq!(resolved.side_channel.enabled, false);
     }
 
+    #[test]
+    fn side_remote_and_branch_flags_override_repo_side_channel() {
+        let base = defaults();
+        let args = RunArgs {
+            side_remote: Some("usb-backup".to_string()),
+            side_branch: Some("oneoff/snapshot".to_string()),
+            ..RunArgs::default()
+        };
+        let global = resolve_run_config(&base, &args).expect("resolve should succeed");
+        let repo = ResolvedRepositoryConfig {
+            path: PathBuf::from("/tmp/repo"),
+            enabled: true,
+            mode: None,
+            include_untracked: None,
+            builtin_excludes: None,
+            push_enabled: None,
+            side_channel: ResolvedRepositorySideChannelConfig {
+                remote_name: Some("backup".to_string()),
+                branch_name: Some("backup/sync".to_string()),
+                ..ResolvedRepositorySideChannelConfig::default()
+            },
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
+        };
+
+        let resolved = resolve_repo_run_config(&global, &args, &repo);
+
+        assert_eq!(
+            (
+                resolved.side_channel.remote_name,
+                resolved.side_channel.branch_name
+            ),
+            ("usb-backup".to_string(), "oneoff/snapshot".to_string())
+        );
+    }
+
     #[test]
     fn apply_side_channel_uses_repo_specific_override() {
         let mut cfg = defaults();
         cfg.repositories = vec![ResolvedRepositoryConfig {
             path: PathBuf::from("/tmp/repo"),
             enabled: true,
+            mode: None,
             include_untracked: None,
+            builtin_excludes: None,
+            push_enabled: None,
             side_channel: ResolvedRepositorySideChannelConfig {
                 enabled: Some(true),
                 remote_name: Some("backup".to_string()),
                 branch_name: Some("backup/sync".to_string()),
+                push_chunk_files: None,
+                min_changed_lines: None,
+                min_changed_files: None,
+                merge_drivers: None,
+                remote_url_template: None,
+                snapshot_ignored: None,
+                auto_apply: None,
+                hide_refs: None,
+                backend: None,
+                destination: None,
             },
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
         }];
 
         let side_channel = resolve_apply_side_channel(&cfg, Path::new("/tmp/repo"));
@@ -490,7 +2579,865 @@ mod tests {
                 enabled: true,
                 remote_name: "backup".to_string(),
                 branch_name: "backup/sync".to_string(),
+                push_chunk_files: None,
+                min_changed_lines: None,
+                min_changed_files: None,
+                merge_drivers: BTreeMap::new(),
+                remote_url_template: None,
+                snapshot_ignored: false,
+                auto_apply: false,
+                hide_refs: false,
+                backend: SideChannelBackend::Git,
+                destination: None,
+            }
+        );
+    }
+
+    #[test]
+    fn discovery_section_sets_workspace_roots_relative_to_config_dir() {
+        let temp = tempfile::tempdir().expect("tempdir should work");
+        let work = temp.path().join("work");
+        std::fs::create_dir_all(&work).expect("root directory should be created");
+
+        let cfg = parse(
+            "[discovery]\nroots = [\"work\"]\ndescend_hidden = true\nexclude = [\"node_modules\", \"~/scratch/**\"]\n",
+            temp.path(),
+        )
+        .expect("parse should succeed");
+        let home = dirs::home_dir().expect("tests need a home directory");
+
+        assert_eq!(
+            (
+                cfg.workspace_roots,
+                cfg.descend_hidden_dirs,
+                cfg.discovery_excludes
+            ),
+            (
+                vec![ResolvedWorkspaceRoot {
+                    path: work.canonicalize().expect("root should canonicalize"),
+                    name: "work".to_string(),
+                    include_untracked: None,
+                    side_channel: ResolvedRepositorySideChannelConfig::default(),
+                    notify: None,
+                }],
+                true,
+                vec![
+                    "node_modules".to_string(),
+                    home.join("scratch/**").to_string_lossy().into_owned()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn workspace_roots_merge_config_and_cli_without_duplicates() {
+        let mut cfg = defaults();
+        cfg.workspace_roots = vec![workspace_root("/tmp/shephard-root-a")];
+        let cli_roots = vec![
+            PathBuf::from("/tmp/shephard-root-a"),
+            PathBuf::from("/tmp/shephard-root-b"),
+        ];
+
+        assert_eq!(
+            resolve_workspace_roots(&cfg, &cli_roots),
+            vec![
+                workspace_root("/tmp/shephard-root-a"),
+                workspace_root("/tmp/shephard-root-b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn discovered_repositories_do_not_override_configured_entries() {
+        let configured = vec![ResolvedRepositoryConfig {
+            path: PathBuf::from("/tmp/repo-a"),
+            enabled: false,
+            mode: None,
+            include_untracked: None,
+            builtin_excludes: None,
+            push_enabled: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
+        }];
+        let discovered = vec![PathBuf::from("/tmp/repo-a"), PathBuf::from("/tmp/repo-b")];
+
+        let merged = merge_discovered_repositories(&configured, &discovered, &[]);
+
+        assert_eq!(
+            merged,
+            vec![
+                configured[0].clone(),
+                ResolvedRepositoryConfig {
+                    path: PathBuf::from("/tmp/repo-b"),
+                    enabled: true,
+                    mode: None,
+                    include_untracked: None,
+                    builtin_excludes: None,
+                    push_enabled: None,
+                    side_channel: ResolvedRepositorySideChannelConfig::default(),
+                    force_include: Vec::new(),
+                    commands: RepoCommands::default(),
+                    colocated_jj: None,
+                    id: None,
+                    lockfiles: None,
+                    priority: 0,
+                    credentials: GitCredentials::default(),
+                    alias: None,
+                    apply_method: None,
+                    clone_url: None,
+                    read_only: false,
+                    hooks: RepoHooks::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn root_tables_name_roots_and_carry_their_own_notify_targets() {
+        let cfg = parse(
+            r#"
+[notify]
+webhooks = [{ url = "https://discord.example.com/personal", kind = "discord" }]
+
+[discovery]
+roots = [
+    "/tmp/shephard-roots/personal",
+    { path = "/tmp/shephard-roots/job", name = "work", notify = { webhooks = [{ url = "https://hooks.slack.example.com/work", kind = "slack" }] } },
+]
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+
+        assert_eq!(
+            cfg.workspace_roots
+                .iter()
+                .map(|root| (
+                    root.name.as_str(),
+                    root.notify
+                        .as_ref()
+                        .map(|notify| notify.webhooks[0].url.as_str())
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("personal", None),
+                ("work", Some("https://hooks.slack.example.com/work")),
+            ]
+        );
+        assert_eq!(
+            root_for(
+                &cfg.workspace_roots,
+                Path::new("/tmp/shephard-roots/job/api")
+            )
+            .map(|root| root.name.as_str()),
+            Some("work")
+        );
+    }
+
+    #[test]
+    fn root_table_defaults_apply_to_discovered_repositories() {
+        let cfg = parse(
+            r#"
+[discovery]
+roots = [
+    "/tmp/shephard-roots",
+    { path = "/tmp/shephard-roots/work", include_untracked = false, side_channel = { remote_name = "work-backup" } },
+]
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+        let discovered = vec![
+            PathBuf::from("/tmp/shephard-roots/personal/notes"),
+            PathBuf::from("/tmp/shephard-roots/work/api"),
+        ];
+
+        let merged = merge_discovered_repositories(&[], &discovered, &cfg.workspace_roots);
+
+        assert_eq!(
+            merged,
+            vec![
+                ResolvedRepositoryConfig {
+                    path: PathBuf::from("/tmp/shephard-roots/personal/notes"),
+                    enabled: true,
+                    mode: None,
+                    include_untracked: None,
+                    builtin_excludes: None,
+                    push_enabled: None,
+                    side_channel: ResolvedRepositorySideChannelConfig::default(),
+                    force_include: Vec::new(),
+                    commands: RepoCommands::default(),
+                    colocated_jj: None,
+                    id: None,
+                    lockfiles: None,
+                    priority: 0,
+                    credentials: GitCredentials::default(),
+                    alias: None,
+                    apply_method: None,
+                    clone_url: None,
+                    read_only: false,
+                    hooks: RepoHooks::default(),
+                },
+                ResolvedRepositoryConfig {
+                    path: PathBuf::from("/tmp/shephard-roots/work/api"),
+                    enabled: true,
+                    mode: None,
+                    include_untracked: Some(UntrackedPolicy::Exclude),
+                    builtin_excludes: None,
+                    push_enabled: None,
+                    side_channel: ResolvedRepositorySideChannelConfig {
+                        remote_name: Some("work-backup".to_string()),
+                        ..ResolvedRepositorySideChannelConfig::default()
+                    },
+                    force_include: Vec::new(),
+                    commands: RepoCommands::default(),
+                    colocated_jj: None,
+                    id: None,
+                    lockfiles: None,
+                    priority: 0,
+                    credentials: GitCredentials::default(),
+                    alias: None,
+                    apply_method: None,
+                    clone_url: None,
+                    read_only: false,
+                    hooks: RepoHooks::default(),
+                },
+            ]
+        );
+    }
+
+    fn workspace_root(path: &str) -> ResolvedWorkspaceRoot {
+        let path = PathBuf::from(path);
+        ResolvedWorkspaceRoot {
+            name: default_root_name(&path),
+            path,
+            include_untracked: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            notify: None,
+        }
+    }
+
+    #[test]
+    fn include_untracked_accepts_bool_or_ask() {
+        let cfg = parse(
+            r#"
+include_untracked = "ask"
+
+[[repositories]]
+path = "/tmp/shephard-untracked-a"
+include_untracked = true
+
+[[repositories]]
+path = "/tmp/shephard-untracked-b"
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+
+        assert_eq!(
+            (
+                cfg.include_untracked,
+                cfg.repositories
+                    .iter()
+                    .map(|repo| repo.include_untracked)
+                    .collect::<Vec<_>>()
+            ),
+            (
+                UntrackedPolicy::Ask,
+                vec![Some(UntrackedPolicy::Include), None]
+            )
+        );
+    }
+
+    #[test]
+    fn apply_auto_chain_is_configurable_but_not_empty() {
+        let cfg = parse(
+            "[apply]\nauto_chain = [\"rebase\", \"merge\"]\n",
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+        let err =
+            parse("[apply]\nauto_chain = []\n", Path::new("/tmp")).expect_err("parse should fail");
+
+        assert_eq!(
+            (cfg.apply.auto_chain, defaults().apply.auto_chain),
+            (
+                vec![ApplyStep::Rebase, ApplyStep::Merge],
+                vec![ApplyStep::FastForward, ApplyStep::Rebase, ApplyStep::Merge]
+            )
+        );
+        assert_eq!(err.to_string(), "apply.auto_chain needs at least one step");
+    }
+
+    #[test]
+    fn apply_method_comes_from_the_repository_then_the_global_setting() {
+        let cfg = parse(
+            r#"
+[apply]
+method = "rebase"
+
+[[repositories]]
+path = "/tmp/shephard-apply-notes"
+apply = { method = "squash" }
+
+[[repositories]]
+path = "/tmp/shephard-apply-code"
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+
+        assert_eq!(
+            [
+                "/tmp/shephard-apply-notes",
+                "/tmp/shephard-apply-code",
+                "/tmp/shephard-apply-unconfigured"
+            ]
+            .map(|repo| resolve_apply_method(&cfg, Path::new(repo))),
+            [
+                ApplyMethodArg::Squash,
+                ApplyMethodArg::Rebase,
+                ApplyMethodArg::Rebase
+            ]
+        );
+        assert_eq!(defaults().apply.method, ApplyMethodArg::Merge);
+    }
+
+    #[test]
+    fn repository_hooks_replace_only_the_global_hooks_they_set() {
+        let cfg = parse(
+            r#"
+[hooks]
+pre_run = "echo start"
+pre_repo = "make check"
+post_repo = "notify-send synced"
+
+[[repositories]]
+path = "/tmp/shephard-hooks-site"
+hooks = { post_repo = "make deploy" }
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+        let base = resolve_run_config(&cfg, &RunArgs::default()).expect("run config");
+        let resolved = resolve_repo_run_config(&base, &RunArgs::default(), &cfg.repositories[0]);
+
+        assert_eq!(
+            (cfg.hooks.pre_run.as_deref(), resolved.hooks),
+            (
+                Some("echo start"),
+                RepoHooks {
+                    pre_repo: Some("make check".to_string()),
+                    post_repo: Some("make deploy".to_string()),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn commit_trailers_reject_keys_with_separators() {
+        let err = parse(
+            "[commit.trailers]\n\"Synced by:\" = \"shephard\"\n",
+            Path::new("/tmp"),
+        )
+        .expect_err("parse should fail");
+
+        assert_eq!(
+            err.to_string(),
+            "commit.trailers key 'Synced by:' must contain only letters, digits and '-'"
+        );
+    }
+
+    #[test]
+    fn commit_timestamps_take_a_format_and_a_timezone() {
+        let parse_commit = |commit: &str| parse(&format!("[commit]\n{commit}"), Path::new("/tmp"));
+        let fixed = parse_commit("timestamp_format = \"%Y%m%dT%H%M%z\"\ntimezone = \"-08:00\"\n")
+            .expect("parse should succeed");
+        let utc = parse_commit("timezone = \"UTC\"\n").expect("parse should succeed");
+        let bad_format =
+            parse_commit("timestamp_format = \"%Q\"\n").expect_err("parse should fail");
+        let bad_zone = parse_commit("timezone = \"PST\"\n").expect_err("parse should fail");
+
+        assert_eq!(
+            (
+                fixed.commit_timestamp,
+                utc.commit_timestamp.zone,
+                bad_format.to_string(),
+                bad_zone.to_string().contains("unknown timezone \"PST\""),
+            ),
+            (
+                TimestampStyle {
+                    format: "%Y%m%dT%H%M%z".to_string(),
+                    zone: TimestampZone::Fixed(
+                        FixedOffset::west_opt(8 * 3600).expect("offset should be valid")
+                    ),
+                },
+                TimestampZone::Utc,
+                "commit.timestamp_format \"%Q\" is not a valid strftime format".to_string(),
+                true,
+            )
+        );
+        assert_eq!(
+            TimestampStyle {
+                format: "%z".to_string(),
+                zone: TimestampZone::Utc,
             }
+            .now(),
+            "+0000"
+        );
+    }
+
+    #[test]
+    fn merge_drivers_reject_patterns_with_whitespace() {
+        let err = parse(
+            "[side_channel.merge_drivers]\n\"my notes.md\" = \"union\"\n",
+            Path::new("/tmp"),
+        )
+        .expect_err("parse should fail");
+
+        assert_eq!(
+            err.to_string(),
+            "side_channel.merge_drivers pattern 'my notes.md' must be non-empty without whitespace"
+        );
+    }
+
+    #[test]
+    fn repo_commands_parse_and_reject_empty_commands() {
+        let cfg = parse(
+            r#"
+[[repositories]]
+path = "/tmp/shephard-commands"
+commands = { pull = "jj git fetch", push = "./sync.sh" }
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+        let err = parse(
+            "[[repositories]]\npath = \"/tmp/x\"\ncommands = { push = \" \" }\n",
+            Path::new("/tmp"),
+        )
+        .expect_err("parse should fail");
+
+        assert_eq!(
+            (cfg.repositories[0].commands.clone(), err.to_string()),
+            (
+                RepoCommands {
+                    pull: Some("jj git fetch".to_string()),
+                    push: Some("./sync.sh".to_string()),
+                },
+                "repositories[0].commands.push cannot be empty".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn side_channel_backend_resolves_per_repository() {
+        let cfg = parse(
+            r#"
+[side_channel]
+enabled = true
+backend = "rclone"
+destination = "b2:backups/shephard"
+
+[[repositories]]
+path = "/tmp/shephard-restic"
+side_channel = { backend = "restic", destination = "/srv/restic" }
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+        let side = resolve_repo_side_channel(&cfg, &cfg.repositories[0]);
+
+        assert_eq!(
+            (
+                cfg.side_channel.backend,
+                side.backend,
+                side.destination.as_deref()
+            ),
+            (
+                SideChannelBackend::Rclone,
+                SideChannelBackend::Restic,
+                Some("/srv/restic")
+            )
+        );
+    }
+
+    #[test]
+    fn rewrite_repository_path_edits_only_matching_entry_paths() {
+        let raw = r#"# laptop repos, formerly "/tmp/shephard-move-old"
+[discovery]
+roots = ["/tmp/shephard-move-old"]
+
+[[repositories]]
+path = "/tmp/shephard-move-\u006Fld" # notes
+mode = "pull_only"
+
+[[repositories]]
+path = '/tmp/shephard-move-other'
+"#;
+
+        let (rewritten, changed) = rewrite_repository_path(
+            raw,
+            Path::new("/tmp"),
+            Path::new("/tmp/shephard-move-old"),
+            Path::new("/tmp/shephard-move-new"),
+        )
+        .expect("rewrite should succeed");
+
+        assert_eq!(
+            (rewritten, changed),
+            (
+                raw.replace(
+                    "\"/tmp/shephard-move-\\u006Fld\"",
+                    "\"/tmp/shephard-move-new\""
+                ),
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn repository_entries_are_added_disabled_and_removed_keeping_comments() {
+        let raw = r#"# shared settings
+push_enabled = false
+
+# work
+[[repositories]]
+path = "/tmp/shephard-edit-work" # laptop only
+"#;
+        let dir = Path::new("/tmp");
+        let new = Path::new("/tmp/shephard-edit-new");
+
+        let added = add_repository(raw, dir, new, Some("new")).expect("add should succeed");
+        let duplicate = add_repository(&added, dir, new, None).expect_err("add should fail");
+        let (disabled, matched) =
+            set_repository_enabled(&added, dir, new, false).expect("disable should succeed");
+        let (enabled, _) =
+            set_repository_enabled(&disabled, dir, new, true).expect("enable should succeed");
+        let (removed, count) =
+            remove_repository(&enabled, dir, new).expect("remove should succeed");
+
+        assert_eq!(
+            added,
+            format!(
+                "{raw}\n[[repositories]]\npath = \"/tmp/shephard-edit-new\"\nalias = \"new\"\n"
+            )
+        );
+        assert_eq!(
+            duplicate.to_string(),
+            "/tmp/shephard-edit-new is already configured"
+        );
+        assert_eq!(
+            (disabled, matched),
+            (
+                added.replace("alias = \"new\"\n", "alias = \"new\"\nenabled = false\n"),
+                1
+            )
         );
+        assert_eq!(enabled, added);
+        assert_eq!((removed, count), (raw.to_string(), 1));
+    }
+
+    #[test]
+    fn expand_path_handles_home_and_environment_variables() {
+        let home = dirs::home_dir().expect("tests need a home directory");
+        let expanded = [
+            "~/src",
+            "$HOME/src",
+            "${HOME}/src",
+            "/srv/$/cost",
+            "/srv/plain",
+        ]
+        .map(|raw| expand_path(Path::new(raw)).expect("expansion should succeed"));
+        let err = expand_path(Path::new("$SHEPHARD_TEST_UNSET_VAR/src"))
+            .expect_err("unset variables should fail");
+
+        assert_eq!(
+            (expanded, err.to_string()),
+            (
+                [
+                    home.join("src"),
+                    home.join("src"),
+                    home.join("src"),
+                    PathBuf::from("/srv/$/cost"),
+                    PathBuf::from("/srv/plain"),
+                ],
+                "environment variable SHEPHARD_TEST_UNSET_VAR in $SHEPHARD_TEST_UNSET_VAR/src is not set"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn repository_and_root_paths_expand_home() {
+        let home = dirs::home_dir().expect("tests need a home directory");
+        let cfg = parse(
+            r#"
+[discovery]
+roots = ["~/shephard-missing-root"]
+
+[[repositories]]
+path = "${HOME}/shephard-missing-repo"
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+
+        assert_eq!(
+            (
+                cfg.repositories[0].path.clone(),
+                cfg.workspace_roots[0].path.clone()
+            ),
+            (
+                home.join("shephard-missing-repo"),
+                home.join("shephard-missing-root")
+            )
+        );
+    }
+
+    #[test]
+    fn repository_templates_fill_in_options_the_entry_leaves_unset() {
+        let raw = r#"
+[templates.work]
+push_enabled = false
+priority = 5
+side_channel = { enabled = true, branch_name = "shephard/work" }
+
+[[repositories]]
+path = "/tmp/shephard-work-a"
+template = "work"
+
+[[repositories]]
+path = "/tmp/shephard-work-b"
+template = "work"
+priority = 1
+side_channel = { branch_name = "shephard/b" }
+
+[[repositories]]
+path = "/tmp/shephard-personal"
+"#;
+
+        let cfg = parse(raw, Path::new("/tmp")).expect("parse should succeed");
+        let summary = |repo: &ResolvedRepositoryConfig| {
+            (
+                repo.push_enabled,
+                repo.priority,
+                repo.side_channel.enabled,
+                repo.side_channel.branch_name.clone(),
+            )
+        };
+        assert_eq!(
+            cfg.repositories.iter().map(summary).collect::<Vec<_>>(),
+            [
+                (
+                    Some(false),
+                    5,
+                    Some(true),
+                    Some("shephard/work".to_string())
+                ),
+                (Some(false), 1, Some(true), Some("shephard/b".to_string())),
+                (None, 0, None, None),
+            ]
+        );
+
+        let err = parse(
+            "[[repositories]]\npath = \"/tmp/x\"\ntemplate = \"missing\"\n",
+            Path::new("/tmp"),
+        )
+        .expect_err("parse should fail");
+        assert_eq!(
+            err.to_string(),
+            "repositories[0].template names unknown template \"missing\""
+        );
+    }
+
+    #[test]
+    fn url_rewrites_apply_to_remote_templates_and_clone_urls() {
+        let raw = r#"
+[url_rewrites]
+"git@old-git.corp:" = "git@git.corp:"
+
+[side_channel]
+remote_url_template = "git@old-git.corp:backup/{repo_name}.git"
+
+[[repositories]]
+path = "/tmp/shephard-rewritten"
+clone_url = "git@old-git.corp:you/rewritten.git"
+side_channel = { remote_url_template = "ssh://nas/{repo_name}.git" }
+"#;
+
+        let cfg = parse(raw, Path::new("/tmp")).expect("parse should succeed");
+        assert_eq!(
+            (
+                cfg.side_channel.remote_url_template.as_deref(),
+                cfg.repositories[0].clone_url.as_deref(),
+                cfg.repositories[0]
+                    .side_channel
+                    .remote_url_template
+                    .as_deref(),
+            ),
+            (
+                Some("git@git.corp:backup/{repo_name}.git"),
+                Some("git@git.corp:you/rewritten.git"),
+                Some("ssh://nas/{repo_name}.git"),
+            )
+        );
+    }
+
+    #[test]
+    fn remote_url_is_an_alias_for_remote_url_template() {
+        let raw = r#"
+[[repositories]]
+path = "/tmp/shephard-literal"
+side_channel = { remote_url = "ssh://nas/backups/literal.git" }
+"#;
+        let cfg = parse(raw, Path::new("/tmp")).expect("parse should succeed");
+        assert_eq!(
+            cfg.repositories[0]
+                .side_channel
+                .remote_url_template
+                .as_deref(),
+            Some("ssh://nas/backups/literal.git")
+        );
+
+        let both = r#"
+[side_channel]
+remote_url = "ssh://nas/{repo_name}.git"
+remote_url_template = "ssh://nas/{repo_name}.git"
+"#;
+        let err = parse(both, Path::new("/tmp")).expect_err("both keys should be rejected");
+        assert!(
+            format!("{err:#}").contains("duplicate field `remote_url_template`"),
+            "unexpected error: {err:#}"
+        );
+    }
+
+    #[test]
+    fn host_overlay_overrides_base_config_on_matching_host() {
+        let raw = r#"
+include_untracked = false
+
+[side_channel]
+enabled = true
+branch_name = "shephard/sync"
+
+[discovery]
+roots = ["/tmp/shephard-base-root"]
+
+[hosts."laptop"]
+include_untracked = true
+
+[hosts."laptop".side_channel]
+branch_name = "shephard/laptop"
+
+[hosts."laptop".discovery]
+roots = ["/tmp/shephard-laptop-root"]
+
+[hosts."desktop".side_channel]
+branch_name = "shephard/desktop"
+"#;
+
+        let laptop =
+            parse_for_host(raw, Path::new("/tmp"), "laptop.lan").expect("parse should succeed");
+        let other = parse_for_host(raw, Path::new("/tmp"), "server").expect("parse should succeed");
+
+        let summary = |cfg: &ResolvedConfig| {
+            (
+                cfg.include_untracked,
+                cfg.side_channel.enabled,
+                cfg.side_channel.branch_name.clone(),
+                cfg.workspace_roots[0].path.clone(),
+            )
+        };
+        assert_eq!(
+            (summary(&laptop), summary(&other)),
+            (
+                (
+                    UntrackedPolicy::Include,
+                    true,
+                    "shephard/laptop".to_string(),
+                    PathBuf::from("/tmp/shephard-laptop-root"),
+                ),
+                (
+                    UntrackedPolicy::Exclude,
+                    true,
+                    "shephard/sync".to_string(),
+                    PathBuf::from("/tmp/shephard-base-root"),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn repository_credentials_override_global_ones_per_setting() {
+        let cfg = parse(
+            r#"
+askpass = "/usr/local/bin/keychain-askpass"
+credential_helper = "osxkeychain"
+
+[[repositories]]
+path = "/tmp/shephard-credentials-repo"
+credential_helper = "!op plugin run -- gh auth git-credential"
+"#,
+            Path::new("/tmp"),
+        )
+        .expect("parse should succeed");
+
+        assert_eq!(
+            (
+                resolve_git_auth(&cfg, Path::new("/tmp/shephard-credentials-repo")).credentials,
+                resolve_git_auth(&cfg, Path::new("/tmp/shephard-other-repo")).credentials,
+            ),
+            (
+                GitCredentials {
+                    askpass: Some("/usr/local/bin/keychain-askpass".to_string()),
+                    credential_helper: Some("!op plugin run -- gh auth git-credential".to_string()),
+                },
+                GitCredentials {
+                    askpass: Some("/usr/local/bin/keychain-askpass".to_string()),
+                    credential_helper: Some("osxkeychain".to_string()),
+                },
+            )
+        );
+    }
+
+    #[test]
+    fn notify_email_requires_a_recipient() {
+        let err = parse(
+            "[notify.email]\nserver = \"smtps://smtp.example.com\"\nfrom = \"me@example.com\"\nto = []\n",
+            Path::new("/tmp"),
+        )
+        .expect_err("parse should fail");
+
+        assert_eq!(
+            err.to_string(),
+            "notify.email.to must list at least one recipient"
+        );
+    }
+
+    #[test]
+    fn gix_backend_is_only_accepted_when_built_in() {
+        let parsed = parse("git_backend = \"gix\"\n", Path::new("/tmp"));
+
+        if cfg!(feature = "gix") {
+            let cfg = parsed.expect("config should parse");
+            assert_eq!(cfg.git_backend, GitBackendKind::Gix);
+        } else {
+            assert_eq!(
+                parsed.expect_err("parse should fail").to_string(),
+                "git_backend = \"gix\" needs shephard built with the `gix` feature"
+            );
+        }
     }
 }
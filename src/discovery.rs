@@ -1,7 +1,8 @@
 use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
 use walkdir::{DirEntry, WalkDir};
 
 #[derive(Debug, Clone)]
@@ -9,7 +10,61 @@ pub struct Repo {
     pub path: PathBuf,
 }
 
-pub fn discover_repositories(roots: &[PathBuf], descend_hidden_dirs: bool) -> Result<Vec<Repo>> {
+/// A `[discovery] exclude` glob. One without a `/` matches a directory name
+/// at any depth; an absolute one matches the whole path and any other the
+/// path relative to the workspace root.
+enum Exclude {
+    Name(Pattern),
+    Absolute(Pattern),
+    Relative(Pattern),
+}
+
+impl Exclude {
+    fn parse(raw: &str) -> Result<Self> {
+        // A trailing `/` only says the match is a directory, which all are.
+        let trimmed = raw.trim_end_matches('/');
+        let pattern = Pattern::new(trimmed)
+            .with_context(|| format!("invalid discovery exclude pattern {raw:?}"))?;
+        Ok(if !trimmed.contains('/') {
+            Self::Name(pattern)
+        } else if Path::new(trimmed).is_absolute() {
+            Self::Absolute(pattern)
+        } else {
+            Self::Relative(pattern)
+        })
+    }
+
+    fn matches(&self, root: &Path, entry: &DirEntry) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        match self {
+            Self::Name(pattern) => entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| pattern.matches_with(name, options)),
+            Self::Absolute(pattern) => pattern.matches_path_with(entry.path(), options),
+            Self::Relative(pattern) => entry
+                .path()
+                .strip_prefix(root)
+                .is_ok_and(|relative| pattern.matches_path_with(relative, options)),
+        }
+    }
+}
+
+/// Finds the git repositories under `roots`, skipping hidden directories
+/// unless `descend_hidden_dirs` and never entering a directory an `excludes`
+/// glob matches.
+pub fn discover_repositories(
+    roots: &[PathBuf],
+    descend_hidden_dirs: bool,
+    excludes: &[String],
+) -> Result<Vec<Repo>> {
+    let excludes = excludes
+        .iter()
+        .map(|raw| Exclude::parse(raw))
+        .collect::<Result<Vec<_>>>()?;
     let mut found = BTreeSet::new();
 
     for root in roots {
@@ -20,7 +75,11 @@ pub fn discover_repositories(roots: &[PathBuf], descend_hidden_dirs: bool) -> Re
         for entry in WalkDir::new(root)
             .follow_links(false)
             .into_iter()
-            .filter_entry(|entry| should_descend(entry, descend_hidden_dirs))
+            .filter_entry(|entry| {
+                should_descend(entry, descend_hidden_dirs)
+                    && (entry.depth() == 0
+                        || !excludes.iter().any(|exclude| exclude.matches(root, entry)))
+            })
             .filter_map(Result::ok)
         {
             if !entry.file_type().is_dir() {
@@ -60,11 +119,16 @@ fn is_hidden(entry: &DirEntry) -> bool {
     false
 }
 
-fn is_git_repository(path: &Path) -> bool {
+pub fn is_git_repository(path: &Path) -> bool {
     let git_dir = path.join(".git");
     git_dir.is_dir() || git_dir.is_file()
 }
 
+/// Whether jj (Jujutsu) manages this git working copy too.
+pub fn is_colocated_jj(path: &Path) -> bool {
+    path.join(".jj").is_dir()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,8 +146,8 @@ mod tests {
         init_fake_repo(&visible_repo);
         init_fake_repo(&hidden_repo);
 
-        let discovered =
-            discover_repositories(&[root.to_path_buf()], false).expect("discovery should work");
+        let discovered = discover_repositories(&[root.to_path_buf()], false, &[])
+            .expect("discovery should work");
         let discovered_paths: Vec<PathBuf> = discovered.into_iter().map(|repo| repo.path).collect();
         let expected = vec![
             visible_repo
@@ -106,7 +170,7 @@ mod tests {
         init_fake_repo(&hidden_repo);
 
         let discovered =
-            discover_repositories(&[root.to_path_buf()], true).expect("discovery should work");
+            discover_repositories(&[root.to_path_buf()], true, &[]).expect("discovery should work");
         let discovered_paths: Vec<PathBuf> = discovered.into_iter().map(|repo| repo.path).collect();
         let expected = vec![
             hidden_repo
@@ -120,6 +184,38 @@ mod tests {
         assert_eq!(discovered_paths, expected);
     }
 
+    #[test]
+    fn excluded_directories_are_not_walked() {
+        let temp = tempfile::tempdir().expect("tempdir should work");
+        let root = temp
+            .path()
+            .canonicalize()
+            .expect("tempdir should canonicalize");
+
+        let kept_repo = root.join("app");
+        for skipped in [
+            "app/node_modules/dep",
+            "vendor/lib",
+            "scratch/old/try",
+            "work/target/debug/fixture",
+        ] {
+            init_fake_repo(&root.join(skipped));
+        }
+        init_fake_repo(&kept_repo);
+
+        let excludes = [
+            "node_modules".to_string(),
+            "target/".to_string(),
+            "vendor/*".to_string(),
+            format!("{}/scratch/**", root.display()),
+        ];
+        let discovered = discover_repositories(std::slice::from_ref(&root), false, &excludes)
+            .expect("discovery should work");
+        let discovered_paths: Vec<PathBuf> = discovered.into_iter().map(|repo| repo.path).collect();
+
+        assert_eq!(discovered_paths, vec![kept_repo]);
+    }
+
     fn init_fake_repo(path: &Path) {
         fs::create_dir_all(path.join(".git")).expect("repo marker creation should work");
     }
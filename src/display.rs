@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, PathStyle, ResolvedConfig, ResolvedWorkspaceRoot};
+
+/// Turns canonical repository paths into the names reports and `status`
+/// print, per `[display]` and repository aliases. The default shows paths
+/// unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct PathDisplay {
+    style: PathStyle,
+    home: Option<PathBuf>,
+    roots: Vec<ResolvedWorkspaceRoot>,
+    aliases: BTreeMap<PathBuf, String>,
+}
+
+impl PathDisplay {
+    pub fn new(config: &ResolvedConfig, roots: &[ResolvedWorkspaceRoot]) -> Self {
+        PathDisplay {
+            style: config.display.paths,
+            home: dirs::home_dir().map(|home| home.canonicalize().unwrap_or(home)),
+            roots: roots.to_vec(),
+            aliases: config
+                .repositories
+                .iter()
+                .filter_map(|repo| Some((repo.path.clone(), repo.alias.clone()?)))
+                .collect(),
+        }
+    }
+
+    pub fn show(&self, repo: &Path) -> String {
+        if let Some(alias) = self.aliases.get(repo) {
+            return alias.clone();
+        }
+        let root_relative = || {
+            let root = config::root_for(&self.roots, repo)?;
+            let inside = repo.strip_prefix(&root.path).ok()?;
+            Some(if inside.as_os_str().is_empty() {
+                root.name.clone()
+            } else {
+                format!("{}/{}", root.name, inside.display())
+            })
+        };
+        let home_relative = || {
+            let inside = repo.strip_prefix(self.home.as_ref()?).ok()?;
+            Some(if inside.as_os_str().is_empty() {
+                "~".to_string()
+            } else {
+                format!("~/{}", inside.display())
+            })
+        };
+        let shown = match self.style {
+            PathStyle::Absolute => None,
+            PathStyle::Home => home_relative(),
+            PathStyle::Root => root_relative().or_else(home_relative),
+        };
+        shown.unwrap_or_else(|| repo.display().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::config::ResolvedRepositorySideChannelConfig;
+
+    #[test]
+    fn paths_render_per_style_with_aliases_winning() {
+        let root = |path: &str, name: &str| ResolvedWorkspaceRoot {
+            path: PathBuf::from(path),
+            name: name.to_string(),
+            include_untracked: None,
+            side_channel: ResolvedRepositorySideChannelConfig::default(),
+            notify: None,
+        };
+        let display = |style: PathStyle| PathDisplay {
+            style,
+            home: Some(PathBuf::from("/home/me")),
+            roots: vec![root("/home/me/work", "work")],
+            aliases: BTreeMap::from([(PathBuf::from("/home/me/dotfiles"), "dotfiles".to_string())]),
+        };
+        let repos = [
+            "/home/me/work/api",
+            "/home/me/notes",
+            "/srv/mirror",
+            "/home/me/dotfiles",
+        ];
+
+        let shown = [PathStyle::Absolute, PathStyle::Home, PathStyle::Root]
+            .map(|style| repos.map(|repo| display(style).show(Path::new(repo))));
+
+        assert_eq!(
+            shown,
+            [
+                [
+                    "/home/me/work/api",
+                    "/home/me/notes",
+                    "/srv/mirror",
+                    "dotfiles"
+                ],
+                ["~/work/api", "~/notes", "/srv/mirror", "dotfiles"],
+                ["work/api", "~/notes", "/srv/mirror", "dotfiles"],
+            ]
+            .map(|row| row.map(String::from))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Error, Result};
+
+use crate::cli::DoctorArgs;
+use crate::config::{
+    self, RepoCommands, ResolvedConfig, ResolvedRepositoryConfig, SideChannelBackend,
+    SideChannelConfig,
+};
+use crate::discovery;
+use crate::environment;
+use crate::git;
+use crate::journal;
+use crate::prompt;
+
+/// `git status` slower than this is worth an untracked cache.
+const SLOW_STATUS: Duration = Duration::from_millis(200);
+
+/// Side-channel snapshots run `git merge-tree --write-tree --merge-base`;
+/// `--write-tree` arrived in git 2.38 and `--merge-base` in 2.40.
+const MIN_GIT_VERSION: (u32, u32) = (2, 40);
+
+/// Checks that the config file loaded (`config_error` is why it did not),
+/// then this process's environment, or with `--cron` the minimal one cron
+/// would start shephard in, after `[environment]` fallbacks, and the git
+/// version. Returns 1 when the config is invalid or shephard could not sync
+/// anything from the environment. Each of `repos` must be a git repository
+/// with the remotes its sync needs (see [`remote_problems`]) and a reachable
+/// side-channel remote. With `--untracked-cache`, also checks how fast each
+/// of `repos` lists untracked files.
+pub fn run(
+    args: &DoctorArgs,
+    config: &ResolvedConfig,
+    config_error: Option<&Error>,
+    repos: &[ResolvedRepositoryConfig],
+) -> i32 {
+    match (config_error, config::config_path()) {
+        (Some(err), _) => println!("[FAIL] {err:#}"),
+        (None, Ok(path)) if path.exists() => println!("[OK] config at {}", path.display()),
+        (None, Ok(path)) => println!("[OK] no config at {}, using defaults", path.display()),
+        (None, Err(err)) => println!("[WARN] {err:#}"),
+    }
+
+    let current = environment::current();
+    let vars = if args.cron {
+        println!(
+            "Checking a cron environment: PATH={}, HOME kept, no SSH_AUTH_SOCK",
+            environment::CRON_PATH
+        );
+        environment::cron_like(&current)
+    } else {
+        current
+    };
+
+    let inspection = environment::inspect(&vars, &config.environment);
+    for applied in &inspection.applied {
+        println!("[FIX] {applied} (from [environment])");
+    }
+    if inspection.problems.is_empty() {
+        println!("[OK] git, HOME and an ssh agent are available");
+    }
+    for problem in &inspection.problems {
+        let label = if problem.is_fatal() { "FAIL" } else { "WARN" };
+        println!("[{label}] {problem}");
+    }
+    let git_found = !inspection.problems.iter().any(|problem| problem.is_fatal());
+    if git_found && let Ok(version) = git::version() {
+        let (major, minor) = MIN_GIT_VERSION;
+        match parse_version(&version) {
+            Some(found) if found < MIN_GIT_VERSION => println!(
+                "[WARN] git {version} is older than {major}.{minor}; side-channel snapshots need `git merge-tree --write-tree --merge-base`"
+            ),
+            Some(_) => println!("[OK] git {version}"),
+            None => {
+                println!("[WARN] cannot tell whether git {version} is {major}.{minor} or newer")
+            }
+        }
+    }
+    if let Ok(dir) = journal::journal_dir() {
+        for intent in journal::stale(&dir) {
+            println!("[WARN] {}", intent.describe());
+        }
+    }
+    let probe_timeout = Duration::from_secs(config.probe_timeout_secs);
+    for repo in repos {
+        if let Err(err) = check_repo(config, repo, probe_timeout) {
+            println!("[WARN] {} :: {err:#}", repo.path.display());
+        }
+    }
+    if args.untracked_cache {
+        let interactive = prompt::is_interactive(false);
+        for repo in repos {
+            if let Err(err) = check_untracked_cache(&repo.path, args.yes, interactive) {
+                println!("[WARN] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+    i32::from(config_error.is_some() || !git_found)
+}
+
+/// Reports whether `repo` exists and is a git repository, its
+/// [`remote_problems`], and whether its side-channel remote answers within
+/// `probe_timeout` (zero skips the probe, like `probe_timeout_secs`).
+fn check_repo(
+    config: &ResolvedConfig,
+    repo: &ResolvedRepositoryConfig,
+    probe_timeout: Duration,
+) -> Result<()> {
+    let path = &repo.path;
+    if !path.exists() {
+        println!("[WARN] {} :: does not exist", path.display());
+        return Ok(());
+    }
+    if !discovery::is_git_repository(path) {
+        println!("[WARN] {} :: is not a git repository", path.display());
+        return Ok(());
+    }
+
+    let side = config::resolve_repo_side_channel(config, repo);
+    let problems = remote_problems(path, &side, &repo.commands)?;
+    for problem in &problems {
+        println!("[WARN] {} :: {problem}", path.display());
+    }
+    let probe_side = match side.backend {
+        SideChannelBackend::Git => side.enabled && !probe_timeout.is_zero(),
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => false,
+    };
+    if probe_side && git::remote_url(path, &side.remote_name).is_ok() {
+        match git::probe_remote(
+            path,
+            &side.remote_name,
+            probe_timeout,
+            &config::resolve_git_auth(config, path),
+        ) {
+            Ok(()) => println!(
+                "[OK] {} :: side-channel remote '{}' reachable",
+                path.display(),
+                side.remote_name
+            ),
+            Err(err) => println!(
+                "[WARN] {} :: side-channel remote '{}' unreachable: {err:#}",
+                path.display(),
+                side.remote_name
+            ),
+        }
+    } else if problems.is_empty() {
+        println!("[OK] {} :: remotes configured", path.display());
+    }
+    Ok(())
+}
+
+/// The major and minor numbers of a [`git::version`] such as `2.43.0` or
+/// `2.39.3 (Apple Git-146)`.
+pub fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(['.', ' ']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Remotes `repo` is configured to sync through that its `.git/config` lacks
+/// or points elsewhere: the remote its branch pulls from and pushes to (unless
+/// `commands.pull` replaces the pull), and the side-channel remote. A missing
+/// side-channel remote with a `remote_url_template` is only added on the first
+/// snapshot, so it is not reported, but an existing one must match the URL the
+/// template renders.
+pub fn remote_problems(
+    repo: &Path,
+    side: &SideChannelConfig,
+    commands: &RepoCommands,
+) -> Result<Vec<String>> {
+    let remotes = git::remotes(repo)?;
+    let url_of = |name: &str| {
+        remotes
+            .iter()
+            .find(|(remote, _)| remote == name)
+            .map(|(_, url)| url.as_str())
+    };
+    let mut problems = Vec::new();
+
+    if let Some(branch) = git::current_branch(repo)? {
+        match git::branch_remote(repo, &branch)? {
+            // `.` is a branch tracking another local branch.
+            Some(remote) if remote != "." && url_of(&remote).is_none() => problems.push(format!(
+                "{branch} tracks remote '{remote}', which is not configured"
+            )),
+            Some(_) => {}
+            None if commands.pull.is_none() => {
+                problems.push(format!("{branch} has no upstream to pull from"));
+            }
+            None => {}
+        }
+    }
+
+    let uses_git_remote = match side.backend {
+        SideChannelBackend::Git => side.enabled,
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => false,
+    };
+    if uses_git_remote {
+        let expected = side
+            .remote_url_template
+            .as_ref()
+            .map(|template| template.replace("{repo_name}", &git::repo_name(repo)));
+        match (url_of(&side.remote_name), expected) {
+            (None, None) => problems.push(format!(
+                "side-channel remote '{}' is not configured and there is no remote_url_template to add it from",
+                side.remote_name
+            )),
+            (Some(actual), Some(expected)) if actual != expected => problems.push(format!(
+                "side-channel remote '{}' points at {actual}, but remote_url_template gives {expected}",
+                side.remote_name
+            )),
+            (None, Some(_)) | (Some(_), None) | (Some(_), Some(_)) => {}
+        }
+    }
+    Ok(problems)
+}
+
+/// Times `git status` in `repo` and, when it is slow without an untracked
+/// cache, turns `core.untrackedCache` on if `yes` or the user agrees, then
+/// reports the timing before and after.
+fn check_untracked_cache(repo: &Path, yes: bool, interactive: bool) -> Result<()> {
+    let enabled = git::untracked_cache_enabled(repo)?;
+    let before = time_status(repo)?;
+    if enabled || before < SLOW_STATUS {
+        println!(
+            "[OK] {} :: status {}ms, untracked cache {}",
+            repo.display(),
+            before.as_millis(),
+            if enabled { "on" } else { "off" }
+        );
+        return Ok(());
+    }
+
+    println!(
+        "[WARN] {} :: status {}ms, untracked cache off",
+        repo.display(),
+        before.as_millis()
+    );
+    let consent = yes
+        || (interactive
+            && prompt::confirm(&format!(
+                "Set core.untrackedCache = true in {}?",
+                repo.display()
+            ))?);
+    if !consent {
+        return Ok(());
+    }
+    git::enable_untracked_cache(repo)?;
+    let after = time_status(repo)?;
+    println!(
+        "[FIX] {} :: core.untrackedCache on, status {}ms -> {}ms",
+        repo.display(),
+        before.as_millis(),
+        after.as_millis()
+    );
+    Ok(())
+}
+
+fn time_status(repo: &Path) -> Result<Duration> {
+    let started = Instant::now();
+    git::changed_path_count(repo)?;
+    Ok(started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_versions_parse_to_major_and_minor() {
+        assert_eq!(
+            [
+                "2.43.0",
+                "2.39.3 (Apple Git-146)",
+                "2.45.1.windows.1",
+                "unknown"
+            ]
+            .map(parse_version),
+            [Some((2, 43)), Some((2, 39)), Some((2, 45)), None]
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::EnvironmentConfig;
+use crate::report;
+use crate::workflow::{RepoResult, RepoStatus};
+
+/// The `PATH` cron gives jobs on most systems.
+pub const CRON_PATH: &str = "/usr/bin:/bin";
+
+/// Environment variables by name, as a process would see them.
+pub type Vars = BTreeMap<String, OsString>;
+
+/// Something the environment lacks that git, ssh or a credential helper needs.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum EnvironmentProblem {
+    /// No `git` executable in any `PATH` directory.
+    GitNotFound { path: String },
+    /// `HOME` is unset, so git finds neither its config nor stored credentials.
+    NoHome,
+    /// `SSH_AUTH_SOCK` is unset, so ssh can only use keys without a passphrase.
+    NoSshAgent,
+    /// `SSH_AUTH_SOCK` points at a socket that no longer exists.
+    StaleSshAgent(PathBuf),
+}
+
+impl EnvironmentProblem {
+    /// Whether this can explain `item`'s credential failure; a missing agent
+    /// only matters to ssh.
+    fn explains(&self, item: &RepoResult) -> bool {
+        match self {
+            EnvironmentProblem::GitNotFound { .. } | EnvironmentProblem::NoHome => true,
+            EnvironmentProblem::NoSshAgent | EnvironmentProblem::StaleSshAgent(_) => {
+                item.message.contains("(publickey")
+            }
+        }
+    }
+
+    /// Whether no repository can be synced until this is fixed.
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            EnvironmentProblem::GitNotFound { .. } => true,
+            EnvironmentProblem::NoHome
+            | EnvironmentProblem::NoSshAgent
+            | EnvironmentProblem::StaleSshAgent(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for EnvironmentProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentProblem::GitNotFound { path } => write!(
+                f,
+                "git not found in PATH ({path}); add its directory to environment.path"
+            ),
+            EnvironmentProblem::NoHome => write!(f, "HOME is not set"),
+            EnvironmentProblem::NoSshAgent => write!(
+                f,
+                "SSH_AUTH_SOCK is not set, so ssh cannot reach an agent; set environment.ssh_auth_sock_path"
+            ),
+            EnvironmentProblem::StaleSshAgent(sock) => write!(
+                f,
+                "SSH_AUTH_SOCK points at {}, which does not exist; set environment.ssh_auth_sock_path",
+                sock.display()
+            ),
+        }
+    }
+}
+
+/// An environment with `[environment]` fallbacks applied, and what it still
+/// lacks.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Inspection {
+    pub vars: Vars,
+    /// `NAME=value` for each variable a fallback set or extended.
+    pub applied: Vec<String>,
+    pub problems: Vec<EnvironmentProblem>,
+}
+
+/// This process's environment, leaving out variables with non-UTF-8 names.
+pub fn current() -> Vars {
+    std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value)))
+        .collect()
+}
+
+/// The environment cron would give a job run by the owner of `vars`: the
+/// same `HOME` and user, `/bin/sh`, a bare `PATH`, and nothing from the login
+/// session such as `SSH_AUTH_SOCK`.
+pub fn cron_like(vars: &Vars) -> Vars {
+    let mut cron = Vars::new();
+    for name in ["HOME", "LOGNAME", "USER"] {
+        if let Some(value) = vars.get(name) {
+            cron.insert(name.to_string(), value.clone());
+        }
+    }
+    cron.insert("SHELL".to_string(), "/bin/sh".into());
+    cron.insert("PATH".to_string(), CRON_PATH.into());
+    cron
+}
+
+/// Applies `config`'s fallbacks to `vars` and checks what the result still
+/// lacks: extra `PATH` directories are appended when missing, and the
+/// configured agent socket replaces an unset or stale `SSH_AUTH_SOCK`.
+pub fn inspect(vars: &Vars, config: &EnvironmentConfig) -> Inspection {
+    let mut vars = vars.clone();
+    let mut applied = Vec::new();
+
+    let mut path_dirs: Vec<PathBuf> = vars
+        .get("PATH")
+        .map(|path| std::env::split_paths(path).collect())
+        .unwrap_or_default();
+    let missing_dirs = config
+        .path
+        .iter()
+        .filter(|dir| !path_dirs.contains(dir))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !missing_dirs.is_empty() {
+        path_dirs.extend(missing_dirs);
+        if let Ok(joined) = std::env::join_paths(&path_dirs) {
+            applied.push(format!("PATH={}", joined.to_string_lossy()));
+            vars.insert("PATH".to_string(), joined);
+        }
+    }
+
+    let agent_alive = agent_socket(&vars).is_some_and(|sock| sock.exists());
+    if !agent_alive
+        && let Some(sock) = config
+            .ssh_auth_sock_path
+            .as_ref()
+            .filter(|sock| sock.exists())
+    {
+        applied.push(format!("SSH_AUTH_SOCK={}", sock.display()));
+        vars.insert("SSH_AUTH_SOCK".to_string(), sock.into());
+    }
+
+    let mut problems = Vec::new();
+    if !path_dirs.iter().any(|dir| is_executable(&dir.join("git"))) {
+        problems.push(EnvironmentProblem::GitNotFound {
+            path: vars
+                .get("PATH")
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        });
+    }
+    if vars.get("HOME").is_none_or(|home| home.is_empty()) {
+        problems.push(EnvironmentProblem::NoHome);
+    }
+    match agent_socket(&vars) {
+        None => problems.push(EnvironmentProblem::NoSshAgent),
+        Some(sock) if !sock.exists() => problems.push(EnvironmentProblem::StaleSshAgent(sock)),
+        Some(_) => {}
+    }
+
+    Inspection {
+        vars,
+        applied,
+        problems,
+    }
+}
+
+/// Applies `config`'s fallbacks to this process's environment, so every git,
+/// ssh and helper process shephard starts inherits them, and returns what the
+/// environment still lacks. An unset `HOME` is filled in from the password
+/// database first.
+///
+/// # Safety
+///
+/// Changes the process environment, so no other thread may be running.
+pub unsafe fn prepare(config: &EnvironmentConfig) -> Vec<EnvironmentProblem> {
+    let mut vars = current();
+    if vars.get("HOME").is_none_or(|home| home.is_empty())
+        && let Some(home) = dirs::home_dir()
+    {
+        vars.insert("HOME".to_string(), home.into());
+    }
+    let inspection = inspect(&vars, config);
+    for (name, value) in &inspection.vars {
+        if std::env::var_os(name).as_ref() != Some(value) {
+            // SAFETY: the caller guarantees no other thread is running.
+            unsafe { std::env::set_var(name, value) };
+        }
+    }
+    inspection.problems
+}
+
+/// Marks credential failures as environment problems when `problems` can
+/// explain them, e.g. a rejected ssh key under cron without an agent, with the
+/// cause in front of git's message.
+pub fn attribute_failures(results: &mut [RepoResult], problems: &[EnvironmentProblem]) {
+    for item in results
+        .iter_mut()
+        .filter(|item| report::is_auth_failure(item))
+    {
+        let causes = problems
+            .iter()
+            .filter(|problem| problem.explains(item))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !causes.is_empty() {
+            item.status = RepoStatus::Environment;
+            item.message = format!("{}: {}", causes.join("; "), item.message);
+        }
+    }
+}
+
+fn agent_socket(vars: &Vars) -> Option<PathBuf> {
+    vars.get("SSH_AUTH_SOCK")
+        .filter(|sock| !sock.is_empty())
+        .map(PathBuf::from)
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn cron_environment_uses_configured_path_and_agent_fallbacks() {
+        let temp = tempfile::tempdir().expect("tempdir should work");
+        let bin = temp.path().join("bin");
+        fs::create_dir(&bin).expect("bin dir should be created");
+        fs::write(bin.join("git"), "#!/bin/sh\n").expect("fake git should be written");
+        fs::set_permissions(bin.join("git"), fs::Permissions::from_mode(0o755))
+            .expect("fake git should be executable");
+        let sock = temp.path().join("agent.sock");
+        fs::write(&sock, "").expect("fake socket should be written");
+        let session = Vars::from([
+            ("HOME".to_string(), OsString::from("/home/me")),
+            ("PATH".to_string(), OsString::from("/nowhere")),
+            (
+                "SSH_AUTH_SOCK".to_string(),
+                OsString::from("/tmp/ssh-gone/agent.1"),
+            ),
+        ]);
+        let cron = Vars::from([
+            ("HOME".to_string(), OsString::from("/home/me")),
+            ("PATH".to_string(), OsString::from("/nowhere")),
+        ]);
+        let config = EnvironmentConfig {
+            ssh_auth_sock_path: Some(sock.clone()),
+            path: vec![bin.clone()],
+        };
+
+        let bare = inspect(&cron, &EnvironmentConfig::default());
+        let fixed = inspect(&cron, &config);
+        let stale = inspect(
+            &session,
+            &EnvironmentConfig {
+                ssh_auth_sock_path: None,
+                path: vec![bin.clone()],
+            },
+        );
+
+        assert_eq!(
+            bare.problems,
+            vec![
+                EnvironmentProblem::GitNotFound {
+                    path: "/nowhere".to_string()
+                },
+                EnvironmentProblem::NoSshAgent,
+            ]
+        );
+        assert_eq!(
+            (fixed.applied, fixed.problems),
+            (
+                vec![
+                    format!("PATH=/nowhere:{}", bin.display()),
+                    format!("SSH_AUTH_SOCK={}", sock.display()),
+                ],
+                Vec::new()
+            )
+        );
+        assert_eq!(
+            stale.problems,
+            vec![EnvironmentProblem::StaleSshAgent(PathBuf::from(
+                "/tmp/ssh-gone/agent.1"
+            ))]
+        );
+    }
+
+    #[test]
+    fn cron_like_keeps_only_what_cron_sets() {
+        let session = Vars::from([
+            ("HOME".to_string(), OsString::from("/home/me")),
+            ("USER".to_string(), OsString::from("me")),
+            ("PATH".to_string(), OsString::from("/home/me/bin:/usr/bin")),
+            ("SSH_AUTH_SOCK".to_string(), OsString::from("/tmp/agent")),
+        ]);
+
+        assert_eq!(
+            cron_like(&session),
+            Vars::from([
+                ("HOME".to_string(), OsString::from("/home/me")),
+                ("PATH".to_string(), OsString::from(CRON_PATH)),
+                ("SHELL".to_string(), OsString::from("/bin/sh")),
+                ("USER".to_string(), OsString::from("me")),
+            ])
+        );
+    }
+
+    #[test]
+    fn auth_failures_are_attributed_to_environment_problems() {
+        let result = |repo: &str, message: &str| RepoResult {
+            repo: PathBuf::from(repo),
+            status: RepoStatus::Failed,
+            message: message.to_string(),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
+        };
+        let mut results = vec![
+            result("/tmp/a", "git@github.com: Permission denied (publickey)."),
+            result("/tmp/b", "merge conflict"),
+            result(
+                "/tmp/c",
+                "fatal: could not read Username for 'https://github.com'",
+            ),
+        ];
+
+        attribute_failures(&mut results, &[EnvironmentProblem::NoSshAgent]);
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|item| (item.status, item.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    RepoStatus::Environment,
+                    "SSH_AUTH_SOCK is not set, so ssh cannot reach an agent; set environment.ssh_auth_sock_path: git@github.com: Permission denied (publickey).",
+                ),
+                (RepoStatus::Failed, "merge conflict"),
+                (
+                    RepoStatus::Failed,
+                    "fatal: could not read Username for 'https://github.com'",
+                ),
+            ]
+        );
+    }
+}
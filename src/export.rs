@@ -0,0 +1,297 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::RunArgs;
+use crate::config::{self, ResolvedConfig, ResolvedRepositoryConfig, UntrackedPolicy};
+use crate::discovery;
+use crate::git;
+
+const MANIFEST_FILE: &str = "manifest.json";
+/// Temporary ref that carries the working-tree snapshot into a bundle.
+const SNAPSHOT_REF: &str = "refs/shephard/export/snapshot";
+
+/// Index of an export archive: one entry per repository with something to carry.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub created_at: i64,
+    pub host: String,
+    pub repos: Vec<ManifestRepo>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ManifestRepo {
+    /// Canonical repository path on the exporting machine.
+    pub path: String,
+    pub branch: String,
+    /// `HEAD` when exported; the snapshot's parent.
+    pub head: String,
+    /// Upstream commit when exported. The bundle carries `branch`'s whole
+    /// history, so importing never needs it.
+    pub base: Option<String>,
+    /// URL of the upstream remote, which a repository cloned from the bundle
+    /// gets as `origin`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+    /// Commits on `branch` past `base`.
+    pub commits: usize,
+    pub snapshot: Option<String>,
+    /// Bundle file name inside the archive.
+    pub bundle: String,
+}
+
+/// Writes a tar archive holding a bundle of unpushed commits and a
+/// working-tree snapshot for every selected repository, plus a manifest.
+pub fn export(
+    config: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+    output: &Path,
+) -> Result<i32> {
+    let staging = tempfile::tempdir().context("failed to create export staging directory")?;
+    let base_run = config::resolve_run_config(config, &RunArgs::default())?;
+    let mut manifest = Manifest {
+        created_at: Utc::now().timestamp(),
+        host: hostname::get()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        repos: Vec::new(),
+    };
+    let mut failed = false;
+
+    for repo in repos {
+        let run_cfg = config::resolve_repo_run_config(&base_run, &RunArgs::default(), repo);
+        let include_untracked = match run_cfg.include_untracked {
+            UntrackedPolicy::Include => true,
+            UntrackedPolicy::Exclude | UntrackedPolicy::Ask => false,
+        };
+        let excludes = if run_cfg.builtin_excludes {
+            git::BUILTIN_EXCLUDES
+        } else {
+            &[]
+        };
+        let bundle = format!("{:03}.bundle", manifest.repos.len());
+        match export_repo(
+            &repo.path,
+            include_untracked,
+            excludes,
+            &staging.path().join(&bundle),
+        ) {
+            Ok(Some(entry)) => {
+                println!(
+                    "[OK] {} :: {} commits, {}",
+                    repo.path.display(),
+                    entry.commits,
+                    if entry.snapshot.is_some() {
+                        "working tree snapshot"
+                    } else {
+                        "clean working tree"
+                    }
+                );
+                manifest.repos.push(ManifestRepo { bundle, ..entry });
+            }
+            Ok(None) => println!("[NOOP] {} :: nothing to carry", repo.path.display()),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+
+    let raw = serde_json::to_string_pretty(&manifest).context("failed serializing manifest")?;
+    fs::write(staging.path().join(MANIFEST_FILE), raw).context("failed writing manifest")?;
+    tar(&[
+        "-cf",
+        &output.to_string_lossy(),
+        "-C",
+        &staging.path().to_string_lossy(),
+        ".",
+    ])?;
+    println!(
+        "Exported {} repos to {}",
+        manifest.repos.len(),
+        output.display()
+    );
+    Ok(if failed { 1 } else { 0 })
+}
+
+fn export_repo(
+    repo: &Path,
+    include_untracked: bool,
+    excludes: &[&str],
+    bundle: &Path,
+) -> Result<Option<ManifestRepo>> {
+    let Some(branch) = git::current_branch(repo)? else {
+        bail!("detached HEAD; check out a branch to export");
+    };
+    let head = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    let base = git::rev_parse_optional(repo, "@{upstream}")?;
+    let remote_url = match git::upstream_remote(repo)? {
+        Some(remote) => Some(git::remote_url(repo, &remote)?),
+        None => None,
+    };
+    let commits = match &base {
+        Some(base) => git::commit_count(repo, &format!("{base}..HEAD"))?,
+        None => git::commit_count(repo, "HEAD")?,
+    };
+    let snapshot = git::snapshot_commit(
+        repo,
+        include_untracked,
+        excludes,
+        "shephard export snapshot",
+    )?;
+    if commits == 0 && snapshot.is_none() {
+        return Ok(None);
+    }
+
+    // The whole history goes in, with no prerequisites, so a machine that
+    // has never seen the repository (or is offline) can clone it from here.
+    let mut revs = vec![format!("refs/heads/{branch}")];
+    if let Some(snapshot) = &snapshot {
+        git::update_ref(repo, SNAPSHOT_REF, snapshot)?;
+        revs.push(SNAPSHOT_REF.to_string());
+    }
+    let bundled = git::create_bundle(repo, bundle, &revs);
+    if snapshot.is_some() {
+        git::delete_ref(repo, SNAPSHOT_REF)?;
+    }
+    bundled?;
+
+    Ok(Some(ManifestRepo {
+        path: config::canonical_repo_key(repo),
+        branch,
+        head,
+        base,
+        remote_url,
+        commits,
+        snapshot,
+        bundle: String::new(),
+    }))
+}
+
+/// Unpacks an archive written by [`export`] and recreates each repository's
+/// commits and working tree, matching repositories by path after `maps`
+/// (`OLD=NEW` path prefixes, the longest matching one applied). Repositories
+/// missing from disk are cloned from their bundle.
+pub fn import(archive: &Path, maps: &[String]) -> Result<i32> {
+    let maps = maps
+        .iter()
+        .map(|map| {
+            let (old, new) = map
+                .split_once('=')
+                .with_context(|| format!("--map {map} is not OLD=NEW"))?;
+            Ok((PathBuf::from(old), config::expand_path(Path::new(new))?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let staging = tempfile::tempdir().context("failed to create import staging directory")?;
+    tar(&[
+        "-xf",
+        &archive.to_string_lossy(),
+        "-C",
+        &staging.path().to_string_lossy(),
+    ])?;
+    let raw = fs::read_to_string(staging.path().join(MANIFEST_FILE))
+        .with_context(|| format!("{} has no {MANIFEST_FILE}", archive.display()))?;
+    let manifest: Manifest = serde_json::from_str(&raw).context("failed parsing manifest")?;
+    let mut failed = false;
+
+    for entry in &manifest.repos {
+        let repo = map_path(&maps, Path::new(&entry.path));
+        let bundle = staging.path().join(&entry.bundle);
+        let imported = if discovery::is_git_repository(&repo) {
+            import_repo(&repo, entry, &bundle)
+        } else if repo.exists() {
+            Err(anyhow::anyhow!("exists but is not a git repository"))
+        } else {
+            clone_from_bundle(&repo, entry, &bundle)
+                .and_then(|()| import_repo(&repo, entry, &bundle))
+                .map(|outcome| format!("cloned from the archive, {outcome}"))
+        };
+        match imported {
+            Ok(outcome) => println!("[OK] {} :: {outcome}", repo.display()),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.display());
+            }
+        }
+    }
+
+    Ok(if failed { 1 } else { 0 })
+}
+
+/// `path` with the longest `(old, new)` prefix in `maps` swapped, or as is.
+fn map_path(maps: &[(PathBuf, PathBuf)], path: &Path) -> PathBuf {
+    maps.iter()
+        .filter_map(|(old, new)| Some((old, new.join(path.strip_prefix(old).ok()?))))
+        .max_by_key(|(old, _)| old.components().count())
+        .map_or_else(|| path.to_path_buf(), |(_, mapped)| mapped)
+}
+
+/// Clones `entry`'s branch from `bundle` into `repo`, then points `origin` at
+/// the exported upstream, with its tracking ref at the exported upstream
+/// commit, or drops it when there was no upstream.
+fn clone_from_bundle(repo: &Path, entry: &ManifestRepo, bundle: &Path) -> Result<()> {
+    git::clone_bundle(bundle, repo, &entry.branch)?;
+    match (&entry.remote_url, &entry.base) {
+        (Some(url), Some(base)) => {
+            git::set_remote_url(repo, "origin", url)?;
+            git::update_ref(repo, &format!("refs/remotes/origin/{}", entry.branch), base)
+        }
+        (Some(_), None) | (None, _) => git::remove_remote(repo, "origin"),
+    }
+}
+
+fn import_repo(repo: &Path, entry: &ManifestRepo, bundle: &Path) -> Result<String> {
+    let branch_ref = format!("refs/shephard/import/{}", entry.branch);
+    let snapshot_ref = "refs/shephard/import/snapshot";
+    let mut refspecs = Vec::new();
+    if entry.commits > 0 {
+        refspecs.push(format!("+refs/heads/{}:{branch_ref}", entry.branch));
+    }
+    if entry.snapshot.is_some() {
+        refspecs.push(format!("+{SNAPSHOT_REF}:{snapshot_ref}"));
+    }
+    git::fetch_bundle(repo, bundle, &refspecs)?;
+
+    let mut outcome = Vec::new();
+    let on_branch = git::current_branch(repo)?.as_deref() == Some(entry.branch.as_str());
+    let head = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    if entry.commits == 0 || head == entry.head {
+        outcome.push("commits already present".to_string());
+    } else if on_branch && git::is_ancestor(repo, &head, &branch_ref)? {
+        git::merge_ff_only(repo, &branch_ref)?;
+        outcome.push(format!("fast-forwarded {}", entry.branch));
+    } else {
+        outcome.push(format!("commits left at {branch_ref}"));
+    }
+
+    if entry.snapshot.is_some() {
+        let head = git::rev_parse(repo, "HEAD")?.trim().to_string();
+        if head == entry.head && git::changed_path_count(repo)? == 0 {
+            git::restore_snapshot(repo, snapshot_ref)?;
+            outcome.push("working tree restored".to_string());
+        } else {
+            outcome.push(format!("working tree snapshot left at {snapshot_ref}"));
+        }
+    }
+
+    Ok(outcome.join(", "))
+}
+
+fn tar(args: &[&str]) -> Result<()> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .context("failed running tar")?;
+    if !output.status.success() {
+        bail!(
+            "tar {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
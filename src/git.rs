@@ -1,15 +1,78 @@
-use std::collections::BTreeSet;
-use std::path::Path;
-use std::process::Command;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail};
-use chrono::Local;
 
-use crate::config::SideChannelConfig;
+use crate::backend::SnapshotRequest;
+use crate::config::{AuthMode, GitAuth, LastSnapshot, SideChannelConfig, TimestampStyle};
+use crate::logging;
 
 pub enum SideChannelSyncResult {
-    Pushed,
+    /// `snapshot` records where the working tree went, for deduplicating
+    /// the next run.
+    Pushed {
+        stats: SnapshotStats,
+        snapshot: LastSnapshot,
+    },
     NoChanges,
+    /// The working tree is the one the last snapshot was taken from, though
+    /// `HEAD` may have moved since, and the side channel still has it.
+    Unchanged,
+    /// Changes since the previous snapshot fall short of `min_changed_lines`
+    /// and `min_changed_files`.
+    BelowThreshold,
+}
+
+/// What a pushed side-channel snapshot contains, counted over its whole tree.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SnapshotStats {
+    pub files: usize,
+    pub bytes: u64,
+    /// Largest top-level directories by bytes, biggest first.
+    pub top_dirs: Vec<(String, u64)>,
+}
+
+/// How many top-level directories snapshot stats keep.
+const SNAPSHOT_TOP_DIRS: usize = 3;
+
+/// How many commits each fetch deepening a shallow clone asks for, and how
+/// many such fetches run before the clone is reported as too shallow.
+const DEEPEN_STEP: usize = 256;
+const DEEPEN_ATTEMPTS: usize = 4;
+
+impl fmt::Display for SnapshotStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let size = |bytes: u64| {
+            let units = ["B", "KiB", "MiB", "GiB"];
+            let mut value = bytes as f64;
+            let mut unit = 0;
+            while value >= 1024.0 && unit + 1 < units.len() {
+                value /= 1024.0;
+                unit += 1;
+            }
+            match unit {
+                0 => format!("{bytes} B"),
+                _ => format!("{value:.1} {}", units[unit]),
+            }
+        };
+
+        write!(f, "{} files, {}", self.files, size(self.bytes))?;
+        if !self.top_dirs.is_empty() {
+            let dirs = self
+                .top_dirs
+                .iter()
+                .map(|(dir, bytes)| format!("{dir}/ {}", size(*bytes)))
+                .collect::<Vec<_>>();
+            write!(f, " ({})", dirs.join(", "))?;
+        }
+        Ok(())
+    }
 }
 
 enum SideChannelPushResult {
@@ -17,23 +80,327 @@ enum SideChannelPushResult {
     NonFastForward,
 }
 
-pub fn pull_ff_only(repo: &Path) -> Result<()> {
-    run_git(repo, &["pull", "--ff-only"]).map(|_| ())
+pub fn pull_ff_only(repo: &Path, auth: &GitAuth) -> Result<()> {
+    run_git_network(repo, &["pull", "--ff-only"], auth).map(|_| ())
 }
 
-pub fn side_channel_preflight(repo: &Path, side: &SideChannelConfig) -> Result<()> {
-    ensure_remote_exists(repo, &side.remote_name)?;
-    run_git(repo, &["fetch", &side.remote_name, "--prune"]).map(|_| ())
+pub fn side_channel_preflight(repo: &Path, side: &SideChannelConfig, auth: &GitAuth) -> Result<()> {
+    ensure_side_channel_remote(repo, side)?;
+    configure_side_channel_fetch(repo, side)?;
+    run_git_network(repo, &["fetch", &side.remote_name, "--prune"], auth)?;
+    // The remote's fetch refspec only covers branches, so a full ref is
+    // fetched on its own, and only when the remote has it already.
+    if side.branch_name.starts_with("refs/") {
+        let remote_ref = side_channel_remote_ref(side);
+        let listed = run_git_network(repo, &["ls-remote", &side.remote_name, &remote_ref], auth)?;
+        if listed.stdout.trim().is_empty() {
+            let tracking_ref = side_channel_tracking_ref(side);
+            if rev_parse_optional(repo, &tracking_ref)?.is_some() {
+                delete_ref(repo, &tracking_ref)?;
+            }
+        } else {
+            fetch_side_channel(repo, side, auth)?;
+        }
+    }
+    Ok(())
 }
 
-pub fn stage_changes(repo: &Path, include_untracked: bool) -> Result<()> {
-    if include_untracked {
-        run_git(repo, &["add", "-A"]).map(|_| ())
+/// The side-channel ref on the remote: `branch_name` itself when it is a full
+/// ref such as `refs/shephard/sync`, otherwise `refs/heads/<branch_name>`.
+pub fn side_channel_remote_ref(side: &SideChannelConfig) -> String {
+    if side.branch_name.starts_with("refs/") {
+        side.branch_name.clone()
     } else {
-        run_git(repo, &["add", "-u"]).map(|_| ())
+        format!("refs/heads/{}", side.branch_name)
+    }
+}
+
+/// The local ref tracking the side-channel tip,
+/// `refs/remotes/<remote_name>/<branch_name>`, or under
+/// `refs/shephard/remotes/` with `hide_refs`. A full ref keeps its `refs/`
+/// prefix there, so it never shares a name with a branch.
+pub fn side_channel_tracking_ref(side: &SideChannelConfig) -> String {
+    format!(
+        "{}/{}",
+        side_channel_tracking_namespace(side, side.hide_refs),
+        side.branch_name
+    )
+}
+
+fn side_channel_tracking_namespace(side: &SideChannelConfig, hidden: bool) -> String {
+    if hidden {
+        format!("refs/shephard/remotes/{}", side.remote_name)
+    } else {
+        format!("refs/remotes/{}", side.remote_name)
+    }
+}
+
+/// Points the side-channel remote's fetch refspec at the tracking namespace
+/// `hide_refs` asks for and drops tracking refs left in the other one. Turning
+/// `hide_refs` off again restores git's default refspec. Hiding refuses the
+/// upstream remote, whose branches belong in `git branch -r`.
+fn configure_side_channel_fetch(repo: &Path, side: &SideChannelConfig) -> Result<()> {
+    let refspec = |hidden: bool| {
+        format!(
+            "+refs/heads/*:{}/*",
+            side_channel_tracking_namespace(side, hidden)
+        )
+    };
+    let current = remote_fetch_refspecs(repo, &side.remote_name)?;
+    let (wanted, stale) = if side.hide_refs {
+        if upstream_remote(repo)?.as_deref() == Some(side.remote_name.as_str()) {
+            bail!(
+                "side_channel.hide_refs would hide the upstream remote '{}'; use a remote dedicated to the side channel",
+                side.remote_name
+            );
+        }
+        (refspec(true), side_channel_tracking_namespace(side, false))
+    } else if current == [refspec(true)] {
+        (refspec(false), side_channel_tracking_namespace(side, true))
+    } else {
+        return Ok(());
+    };
+    if current != [wanted.clone()] {
+        set_remote_fetch_refspecs(repo, &side.remote_name, &[&wanted])?;
+    }
+    delete_refs_under(repo, &stale)
+}
+
+/// The `remote.<name>.fetch` refspecs, in config order.
+pub fn remote_fetch_refspecs(repo: &Path, remote_name: &str) -> Result<Vec<String>> {
+    let key = format!("remote.{remote_name}.fetch");
+    let output = logging::output(
+        Command::new("git")
+            .args(["config", "--get-all", &key])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git config in {}", repo.display()))?;
+    // `--get-all` exits 1 when the key is unset.
+    match output.status.code() {
+        Some(0) => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect()),
+        Some(1) => Ok(Vec::new()),
+        _ => bail!(
+            "git config --get-all {key} failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// Replaces every `remote.<name>.fetch` refspec with `refspecs`.
+pub fn set_remote_fetch_refspecs(repo: &Path, remote_name: &str, refspecs: &[&str]) -> Result<()> {
+    let key = format!("remote.{remote_name}.fetch");
+    if !remote_fetch_refspecs(repo, remote_name)?.is_empty() {
+        run_git(repo, &["config", "--unset-all", &key])?;
+    }
+    for refspec in refspecs {
+        run_git(repo, &["config", "--add", &key, refspec])?;
+    }
+    Ok(())
+}
+
+/// The global attributes file git reads in `repo`: `core.attributesFile`,
+/// else `$XDG_CONFIG_HOME/git/attributes` or `~/.config/git/attributes`.
+fn global_attributes_file(repo: &Path) -> Result<Option<PathBuf>> {
+    if let Some(file) = config_value(repo, "core.attributesFile")? {
+        // git expands a leading `~/` in path settings, and nothing else.
+        return Ok(match file.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().map(|home| home.join(rest)),
+            None => Some(repo.join(file)),
+        });
+    }
+    Ok(std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("git").join("attributes")))
+}
+
+/// The value of `key` in `repo`'s git config, or `None` when unset.
+fn config_value(repo: &Path, key: &str) -> Result<Option<String>> {
+    let output = logging::output(
+        Command::new("git")
+            .args(["config", "--get", key])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git config in {}", repo.display()))?;
+    // `--get` exits 1 when the key is unset.
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )),
+        Some(1) => Ok(None),
+        _ => bail!(
+            "git config --get {key} failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
     }
 }
 
+/// The remote `branch.<branch>.remote` names, whether or not it exists.
+pub fn branch_remote(repo: &Path, branch: &str) -> Result<Option<String>> {
+    config_value(repo, &format!("branch.{branch}.remote"))
+}
+
+/// Whether git keeps an untracked cache for `repo`: `core.untrackedCache`
+/// is true, or unset with `feature.manyFiles` on.
+pub fn untracked_cache_enabled(repo: &Path) -> Result<bool> {
+    let is_true = |value: &str| {
+        matches!(
+            value.to_ascii_lowercase().as_str(),
+            "true" | "yes" | "on" | "1"
+        )
+    };
+    Ok(match config_value(repo, "core.untrackedCache")? {
+        Some(value) => is_true(&value),
+        None => config_value(repo, "feature.manyFiles")?.is_some_and(|value| is_true(&value)),
+    })
+}
+
+/// Turns on `core.untrackedCache` in `repo`'s own config, then runs
+/// `git status` once so the cache is built before it is next needed.
+pub fn enable_untracked_cache(repo: &Path) -> Result<()> {
+    run_git(repo, &["config", "core.untrackedCache", "true"])?;
+    changed_path_count(repo).map(|_| ())
+}
+
+/// Deletes every ref under `prefix`, e.g. `refs/remotes/shephard`.
+pub fn delete_refs_under(repo: &Path, prefix: &str) -> Result<()> {
+    let listed = run_git(
+        repo,
+        &["for-each-ref", "--format=%(refname)", &format!("{prefix}/")],
+    )?;
+    for name in listed.stdout.lines().filter(|line| !line.is_empty()) {
+        delete_ref(repo, name)?;
+    }
+    Ok(())
+}
+
+/// Makes sure `side.remote_name` is configured in `repo`, adding it from
+/// `remote_url_template` when missing, and returns its URL.
+pub fn ensure_side_channel_remote(repo: &Path, side: &SideChannelConfig) -> Result<String> {
+    if let Some(template) = &side.remote_url_template
+        && run_git(repo, &["remote", "get-url", &side.remote_name]).is_err()
+    {
+        let url = template.replace("{repo_name}", &repo_name(repo));
+        run_git(repo, &["remote", "add", &side.remote_name, &url]).with_context(|| {
+            format!(
+                "failed adding side-channel remote '{}' from remote_url_template",
+                side.remote_name
+            )
+        })?;
+    }
+    ensure_remote_exists(repo, &side.remote_name)?;
+    remote_url(repo, &side.remote_name)
+}
+
+/// Directory name of the repository, used to key per-repository storage.
+pub fn repo_name(repo: &Path) -> String {
+    repo.canonicalize()
+        .unwrap_or_else(|_| repo.to_path_buf())
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Pushes `HEAD` as the side-channel branch when the remote does not have it
+/// yet. Returns whether a branch was created.
+pub fn seed_side_branch(repo: &Path, side: &SideChannelConfig, auth: &GitAuth) -> Result<bool> {
+    if side_branch_exists(repo, side, auth)? {
+        return Ok(false);
+    }
+
+    let refspec = format!("HEAD:{}", side_channel_remote_ref(side));
+    run_git_network(repo, &["push", &side.remote_name, &refspec], auth)?;
+    Ok(true)
+}
+
+/// Whether the side-channel remote has the side branch yet.
+pub fn side_branch_exists(repo: &Path, side: &SideChannelConfig, auth: &GitAuth) -> Result<bool> {
+    let remote_ref = side_channel_remote_ref(side);
+    let heads = run_git_network(repo, &["ls-remote", &side.remote_name, &remote_ref], auth)?;
+    Ok(!heads.stdout.trim().is_empty())
+}
+
+/// Clones `url` into `dest`, creating the directories above it.
+pub fn clone_repo(url: &str, dest: &Path, auth: &GitAuth) -> Result<()> {
+    let parent = dest
+        .parent()
+        .with_context(|| format!("{} has no parent directory", dest.display()))?;
+    fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    let dest = dest.to_string_lossy();
+    run_git_network(parent, &["clone", "--quiet", url, &dest], auth).map(|_| ())
+}
+
+/// Clones the bundle at `bundle` into `dest` with `branch` checked out,
+/// creating the directories above it.
+pub fn clone_bundle(bundle: &Path, dest: &Path, branch: &str) -> Result<()> {
+    let parent = dest
+        .parent()
+        .with_context(|| format!("{} has no parent directory", dest.display()))?;
+    fs::create_dir_all(parent).with_context(|| format!("failed to create {}", parent.display()))?;
+    let bundle = bundle.to_string_lossy();
+    let dest = dest.to_string_lossy();
+    run_git(
+        parent,
+        &["clone", "--quiet", "--branch", branch, &bundle, &dest],
+    )
+    .map(|_| ())
+}
+
+/// Glob patterns for build output and dependency directories that are never
+/// worth committing when untracked files are included.
+pub const BUILTIN_EXCLUDES: &[&str] = &[
+    "**/target/**",
+    "**/node_modules/**",
+    "**/.venv/**",
+    "**/dist/**",
+    "**/__pycache__/**",
+    "**/.tox/**",
+    "**/*.o",
+    "**/*.pyc",
+    "**/*.class",
+];
+
+/// Stages tracked changes and, with `include_untracked`, untracked files that
+/// match none of the `excludes` globs.
+pub fn stage_changes(repo: &Path, include_untracked: bool, excludes: &[&str]) -> Result<()> {
+    stage_with_env(repo, include_untracked, false, excludes, &[])
+}
+
+/// Ignore rules (`.gitignore`, `.git/info/exclude`, and `core.excludesFile`)
+/// apply to untracked files unless `include_ignored` is set.
+fn stage_with_env(
+    repo: &Path,
+    include_untracked: bool,
+    include_ignored: bool,
+    excludes: &[&str],
+    env: &[(&str, &str)],
+) -> Result<()> {
+    // Tracked files are always updated, even inside excluded directories;
+    // excludes only keep new files out.
+    run_git_with_env(repo, &["add", "-u"], env)?;
+    if !include_untracked {
+        return Ok(());
+    }
+
+    let pathspecs = excludes
+        .iter()
+        .map(|pattern| format!(":(exclude,glob){pattern}"))
+        .collect::<Vec<_>>();
+    let mut args = vec!["add", "-A"];
+    if include_ignored {
+        args.push("-f");
+    }
+    args.extend(["--", "."]);
+    args.extend(pathspecs.iter().map(String::as_str));
+    run_git_with_env(repo, &args, env).map(|_| ())
+}
+
 pub fn has_staged_changes(repo: &Path) -> Result<bool> {
     has_staged_changes_with_env(repo, &[])
 }
@@ -42,17 +409,442 @@ pub fn commit(repo: &Path, message: &str) -> Result<()> {
     run_git(repo, &["commit", "-m", message]).map(|_| ())
 }
 
-pub fn push(repo: &Path) -> Result<()> {
-    run_git(repo, &["push"]).map(|_| ())
+/// Commits the staged changes under `paths` only, leaving anything else staged
+/// out of the commit.
+pub fn commit_paths(repo: &Path, message: &str, paths: &[String]) -> Result<()> {
+    let mut args = vec!["commit", "-m", message, "--"];
+    args.extend(paths.iter().map(String::as_str));
+    run_git(repo, &args).map(|_| ())
 }
 
-pub fn side_channel_sync(
+/// Moves the current branch to `rev` like `git reset --keep`: uncommitted
+/// changes survive, and the reset fails instead of overwriting them.
+pub fn reset_keep(repo: &Path, rev: &str) -> Result<()> {
+    run_git(repo, &["reset", "--keep", rev]).map(|_| ())
+}
+
+/// Which side of a conflicted merge to keep for a path.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConflictSide {
+    /// The current branch.
+    Ours,
+    /// What was merged in, e.g. the side-channel tip.
+    Theirs,
+}
+
+/// Resolves the conflicted `path` by keeping one side's version and marks it
+/// resolved.
+pub fn take_conflict_side(repo: &Path, path: &str, side: ConflictSide) -> Result<()> {
+    let flag = match side {
+        ConflictSide::Ours => "--ours",
+        ConflictSide::Theirs => "--theirs",
+    };
+    run_git(repo, &["checkout", flag, "--", path])?;
+    mark_resolved(repo, path)
+}
+
+/// Stages `path` to mark its conflict resolved.
+pub fn mark_resolved(repo: &Path, path: &str) -> Result<()> {
+    run_git(repo, &["add", "--", path]).map(|_| ())
+}
+
+/// Commits the merge, cherry-pick or squash in progress with the message git
+/// prepared for it, once every conflict is resolved.
+pub fn conclude_merge(repo: &Path) -> Result<()> {
+    run_git(repo, &["commit", "--no-edit"]).map(|_| ())
+}
+
+/// Overwrites `paths` in the index and working tree with their content at the
+/// side-channel tip. Paths the tip lacks fail the checkout.
+pub fn checkout_side_channel_paths(
     repo: &Path,
     side: &SideChannelConfig,
+    paths: &[String],
+) -> Result<()> {
+    let tip = side_channel_tracking_ref(side);
+    let mut args = vec!["checkout", tip.as_str(), "--"];
+    args.extend(paths.iter().map(String::as_str));
+    run_git(repo, &args).map(|_| ())
+}
+
+pub fn push(repo: &Path, auth: &GitAuth) -> Result<()> {
+    run_git_network(repo, &["push"], auth).map(|_| ())
+}
+
+/// Fetches from `remote_name` without updating any refs, to time the round
+/// trip.
+pub fn fetch_dry_run(repo: &Path, remote_name: &str, auth: &GitAuth) -> Result<()> {
+    run_git_network(repo, &["fetch", "--dry-run", remote_name], auth).map(|_| ())
+}
+
+/// Negotiates a push with the upstream without sending anything, to time the
+/// round trip.
+pub fn push_dry_run(repo: &Path, auth: &GitAuth) -> Result<()> {
+    run_git_network(repo, &["push", "--dry-run"], auth).map(|_| ())
+}
+
+pub fn fetch(repo: &Path) -> Result<()> {
+    run_git(repo, &["fetch", "--all", "--prune"]).map(|_| ())
+}
+
+/// The installed git's version, such as `2.43.0`.
+pub fn version() -> Result<String> {
+    let out = run_git(Path::new("."), &["--version"])?;
+    let version = out.stdout.trim();
+    Ok(version
+        .strip_prefix("git version ")
+        .unwrap_or(version)
+        .to_string())
+}
+
+pub fn current_branch(repo: &Path) -> Result<Option<String>> {
+    let output = logging::output(
+        Command::new("git")
+            .args(["symbolic-ref", "--quiet", "--short", "HEAD"])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git symbolic-ref in {}", repo.display()))?;
+
+    if output.status.success() {
+        Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Counts paths with staged, unstaged, or untracked changes.
+pub fn changed_path_count(repo: &Path) -> Result<usize> {
+    let out = run_git(repo, &["status", "--porcelain"])?;
+    Ok(out.stdout.lines().filter(|line| !line.is_empty()).count())
+}
+
+/// The paths among `paths` (relative to the repository root) that differ
+/// from `HEAD` or are untracked and not ignored; a directory counts when
+/// anything under it does.
+pub fn changed_paths_among(repo: &Path, paths: &[String]) -> Result<Vec<String>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut args = vec![
+        "--literal-pathspecs",
+        "status",
+        "--porcelain",
+        "-z",
+        "--no-renames",
+        "--untracked-files=all",
+        "--",
+    ];
+    args.extend(paths.iter().map(String::as_str));
+    let out = run_git(repo, &args)?.stdout;
+    let changed = out
+        .split('\0')
+        .filter_map(|entry| entry.get(3..))
+        .collect::<Vec<_>>();
+    Ok(paths
+        .iter()
+        .filter(|path| {
+            changed.iter().any(|entry| {
+                entry == path
+                    || entry
+                        .strip_prefix(path.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+        .cloned()
+        .collect())
+}
+
+/// Paths with unmerged index entries, as a conflicted merge, rebase,
+/// cherry-pick or stash pop leaves them.
+pub fn unmerged_paths(repo: &Path) -> Result<Vec<String>> {
+    let out = run_git(repo, &["diff", "--name-only", "--diff-filter=U"])?;
+    Ok(out
+        .stdout
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Files whose uncommitted changes add a whole leftover conflict block, e.g.
+/// a conflict marked resolved with `git add` before it was edited. Only a
+/// `<<<<<<<`, `=======`, `>>>>>>>` sequence of added lines within one hunk
+/// counts, so a Markdown heading underlined with `=======` does not.
+pub fn conflict_marker_paths(repo: &Path) -> Result<Vec<String>> {
+    if rev_parse_optional(repo, "HEAD")?.is_none() {
+        return Ok(Vec::new());
+    }
+    let diff = run_git(
+        repo,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "diff",
+            "HEAD",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-renames",
+        ],
+    )?
+    .stdout;
+    Ok(conflict_blocks_in_diff(&diff))
+}
+
+/// The files in unified `diff` with a hunk adding a full conflict block.
+fn conflict_blocks_in_diff(diff: &str) -> Vec<String> {
+    let is_marker = |line: &str, marker: &str| {
+        line.strip_prefix(marker)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+    };
+    let mut paths = BTreeSet::new();
+    let mut path = None;
+    // 0: looking for `<<<<<<<`, 1: for `=======`, 2: for `>>>>>>>`.
+    let mut stage = 0;
+    for line in diff.lines() {
+        if let Some(new_path) = line.strip_prefix("+++ ") {
+            path = new_path.strip_prefix("b/").map(str::to_string);
+            stage = 0;
+            continue;
+        }
+        if line.starts_with("@@") || line.starts_with("diff ") {
+            stage = 0;
+            continue;
+        }
+        let Some(added) = line.strip_prefix('+') else {
+            continue;
+        };
+        let added = added.trim_end_matches('\r');
+        stage = match stage {
+            0 if is_marker(added, "<<<<<<<") => 1,
+            1 if added == "=======" => 2,
+            2 if is_marker(added, ">>>>>>>") => {
+                paths.extend(path.clone());
+                0
+            }
+            other => other,
+        };
+    }
+    paths.into_iter().collect()
+}
+
+/// Whether the working tree has changes and every one of them matches a
+/// `patterns` glob, such as a lockfile refreshed by a build. Untracked files are
+/// only considered with `include_untracked`, and those under `excludes` never.
+pub fn only_changes_match(
+    repo: &Path,
     include_untracked: bool,
-    message: &str,
+    patterns: &[String],
+    excludes: &[&str],
+) -> Result<bool> {
+    let untracked = if include_untracked {
+        "--untracked-files=all"
+    } else {
+        "--untracked-files=no"
+    };
+    let status = |globs: &mut dyn Iterator<Item = &str>| -> Result<bool> {
+        let pathspecs = globs
+            .map(|pattern| format!(":(exclude,glob){pattern}"))
+            .collect::<Vec<_>>();
+        let mut args = vec!["status", "--porcelain", untracked, "--", "."];
+        args.extend(pathspecs.iter().map(String::as_str));
+        Ok(!run_git(repo, &args)?.stdout.trim().is_empty())
+    };
+    let changed = status(&mut excludes.iter().copied())?;
+    if !changed {
+        return Ok(false);
+    }
+    let others = status(
+        &mut excludes
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str)),
+    )?;
+    Ok(!others)
+}
+
+/// The paths among `paths` (relative to the repository root) that git does
+/// not ignore.
+pub fn unignored_paths(repo: &Path, paths: &[String]) -> Result<Vec<String>> {
+    let mut cmd = Command::new("git");
+    cmd.args(["check-ignore", "-z", "--stdin"])
+        .current_dir(repo)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let started = Instant::now();
+    let output = cmd.spawn().and_then(|mut child| {
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(paths.join("\0").as_bytes())?;
+        }
+        child.wait_with_output()
+    });
+    logging::log_output(&cmd, started, &output);
+    let output =
+        output.with_context(|| format!("failed running git check-ignore in {}", repo.display()))?;
+    // `check-ignore` exits 1 when none of the paths are ignored.
+    let ignored = match output.status.code() {
+        Some(0) => String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .map(str::to_string)
+            .collect::<BTreeSet<_>>(),
+        Some(1) => BTreeSet::new(),
+        _ => bail!(
+            "git check-ignore failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    };
+    Ok(paths
+        .iter()
+        .filter(|path| !ignored.contains(*path))
+        .cloned()
+        .collect())
+}
+
+/// Paths whose staged content differs from `HEAD`.
+pub fn staged_paths(repo: &Path) -> Result<Vec<String>> {
+    let out = run_git(repo, &["diff", "--cached", "--name-only", "--no-renames"])?;
+    Ok(out.stdout.lines().map(str::to_string).collect())
+}
+
+/// Paths whose working-tree content differs from `HEAD`, plus untracked
+/// files that are not ignored when `include_untracked`.
+pub fn working_tree_changes(repo: &Path, include_untracked: bool) -> Result<Vec<String>> {
+    let out = run_git(repo, &["diff", "HEAD", "--name-only", "--no-renames"])?;
+    let mut paths = out.stdout.lines().map(str::to_string).collect::<Vec<_>>();
+    if include_untracked {
+        paths.extend(untracked_files(repo)?);
+    }
+    Ok(paths)
+}
+
+/// Lists untracked files that are not ignored, relative to the repository root.
+pub fn untracked_files(repo: &Path) -> Result<Vec<String>> {
+    let listed = run_git(repo, &["ls-files", "-z", "--others", "--exclude-standard"])?.stdout;
+    Ok(listed
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// Returns `(ahead, behind)` of `HEAD` relative to its upstream, or `None` when
+/// no upstream is configured.
+pub fn upstream_ahead_behind(repo: &Path) -> Result<Option<(usize, usize)>> {
+    if rev_parse_optional(repo, "@{upstream}")?.is_none() {
+        return Ok(None);
+    }
+
+    left_right_count(repo, "HEAD...@{upstream}").map(Some)
+}
+
+/// The root commit of `HEAD`'s history (the smallest hash when there are
+/// several), or `None` for a repository without commits.
+pub fn root_commit(repo: &Path) -> Result<Option<String>> {
+    if rev_parse_optional(repo, "HEAD")?.is_none() {
+        return Ok(None);
+    }
+    let out = run_git(repo, &["rev-list", "--max-parents=0", "HEAD"])?;
+    Ok(out.stdout.lines().min().map(str::to_string))
+}
+
+/// Counts the commits reachable from `range` (`rev` or `base..rev`).
+pub fn commit_count(repo: &Path, range: &str) -> Result<usize> {
+    let out = run_git(repo, &["rev-list", "--count", range])?;
+    out.stdout.trim().parse::<usize>().with_context(|| {
+        format!(
+            "unexpected git rev-list output in {}: {}",
+            repo.display(),
+            out.stdout.trim()
+        )
+    })
+}
+
+fn left_right_count(repo: &Path, range: &str) -> Result<(usize, usize)> {
+    let out = run_git(repo, &["rev-list", "--left-right", "--count", range])?;
+    let mut counts = out.stdout.split_whitespace().map(str::parse::<usize>);
+    match (counts.next(), counts.next()) {
+        (Some(Ok(left)), Some(Ok(right))) => Ok((left, right)),
+        _ => bail!(
+            "unexpected git rev-list output in {}: {}",
+            repo.display(),
+            out.stdout.trim()
+        ),
+    }
+}
+
+/// Counts commits only on the side-channel tip and only on the upstream of
+/// `HEAD`, as `(side_only, upstream_only)`. Returns `None` when either ref is
+/// missing locally.
+pub fn side_channel_divergence(
+    repo: &Path,
+    side: &SideChannelConfig,
+) -> Result<Option<(usize, usize)>> {
+    let side_ref = side_channel_tracking_ref(side);
+    if rev_parse_optional(repo, &side_ref)?.is_none()
+        || rev_parse_optional(repo, "@{upstream}")?.is_none()
+    {
+        return Ok(None);
+    }
+
+    left_right_count(repo, &format!("{side_ref}...@{{upstream}}")).map(Some)
+}
+
+/// Counts the side-channel commits missing from `HEAD` when they carry work
+/// this checkout lacks: paths the side channel changed since it forked from
+/// `HEAD` whose content differs from `local_tree` (the working tree as a
+/// snapshot would capture it), leaving out paths edited locally. Returns 0
+/// when there is no side-channel tip or nothing is missing.
+pub fn side_channel_pending(
+    repo: &Path,
+    side: &SideChannelConfig,
+    local_tree: &str,
+    auth: &GitAuth,
+) -> Result<usize> {
+    let side_ref = side_channel_tracking_ref(side);
+    if rev_parse_optional(repo, &side_ref)?.is_none() || is_ancestor(repo, &side_ref, "HEAD")? {
+        return Ok(0);
+    }
+
+    let changed = |from: &str, to: &str| -> Result<BTreeSet<String>> {
+        let out = run_git(repo, &["diff", "--name-only", "--no-renames", from, to])?;
+        Ok(out.stdout.lines().map(str::to_string).collect())
+    };
+    let base = merge_base(repo, "HEAD", &side_ref, &side.remote_name, auth)?;
+    let edited = changed("HEAD", local_tree)?;
+    let differing = changed(&side_ref, local_tree)?;
+    let missing = changed(&base, &side_ref)?
+        .into_iter()
+        .any(|path| differing.contains(&path) && !edited.contains(&path));
+    if !missing {
+        return Ok(0);
+    }
+    commit_count(repo, &format!("HEAD..{side_ref}"))
+}
+
+pub fn side_channel_sync(
+    repo: &Path,
+    side: &SideChannelConfig,
+    request: &SnapshotRequest<'_>,
 ) -> Result<SideChannelSyncResult> {
+    let SnapshotRequest {
+        include_untracked,
+        excludes,
+        force_include,
+        message,
+        auth,
+        last_snapshot,
+    } = *request;
     ensure_remote_exists(repo, &side.remote_name)?;
+    let remote_ref = side_channel_tracking_ref(side);
+    let destination_ref = side_channel_remote_ref(side);
+    let target = format!(
+        "git {} {} {destination_ref}",
+        side.remote_name,
+        remote_url(repo, &side.remote_name)?
+    );
 
     // Use a temporary index file so side-channel commits are produced from a
     // detached index snapshot instead of mutating/staging in the real worktree.
@@ -60,11 +852,16 @@ pub fn side_channel_sync(
     let index_path = temp_index.path().to_string_lossy().to_string();
     let env = [("GIT_INDEX_FILE", index_path.as_str())];
 
-    run_git_with_env(repo, &["read-tree", "HEAD"], &env)?;
-    if include_untracked {
-        run_git_with_env(repo, &["add", "-A"], &env)?;
-    } else {
-        run_git_with_env(repo, &["add", "-u"], &env)?;
+    seed_temp_index(repo, temp_index.path(), &env)?;
+    stage_with_env(
+        repo,
+        include_untracked,
+        side.snapshot_ignored,
+        excludes,
+        &env,
+    )?;
+    if !force_include.is_empty() {
+        force_add_ignored(repo, force_include, &env)?;
     }
 
     if !has_staged_changes_with_env(repo, &env)? {
@@ -75,13 +872,14 @@ pub fn side_channel_sync(
         .stdout
         .trim()
         .to_string();
+    if let Some(last) = last_snapshot
+        && last.target == target
+        && last.tree == local_tree
+        && side_tip_contains(repo, &remote_ref, last.tip.as_deref())?
+    {
+        return Ok(SideChannelSyncResult::Unchanged);
+    }
     let local_head = rev_parse(repo, "HEAD")?.trim().to_string();
-    let remote_ref = format!("{}/{}", side.remote_name, side.branch_name);
-    let destination_ref = if side.branch_name.starts_with("refs/") {
-        side.branch_name.clone()
-    } else {
-        format!("refs/heads/{}", side.branch_name)
-    };
     let mut did_retry = false;
     loop {
         let side_tip = rev_parse_optional(repo, &remote_ref)?;
@@ -90,15 +888,52 @@ pub fn side_channel_sync(
         } else {
             local_head.clone()
         };
-        let tree =
-            merge_side_tip_into_snapshot(repo, &local_head, &local_tree, side_tip.as_deref())?;
-        // Build a commit object directly from the temporary tree so HEAD stays put.
-        let commit_hash = commit_tree(repo, &tree, Some(parent.as_str()), message)?;
+        let tree = merge_side_tip_into_snapshot(
+            repo,
+            &local_head,
+            &local_tree,
+            side_tip.as_deref(),
+            side,
+            auth,
+        )?;
 
-        match push_side_channel_commit(repo, side, &destination_ref, &commit_hash)? {
-            SideChannelPushResult::Pushed => return Ok(SideChannelSyncResult::Pushed),
+        let previous = side_tip.as_deref().unwrap_or(&local_head);
+        if below_snapshot_threshold(repo, side, previous, &tree)? {
+            return Ok(SideChannelSyncResult::BelowThreshold);
+        }
+
+        // Record the snapshot's size in its own commit so growth stays visible
+        // in the side-channel history, not only in this run's summary.
+        let stats = snapshot_stats(repo, &tree)?;
+        let top_dirs = stats
+            .top_dirs
+            .iter()
+            .map(|(dir, _)| dir.as_str())
+            .collect::<Vec<_>>();
+        let mut message = message.to_string();
+        append_trailers(
+            &mut message,
+            &[
+                ("Snapshot-Files", stats.files.to_string()),
+                ("Snapshot-Bytes", stats.bytes.to_string()),
+                ("Snapshot-Top-Dirs", top_dirs.join(", ")),
+            ],
+        );
+
+        match push_snapshot(repo, side, &destination_ref, &parent, &tree, &message, auth)? {
+            SideChannelPushResult::Pushed => {
+                let tip = rev_parse(repo, &remote_ref)?.trim().to_string();
+                return Ok(SideChannelSyncResult::Pushed {
+                    stats,
+                    snapshot: LastSnapshot {
+                        target,
+                        tree: local_tree,
+                        tip: Some(tip),
+                    },
+                });
+            }
             SideChannelPushResult::NonFastForward if !did_retry => {
-                fetch_side_channel(repo, side)?;
+                fetch_side_channel(repo, side, auth)?;
                 did_retry = true;
             }
             SideChannelPushResult::NonFastForward => {
@@ -108,61 +943,715 @@ pub fn side_channel_sync(
     }
 }
 
+/// Whether the fetched side-channel tip at `remote_ref` still has the commit
+/// `tip`, so a branch reset or pruned since it was pushed is snapshot again.
+fn side_tip_contains(repo: &Path, remote_ref: &str, tip: Option<&str>) -> Result<bool> {
+    let Some(tip) = tip else {
+        return Ok(false);
+    };
+    if rev_parse_optional(repo, remote_ref)?.is_none()
+        || rev_parse_optional(repo, &format!("{tip}^{{commit}}"))?.is_none()
+    {
+        return Ok(false);
+    }
+    is_ancestor(repo, tip, remote_ref)
+}
+
+/// Whether going from `previous` to `tree` changes too little to be worth a
+/// snapshot under the side channel's `min_changed_lines`/`min_changed_files`.
+/// Whitespace-only edits count for nothing; binary files count as changed
+/// files. Without either threshold every change is worth a snapshot.
+pub fn below_snapshot_threshold(
+    repo: &Path,
+    side: &SideChannelConfig,
+    previous: &str,
+    tree: &str,
+) -> Result<bool> {
+    if side.min_changed_lines.is_none() && side.min_changed_files.is_none() {
+        return Ok(false);
+    }
+
+    let numstat = run_git(
+        repo,
+        &[
+            "diff",
+            "--numstat",
+            "--no-renames",
+            "--ignore-all-space",
+            "--ignore-blank-lines",
+            previous,
+            tree,
+        ],
+    )?
+    .stdout;
+    let (mut files, mut lines) = (0, 0);
+    for entry in numstat.lines() {
+        let mut fields = entry.split('\t');
+        let added = fields.next().unwrap_or_default();
+        let deleted = fields.next().unwrap_or_default();
+        match (added.parse::<usize>(), deleted.parse::<usize>()) {
+            (Ok(0), Ok(0)) => {}
+            (Ok(added), Ok(deleted)) => {
+                files += 1;
+                lines += added + deleted;
+            }
+            // Binary files report `-` for both counts.
+            _ => files += 1,
+        }
+    }
+
+    let lines_met = side.min_changed_lines.is_some_and(|min| lines >= min);
+    let files_met = side.min_changed_files.is_some_and(|min| files >= min);
+    Ok(!lines_met && !files_met)
+}
+
+/// Counts files and bytes in `tree`, grouping bytes by top-level directory.
+pub fn snapshot_stats(repo: &Path, tree: &str) -> Result<SnapshotStats> {
+    let listed = run_git(repo, &["ls-tree", "-r", "-l", "-z", tree])?.stdout;
+    let mut stats = SnapshotStats::default();
+    let mut dir_bytes = BTreeMap::<String, u64>::new();
+    for entry in listed.split('\0').filter(|entry| !entry.is_empty()) {
+        let Some((meta, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        // Submodule entries report their size as `-`.
+        let bytes = meta
+            .split_whitespace()
+            .nth(3)
+            .and_then(|size| size.parse::<u64>().ok())
+            .unwrap_or(0);
+        stats.files += 1;
+        stats.bytes += bytes;
+        if let Some((dir, _)) = path.split_once('/') {
+            *dir_bytes.entry(dir.to_string()).or_default() += bytes;
+        }
+    }
+
+    let mut top_dirs = dir_bytes.into_iter().collect::<Vec<_>>();
+    top_dirs.sort_by(|left, right| right.1.cmp(&left.1).then_with(|| left.0.cmp(&right.0)));
+    top_dirs.truncate(SNAPSHOT_TOP_DIRS);
+    stats.top_dirs = top_dirs;
+    Ok(stats)
+}
+
+/// Appended to the side-channel ref to name the ref chunked pushes stage on.
+const STAGING_SUFFIX: &str = ".partial";
+
+/// Commits `tree` on top of `parent` and pushes it to the side channel. When the
+/// snapshot touches more than `push_chunk_files` paths, all but the last chunk
+/// are pushed as a chain of partial commits to the staging ref
+/// `<destination_ref>.partial`, and the side channel only moves once the whole
+/// snapshot has landed, so no machine applies half of one. The local tracking
+/// ref of the staging ref records how far an interrupted upload got; the next
+/// sync builds on those chunks while they still sit on the same side tip and
+/// only pushes what is left.
+fn push_snapshot(
+    repo: &Path,
+    side: &SideChannelConfig,
+    destination_ref: &str,
+    parent: &str,
+    tree: &str,
+    message: &str,
+    auth: &GitAuth,
+) -> Result<SideChannelPushResult> {
+    let Some(chunk_size) = side.push_chunk_files else {
+        // Build a commit object directly from the temporary tree so HEAD stays put.
+        let commit_hash = commit_tree(repo, tree, Some(parent), message)?;
+        return push_side_channel_commit(repo, side, destination_ref, &commit_hash, auth);
+    };
+
+    let staging_ref = format!("{destination_ref}{STAGING_SUFFIX}");
+    let staging_tracking = format!("{}{STAGING_SUFFIX}", side_channel_tracking_ref(side));
+    let staged = rev_parse_optional(repo, &staging_tracking)?;
+    let start = match &staged {
+        Some(staged) if staged != parent && is_ancestor(repo, parent, staged)? => staged.clone(),
+        Some(_) | None => parent.to_string(),
+    };
+    let start_tree = rev_parse(repo, &format!("{start}^{{tree}}"))?;
+    let changed = run_git(
+        repo,
+        &[
+            "diff-tree",
+            "-r",
+            "-z",
+            "--no-renames",
+            "--name-only",
+            start_tree.trim(),
+            tree,
+        ],
+    )?
+    .stdout
+    .split('\0')
+    .filter(|path| !path.is_empty())
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+    let chunks = changed.chunks(chunk_size.max(1)).collect::<Vec<_>>();
+
+    let temp_index = tempfile::NamedTempFile::new().context("failed to allocate temp git index")?;
+    let index_path = temp_index.path().to_string_lossy().to_string();
+    let env = [("GIT_INDEX_FILE", index_path.as_str())];
+    run_git_with_env(repo, &["read-tree", &start], &env)?;
+
+    let total = chunks.len().max(1);
+    let mut tip = start;
+    for (idx, chunk) in chunks.iter().enumerate().take(total - 1) {
+        stage_paths_from_tree(repo, tree, chunk, &env)?;
+        let chunk_tree = run_git_with_env(repo, &["write-tree"], &env)?
+            .stdout
+            .trim()
+            .to_string();
+        // Keep the part marker on the subject line, ahead of any trailers.
+        let part = format!(" (part {}/{total})", idx + 1);
+        let chunk_message = match message.split_once('\n') {
+            Some((subject, rest)) => format!("{subject}{part}\n{rest}"),
+            None => format!("{message}{part}"),
+        };
+        tip = commit_tree(repo, &chunk_tree, Some(tip.as_str()), &chunk_message)?;
+        // The staging ref belongs to whichever machine pushed last, so it is
+        // overwritten rather than fast-forwarded.
+        run_git_network(
+            repo,
+            &["push", &side.remote_name, &format!("+{tip}:{staging_ref}")],
+            auth,
+        )
+        .with_context(|| {
+            format!("staged {idx} of {total} snapshot chunks on {staging_ref}; rerun to resume")
+        })?;
+        update_ref(repo, &staging_tracking, &tip)?;
+    }
+
+    let commit_hash = commit_tree(repo, tree, Some(tip.as_str()), message)?;
+    let pushed = push_side_channel_commit(repo, side, destination_ref, &commit_hash, auth)
+        .with_context(|| {
+            format!(
+                "staged {} of {total} snapshot chunks on {staging_ref}; rerun to resume",
+                total - 1
+            )
+        })?;
+    if matches!(pushed, SideChannelPushResult::Pushed)
+        && rev_parse_optional(repo, &staging_tracking)?.is_some()
+    {
+        // A staging ref left behind is harmless: it no longer sits on the side
+        // tip, so the next chunked push starts over.
+        if let Err(err) = run_git_network(
+            repo,
+            &["push", &side.remote_name, &format!(":{staging_ref}")],
+            auth,
+        ) {
+            tracing::warn!("could not delete {staging_ref}: {err:#}");
+        }
+        delete_ref(repo, &staging_tracking)?;
+    }
+    Ok(pushed)
+}
+
+/// Copies the entries for `paths` from `tree` into the index selected by `env`,
+/// removing paths that `tree` does not contain.
+fn stage_paths_from_tree(
+    repo: &Path,
+    tree: &str,
+    paths: &[String],
+    env: &[(&str, &str)],
+) -> Result<()> {
+    let mut args = vec!["--literal-pathspecs", "ls-tree", "-r", "-z", tree, "--"];
+    args.extend(paths.iter().map(String::as_str));
+    let listed = run_git(repo, &args)?.stdout;
+
+    let mut present = BTreeSet::new();
+    let mut index_info = String::new();
+    for entry in listed.split('\0').filter(|entry| !entry.is_empty()) {
+        if let Some((_, path)) = entry.split_once('\t') {
+            present.insert(path.to_string());
+        }
+        index_info.push_str(entry);
+        index_info.push('\0');
+    }
+    for path in paths.iter().filter(|path| !present.contains(*path)) {
+        index_info.push_str(&format!("0 {}\t{path}\0", "0".repeat(40)));
+    }
+
+    run_git_with_input(
+        repo,
+        &["update-index", "-z", "--index-info"],
+        env,
+        index_info.as_bytes(),
+    )
+    .map(|_| ())
+}
+
+/// Adds ignored files matching `patterns` to the index selected by `env`, like
+/// `git add -f`, without touching the repository's ignore rules.
+fn force_add_ignored(repo: &Path, patterns: &[String], env: &[(&str, &str)]) -> Result<()> {
+    let mut args = vec![
+        "ls-files",
+        "-z",
+        "--others",
+        "--ignored",
+        "--exclude-standard",
+        "--",
+    ];
+    let pathspecs = patterns
+        .iter()
+        .map(|pattern| format!(":(glob){pattern}"))
+        .collect::<Vec<_>>();
+    args.extend(pathspecs.iter().map(String::as_str));
+
+    let listed = run_git_with_env(repo, &args, env)?.stdout;
+    let files = listed
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let mut add_args = vec!["add", "-f", "--"];
+    add_args.extend(files);
+    run_git_with_env(repo, &add_args, env).map(|_| ())
+}
+
+/// Name of the remote the current branch tracks, if any.
+pub fn upstream_remote(repo: &Path) -> Result<Option<String>> {
+    let Some(branch) = current_branch(repo)? else {
+        return Ok(None);
+    };
+    let out = run_git(
+        repo,
+        &[
+            "for-each-ref",
+            "--format=%(upstream:remotename)",
+            &format!("refs/heads/{branch}"),
+        ],
+    )?;
+    let remote = out.stdout.trim();
+    Ok((!remote.is_empty()).then(|| remote.to_string()))
+}
+
+/// Asks `remote_name` for its `HEAD` with `git ls-remote`, giving up after
+/// `timeout` instead of waiting for the transport's own (often minutes long)
+/// connect timeout. Only a timeout or a failure to resolve or connect is an
+/// error: a remote that answers but rejects the credentials from `auth` is
+/// reachable, and the sync itself reports (and retries) the auth failure.
+pub fn probe_remote(
+    repo: &Path,
+    remote_name: &str,
+    timeout: Duration,
+    auth: &GitAuth,
+) -> Result<()> {
+    // Never prompt: probes run in parallel before any repository syncs.
+    let auth = GitAuth {
+        mode: AuthMode::NoPrompt,
+        credentials: auth.credentials.clone(),
+    };
+    let mut child = network_command(repo, &["ls-remote", remote_name, "HEAD"], &auth)?
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed running git ls-remote in {}", repo.display()))?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+            let output = child.wait_with_output()?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if let Some(line) = stderr.lines().find(|line| is_connection_error(line)) {
+                bail!("{}", line.trim());
+            }
+            tracing::debug!(remote_name, stderr = %stderr.trim(), "remote answered the probe with an error");
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            // Ignore kill errors: the child may have exited since `try_wait`.
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("no response within {}s", timeout.as_secs());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Whether a line of git or ssh output says the host could not be resolved or
+/// reached, as opposed to the remote refusing the request.
+fn is_connection_error(line: &str) -> bool {
+    const MARKERS: [&str; 11] = [
+        "Could not resolve host",
+        "Could not resolve hostname",
+        "Temporary failure in name resolution",
+        "Name or service not known",
+        "Connection refused",
+        "Connection timed out",
+        "Operation timed out",
+        "Network is unreachable",
+        "No route to host",
+        "Failed to connect",
+        "Couldn't connect to server",
+    ];
+    MARKERS.iter().any(|marker| line.contains(marker))
+}
+
+/// Commits the working tree (tracked changes, plus untracked files when
+/// `include_untracked`) on top of `HEAD` using a temporary index, leaving the
+/// real index and `HEAD` alone. Returns `None` when nothing differs from `HEAD`.
+pub fn snapshot_commit(
+    repo: &Path,
+    include_untracked: bool,
+    excludes: &[&str],
+    message: &str,
+) -> Result<Option<String>> {
+    let Some(tree) = snapshot_tree(repo, include_untracked, false, excludes, &[])? else {
+        return Ok(None);
+    };
+    let head = rev_parse(repo, "HEAD")?.trim().to_string();
+    commit_tree(repo, &tree, Some(&head), message).map(Some)
+}
+
+/// Fills the temporary index at `index_path` with `HEAD`. With an untracked
+/// cache, it starts from a copy of the real index and resets that to `HEAD`,
+/// which keeps the cache and the stat data of unchanged files, so staging
+/// need not rescan and rehash the whole tree.
+fn seed_temp_index(repo: &Path, index_path: &Path, env: &[(&str, &str)]) -> Result<()> {
+    let real_index = git_path(repo, "index")?;
+    if untracked_cache_enabled(repo)?
+        && real_index.exists()
+        && fs::copy(&real_index, index_path).is_ok()
+        && run_git_with_env(repo, &["read-tree", "-m", "HEAD"], env).is_ok()
+    {
+        return Ok(());
+    }
+    run_git_with_env(repo, &["read-tree", "HEAD"], env).map(|_| ())
+}
+
+/// Writes the working tree as a tree object the way a side-channel snapshot
+/// stages it, without touching the real index. Returns `None` when nothing
+/// differs from `HEAD`.
+pub fn snapshot_tree(
+    repo: &Path,
+    include_untracked: bool,
+    include_ignored: bool,
+    excludes: &[&str],
+    force_include: &[String],
+) -> Result<Option<String>> {
+    let temp_index = tempfile::NamedTempFile::new().context("failed to allocate temp git index")?;
+    let index_path = temp_index.path().to_string_lossy().to_string();
+    let env = [("GIT_INDEX_FILE", index_path.as_str())];
+
+    seed_temp_index(repo, temp_index.path(), &env)?;
+    stage_with_env(repo, include_untracked, include_ignored, excludes, &env)?;
+    if !force_include.is_empty() {
+        force_add_ignored(repo, force_include, &env)?;
+    }
+    if !has_staged_changes_with_env(repo, &env)? {
+        return Ok(None);
+    }
+
+    run_git_with_env(repo, &["write-tree"], &env).map(|out| Some(out.stdout.trim().to_string()))
+}
+
+pub fn update_ref(repo: &Path, name: &str, target: &str) -> Result<()> {
+    run_git(repo, &["update-ref", name, target]).map(|_| ())
+}
+
+pub fn delete_ref(repo: &Path, name: &str) -> Result<()> {
+    run_git(repo, &["update-ref", "-d", name]).map(|_| ())
+}
+
+/// Writes a git bundle of `revs` (refs to include, `^rev` to exclude).
+pub fn create_bundle(repo: &Path, path: &Path, revs: &[String]) -> Result<()> {
+    let path = path.to_string_lossy();
+    let mut args = vec!["bundle", "create", "--quiet", path.as_ref()];
+    args.extend(revs.iter().map(String::as_str));
+    run_git(repo, &args).map(|_| ())
+}
+
+pub fn fetch_bundle(repo: &Path, bundle: &Path, refspecs: &[String]) -> Result<()> {
+    let bundle = bundle.to_string_lossy();
+    let mut args = vec!["fetch", "--quiet", "--no-tags", bundle.as_ref()];
+    args.extend(refspecs.iter().map(String::as_str));
+    run_git(repo, &args).map(|_| ())
+}
+
+pub fn merge_ff_only(repo: &Path, rev: &str) -> Result<()> {
+    run_git(repo, &["merge", "--ff-only", "--quiet", rev]).map(|_| ())
+}
+
+/// Applies a [`snapshot_commit`] to the working tree as uncommitted changes.
+pub fn restore_snapshot(repo: &Path, snapshot: &str) -> Result<()> {
+    run_git(repo, &["cherry-pick", "--no-commit", snapshot])?;
+    run_git(repo, &["reset", "--quiet"]).map(|_| ())
+}
+
+pub fn remote_url(repo: &Path, remote_name: &str) -> Result<String> {
+    Ok(run_git(repo, &["remote", "get-url", remote_name])?
+        .stdout
+        .trim()
+        .to_string())
+}
+
+/// Lists configured remotes as `(name, url)` pairs.
+pub fn remotes(repo: &Path) -> Result<Vec<(String, String)>> {
+    run_git(repo, &["remote"])?
+        .stdout
+        .lines()
+        .map(|name| Ok((name.to_string(), remote_url(repo, name)?)))
+        .collect()
+}
+
+pub fn set_remote_url(repo: &Path, remote_name: &str, url: &str) -> Result<()> {
+    run_git(repo, &["remote", "set-url", remote_name, url]).map(|_| ())
+}
+
+pub fn remove_remote(repo: &Path, remote_name: &str) -> Result<()> {
+    run_git(repo, &["remote", "remove", remote_name]).map(|_| ())
+}
+
+pub fn rename_remote(repo: &Path, from: &str, to: &str) -> Result<()> {
+    run_git(repo, &["remote", "rename", from, to]).map(|_| ())
+}
+
+/// Creates a bare repository at `path` (relative paths resolve against `repo`).
+pub fn init_bare(repo: &Path, path: &Path) -> Result<()> {
+    let path = path.to_string_lossy();
+    run_git(repo, &["init", "--bare", "--quiet", &path]).map(|_| ())
+}
+
+/// Runs `git gc` inside a bare repository to drop unreachable objects now.
+pub fn gc_prune_now(git_dir: &Path) -> Result<()> {
+    run_git(git_dir, &["gc", "--prune=now", "--quiet"]).map(|_| ())
+}
+
 pub fn ensure_remote_exists(repo: &Path, remote_name: &str) -> Result<()> {
     run_git(repo, &["remote", "get-url", remote_name])
         .with_context(|| format!("missing side-channel remote '{remote_name}'"))
         .map(|_| ())
 }
 
-pub fn generate_commit_message(template: &str, include_untracked: bool) -> String {
-    let ts = Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string();
+/// Placeholder for the top-level paths a commit or snapshot changes.
+pub const CHANGED_PATHS_PLACEHOLDER: &str = "{changed_paths}";
+
+/// Placeholder for every changed file, one `- path` line each, in a body.
+pub const CHANGED_FILES_PLACEHOLDER: &str = "{changed_files}";
+
+/// How many top-level paths `{changed_paths}` names before counting the rest.
+const CHANGED_PATHS_LIMIT: usize = 5;
+
+/// How many files `{changed_files}` lists before counting the rest.
+const CHANGED_FILES_LIMIT: usize = 50;
+
+/// Renders the subject `template`, with `{timestamp}` in `timestamp`'s style,
+/// then `body_template` wrapped at
+/// `wrap_width` columns (`0` does not wrap) after a blank line, then
+/// `trailers`. `changed_paths` fills `{changed_paths}` with the top-level
+/// entries they fall under, directories marked with a trailing `/`, and
+/// `{changed_files}` with the paths themselves.
+pub fn generate_commit_message(
+    template: &str,
+    body_template: Option<&str>,
+    wrap_width: usize,
+    timestamp: &TimestampStyle,
+    trailers: &BTreeMap<String, String>,
+    include_untracked: bool,
+    changed_paths: &[String],
+) -> String {
+    let ts = timestamp.now();
     let host = hostname::get()
         .unwrap_or_default()
         .to_string_lossy()
         .to_string();
     let scope = if include_untracked { "all" } else { "tracked" };
+    let top_level = changed_paths
+        .iter()
+        .map(|path| match path.split_once('/') {
+            Some((dir, _)) => format!("{dir}/"),
+            None => path.clone(),
+        })
+        .collect::<BTreeSet<_>>();
+    let mut changed = top_level
+        .iter()
+        .take(CHANGED_PATHS_LIMIT)
+        .cloned()
+        .collect::<Vec<_>>();
+    if top_level.len() > CHANGED_PATHS_LIMIT {
+        changed.push(format!("+{} more", top_level.len() - CHANGED_PATHS_LIMIT));
+    }
+    let changed = changed.join(", ");
+    let mut files = changed_paths
+        .iter()
+        .take(CHANGED_FILES_LIMIT)
+        .map(|path| format!("- {path}"))
+        .collect::<Vec<_>>();
+    if changed_paths.len() > CHANGED_FILES_LIMIT {
+        files.push(format!(
+            "- +{} more",
+            changed_paths.len() - CHANGED_FILES_LIMIT
+        ));
+    }
+    let files = files.join("\n");
+    let render = |text: &str| {
+        text.replace("{timestamp}", &ts)
+            .replace(CHANGED_PATHS_PLACEHOLDER, &changed)
+            .replace(CHANGED_FILES_PLACEHOLDER, &files)
+            .replace("{hostname}", &host)
+            .replace("{scope}", scope)
+            .replace("{version}", env!("CARGO_PKG_VERSION"))
+    };
 
-    template
-        .replace("{timestamp}", &ts)
-        .replace("{hostname}", &host)
-        .replace("{scope}", scope)
+    let mut message = render(template);
+    if let Some(body) = body_template.map(render)
+        && !body.trim().is_empty()
+    {
+        message.push_str("\n\n");
+        message.push_str(&wrap_body(body.trim_end(), wrap_width));
+    }
+    let trailers = trailers
+        .iter()
+        .map(|(key, value)| (key.as_str(), render(value)))
+        .collect::<Vec<_>>();
+    append_trailers(&mut message, &trailers);
+    message
 }
 
-pub fn fetch_side_channel(repo: &Path, side: &SideChannelConfig) -> Result<()> {
+/// Adds `Key: value` lines to `message` as git trailers: joined onto its last
+/// paragraph when that already is a trailer block, otherwise after a blank
+/// line so they stay out of the subject and body.
+pub fn append_trailers(message: &mut String, trailers: &[(&str, String)]) {
+    if trailers.is_empty() {
+        return;
+    }
+    let in_trailer_block = message
+        .trim_end()
+        .rsplit_once("\n\n")
+        .is_some_and(|(_, last)| last.lines().all(is_trailer_line));
+    let trimmed = message.trim_end().len();
+    message.truncate(trimmed);
+    message.push_str(if in_trailer_block { "\n" } else { "\n\n" });
+    let lines = trailers
+        .iter()
+        .map(|(key, value)| format!("{key}: {value}"))
+        .collect::<Vec<_>>();
+    message.push_str(&lines.join("\n"));
+}
+
+fn is_trailer_line(line: &str) -> bool {
+    line.split_once(": ").is_some_and(|(key, _)| {
+        !key.is_empty()
+            && key
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+    })
+}
+
+/// Breaks lines of `body` longer than `width` at spaces. Continuations of a
+/// `- ` list item are indented under its text; words longer than `width`
+/// stay whole.
+fn wrap_body(body: &str, width: usize) -> String {
+    if width == 0 {
+        return body.to_string();
+    }
+    let mut wrapped = Vec::new();
+    for line in body.lines() {
+        if line.chars().count() <= width {
+            wrapped.push(line.to_string());
+            continue;
+        }
+        let (mut current, text, indent) = match line.strip_prefix("- ") {
+            Some(text) => ("- ".to_string(), text, "  "),
+            None => (String::new(), line, ""),
+        };
+        let mut has_words = false;
+        for word in text.split(' ').filter(|word| !word.is_empty()) {
+            if has_words && current.chars().count() + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::replace(&mut current, indent.to_string()));
+                has_words = false;
+            }
+            if has_words {
+                current.push(' ');
+            }
+            current.push_str(word);
+            has_words = true;
+        }
+        wrapped.push(current);
+    }
+    wrapped.join("\n")
+}
+
+pub fn fetch_side_channel(repo: &Path, side: &SideChannelConfig, auth: &GitAuth) -> Result<()> {
     ensure_remote_exists(repo, &side.remote_name)?;
-    run_git(repo, &["fetch", &side.remote_name, &side.branch_name]).map(|_| ())
+    let refspec = format!(
+        "+{}:{}",
+        side_channel_remote_ref(side),
+        side_channel_tracking_ref(side)
+    );
+    run_git_network(repo, &["fetch", &side.remote_name, &refspec], auth).map(|_| ())
 }
 
 pub fn merge_side_channel_ff(repo: &Path, side: &SideChannelConfig) -> Result<()> {
     run_git(
         repo,
-        &[
-            "merge",
-            "--ff-only",
-            &format!("{}/{}", side.remote_name, side.branch_name),
-        ],
+        &["merge", "--ff-only", &side_channel_tracking_ref(side)],
     )
     .map(|_| ())
 }
 
+/// Replays the current branch's own commits on top of the side-channel tip,
+/// aborting the rebase if it stops on a conflict.
+pub fn rebase_onto_side_channel(repo: &Path, side: &SideChannelConfig) -> Result<()> {
+    let tip = side_channel_tracking_ref(side);
+    run_git(repo, &["rebase", &tip])
+        .map(|_| ())
+        .inspect_err(|_| {
+            let _ = run_git(repo, &["rebase", "--abort"]);
+        })
+}
+
+/// Merges the side-channel tip with a merge commit, aborting the merge if it
+/// conflicts.
+pub fn merge_side_channel_commit(repo: &Path, side: &SideChannelConfig) -> Result<()> {
+    let tip = side_channel_tracking_ref(side);
+    run_git(repo, &["merge", "--no-edit", &tip])
+        .map(|_| ())
+        .inspect_err(|_| {
+            let _ = run_git(repo, &["merge", "--abort"]);
+        })
+}
+
+/// Merges the side-channel tip with a merge commit. A conflicted merge is left
+/// in progress, with conflict markers in the working tree, and its conflicted
+/// paths are returned; other failures leave nothing behind.
+pub fn merge_side_channel_keep_conflicts(
+    repo: &Path,
+    side: &SideChannelConfig,
+) -> Result<Vec<String>> {
+    let tip = side_channel_tracking_ref(side);
+    match run_git(repo, &["merge", "--no-edit", &tip]) {
+        Ok(_) => Ok(Vec::new()),
+        Err(err) => {
+            let conflicted = unmerged_paths(repo)?;
+            if conflicted.is_empty() {
+                return Err(err);
+            }
+            Ok(conflicted)
+        }
+    }
+}
+
 pub fn cherry_pick_side_channel_tip(repo: &Path, side: &SideChannelConfig) -> Result<()> {
-    let commit = rev_parse(repo, &format!("{}/{}", side.remote_name, side.branch_name))?;
+    let commit = rev_parse(repo, &side_channel_tracking_ref(side))?;
     run_git(repo, &["cherry-pick", commit.trim()]).map(|_| ())
 }
 
 pub fn squash_merge_side_channel(repo: &Path, side: &SideChannelConfig) -> Result<()> {
     run_git(
         repo,
-        &[
-            "merge",
-            "--squash",
-            &format!("{}/{}", side.remote_name, side.branch_name),
-        ],
+        &["merge", "--squash", &side_channel_tracking_ref(side)],
     )
     .map(|_| ())
 }
 
-fn rev_parse(repo: &Path, rev: &str) -> Result<String> {
+pub fn rev_parse(repo: &Path, rev: &str) -> Result<String> {
     let out = run_git(repo, &["rev-parse", rev])?;
     Ok(out.stdout)
 }
@@ -172,6 +1661,8 @@ fn merge_side_tip_into_snapshot(
     local_head: &str,
     local_tree: &str,
     side_tip: Option<&str>,
+    side: &SideChannelConfig,
+    auth: &GitAuth,
 ) -> Result<String> {
     let Some(side_tip) = side_tip else {
         return Ok(local_tree.to_string());
@@ -181,7 +1672,7 @@ fn merge_side_tip_into_snapshot(
         return Ok(local_tree.to_string());
     }
 
-    let base = merge_base(repo, local_head, side_tip)?;
+    let base = merge_base(repo, local_head, side_tip, &side.remote_name, auth)?;
     let local_commit = commit_tree(
         repo,
         local_tree,
@@ -189,8 +1680,34 @@ fn merge_side_tip_into_snapshot(
         "shephard side-channel local snapshot",
     )?;
 
-    let output = Command::new("git")
-        .args([
+    // Configured merge drivers are passed as a throwaway global attributes file
+    // holding the user's own global attributes followed by the driver rules,
+    // which win over them. The repository's .gitattributes stays untouched and
+    // still wins over both.
+    let attributes =
+        tempfile::NamedTempFile::new().context("failed to allocate temp git attributes file")?;
+    let mut cmd = Command::new("git");
+    if !side.merge_drivers.is_empty() {
+        let mut rules = match global_attributes_file(repo)? {
+            Some(file) => fs::read_to_string(&file).unwrap_or_default(),
+            None => String::new(),
+        };
+        if !rules.is_empty() && !rules.ends_with('\n') {
+            rules.push('\n');
+        }
+        rules.extend(
+            side.merge_drivers
+                .iter()
+                .map(|(pattern, driver)| format!("{pattern} merge={driver}\n")),
+        );
+        fs::write(attributes.path(), rules).context("failed to write temp git attributes")?;
+        cmd.arg("-c").arg(format!(
+            "core.attributesFile={}",
+            attributes.path().display()
+        ));
+    }
+    let output = logging::output(
+        cmd.args([
             "merge-tree",
             "--write-tree",
             "--merge-base",
@@ -198,9 +1715,9 @@ fn merge_side_tip_into_snapshot(
             &local_commit,
             side_tip,
         ])
-        .current_dir(repo)
-        .output()
-        .with_context(|| format!("failed running git merge-tree in {}", repo.display()))?;
+        .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git merge-tree in {}", repo.display()))?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -213,8 +1730,14 @@ fn merge_side_tip_into_snapshot(
                 conflicts.join(", ")
             );
         }
+        let hint = if is_partial_clone(repo)? {
+            "; this is a partial clone, so git fetches missing file contents from its \
+             promisor remote while merging, which has to be reachable"
+        } else {
+            ""
+        };
         bail!(
-            "git merge-tree failed in {} while combining local changes with remote tip {}: {} {}",
+            "git merge-tree failed in {} while combining local changes with remote tip {}: {} {}{hint}",
             repo.display(),
             side_tip,
             stderr.trim(),
@@ -232,19 +1755,88 @@ fn merge_side_tip_into_snapshot(
     }
 }
 
-fn merge_base(repo: &Path, left: &str, right: &str) -> Result<String> {
-    Ok(run_git(repo, &["merge-base", left, right])?
+/// The best common ancestor of `left` and `right`. When a shallow clone's
+/// history stops before they meet, it is deepened from its upstream remote
+/// (else `fallback_remote`) a few times before giving up with a hint.
+fn merge_base(
+    repo: &Path,
+    left: &str,
+    right: &str,
+    fallback_remote: &str,
+    auth: &GitAuth,
+) -> Result<String> {
+    for attempt in 0..=DEEPEN_ATTEMPTS {
+        if let Some(base) = find_merge_base(repo, left, right)? {
+            return Ok(base);
+        }
+        if !is_shallow(repo)? {
+            bail!("{left} and {right} share no history in {}", repo.display());
+        }
+        if attempt == DEEPEN_ATTEMPTS {
+            break;
+        }
+        let remote = upstream_remote(repo)?.unwrap_or_else(|| fallback_remote.to_string());
+        let deepen = format!("--deepen={DEEPEN_STEP}");
+        run_git_network(repo, &["fetch", "--quiet", &deepen, &remote], auth).with_context(
+            || {
+                format!(
+                    "failed deepening shallow clone {} from {remote}",
+                    repo.display()
+                )
+            },
+        )?;
+    }
+    bail!(
+        "{} is a shallow clone whose history still ends before {left} and {right} meet, {} commits deeper; run `git fetch --unshallow` there",
+        repo.display(),
+        DEEPEN_STEP * DEEPEN_ATTEMPTS
+    )
+}
+
+fn find_merge_base(repo: &Path, left: &str, right: &str) -> Result<Option<String>> {
+    let output = logging::output(
+        Command::new("git")
+            .args(["merge-base", left, right])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git merge-base in {}", repo.display()))?;
+
+    // Exit 1 without output means no common ancestor is known.
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        )),
+        Some(1) => Ok(None),
+        _ => bail!(
+            "git merge-base failed in {}: {}",
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// Whether `repo` was cloned or fetched with `--depth`, so its history is cut
+/// off at some commits.
+pub fn is_shallow(repo: &Path) -> Result<bool> {
+    Ok(run_git(repo, &["rev-parse", "--is-shallow-repository"])?
         .stdout
         .trim()
-        .to_string())
+        == "true")
 }
 
-fn is_ancestor(repo: &Path, ancestor: &str, descendant: &str) -> Result<bool> {
-    let output = Command::new("git")
-        .args(["merge-base", "--is-ancestor", ancestor, descendant])
-        .current_dir(repo)
-        .output()
-        .with_context(|| format!("failed running git merge-base in {}", repo.display()))?;
+/// Whether `repo` was cloned with `--filter`, so some objects are only
+/// fetched from its promisor remote when needed.
+pub fn is_partial_clone(repo: &Path) -> Result<bool> {
+    Ok(config_value(repo, "extensions.partialClone")?.is_some())
+}
+
+pub fn is_ancestor(repo: &Path, ancestor: &str, descendant: &str) -> Result<bool> {
+    let output = logging::output(
+        Command::new("git")
+            .args(["merge-base", "--is-ancestor", ancestor, descendant])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git merge-base in {}", repo.display()))?;
 
     match output.status.code() {
         Some(0) => Ok(true),
@@ -275,18 +1867,23 @@ fn push_side_channel_commit(
     side: &SideChannelConfig,
     destination_ref: &str,
     commit_hash: &str,
+    auth: &GitAuth,
 ) -> Result<SideChannelPushResult> {
-    let output = Command::new("git")
-        .args([
+    let output = logging::output(&mut network_command(
+        repo,
+        &[
             "push",
             &side.remote_name,
             &format!("{commit_hash}:{destination_ref}"),
-        ])
-        .current_dir(repo)
-        .output()
-        .with_context(|| format!("failed running git push in {}", repo.display()))?;
+        ],
+        auth,
+    )?)
+    .with_context(|| format!("failed running git push in {}", repo.display()))?;
 
     if output.status.success() {
+        // git only moves tracking refs its fetch refspec covers, which leaves
+        // out full-ref side channels.
+        update_ref(repo, &side_channel_tracking_ref(side), commit_hash)?;
         return Ok(SideChannelPushResult::Pushed);
     }
 
@@ -300,12 +1897,35 @@ fn push_side_channel_commit(
     bail!("git push failed in {}: {}", repo.display(), combined.trim())
 }
 
-fn rev_parse_optional(repo: &Path, rev: &str) -> Result<Option<String>> {
-    let output = Command::new("git")
-        .args(["rev-parse", "--verify", "--quiet", rev])
-        .current_dir(repo)
-        .output()
-        .with_context(|| format!("failed running git rev-parse in {}", repo.display()))?;
+/// Path of the repository's `index.lock`, held while another git process
+/// (an IDE, a concurrent shephard run) is updating the index.
+pub fn index_lock_path(repo: &Path) -> Result<PathBuf> {
+    git_path(repo, "index.lock")
+}
+
+/// Resolves `name` inside the repository's git directory the way git does,
+/// honoring worktrees and `core.hooksPath` for `hooks`.
+pub fn git_path(repo: &Path, name: &str) -> Result<PathBuf> {
+    let path = run_git(repo, &["rev-parse", "--git-path", name])?;
+    Ok(repo.join(path.stdout.trim()))
+}
+
+/// Committer time of `rev` in Unix seconds.
+pub fn commit_time(repo: &Path, rev: &str) -> Result<i64> {
+    let out = run_git(repo, &["log", "-1", "--format=%ct", rev])?;
+    out.stdout
+        .trim()
+        .parse()
+        .with_context(|| format!("unexpected commit time for {rev} in {}", repo.display()))
+}
+
+pub fn rev_parse_optional(repo: &Path, rev: &str) -> Result<Option<String>> {
+    let output = logging::output(
+        Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", rev])
+            .current_dir(repo),
+    )
+    .with_context(|| format!("failed running git rev-parse in {}", repo.display()))?;
 
     if output.status.success() {
         Ok(Some(
@@ -316,7 +1936,7 @@ fn rev_parse_optional(repo: &Path, rev: &str) -> Result<Option<String>> {
     }
 }
 
-fn commit_tree(repo: &Path, tree: &str, parent: Option<&str>, message: &str) -> Result<String> {
+pub fn commit_tree(repo: &Path, tree: &str, parent: Option<&str>, message: &str) -> Result<String> {
     let mut cmd = Command::new("git");
     cmd.current_dir(repo);
     cmd.arg("commit-tree").arg(tree).arg("-m").arg(message);
@@ -324,8 +1944,7 @@ fn commit_tree(repo: &Path, tree: &str, parent: Option<&str>, message: &str) ->
         cmd.arg("-p").arg(parent);
     }
 
-    let output = cmd
-        .output()
+    let output = logging::output(&mut cmd)
         .with_context(|| format!("failed running git commit-tree in {}", repo.display()))?;
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -366,15 +1985,97 @@ fn run_git(repo: &Path, args: &[&str]) -> Result<GitOutput> {
 }
 
 fn run_git_with_env(repo: &Path, args: &[&str], env: &[(&str, &str)]) -> Result<GitOutput> {
+    run_git_with_input(repo, args, env, &[])
+}
+
+/// Runs a git command that talks to a remote, prompting for credentials only
+/// as `auth` allows.
+fn run_git_network(repo: &Path, args: &[&str], auth: &GitAuth) -> Result<GitOutput> {
+    git_output(network_command(repo, args, auth)?, repo, args, &[])
+}
+
+/// Builds a git command that talks to a remote. `NoPrompt` turns off git's
+/// credential prompts and runs ssh in batch mode, so a missing passphrase fails
+/// the command instead of waiting; `Terminal` hands git the terminal's stdin.
+/// A configured credential helper replaces the ones from git config, and a
+/// configured askpass program answers both git's and ssh's prompts.
+fn network_command(repo: &Path, args: &[&str], auth: &GitAuth) -> Result<Command> {
+    let mut cmd = Command::new("git");
+    if let Some(helper) = &auth.credentials.credential_helper {
+        cmd.args(["-c", "credential.helper=", "-c"])
+            .arg(format!("credential.helper={helper}"));
+    }
+    cmd.args(args).current_dir(repo);
+    if let Some(askpass) = &auth.credentials.askpass {
+        cmd.env("GIT_ASKPASS", askpass)
+            .env("SSH_ASKPASS", askpass)
+            .env("SSH_ASKPASS_REQUIRE", "force");
+    }
+    match auth.mode {
+        AuthMode::Default => {}
+        // Batch mode would also stop ssh from running the askpass program.
+        AuthMode::NoPrompt if auth.credentials.askpass.is_some() => {
+            cmd.env("GIT_TERMINAL_PROMPT", "0");
+        }
+        AuthMode::NoPrompt => {
+            let ssh = match std::env::var("GIT_SSH_COMMAND") {
+                Ok(command) if !command.trim().is_empty() => command,
+                Ok(_) | Err(_) => {
+                    let configured = logging::output(
+                        Command::new("git")
+                            .args(["config", "--get", "core.sshCommand"])
+                            .current_dir(repo),
+                    )
+                    .with_context(|| format!("failed running git config in {}", repo.display()))?;
+                    match String::from_utf8_lossy(&configured.stdout).trim() {
+                        "" => "ssh".to_string(),
+                        command => command.to_string(),
+                    }
+                }
+            };
+            cmd.env("GIT_TERMINAL_PROMPT", "0")
+                .env("GIT_SSH_COMMAND", format!("{ssh} -o BatchMode=yes"));
+        }
+        AuthMode::Terminal => {
+            cmd.env_remove("GIT_TERMINAL_PROMPT")
+                .stdin(Stdio::inherit());
+        }
+    }
+    Ok(cmd)
+}
+
+fn run_git_with_input(
+    repo: &Path,
+    args: &[&str],
+    env: &[(&str, &str)],
+    input: &[u8],
+) -> Result<GitOutput> {
     let mut cmd = Command::new("git");
     cmd.args(args).current_dir(repo);
     for (key, value) in env {
         cmd.env(key, value);
     }
+    git_output(cmd, repo, args, input)
+}
 
-    let output = cmd
-        .output()
-        .with_context(|| format!("failed running git {:?} in {}", args, repo.display()))?;
+fn git_output(mut cmd: Command, repo: &Path, args: &[&str], input: &[u8]) -> Result<GitOutput> {
+    let output = if input.is_empty() {
+        logging::output(&mut cmd)
+    } else {
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let started = Instant::now();
+        let output = cmd.spawn().and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input)?;
+            }
+            child.wait_with_output()
+        });
+        logging::log_output(&cmd, started, &output);
+        output
+    }
+    .with_context(|| format!("failed running git {:?} in {}", args, repo.display()))?;
 
     if !output.status.success() {
         let stdout = String::from_utf8_lossy(&output.stdout);
@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::config::GitBackendKind;
+use crate::git;
+
+/// Read-only repository queries for `status` and `check`, answered by running `git` or, with the `gix` feature, in-process with
+/// gitoxide. The sync workflow does not go through this trait: staging,
+/// merging, committing and network commands are free functions in `git` that
+/// always run `git`. An implementation only overrides the queries it answers
+/// natively and inherits `git` for the rest.
+pub trait GitBackend {
+    /// The checked-out branch, or `None` on a detached `HEAD`.
+    fn current_branch(&self, repo: &Path) -> Result<Option<String>> {
+        git::current_branch(repo)
+    }
+
+    /// How many commits `HEAD` and its upstream each have that the other
+    /// lacks, or `None` without an upstream.
+    fn upstream_ahead_behind(&self, repo: &Path) -> Result<Option<(usize, usize)>> {
+        git::upstream_ahead_behind(repo)
+    }
+
+    /// Paths `git status --porcelain` lists.
+    fn changed_path_count(&self, repo: &Path) -> Result<usize> {
+        git::changed_path_count(repo)
+    }
+}
+
+/// Returns the backend configured by `git_backend`.
+pub fn for_config(kind: GitBackendKind) -> Box<dyn GitBackend> {
+    match kind {
+        GitBackendKind::Subprocess => Box::new(Subprocess),
+        #[cfg(feature = "gix")]
+        GitBackendKind::Gix => Box::new(Gix),
+        // Config loading rejects `gix` in builds without the feature.
+        #[cfg(not(feature = "gix"))]
+        GitBackendKind::Gix => Box::new(Subprocess),
+    }
+}
+
+/// Runs the `git` binary for every query.
+pub struct Subprocess;
+
+impl GitBackend for Subprocess {}
+
+/// Answers branch and upstream queries with gitoxide, without starting a
+/// process.
+#[cfg(feature = "gix")]
+pub struct Gix;
+
+#[cfg(feature = "gix")]
+impl Gix {
+    fn open(repo: &Path) -> Result<gix::Repository> {
+        use anyhow::Context;
+
+        gix::open(repo).with_context(|| format!("failed to open {} with gix", repo.display()))
+    }
+
+    fn resolve(repo: &gix::Repository, rev: &str) -> Result<gix::ObjectId> {
+        Ok(repo.rev_parse_single(rev)?.detach())
+    }
+}
+
+#[cfg(feature = "gix")]
+impl GitBackend for Gix {
+    fn current_branch(&self, repo: &Path) -> Result<Option<String>> {
+        let repo = Self::open(repo)?;
+        Ok(repo.head_name()?.map(|name| name.shorten().to_string()))
+    }
+
+    fn upstream_ahead_behind(&self, repo: &Path) -> Result<Option<(usize, usize)>> {
+        let repo = Self::open(repo)?;
+        // Like `git`, a detached `HEAD`, a branch without upstream and an
+        // upstream that was never fetched all mean there is nothing to count.
+        let Some(branch) = repo.head_name()? else {
+            return Ok(None);
+        };
+        let Some(tracking) =
+            repo.branch_remote_tracking_ref_name(branch.as_ref(), gix::remote::Direction::Fetch)
+        else {
+            return Ok(None);
+        };
+        let Some(mut upstream) = repo.try_find_reference(tracking?.as_ref())? else {
+            return Ok(None);
+        };
+        let upstream = upstream.peel_to_id()?.detach();
+        let head = Self::resolve(&repo, "HEAD")?;
+        let only_in = |tip: gix::ObjectId, hidden: gix::ObjectId| -> Result<usize> {
+            let mut count = 0;
+            for info in repo.rev_walk([tip]).with_hidden([hidden]).all()? {
+                info?;
+                count += 1;
+            }
+            Ok(count)
+        };
+        Ok(Some((only_in(head, upstream)?, only_in(upstream, head)?)))
+    }
+}
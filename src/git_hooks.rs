@@ -0,0 +1,102 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::cli::{HookArgs, HookCommand};
+use crate::config::ResolvedRepositoryConfig;
+use crate::git;
+
+/// Hooks that mark a repository as changed: new commits, branch switches and
+/// merges.
+pub const HOOK_NAMES: &[&str] = &["post-commit", "post-checkout", "post-merge"];
+
+/// File in the git directory the installed hooks touch, holding the Unix time
+/// of the last commit, checkout or merge. `watch` counts writes to it.
+pub const CHANGED_MARKER: &str = "shephard-changed";
+
+/// First line after the shebang of every hook shephard writes; hooks without
+/// it belong to someone else and are never overwritten or removed.
+const HOOK_TAG: &str =
+    "# Installed by `shephard hook install`; remove with `shephard hook uninstall`.";
+
+/// Installs or removes the change-marker hooks in every selected repository
+/// and prints what happened to each.
+pub fn run(args: &HookArgs, repos: &[ResolvedRepositoryConfig]) -> i32 {
+    let mut failed = false;
+    for repo in repos {
+        let outcome = match args.command {
+            HookCommand::Install => install(&repo.path),
+            HookCommand::Uninstall => uninstall(&repo.path),
+        };
+        match outcome {
+            Ok(notes) => println!("[OK] {} :: {}", repo.path.display(), notes.join(", ")),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+    i32::from(failed)
+}
+
+/// Writes the hooks in [`HOOK_NAMES`] into `repo`, replacing earlier shephard
+/// hooks and leaving any other existing hook alone.
+pub fn install(repo: &Path) -> Result<Vec<String>> {
+    let hooks_dir = git::git_path(repo, "hooks")?;
+    fs::create_dir_all(&hooks_dir)
+        .with_context(|| format!("failed creating {}", hooks_dir.display()))?;
+    let mut notes = Vec::new();
+    for name in HOOK_NAMES {
+        let path = hooks_dir.join(name);
+        if path.exists() && !is_shephard_hook(&path) {
+            notes.push(format!("kept existing {name} hook"));
+            continue;
+        }
+        fs::write(&path, hook_script())
+            .with_context(|| format!("failed writing {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed marking {} executable", path.display()))?;
+        notes.push(format!("installed {name}"));
+    }
+    Ok(notes)
+}
+
+/// Removes the hooks shephard installed in `repo`.
+pub fn uninstall(repo: &Path) -> Result<Vec<String>> {
+    let hooks_dir = git::git_path(repo, "hooks")?;
+    let mut notes = Vec::new();
+    for name in HOOK_NAMES {
+        let path = hooks_dir.join(name);
+        if !path.exists() {
+            continue;
+        }
+        if !is_shephard_hook(&path) {
+            notes.push(format!("kept existing {name} hook"));
+            continue;
+        }
+        fs::remove_file(&path).with_context(|| format!("failed removing {}", path.display()))?;
+        notes.push(format!("removed {name}"));
+    }
+    if notes.is_empty() {
+        notes.push("no hooks installed".to_string());
+    }
+    Ok(notes)
+}
+
+/// Where the installed hooks record the last commit, checkout or merge in
+/// `repo`.
+pub fn changed_marker(repo: &Path) -> Result<PathBuf> {
+    git::git_path(repo, CHANGED_MARKER)
+}
+
+fn hook_script() -> String {
+    format!(
+        "#!/bin/sh\n{HOOK_TAG}\ndate +%s > \"$(git rev-parse --git-path {CHANGED_MARKER})\" 2>/dev/null || true\n"
+    )
+}
+
+fn is_shephard_hook(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|script| script.lines().any(|line| line == HOOK_TAG))
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::config::ResolvedRunConfig;
+use crate::git;
+use crate::state;
+use crate::workflow::{PhaseResult, RepoResult, RepoStatus};
+
+/// What produced a run, so results from different machines can be told apart.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RunMeta {
+    pub shephard_version: String,
+    /// Empty when `git --version` failed.
+    pub git_version: String,
+    pub host: String,
+    /// Unix seconds when the run started.
+    pub started_at: i64,
+}
+
+impl RunMeta {
+    pub fn collect(started_at: i64) -> Self {
+        RunMeta {
+            shephard_version: env!("CARGO_PKG_VERSION").to_string(),
+            git_version: git::version().unwrap_or_default(),
+            host: hostname::get()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            started_at,
+        }
+    }
+
+    /// One line such as `shephard 0.1.0, git 2.43.0, on laptop at 2024-05-01
+    /// 22:00:00 +02:00`.
+    pub fn describe(&self) -> String {
+        let when = Local
+            .timestamp_opt(self.started_at, 0)
+            .single()
+            .map(|at| at.format("%Y-%m-%d %H:%M:%S %:z").to_string())
+            .unwrap_or_else(|| self.started_at.to_string());
+        let git = if self.git_version.is_empty() {
+            "unknown"
+        } else {
+            &self.git_version
+        };
+        format!(
+            "shephard {}, git {git}, on {} at {when}",
+            self.shephard_version, self.host
+        )
+    }
+}
+
+/// One line of `history.jsonl`: a run and what happened to each repository.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    #[serde(flatten)]
+    pub meta: RunMeta,
+    pub repos: Vec<RepoRecord>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RepoRecord {
+    pub repo: PathBuf,
+    pub status: RepoStatus,
+    pub message: String,
+    /// The settings the repository synced with, after every override, so a
+    /// result can be explained later without redoing flag precedence. Missing
+    /// for repositories that were never resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<ResolvedRunConfig>,
+    /// How each phase of the sync went; missing for records written before
+    /// phases were tracked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub phases: Vec<PhaseResult>,
+}
+
+impl RunRecord {
+    pub fn new(
+        meta: &RunMeta,
+        results: &[RepoResult],
+        configs: &BTreeMap<PathBuf, ResolvedRunConfig>,
+    ) -> Self {
+        RunRecord {
+            meta: meta.clone(),
+            repos: results
+                .iter()
+                .map(|item| RepoRecord {
+                    repo: item.repo.clone(),
+                    status: item.status,
+                    message: item.message.clone(),
+                    config: configs.get(&item.repo).cloned(),
+                    phases: item.phases.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `history.jsonl` next to the state file.
+pub fn history_path() -> Result<PathBuf> {
+    Ok(state::state_path()?.with_file_name("history.jsonl"))
+}
+
+/// Appends `record` as one JSON line to the run history.
+pub fn append(record: &RunRecord) -> Result<()> {
+    let path = history_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed creating state directory {}", dir.display()))?;
+    }
+    let line = serde_json::to_string(record).context("failed serializing run history")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed opening run history {}", path.display()))?;
+    // One write per record, so lines appended by concurrent runs never
+    // interleave.
+    file.write_all(format!("{line}\n").as_bytes())
+        .with_context(|| format!("failed writing run history {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn run_records_are_one_flat_json_line() {
+        let meta = RunMeta {
+            shephard_version: "0.1.0".to_string(),
+            git_version: "2.43.0".to_string(),
+            host: "laptop".to_string(),
+            started_at: 1_700_000_000,
+        };
+        let results = [RepoResult {
+            repo: PathBuf::from("/srv/a"),
+            status: RepoStatus::Failed,
+            message: "merge conflict".to_string(),
+            excluded_untracked: vec!["notes.txt".to_string()],
+            phases: Vec::new(),
+            snapshot: None,
+        }];
+
+        let line = serde_json::to_string(&RunRecord::new(&meta, &results, &BTreeMap::new()))
+            .expect("record should serialize");
+
+        assert_eq!(
+            line,
+            r#"{"shephard_version":"0.1.0","git_version":"2.43.0","host":"laptop","started_at":1700000000,"repos":[{"repo":"/srv/a","status":"failed","message":"merge conflict"}]}"#
+        );
+        assert!(
+            meta.describe()
+                .starts_with("shephard 0.1.0, git 2.43.0, on laptop at 2023-11-")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::cli::InitArgs;
+use crate::config;
+use crate::discovery;
+use crate::prompt;
+
+/// What `shephard init` writes, from the answers to its questions or, without
+/// a terminal, from flags and defaults.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InitChoices {
+    /// Workspace roots as typed, so `~` and variables stay unexpanded.
+    pub roots: Vec<String>,
+    pub side_channel: bool,
+    pub remote_name: String,
+    pub remote_url_template: Option<String>,
+    pub commit_template: String,
+    /// Discovered repositories written as `[[repositories]]` entries.
+    pub repositories: Vec<PathBuf>,
+}
+
+/// Creates the config file, asking for workspace roots, side-channel
+/// defaults and the commit template on a terminal, and optionally lists the
+/// repositories found under the roots. Refuses to replace an existing file
+/// without `--force`.
+pub fn run(args: &InitArgs) -> Result<i32> {
+    let path = config::config_path()?;
+    if path.exists() && !args.force {
+        bail!(
+            "{} already exists; pass --force to replace it",
+            path.display()
+        );
+    }
+    let config_dir = path
+        .parent()
+        .context("unable to determine parent directory for config file")?;
+
+    let interactive = prompt::is_interactive(args.non_interactive);
+    let choices = if interactive {
+        ask_choices(args)?
+    } else {
+        default_choices(args)?
+    };
+    let raw = render(&choices);
+    config::parse(&raw, config_dir).context("the written config would not load")?;
+
+    fs::create_dir_all(config_dir)
+        .with_context(|| format!("failed creating {}", config_dir.display()))?;
+    fs::write(&path, raw)
+        .with_context(|| format!("failed writing config file at {}", path.display()))?;
+    println!(
+        "Wrote {} with {} workspace roots and {} repositories",
+        path.display(),
+        choices.roots.len(),
+        choices.repositories.len()
+    );
+    Ok(0)
+}
+
+fn default_choices(args: &InitArgs) -> Result<InitChoices> {
+    let defaults = config::defaults();
+    let roots = root_strings(args);
+    let repositories = if args.discover {
+        discover(&roots)?
+    } else {
+        Vec::new()
+    };
+    Ok(InitChoices {
+        roots,
+        side_channel: defaults.side_channel.enabled,
+        remote_name: defaults.side_channel.remote_name,
+        remote_url_template: None,
+        commit_template: defaults.commit_template,
+        repositories,
+    })
+}
+
+fn ask_choices(args: &InitArgs) -> Result<InitChoices> {
+    let defaults = config::defaults();
+    let roots = prompt::ask_or(
+        "Workspace roots to discover repositories under (space-separated)",
+        &root_strings(args).join(" "),
+    )?
+    .split_whitespace()
+    .map(str::to_string)
+    .collect::<Vec<_>>();
+
+    let side_channel = prompt::confirm(
+        "Push snapshots of uncommitted work to a side-channel remote for other machines?",
+    )?;
+    let (remote_name, remote_url_template) = if side_channel {
+        let remote_name = prompt::ask_or(
+            "Side-channel remote name",
+            &defaults.side_channel.remote_name,
+        )?;
+        let template = prompt::ask_or(
+            "URL for adding that remote where it is missing, with {repo_name} (- for none)",
+            "-",
+        )?;
+        (remote_name, (template != "-").then_some(template))
+    } else {
+        (defaults.side_channel.remote_name, None)
+    };
+    let commit_template = prompt::ask_or("Commit message template", &defaults.commit_template)?;
+
+    let found = discover(&roots)?;
+    let repositories = if !found.is_empty()
+        && (args.discover
+            || prompt::confirm(&format!(
+                "List the {} repositories found under the roots as [[repositories]] entries?",
+                found.len()
+            ))?) {
+        found
+    } else {
+        Vec::new()
+    };
+    Ok(InitChoices {
+        roots,
+        side_channel,
+        remote_name,
+        remote_url_template,
+        commit_template,
+        repositories,
+    })
+}
+
+fn root_strings(args: &InitArgs) -> Vec<String> {
+    args.roots
+        .iter()
+        .map(|root| root.to_string_lossy().to_string())
+        .collect()
+}
+
+fn discover(roots: &[String]) -> Result<Vec<PathBuf>> {
+    let roots = roots
+        .iter()
+        .map(|root| config::expand_path(Path::new(root)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(discovery::discover_repositories(&roots, false, &[])?
+        .into_iter()
+        .map(|repo| repo.path)
+        .collect())
+}
+
+/// The config file for `choices`, leaving everything else at its default.
+pub fn render(choices: &InitChoices) -> String {
+    let quote = |value: &str| toml::Value::String(value.to_string()).to_string();
+    let mut raw =
+        String::from("# Written by `shephard init`; see `man shephard` for every option.\n");
+    if !choices.roots.is_empty() {
+        let roots = choices
+            .roots
+            .iter()
+            .map(|root| quote(root))
+            .collect::<Vec<_>>()
+            .join(", ");
+        raw.push_str(&format!("\n[discovery]\nroots = [{roots}]\n"));
+    }
+    raw.push_str(&format!(
+        "\n[side_channel]\nenabled = {}\nremote_name = {}\n",
+        choices.side_channel,
+        quote(&choices.remote_name)
+    ));
+    if let Some(template) = &choices.remote_url_template {
+        raw.push_str(&format!("remote_url_template = {}\n", quote(template)));
+    }
+    raw.push_str(&format!(
+        "\n[commit]\nmessage_template = {}\n",
+        quote(&choices.commit_template)
+    ));
+    for repo in &choices.repositories {
+        raw.push_str(&format!(
+            "\n[[repositories]]\npath = {}\n",
+            quote(&repo.to_string_lossy())
+        ));
+    }
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendered_config_loads_with_the_chosen_values() {
+        let choices = InitChoices {
+            roots: vec!["/srv/src".to_string(), "/srv/work \"q\"".to_string()],
+            side_channel: true,
+            remote_name: "backup".to_string(),
+            remote_url_template: Some("git@nas:shephard/{repo_name}.git".to_string()),
+            commit_template: "sync {hostname} [{scope}]".to_string(),
+            repositories: vec![PathBuf::from("/srv/src/app")],
+        };
+
+        let cfg = config::parse(&render(&choices), Path::new("/tmp")).expect("config loads");
+        assert_eq!(
+            (
+                cfg.workspace_roots
+                    .iter()
+                    .map(|root| root.path.clone())
+                    .collect::<Vec<_>>(),
+                cfg.side_channel.enabled,
+                cfg.side_channel.remote_name,
+                cfg.side_channel.remote_url_template,
+                cfg.commit_template,
+                cfg.repositories
+                    .iter()
+                    .map(|repo| repo.path.clone())
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                vec![PathBuf::from("/srv/src"), PathBuf::from("/srv/work \"q\"")],
+                true,
+                "backup".to_string(),
+                Some("git@nas:shephard/{repo_name}.git".to_string()),
+                "sync {hostname} [{scope}]".to_string(),
+                vec![PathBuf::from("/srv/src/app")],
+            )
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::git;
+use crate::state;
+
+/// A step that changes a repository or its remotes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Pull,
+    Stage,
+    Commit,
+    Push,
+    Snapshot,
+    Apply,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Pull => "pull",
+            Phase::Stage => "stage",
+            Phase::Commit => "commit",
+            Phase::Push => "push",
+            Phase::Snapshot => "snapshot",
+            Phase::Apply => "apply",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One journal line, written before `phase` starts.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Intent {
+    pub repo: PathBuf,
+    pub phase: Phase,
+    /// `HEAD` and `@{upstream}` as they were before the phase, by name.
+    pub refs: BTreeMap<String, String>,
+    pub pid: u32,
+    /// Unix seconds.
+    pub at: i64,
+}
+
+impl Intent {
+    /// One line such as `/home/me/notes may have been left mid-commit by a run
+    /// interrupted at 2024-05-01 22:00:00 +02:00 (HEAD was 1a2b3c4)`.
+    pub fn describe(&self) -> String {
+        let when = Local
+            .timestamp_opt(self.at, 0)
+            .single()
+            .map(|at| at.format("%Y-%m-%d %H:%M:%S %:z").to_string())
+            .unwrap_or_else(|| self.at.to_string());
+        let refs = self
+            .refs
+            .iter()
+            .map(|(name, target)| format!("{name} was {}", short(target)))
+            .collect::<Vec<_>>();
+        let refs = if refs.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", refs.join(", "))
+        };
+        format!(
+            "{} may have been left mid-{} by a run interrupted at {when}{refs}",
+            self.repo.display(),
+            self.phase
+        )
+    }
+}
+
+/// `journal/` next to the state file.
+pub fn journal_dir() -> Result<PathBuf> {
+    Ok(state::state_path()?.with_file_name("journal"))
+}
+
+/// Records the phases of one repository's sync or apply in its own file under
+/// the journal directory, and removes the file once the repository is done.
+/// Journaling is best-effort: a failed write never stops a sync.
+#[derive(Debug)]
+pub struct Journal {
+    repo: PathBuf,
+    path: Option<PathBuf>,
+    recorded: bool,
+}
+
+impl Journal {
+    /// A journal for `repo` in `dir`; with no `dir`, nothing is recorded.
+    pub fn open(dir: Option<&Path>, repo: &Path) -> Self {
+        Journal {
+            repo: repo.to_path_buf(),
+            path: dir.map(|dir| dir.join(file_name(repo))),
+            recorded: false,
+        }
+    }
+
+    /// Appends an intent for `phase` with the refs it may move.
+    pub fn record(&mut self, phase: Phase) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let refs = ["HEAD", "@{upstream}"]
+            .into_iter()
+            .filter_map(|name| {
+                let target = git::rev_parse_optional(&self.repo, name).ok()??;
+                Some((name.to_string(), target))
+            })
+            .collect();
+        let intent = Intent {
+            repo: self.repo.clone(),
+            phase,
+            refs,
+            pid: std::process::id(),
+            at: Utc::now().timestamp(),
+        };
+        if append(path, &intent).is_ok() {
+            self.recorded = true;
+        }
+    }
+
+    /// Clears the journal after the last phase finished, whatever its outcome.
+    /// A journal that recorded nothing leaves an earlier run's entry in place.
+    pub fn finish(self) {
+        if self.recorded
+            && let Some(path) = &self.path
+        {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The last intent of every journal in `dir` whose process is gone, i.e.
+/// repositories an interrupted run may have left mid-operation.
+pub fn stale(dir: &Path) -> Vec<Intent> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut intents = entries
+        .filter_map(|entry| {
+            let raw = fs::read_to_string(entry.ok()?.path()).ok()?;
+            serde_json::from_str::<Intent>(raw.lines().last()?).ok()
+        })
+        .filter(|intent| !is_running(intent.pid))
+        .collect::<Vec<_>>();
+    intents.sort_by(|a, b| a.repo.cmp(&b.repo));
+    intents
+}
+
+fn append(path: &Path, intent: &Intent) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed creating journal directory {}", dir.display()))?;
+    }
+    let line = serde_json::to_string(intent).context("failed serializing journal entry")?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed opening journal {}", path.display()))?;
+    writeln!(file, "{line}")
+        .and_then(|()| file.sync_data())
+        .with_context(|| format!("failed writing journal {}", path.display()))
+}
+
+/// The repository path with `%` and `/` percent-encoded, so each repository
+/// gets one readable, unambiguous file name.
+fn file_name(repo: &Path) -> String {
+    let encoded = repo
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+    format!("{encoded}.jsonl")
+}
+
+/// Whether `pid` is a live process. Without `/proc` every journal counts as
+/// stale.
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+fn short(target: &str) -> &str {
+    target.get(..12).unwrap_or(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn journals_survive_interruptions_and_clear_on_finish() {
+        let temp = tempfile::tempdir().expect("tempdir should work");
+        let dir = temp.path().join("journal");
+        let interrupted = temp.path().join("interrupted");
+        let finished = temp.path().join("finished");
+        let untouched = temp.path().join("untouched");
+        fs::create_dir(&interrupted).expect("repo dir should be created");
+        fs::create_dir(&finished).expect("repo dir should be created");
+
+        let mut journal = Journal::open(Some(&dir), &interrupted);
+        journal.record(Phase::Stage);
+        journal.record(Phase::Commit);
+        // A crashed process: the journal is never finished, and its pid is gone.
+        let path = dir.join(file_name(&interrupted));
+        let raw = fs::read_to_string(&path).expect("journal should exist");
+        fs::write(
+            &path,
+            raw.replace(&format!("\"pid\":{}", std::process::id()), "\"pid\":0"),
+        )
+        .expect("journal should be rewritten");
+
+        let mut journal = Journal::open(Some(&dir), &finished);
+        journal.record(Phase::Pull);
+        journal.record(Phase::Push);
+        journal.finish();
+        // Skipped before any phase, e.g. busy: an earlier entry is kept.
+        Journal::open(Some(&dir), &interrupted).finish();
+        Journal::open(Some(&dir), &untouched).finish();
+
+        let stale = stale(&dir);
+        assert_eq!(
+            stale
+                .iter()
+                .map(|intent| (intent.repo.clone(), intent.phase, intent.refs.len()))
+                .collect::<Vec<_>>(),
+            vec![(interrupted.clone(), Phase::Commit, 0)]
+        );
+        assert!(stale[0].describe().starts_with(&format!(
+            "{} may have been left mid-commit",
+            interrupted.display()
+        )));
+        assert_eq!(
+            fs::read_dir(&dir)
+                .expect("journal dir should exist")
+                .count(),
+            1
+        );
+    }
+}
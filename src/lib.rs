@@ -1,7 +1,38 @@
 pub mod apply;
+pub mod backend;
+pub mod bench;
+pub mod bootstrap;
+pub mod check;
 pub mod cli;
+pub mod completions;
 pub mod config;
 pub mod discovery;
+pub mod display;
+pub mod doctor;
+pub mod environment;
+pub mod export;
 pub mod git;
+pub mod git_backend;
+pub mod git_hooks;
+pub mod history;
+pub mod init;
+pub mod journal;
+pub mod logging;
+pub mod migrate;
+pub mod notify;
+pub mod offload;
+pub mod pick;
+pub mod plan;
+pub mod probe;
+pub mod prompt;
+pub mod prune;
+pub mod remote;
 pub mod report;
+pub mod resolve;
+#[cfg(feature = "self-update")]
+pub mod self_update;
+pub mod side_channel;
+pub mod state;
+pub mod status;
+pub mod watch;
 pub mod workflow;
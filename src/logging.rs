@@ -0,0 +1,184 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use tracing::{debug, trace};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, fmt};
+
+/// How many run logs are kept; starting a run removes the oldest beyond this.
+const KEPT_LOGS: usize = 50;
+
+static RUN_LOG: OnceLock<PathBuf> = OnceLock::new();
+
+/// `shephard/logs` under the XDG data directory, one file per run.
+pub fn log_dir() -> Result<PathBuf> {
+    let base = dirs::data_dir().context("unable to resolve XDG data directory")?;
+    Ok(base.join("shephard").join("logs"))
+}
+
+/// What reaches the terminal for `-v` given `verbose` times: warnings, then
+/// each repository's result, then every git command with its timing, then
+/// what the commands printed.
+pub fn console_level(verbose: u8) -> LevelFilter {
+    match verbose {
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Sends events at [`console_level`] to stderr and, with `run_log`, all of
+/// them to a new file in [`log_dir`]. A log file that cannot be created only
+/// warns; the sync matters more than its log.
+pub fn init(verbose: u8, run_log: bool) {
+    let file = run_log
+        .then(|| match open_run_log() {
+            Ok((path, file)) => {
+                let _ = RUN_LOG.set(path);
+                Some(file)
+            }
+            Err(err) => {
+                eprintln!("Warning: not writing a run log: {err:#}");
+                None
+            }
+        })
+        .flatten();
+
+    let console = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .without_time()
+        .with_filter(console_level(verbose));
+    let file = file.map(|file| {
+        fmt::layer()
+            .with_writer(Mutex::new(file))
+            .with_ansi(false)
+            .with_filter(LevelFilter::TRACE)
+    });
+    // Only fails when a subscriber is already installed.
+    let _ = tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init();
+}
+
+/// The log file this run writes, when [`init`] opened one.
+pub fn run_log() -> Option<&'static Path> {
+    RUN_LOG.get().map(PathBuf::as_path)
+}
+
+/// Runs `cmd` like [`Command::output`], logging the command line, exit status
+/// and timing at debug and what it printed at trace.
+pub fn output(cmd: &mut Command) -> io::Result<Output> {
+    let started = Instant::now();
+    let output = cmd.output();
+    log_output(cmd, started, &output);
+    output
+}
+
+/// Logs a command [`output`] could not run itself, such as one fed on stdin.
+pub fn log_output(cmd: &Command, started: Instant, output: &io::Result<Output>) {
+    let line = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|part| part.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let dir = cmd
+        .get_current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default();
+    let elapsed_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    match output {
+        Ok(output) => {
+            debug!(dir, status = %output.status, elapsed_ms, "{line}");
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stdout.trim().is_empty() || !stderr.trim().is_empty() {
+                trace!(
+                    dir,
+                    stdout = %stdout.trim_end(),
+                    stderr = %stderr.trim_end(),
+                    "{line}"
+                );
+            }
+        }
+        Err(err) => debug!(dir, elapsed_ms, error = %err, "{line}"),
+    }
+}
+
+fn open_run_log() -> Result<(PathBuf, File)> {
+    let dir = log_dir()?;
+    fs::create_dir_all(&dir).with_context(|| format!("failed creating {}", dir.display()))?;
+    prune_logs(&dir, KEPT_LOGS - 1);
+    let path = dir.join(format!(
+        "run-{}-{}.log",
+        Local::now().format("%Y%m%dT%H%M%S"),
+        std::process::id()
+    ));
+    let file =
+        File::create(&path).with_context(|| format!("failed creating {}", path.display()))?;
+    Ok((path, file))
+}
+
+/// Removes all but the newest `keep` run logs in `dir`; their names start
+/// with the time they were written, so the newest sort last.
+fn prune_logs(dir: &Path, keep: usize) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut logs = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("run-") && name.ends_with(".log"))
+        })
+        .collect::<Vec<_>>();
+    logs.sort();
+    for old in &logs[..logs.len().saturating_sub(keep)] {
+        let _ = fs::remove_file(old);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pruning_keeps_the_newest_run_logs_and_other_files() {
+        let dir = tempfile::tempdir().expect("tempdir should work");
+        for name in [
+            "run-20260101T090000-1.log",
+            "run-20260102T090000-7.log",
+            "run-20260103T090000-3.log",
+            "notes.txt",
+        ] {
+            fs::write(dir.path().join(name), "").expect("write");
+        }
+
+        prune_logs(dir.path(), 2);
+
+        let mut left = fs::read_dir(dir.path())
+            .expect("read_dir")
+            .map(|entry| entry.expect("entry").file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "notes.txt",
+                "run-20260102T090000-7.log",
+                "run-20260103T090000-3.log"
+            ]
+        );
+    }
+}
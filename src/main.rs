@@ -1,12 +1,27 @@
 use std::collections::{BTreeMap, BTreeSet};
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use chrono::Utc;
 use clap::Parser;
-use shephard::{apply, config, report, workflow};
+use shephard::{
+    apply, bench, bootstrap, check, completions, config, discovery, doctor, environment, export,
+    git_backend, git_hooks, history, init, journal, logging, migrate, notify, pick, plan, probe,
+    prompt, prune, report, side_channel, state, status, watch, workflow,
+};
 
-use shephard::cli::{Cli, Command, RunArgs};
-use shephard::config::ResolvedRepositoryConfig;
+use shephard::cli::{
+    ApplyArgs, Cli, Command, GcStateArgs, InitArgs, MoveArgs, RemotesCommand, RepoCommand, RunArgs,
+    SideChannelCommand, WatchArgs,
+};
+use shephard::config::{
+    AuthMode, NotifyConfig, OutputFormat, ResolvedConfig, ResolvedRepositoryConfig,
+    ResolvedRunConfig, ResolvedWorkspaceRoot, UntrackedPolicy,
+};
+use shephard::display::PathDisplay;
+use shephard::notify::HealthcheckPing;
 
 fn main() {
     let exit_code = match run() {
@@ -21,12 +36,144 @@ fn main() {
 
 fn run() -> Result<i32> {
     let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Run(RunArgs::default()));
+    // Syncs keep a full log of their git commands for when a repository fails.
+    logging::init(
+        cli.verbose,
+        matches!(command, Command::Run(_) | Command::Watch(_)),
+    );
 
-    match cli.command.unwrap_or(Command::Run(RunArgs::default())) {
-        Command::Run(args) => run_sync(&args),
+    match command {
+        Command::Run(mut args) => {
+            if let Some(list) = &args.repos_from {
+                let listed = pick::read_repo_list(list)?;
+                if listed.is_empty() {
+                    println!("No repositories selected.");
+                    return Ok(0);
+                }
+                args.repos.extend(listed);
+            }
+            run_sync(&args)
+        }
         Command::Apply(args) => {
             let cfg = config::load()?;
-            apply::run(&args, &cfg)?;
+            let (repos, keys) = select_repositories_with_keys(&cfg, &[], &[])?;
+            if args.undo {
+                undo_apply(&args, &cfg, &keys)?;
+                return Ok(0);
+            }
+            let (exit_code, records) = if args.all {
+                apply::run_all(&args, &cfg, &repos)
+            } else {
+                (0, apply::run(&args, &cfg)?.into_iter().collect())
+            };
+            // Like run state, a failure to remember the apply should not fail
+            // the apply itself.
+            if !records.is_empty()
+                && let Err(err) = state::StateStore::open().and_then(|store| {
+                    store.update(|saved| {
+                        for (repo, record) in records {
+                            saved.record_apply(&keys, &repo, record);
+                        }
+                    })
+                })
+            {
+                eprintln!("Warning: failed to record the apply for --undo: {err:#}");
+            }
+            Ok(exit_code)
+        }
+        Command::Status(args) => {
+            let cfg = config::load()?;
+            let (repos, keys) = select_repositories_with_keys(&cfg, &args.repos, &args.roots)?;
+            let paths = PathDisplay::new(&cfg, &config::resolve_workspace_roots(&cfg, &args.roots));
+            status::run(&args, &cfg, &repos, &keys, &paths)
+        }
+        Command::Check(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            let backend = git_backend::for_config(cfg.git_backend);
+            Ok(check::run(&args, &cfg.check, &repos, backend.as_ref()))
+        }
+        Command::SideChannel(SideChannelCommand::Gc(args)) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            Ok(side_channel::gc(&cfg, &repos))
+        }
+        Command::SideChannel(SideChannelCommand::Provision(args)) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            Ok(side_channel::provision(&cfg, &repos, args.ssh))
+        }
+        Command::GcState(args) => gc_state(&args),
+        Command::Export(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            export::export(&cfg, &repos, &args.output)
+        }
+        Command::Import(args) => export::import(&args.archive, &args.map),
+        Command::Move(args) => move_repo(&args),
+        Command::Repo(command) => edit_repositories(&command),
+        Command::PruneRemotes(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            prune::run(&args, &cfg.prune_remotes, &repos)
+        }
+        Command::Remotes(RemotesCommand::Migrate(args)) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            migrate::run(&args, &cfg.url_rewrites, &repos)
+        }
+        Command::Doctor(args) => {
+            // A broken config is one of the things doctor reports, so the
+            // environment is still checked against the defaults.
+            let (cfg, config_error) = match config::load() {
+                Ok(cfg) => (cfg, None),
+                Err(err) => (config::defaults(), Some(err)),
+            };
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            Ok(doctor::run(&args, &cfg, config_error.as_ref(), &repos))
+        }
+        Command::Init(args) => init::run(&args),
+        #[cfg(feature = "self-update")]
+        Command::SelfUpdate(args) => shephard::self_update::run(&args),
+        Command::Hook(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            Ok(git_hooks::run(&args, &repos))
+        }
+        Command::Watch(args) => watch(&args),
+        Command::Bootstrap(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &[])?;
+            Ok(bootstrap::run(&cfg, &repos, args.method))
+        }
+        Command::Bench(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+            bench::run(&args, &cfg, &repos)
+        }
+        Command::Pick(args) => {
+            let cfg = config::load()?;
+            let repos = select_repositories(&cfg, &[], &args.roots)?;
+            let picker = match (&args.picker, args.no_picker) {
+                (Some(picker), _) => Some(picker.as_str()),
+                (None, true) => None,
+                (None, false) => cfg.picker.as_deref(),
+            };
+            pick::run(&repos, picker)
+        }
+        Command::Completions(args) => {
+            let cfg = config::load()?;
+            let repos = if args.discovered {
+                known_repositories(&cfg, &[])?
+            } else {
+                cfg.repositories.clone()
+            };
+            let roots = config::resolve_workspace_roots(&cfg, &[]);
+            print!(
+                "{}",
+                completions::list_repos(&cfg.repositories, &repos, &roots, args.format)
+            );
             Ok(0)
         }
     }
@@ -34,17 +181,90 @@ fn run() -> Result<i32> {
 
 fn run_sync(args: &RunArgs) -> Result<i32> {
     let cfg = config::load()?;
+    // SAFETY: no threads have been started yet; probes and syncs start below.
+    let env_problems = unsafe { environment::prepare(&cfg.environment) };
+    sync_in_environment(args, cfg, &env_problems)
+}
+
+/// Syncs in an environment [`environment::prepare`] already set up, with the
+/// problems it reported.
+fn sync_in_environment(
+    args: &RunArgs,
+    cfg: ResolvedConfig,
+    env_problems: &[environment::EnvironmentProblem],
+) -> Result<i32> {
+    let env_fatal = env_problems
+        .iter()
+        .filter(|problem| problem.is_fatal())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
     let base_run_cfg = config::resolve_run_config(&cfg, args)?;
+    let journal_dir = journal::journal_dir().ok();
+    if let Some(dir) = &journal_dir {
+        for intent in journal::stale(dir) {
+            eprintln!("Warning: {}", intent.describe());
+        }
+    }
 
-    let enabled_repositories = config::enabled_repositories(&cfg);
-    let selected_repositories =
-        resolve_configured_targets(args, &enabled_repositories, &cfg.repositories);
+    // A plan replaces `--repos`: it lists the repositories to sync, and its
+    // entries win over config and flags for each of them.
+    let plan = args
+        .plan_file
+        .as_deref()
+        .map(plan::Plan::load)
+        .transpose()?;
+    if let Some(plan) = &plan
+        && plan.repos.is_empty()
+    {
+        println!("No repositories selected.");
+        return Ok(0);
+    }
+    let selected_paths = match &plan {
+        Some(plan) => plan.repos.iter().map(|entry| entry.path.clone()).collect(),
+        None => args.repos.clone(),
+    };
+    let (selected_repositories, keys) =
+        select_repositories_with_keys(&cfg, &selected_paths, &args.roots)?;
 
+    let interactive = prompt::is_interactive(args.non_interactive);
     if selected_repositories.is_empty() {
+        let path = config::config_path()?;
+        if !path.exists() {
+            // A first run on a terminal walks through `shephard init` rather
+            // than syncing nothing with the defaults.
+            if interactive
+                && prompt::confirm(&format!(
+                    "No config found at {}. Choose roots and repositories now?",
+                    path.display()
+                ))?
+            {
+                init::run(&InitArgs {
+                    roots: args.roots.clone(),
+                    ..Default::default()
+                })?;
+                println!("Run shephard again to sync the chosen repositories.");
+                return Ok(0);
+            }
+            eprintln!(
+                "No config found at {}; run `shephard init` to create one",
+                path.display()
+            );
+        }
         println!("No repositories selected.");
         return Ok(0);
     }
 
+    if args.interactive_auth && !interactive {
+        bail!("--interactive-auth needs a terminal to prompt on");
+    }
+    let state_dir = state::state_path()?
+        .parent()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()));
+    // Only used to skip duplicate snapshots, so an unreadable state file just
+    // means every changed working tree is snapshot.
+    let saved = state::StateStore::open()
+        .and_then(|store| store.read())
+        .unwrap_or_default();
     let mut run_targets = Vec::new();
     for repo in selected_repositories {
         if !is_git_repo(&repo.path) {
@@ -55,7 +275,38 @@ fn run_sync(args: &RunArgs) -> Result<i32> {
             continue;
         }
 
-        let run_cfg = config::resolve_repo_run_config(&base_run_cfg, args, &repo);
+        let mut run_cfg = config::resolve_repo_run_config(&base_run_cfg, args, &repo);
+        run_cfg.journal_dir = journal_dir.clone();
+        run_cfg.last_snapshot = saved
+            .lookup(&keys, &repo.path)
+            .and_then(|record| record.last_snapshot.clone());
+        if let Some(entry) = plan.as_ref().and_then(|plan| plan.entry(&repo.path)) {
+            entry.apply(&mut run_cfg);
+        }
+        if interactive && run_cfg.include_untracked == UntrackedPolicy::Ask {
+            run_cfg.include_untracked =
+                prompt::ask_include_untracked(&repo.path).unwrap_or_else(|err| {
+                    eprintln!(
+                        "Warning: could not ask about untracked files in {}: {err:#}",
+                        repo.path.display()
+                    );
+                    UntrackedPolicy::Exclude
+                });
+        }
+        // A state directory inside a synced repository would change on every
+        // run and be committed by the next one; keep it out of staging.
+        if let Some(relative) = state_dir
+            .as_ref()
+            .and_then(|dir| dir.strip_prefix(&repo.path).ok())
+        {
+            eprintln!(
+                "Note: shephard's state directory is inside {}; it is left out of sync commits",
+                repo.path.display()
+            );
+            run_cfg
+                .excludes
+                .push(format!("{}/**", relative.to_string_lossy()));
+        }
         run_targets.push((repo.path.clone(), run_cfg));
     }
 
@@ -64,18 +315,678 @@ fn run_sync(args: &RunArgs) -> Result<i32> {
         return Ok(0);
     }
 
-    let results = workflow::run_with_repo_configs(&run_targets);
-    report::print_run_summary(&results);
+    if let Some(path) = &args.write_plan {
+        // Caught here, a missing remote is fixed before the plan runs instead
+        // of failing it halfway through.
+        for (repo, run_cfg) in &run_targets {
+            let problems = doctor::remote_problems(repo, &run_cfg.side_channel, &run_cfg.commands)
+                .unwrap_or_else(|err| vec![format!("{err:#}")]);
+            for problem in problems {
+                eprintln!("Warning: {} :: {problem}", repo.display());
+            }
+        }
+        plan::Plan::from_targets(&run_targets).save(path)?;
+        println!(
+            "Wrote a plan for {} repositories to {}; run it with `shephard run --plan-file {}`.",
+            run_targets.len(),
+            path.display(),
+            path.display()
+        );
+        return Ok(0);
+    }
+
+    let run_dir = std::env::current_dir().context("failed to resolve current directory")?;
+    if let Some(command) = &cfg.hooks.pre_run {
+        workflow::run_shell(&run_dir, command, &[]).context("pre_run hook failed")?;
+    }
+
+    let meta = history::RunMeta::collect(Utc::now().timestamp());
+    let target_paths = run_targets
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    let roots = config::resolve_workspace_roots(&cfg, &args.roots);
+    let notify_groups = notify_groups(&cfg.notify, &roots, &target_paths);
+    for (notify_cfg, _) in &notify_groups {
+        if let Some(url) = &notify_cfg.healthcheck_url
+            && let Err(err) = notify::ping_healthcheck(url, HealthcheckPing::Start, "")
+        {
+            eprintln!("Warning: failed to ping healthcheck: {err:#}");
+        }
+    }
+
+    let upstreams = probe::upstream_hosts(&target_paths, &cfg.url_rewrites);
+    // One probe per host up front, so a dropped VPN costs one timeout instead
+    // of a pull timeout per repository.
+    let offline = if cfg.probe_timeout_secs == 0 {
+        BTreeMap::new()
+    } else {
+        let auths = run_targets
+            .iter()
+            .map(|(path, run_cfg)| (path.clone(), run_cfg.auth.clone()))
+            .collect::<BTreeMap<_, _>>();
+        probe::unreachable_hosts(
+            &upstreams,
+            Duration::from_secs(cfg.probe_timeout_secs),
+            |repo| auths.get(repo).cloned().unwrap_or_default(),
+        )
+    };
+    let offline_reason = |path: &PathBuf| {
+        let host = &upstreams.get(path)?.host;
+        let reason = offline.get(host)?;
+        Some(format!("skipped (offline): {host} unreachable: {reason}"))
+    };
+
+    let mut run_configs = run_targets.iter().cloned().collect::<BTreeMap<_, _>>();
+    let mut results = Vec::new();
+    let mut online_targets = Vec::new();
+    for (path, run_cfg) in run_targets {
+        if !env_fatal.is_empty() {
+            results.push(workflow::RepoResult {
+                repo: path,
+                status: workflow::RepoStatus::Environment,
+                message: env_fatal.join("; "),
+                excluded_untracked: Vec::new(),
+                phases: Vec::new(),
+                snapshot: None,
+            });
+            continue;
+        }
+        match offline_reason(&path) {
+            Some(message) => results.push(workflow::RepoResult {
+                repo: path,
+                status: workflow::RepoStatus::Skipped,
+                message,
+                excluded_untracked: Vec::new(),
+                phases: Vec::new(),
+                snapshot: None,
+            }),
+            None => online_targets.push((path, run_cfg)),
+        }
+    }
+    // Sync the repository holding the config file first, so a pulled config
+    // change is noticed before the other repositories instead of halfway through.
+    let config_file = config::config_path()?.canonicalize().ok();
+    let config_repo = config_file.as_ref().and_then(|file| {
+        online_targets
+            .iter()
+            .enumerate()
+            .filter(|(_, (path, _))| file.starts_with(path))
+            .max_by_key(|(_, (path, _))| path.components().count())
+            .map(|(idx, _)| idx)
+    });
+    let mut synced_targets = Vec::new();
+    if let (Some(idx), Some(file)) = (config_repo, &config_file) {
+        let target = online_targets.remove(idx);
+        let before = fs::read(file).ok();
+        results.extend(workflow::run_with_repo_configs(std::slice::from_ref(
+            &target,
+        )));
+        if fs::read(file).ok() != before {
+            let reload = interactive
+                && prompt::confirm(&format!(
+                    "Config changed in {}; reload it for the remaining repositories?",
+                    target.0.display()
+                ))?;
+            if reload {
+                match reload_run_targets(args, plan.as_ref(), online_targets.clone()) {
+                    Ok(reloaded) => online_targets = reloaded,
+                    Err(err) => eprintln!(
+                        "Warning: failed to reload config, continuing with the previous one: {err:#}"
+                    ),
+                }
+            } else {
+                eprintln!(
+                    "Note: config changed in {}; the rest of this run uses the config it started with",
+                    target.0.display()
+                );
+            }
+        }
+        synced_targets.push(target);
+    }
+    results.extend(workflow::run_with_repo_configs(&online_targets));
+    synced_targets.extend(online_targets);
+    // A reloaded config replaces the one resolved up front.
+    run_configs.extend(synced_targets.iter().cloned());
+    if args.interactive_auth {
+        retry_with_terminal_auth(&mut results, synced_targets);
+    }
+    environment::attribute_failures(&mut results, env_problems);
+    // Offline and config repositories were set aside above; report everything
+    // in priority order again.
+    results.sort_by_key(|item| target_paths.iter().position(|path| *path == item.repo));
+
+    let hosts = upstreams
+        .into_iter()
+        .map(|(path, upstream)| (path, upstream.host))
+        .collect::<BTreeMap<_, _>>();
+    let root_names = results
+        .iter()
+        .filter_map(|item| {
+            let root = config::root_for(&roots, &item.repo)?;
+            Some((item.repo.clone(), root.name.clone()))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let format = if args.quiet {
+        OutputFormat::Quiet
+    } else {
+        args.format.unwrap_or(cfg.display.format)
+    };
+    // Notifications get plain text whatever the terminal shows.
+    let notify_renderer = report::renderer(match format {
+        OutputFormat::Quiet => OutputFormat::Quiet,
+        OutputFormat::Plain | OutputFormat::Color | OutputFormat::Json | OutputFormat::Markdown => {
+            OutputFormat::Plain
+        }
+    });
+    let path_display = PathDisplay::new(&cfg, &roots);
+    let mut run_report = report::RunReport {
+        results: &results,
+        paths: &path_display,
+        hosts: Some(report::host_summary(&results, &hosts, &offline)),
+        roots: (roots.len() > 1).then(|| report::root_summary(&results, &root_names)),
+        diff: None,
+        meta: Some(meta.clone()),
+    };
+
+    // Recording state is best-effort: a sync that succeeded should not report
+    // failure just because the state directory is unwritable.
+    let recorded = state::StateStore::open()
+        .and_then(|store| {
+            store.update(|saved| {
+                let now = Utc::now().timestamp();
+                let diff = saved.diff_run(&keys, &results);
+                saved.record_run(&keys, &results, now);
+                saved.prune(None, cfg.state_retention_days, now);
+                diff
+            })
+        })
+        .and_then(|diff| {
+            run_report.diff = Some(diff);
+            history::append(&history::RunRecord::new(&meta, &results, &run_configs))
+        });
+    print!("{}", report::renderer(format).render(&run_report));
+    let rendered = notify_renderer.render(&run_report);
+    if let Err(err) = recorded {
+        eprintln!("Warning: failed to record run state: {err:#}");
+    }
+
+    let policy = args.exit_code.unwrap_or(cfg.exit_code);
+    let exit_code = report::exit_code(&results, policy);
+    if exit_code != 0
+        && let Some(log) = logging::run_log()
+    {
+        eprintln!("Full log: {}", log.display());
+    }
+    for (notify_cfg, paths) in &notify_groups {
+        let group = results
+            .iter()
+            .filter(|item| paths.contains(&item.repo))
+            .cloned()
+            .collect::<Vec<_>>();
+        if group.len() == results.len() {
+            send_notifications(notify_cfg, &group, &rendered, exit_code);
+        } else {
+            let report = notify_renderer.render(&report::RunReport {
+                results: &group,
+                paths: &path_display,
+                hosts: None,
+                roots: None,
+                diff: None,
+                meta: None,
+            });
+            send_notifications(
+                notify_cfg,
+                &group,
+                &report,
+                report::exit_code(&group, policy),
+            );
+        }
+    }
+
+    if let Some(command) = &cfg.hooks.post_run {
+        let failed = results
+            .iter()
+            .filter(|item| {
+                matches!(
+                    item.status,
+                    workflow::RepoStatus::Failed | workflow::RepoStatus::Environment
+                )
+            })
+            .count();
+        let env = [
+            ("SHEPHARD_EXIT_CODE", exit_code.to_string()),
+            ("SHEPHARD_REPO_COUNT", results.len().to_string()),
+            ("SHEPHARD_FAILED_COUNT", failed.to_string()),
+        ];
+        let env = env
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect::<Vec<_>>();
+        if let Err(err) = workflow::run_shell(&run_dir, command, &env) {
+            eprintln!("Warning: post_run hook failed: {err:#}");
+        }
+    }
+
+    Ok(exit_code)
+}
 
-    Ok(report::exit_code(&results))
+/// `apply --undo`: resets the repository to before its last recorded apply and
+/// forgets the record.
+fn undo_apply(
+    args: &ApplyArgs,
+    config: &config::ResolvedConfig,
+    keys: &state::RepoKeys,
+) -> Result<()> {
+    let repo = apply::target_repo(args)?;
+    let store = state::StateStore::open()?;
+    let record = store
+        .read()?
+        .last_apply(keys, &repo)
+        .cloned()
+        .with_context(|| format!("no apply recorded for {}", repo.display()))?;
+    apply::undo(config, &repo, &record, args.force)?;
+    store.update(|saved| saved.forget_apply(keys, &repo))?;
+    println!(
+        "Undid the {} apply in {}; HEAD is back at {}",
+        record.method,
+        repo.display(),
+        &record.before[..record.before.len().min(12)]
+    );
+    Ok(())
 }
 
-fn resolve_configured_targets(
+/// Reruns the repositories whose credentials could not be asked for during
+/// the batch, one at a time with git and ssh prompting on the terminal, so
+/// one-time passwords and key passphrases can be typed in.
+fn retry_with_terminal_auth(
+    results: &mut [workflow::RepoResult],
+    targets: Vec<(PathBuf, ResolvedRunConfig)>,
+) {
+    for (path, mut run_cfg) in targets {
+        let Some(result) = results
+            .iter_mut()
+            .find(|item| item.repo == path && report::is_auth_failure(item))
+        else {
+            continue;
+        };
+        eprintln!("Credentials needed for {}:", path.display());
+        run_cfg.auth.mode = AuthMode::Terminal;
+        if let Some(retried) = workflow::run_with_repo_configs(&[(path, run_cfg)]).pop() {
+            *result = retried;
+        }
+    }
+}
+
+/// Splits the repositories of a run by who hears about them: each workspace
+/// root with its own `notify` gets the repositories under it, and `[notify]`
+/// gets the rest. Groups with no repositories are left out.
+fn notify_groups<'a>(
+    global: &'a NotifyConfig,
+    roots: &'a [ResolvedWorkspaceRoot],
+    paths: &[PathBuf],
+) -> Vec<(&'a NotifyConfig, BTreeSet<PathBuf>)> {
+    let mut groups: Vec<(&NotifyConfig, BTreeSet<PathBuf>)> = Vec::new();
+    for path in paths {
+        let notify_cfg = config::root_for(roots, path)
+            .and_then(|root| root.notify.as_ref())
+            .unwrap_or(global);
+        match groups
+            .iter_mut()
+            .find(|(existing, _)| std::ptr::eq(*existing, notify_cfg))
+        {
+            Some((_, group)) => {
+                group.insert(path.clone());
+            }
+            None => groups.push((notify_cfg, BTreeSet::from([path.clone()]))),
+        }
+    }
+    groups
+}
+
+/// Mails, posts and pings one set of notification targets about a run.
+/// Failures are warnings: they never change the run's exit code.
+fn send_notifications(
+    notify_cfg: &NotifyConfig,
+    results: &[workflow::RepoResult],
+    rendered: &str,
+    exit_code: i32,
+) {
+    if let Some(email) = &notify_cfg.email
+        && (exit_code != 0 || !email.only_on_failure)
+        && let Err(err) = notify::send_email(email, &notify::report_subject(results), rendered)
+    {
+        eprintln!("Warning: failed to send run report email: {err:#}");
+    }
+    if let Some(url) = &notify_cfg.healthcheck_url {
+        let ping = if exit_code == 0 {
+            HealthcheckPing::Success
+        } else {
+            HealthcheckPing::Fail
+        };
+        if let Err(err) = notify::ping_healthcheck(url, ping, rendered) {
+            eprintln!("Warning: failed to ping healthcheck: {err:#}");
+        }
+    }
+    for webhook in &notify_cfg.webhooks {
+        if (exit_code != 0 || !webhook.only_on_failure)
+            && let Err(err) = notify::send_webhook(
+                webhook,
+                &notify::report_subject(results),
+                rendered,
+                results,
+                exit_code,
+            )
+        {
+            eprintln!("Warning: failed to post run report to webhook: {err:#}");
+        }
+    }
+}
+
+/// Re-resolves run configs for `targets` from the config file on disk,
+/// keeping earlier answers to `include_untracked = "ask"` and the computed
+/// excludes, and reapplying the plan being executed. Repositories the new
+/// config no longer selects keep their old config for the rest of the run.
+fn reload_run_targets(
     args: &RunArgs,
+    plan: Option<&plan::Plan>,
+    targets: Vec<(PathBuf, ResolvedRunConfig)>,
+) -> Result<Vec<(PathBuf, ResolvedRunConfig)>> {
+    let cfg = config::load()?;
+    let base_run_cfg = config::resolve_run_config(&cfg, args)?;
+    let repos = select_repositories(&cfg, &args.repos, &args.roots)?;
+    Ok(targets
+        .into_iter()
+        .map(|(path, previous)| {
+            let Some(repo) = repos.iter().find(|repo| repo.path == path) else {
+                return (path, previous);
+            };
+            let mut run_cfg = config::resolve_repo_run_config(&base_run_cfg, args, repo);
+            if let Some(entry) = plan.and_then(|plan| plan.entry(&path)) {
+                entry.apply(&mut run_cfg);
+            }
+            if run_cfg.include_untracked == UntrackedPolicy::Ask {
+                run_cfg.include_untracked = previous.include_untracked;
+            }
+            run_cfg.excludes = previous.excludes;
+            run_cfg.journal_dir = previous.journal_dir;
+            run_cfg.last_snapshot = previous.last_snapshot;
+            (path, run_cfg)
+        })
+        .collect())
+}
+
+fn watch(args: &WatchArgs) -> Result<i32> {
+    if args.run.write_plan.is_some() || args.run.plan_file.is_some() || args.run.interactive_auth {
+        bail!("watch cannot be combined with --write-plan, --plan-file or --interactive-auth");
+    }
+    let mut run_args = args.run.clone();
+    if let Some(list) = &run_args.repos_from {
+        run_args.repos.extend(pick::read_repo_list(list)?);
+    }
+    let cfg = config::load()?;
+    // SAFETY: the file watcher's thread has not been started yet. The
+    // environment is set up once here, since syncs run alongside it.
+    let env_problems = unsafe { environment::prepare(&cfg.environment) };
+    let repos = select_repositories(&cfg, &run_args.repos, &run_args.roots)?
+        .into_iter()
+        .filter(|repo| is_git_repo(&repo.path))
+        .map(|repo| repo.path)
+        .collect::<Vec<_>>();
+    if repos.is_empty() {
+        println!("No repositories selected.");
+        return Ok(0);
+    }
+
+    println!(
+        "Watching {} repositories; each syncs {}s after its files stop changing",
+        repos.len(),
+        args.debounce_secs
+    );
+    watch::watch(&repos, Duration::from_secs(args.debounce_secs), |changed| {
+        let sync_args = RunArgs {
+            repos: changed.to_vec(),
+            repos_from: None,
+            non_interactive: true,
+            ..run_args.clone()
+        };
+        sync_in_environment(&sync_args, config::load()?, &env_problems).map(|_| ())
+    })?;
+    Ok(0)
+}
+
+fn move_repo(args: &MoveArgs) -> Result<i32> {
+    if !discovery::is_git_repository(&args.new) {
+        bail!(
+            "{} is not a git repository; move the repository before running shephard move",
+            args.new.display()
+        );
+    }
+    let old = std::path::absolute(&args.old)
+        .with_context(|| format!("failed resolving {}", args.old.display()))?;
+    let new = args
+        .new
+        .canonicalize()
+        .with_context(|| format!("failed resolving {}", args.new.display()))?;
+
+    let path = config::config_path()?;
+    if path.exists() {
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("failed reading config file at {}", path.display()))?;
+        let config_dir = path
+            .parent()
+            .context("unable to determine parent directory for config file")?;
+        let (rewritten, changed) = config::rewrite_repository_path(&raw, config_dir, &old, &new)
+            .with_context(|| format!("failed parsing config file at {}", path.display()))?;
+        if changed > 0 {
+            config::parse(&rewritten, config_dir).context("the edited config would not load")?;
+            fs::write(&path, rewritten)
+                .with_context(|| format!("failed writing config file at {}", path.display()))?;
+        }
+        println!(
+            "Updated {changed} [[repositories]] entries in {}",
+            path.display()
+        );
+    }
+
+    let moved = state::StateStore::open()?.update(|saved| {
+        saved.rename_repo(
+            &config::canonical_repo_key(&old),
+            &config::canonical_repo_key(&new),
+        )
+    })?;
+    if moved {
+        println!("Moved state record to {}", new.display());
+    } else {
+        println!(
+            "No path-keyed state record for {}; records keyed by root commit or id follow the repository",
+            old.display()
+        );
+    }
+    Ok(0)
+}
+
+/// Edits `[[repositories]]` entries in the config file, which is checked to
+/// still load before it is written.
+fn edit_repositories(command: &RepoCommand) -> Result<i32> {
+    let path = config::config_path()?;
+    let config_dir = path
+        .parent()
+        .context("unable to determine parent directory for config file")?;
+    let raw = if path.exists() {
+        fs::read_to_string(&path)
+            .with_context(|| format!("failed reading config file at {}", path.display()))?
+    } else {
+        String::new()
+    };
+    let absolute = |repo: &Path| {
+        std::path::absolute(repo).with_context(|| format!("failed resolving {}", repo.display()))
+    };
+
+    let (edited, note) = match command {
+        RepoCommand::Add(args) => {
+            if !discovery::is_git_repository(&args.path) {
+                bail!("{} is not a git repository", args.path.display());
+            }
+            let repo = args
+                .path
+                .canonicalize()
+                .with_context(|| format!("failed resolving {}", args.path.display()))?;
+            let edited = config::add_repository(&raw, config_dir, &repo, args.alias.as_deref())?;
+            (edited, format!("Added {} to", repo.display()))
+        }
+        RepoCommand::Remove(args) => {
+            let repo = absolute(&args.path)?;
+            let (edited, removed) = config::remove_repository(&raw, config_dir, &repo)?;
+            if removed == 0 {
+                bail!("{} is not configured", repo.display());
+            }
+            (edited, format!("Removed {} from", repo.display()))
+        }
+        RepoCommand::Enable(args) | RepoCommand::Disable(args) => {
+            let enabled = matches!(command, RepoCommand::Enable(_));
+            let repo = absolute(&args.path)?;
+            let (edited, matched) =
+                config::set_repository_enabled(&raw, config_dir, &repo, enabled)?;
+            if matched == 0 {
+                bail!(
+                    "{} is not configured; add it with `shephard repo add`",
+                    repo.display()
+                );
+            }
+            let verb = if enabled { "Enabled" } else { "Disabled" };
+            (edited, format!("{verb} {} in", repo.display()))
+        }
+    };
+
+    config::parse(&edited, config_dir).context("the edited config would not load")?;
+    fs::create_dir_all(config_dir)
+        .with_context(|| format!("failed creating {}", config_dir.display()))?;
+    fs::write(&path, edited)
+        .with_context(|| format!("failed writing config file at {}", path.display()))?;
+    println!("{note} {}", path.display());
+    Ok(0)
+}
+
+fn gc_state(args: &GcStateArgs) -> Result<i32> {
+    let cfg = config::load()?;
+    let store = state::StateStore::open()?;
+    let known = state::RepoKeys::load(&known_repositories(&cfg, &args.roots)?, &store).all_keys();
+
+    let prune = |saved: &mut state::State| {
+        let pruned = saved.prune(
+            Some(&known),
+            cfg.state_retention_days,
+            Utc::now().timestamp(),
+        );
+        (pruned, saved.repos.len())
+    };
+    let (pruned, kept) = if args.dry_run {
+        prune(&mut store.read()?)
+    } else {
+        store.update(prune)?
+    };
+    for (repo, reason) in &pruned {
+        let reason = match reason {
+            state::PruneReason::Unconfigured => "no longer configured".to_string(),
+            state::PruneReason::Expired => {
+                format!("not run in {} days", cfg.state_retention_days)
+            }
+        };
+        println!("[PRUNE] {repo} :: {reason}");
+    }
+    println!(
+        "{} {} state records, {} kept",
+        if args.dry_run {
+            "Would prune"
+        } else {
+            "Pruned"
+        },
+        pruned.len(),
+        kept
+    );
+    Ok(0)
+}
+
+/// Resolves the repositories a command operates on: configured repositories
+/// plus repositories discovered under workspace roots, filtered by `--repos`.
+fn select_repositories(
+    cfg: &ResolvedConfig,
+    repos: &[PathBuf],
+    roots: &[PathBuf],
+) -> Result<Vec<ResolvedRepositoryConfig>> {
+    let all_repositories = known_repositories(cfg, roots)?;
+    Ok(filter_targets(repos, &all_repositories))
+}
+
+/// Like [`select_repositories`], also resolving state keys over every known
+/// repository so root-commit collisions are detected regardless of `--repos`.
+fn select_repositories_with_keys(
+    cfg: &ResolvedConfig,
+    repos: &[PathBuf],
+    roots: &[PathBuf],
+) -> Result<(Vec<ResolvedRepositoryConfig>, state::RepoKeys)> {
+    let all_repositories = known_repositories(cfg, roots)?;
+    Ok((
+        filter_targets(repos, &all_repositories),
+        state::RepoKeys::load(&all_repositories, &state::StateStore::open()?),
+    ))
+}
+
+/// Selects the enabled repositories matching `repos` (all of them when empty),
+/// highest `priority` first and by path within a priority.
+fn filter_targets(
+    repos: &[PathBuf],
+    all_repositories: &[ResolvedRepositoryConfig],
+) -> Vec<ResolvedRepositoryConfig> {
+    let enabled_repositories = all_repositories
+        .iter()
+        .filter(|repo| repo.enabled)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut selected = resolve_configured_targets(repos, &enabled_repositories, all_repositories);
+    selected.sort_by(|left, right| {
+        right
+            .priority
+            .cmp(&left.priority)
+            .then_with(|| left.path.cmp(&right.path))
+    });
+    selected
+}
+
+/// Configured repositories, enabled or not, plus repositories discovered under
+/// the configured and `--roots` workspace roots.
+fn known_repositories(
+    cfg: &ResolvedConfig,
+    roots: &[PathBuf],
+) -> Result<Vec<ResolvedRepositoryConfig>> {
+    let roots = config::resolve_workspace_roots(cfg, roots);
+    let root_paths = roots
+        .iter()
+        .map(|root| root.path.clone())
+        .collect::<Vec<_>>();
+    let discovered = discovery::discover_repositories(
+        &root_paths,
+        cfg.descend_hidden_dirs,
+        &cfg.discovery_excludes,
+    )?
+    .into_iter()
+    .map(|repo| repo.path)
+    .collect::<Vec<_>>();
+    Ok(config::merge_discovered_repositories(
+        &cfg.repositories,
+        &discovered,
+        &roots,
+    ))
+}
+
+fn resolve_configured_targets(
+    repos: &[PathBuf],
     enabled_repositories: &[ResolvedRepositoryConfig],
     all_repositories: &[ResolvedRepositoryConfig],
 ) -> Vec<ResolvedRepositoryConfig> {
-    if args.repos.is_empty() {
+    if repos.is_empty() {
         return enabled_repositories.to_vec();
     }
 
@@ -92,7 +1003,7 @@ fn resolve_configured_targets(
     let mut selected = Vec::new();
     let mut seen = BTreeSet::new();
 
-    for path in &args.repos {
+    for path in repos {
         let key = config::canonical_repo_key(path);
         if !seen.insert(key.clone()) {
             continue;
@@ -123,16 +1034,15 @@ fn is_git_repo(path: &Path) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-
     use pretty_assertions::assert_eq;
 
     use super::*;
-    use shephard::config::ResolvedRepositorySideChannelConfig;
+    use shephard::config::{
+        GitCredentials, RepoCommands, RepoHooks, ResolvedRepositorySideChannelConfig,
+    };
 
     #[test]
     fn resolve_targets_defaults_to_enabled_repositories() {
-        let args = RunArgs::default();
         let all = vec![
             repo_config("/tmp/repo-a", true),
             repo_config("/tmp/repo-b", false),
@@ -144,7 +1054,7 @@ mod tests {
             .cloned()
             .collect::<Vec<_>>();
 
-        let selected = resolve_configured_targets(&args, &enabled, &all);
+        let selected = resolve_configured_targets(&[], &enabled, &all);
         let selected_paths = selected
             .into_iter()
             .map(|repo| repo.path)
@@ -162,14 +1072,10 @@ mod tests {
         let repo_path = temp.path().join("repo");
         std::fs::create_dir_all(&repo_path).expect("repo directory should be created");
 
-        let args = RunArgs {
-            repos: vec![repo_path.clone()],
-            ..RunArgs::default()
-        };
         let all = vec![repo_config(&repo_path.to_string_lossy(), true)];
         let enabled = all.clone();
 
-        let selected = resolve_configured_targets(&args, &enabled, &all);
+        let selected = resolve_configured_targets(std::slice::from_ref(&repo_path), &enabled, &all);
         let selected_paths = selected
             .into_iter()
             .map(|repo| repo.path)
@@ -178,12 +1084,121 @@ mod tests {
         assert_eq!(selected_paths, vec![repo_path]);
     }
 
+    #[test]
+    fn filter_targets_orders_by_priority_then_path() {
+        let all = vec![
+            repo_config("/tmp/repo-c", true),
+            ResolvedRepositoryConfig {
+                priority: 10,
+                ..repo_config("/tmp/repo-notes", true)
+            },
+            repo_config("/tmp/repo-a", true),
+            ResolvedRepositoryConfig {
+                priority: -1,
+                ..repo_config("/tmp/repo-archive", true)
+            },
+        ];
+
+        let selected_paths = filter_targets(&[], &all)
+            .into_iter()
+            .map(|repo| repo.path)
+            .collect::<Vec<PathBuf>>();
+
+        assert_eq!(
+            selected_paths,
+            vec![
+                PathBuf::from("/tmp/repo-notes"),
+                PathBuf::from("/tmp/repo-a"),
+                PathBuf::from("/tmp/repo-c"),
+                PathBuf::from("/tmp/repo-archive"),
+            ]
+        );
+    }
+
+    #[test]
+    fn notify_groups_keep_root_repositories_out_of_global_notify() {
+        let global = NotifyConfig {
+            healthcheck_url: Some("https://hc.example.com/personal".to_string()),
+            ..NotifyConfig::default()
+        };
+        let roots = vec![
+            ResolvedWorkspaceRoot {
+                path: PathBuf::from("/tmp/roots/work"),
+                name: "work".to_string(),
+                include_untracked: None,
+                side_channel: ResolvedRepositorySideChannelConfig::default(),
+                notify: Some(NotifyConfig {
+                    healthcheck_url: Some("https://hc.example.com/work".to_string()),
+                    ..NotifyConfig::default()
+                }),
+            },
+            ResolvedWorkspaceRoot {
+                path: PathBuf::from("/tmp/roots/personal"),
+                name: "personal".to_string(),
+                include_untracked: None,
+                side_channel: ResolvedRepositorySideChannelConfig::default(),
+                notify: None,
+            },
+        ];
+        let paths = [
+            PathBuf::from("/tmp/roots/work/api"),
+            PathBuf::from("/tmp/roots/personal/notes"),
+            PathBuf::from("/tmp/roots/work/web"),
+            PathBuf::from("/tmp/elsewhere"),
+        ];
+
+        let groups = notify_groups(&global, &roots, &paths)
+            .into_iter()
+            .map(|(notify_cfg, paths)| {
+                (
+                    notify_cfg.healthcheck_url.clone().unwrap_or_default(),
+                    paths,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            groups,
+            vec![
+                (
+                    "https://hc.example.com/work".to_string(),
+                    BTreeSet::from([
+                        PathBuf::from("/tmp/roots/work/api"),
+                        PathBuf::from("/tmp/roots/work/web"),
+                    ])
+                ),
+                (
+                    "https://hc.example.com/personal".to_string(),
+                    BTreeSet::from([
+                        PathBuf::from("/tmp/roots/personal/notes"),
+                        PathBuf::from("/tmp/elsewhere"),
+                    ])
+                ),
+            ]
+        );
+    }
+
     fn repo_config(path: &str, enabled: bool) -> ResolvedRepositoryConfig {
         ResolvedRepositoryConfig {
             path: PathBuf::from(path),
             enabled,
+            mode: None,
             include_untracked: None,
+            builtin_excludes: None,
+            push_enabled: None,
             side_channel: ResolvedRepositorySideChannelConfig::default(),
+            force_include: Vec::new(),
+            commands: RepoCommands::default(),
+            colocated_jj: None,
+            id: None,
+            lockfiles: None,
+            priority: 0,
+            credentials: GitCredentials::default(),
+            alias: None,
+            apply_method: None,
+            clone_url: None,
+            read_only: false,
+            hooks: RepoHooks::default(),
         }
     }
 }
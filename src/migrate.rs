@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cli::RemotesMigrateArgs;
+use crate::config::ResolvedRepositoryConfig;
+use crate::git;
+use crate::prompt;
+use crate::remote;
+
+/// One remote whose URL a `[url_rewrites]` rule changes.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UrlChange {
+    pub remote: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Prints which remote URLs `[url_rewrites]` would change in every selected
+/// repository, then rewrites them after confirmation (or straight away with
+/// `--yes`).
+pub fn run(
+    args: &RemotesMigrateArgs,
+    rewrites: &BTreeMap<String, String>,
+    repos: &[ResolvedRepositoryConfig],
+) -> Result<i32> {
+    let mut failed = false;
+    let mut plans = Vec::<(PathBuf, Vec<UrlChange>)>::new();
+    for repo in repos {
+        match plan_repo(&repo.path, rewrites) {
+            Ok(changes) if changes.is_empty() => {}
+            Ok(changes) => plans.push((repo.path.clone(), changes)),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+
+    if plans.is_empty() {
+        println!("No remote URLs to rewrite.");
+        return Ok(i32::from(failed));
+    }
+    for (repo, changes) in &plans {
+        println!("{}", repo.display());
+        for change in changes {
+            println!("  {}: {} -> {}", change.remote, change.from, change.to);
+        }
+    }
+
+    if !args.yes {
+        if !prompt::is_interactive(false) {
+            println!("Re-run with --yes to apply this plan.");
+            return Ok(i32::from(failed));
+        }
+        if !prompt::confirm("Rewrite these remote URLs?")? {
+            return Ok(i32::from(failed));
+        }
+    }
+
+    for (repo, changes) in &plans {
+        for change in changes {
+            if let Err(err) = git::set_remote_url(repo, &change.remote, &change.to) {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.display());
+            }
+        }
+    }
+    Ok(i32::from(failed))
+}
+
+/// The remotes of `repo` whose URL `rewrites` changes.
+pub fn plan_repo(repo: &Path, rewrites: &BTreeMap<String, String>) -> Result<Vec<UrlChange>> {
+    Ok(git::remotes(repo)?
+        .into_iter()
+        .filter_map(|(remote, from)| {
+            let to = remote::rewrite_url(rewrites, &from).filter(|to| *to != from)?;
+            Some(UrlChange { remote, from, to })
+        })
+        .collect())
+}
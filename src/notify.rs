@@ -0,0 +1,302 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+use chrono::{Local, Utc};
+use serde_json::{Value, json};
+
+use crate::config::{EmailNotifyConfig, WebhookKind, WebhookNotifyConfig};
+use crate::report;
+use crate::workflow::RepoResult;
+
+/// Longest report excerpt sent to chat services; Slack sections cap at 3000
+/// characters and Discord embed descriptions at 4096.
+const CHAT_REPORT_LIMIT: usize = 2800;
+
+/// Subject line for a run report, e.g. `shephard on nas: 2 failed, 1 busy of 30`.
+pub fn report_subject(results: &[RepoResult]) -> String {
+    let host = hostname::get()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let summary = report::summarize(results);
+    if summary.failed == 0 && summary.busy == 0 {
+        format!("shephard on {host}: {} repos ok", results.len())
+    } else {
+        format!(
+            "shephard on {host}: {} failed, {} busy of {}",
+            summary.failed,
+            summary.busy,
+            results.len()
+        )
+    }
+}
+
+/// Mails `report` to the configured recipients with curl's SMTP client. With a
+/// `username`, the connection must upgrade to TLS before credentials are sent.
+pub fn send_email(email: &EmailNotifyConfig, subject: &str, report: &str) -> Result<()> {
+    let mut message = tempfile::NamedTempFile::new().context("failed to allocate mail file")?;
+    let headers = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {subject}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        email.from,
+        email.to.join(", "),
+        Local::now().to_rfc2822()
+    );
+    message
+        .write_all(headers.as_bytes())
+        .and_then(|()| message.write_all(report.replace('\n', "\r\n").as_bytes()))
+        .context("failed writing mail file")?;
+
+    let mut args = vec![
+        "--url".to_string(),
+        email.server.clone(),
+        "--mail-from".to_string(),
+        email.from.clone(),
+        "--upload-file".to_string(),
+        message.path().to_string_lossy().to_string(),
+    ];
+    for recipient in &email.to {
+        args.extend(["--mail-rcpt".to_string(), recipient.clone()]);
+    }
+    let mut curl_config = String::new();
+    if let Some(username) = &email.username {
+        args.push("--ssl-reqd".to_string());
+        let password = match &email.password_command {
+            Some(command) => secret_from_command(command)?,
+            None => String::new(),
+        };
+        curl_config = format!("user = {}\n", curl_quote(&format!("{username}:{password}")));
+    } else {
+        args.push("--ssl".to_string());
+    }
+
+    curl(&args, &curl_config).with_context(|| format!("sending mail through {}", email.server))
+}
+
+/// Posts the run report to a webhook in the format its `kind` expects.
+pub fn send_webhook(
+    webhook: &WebhookNotifyConfig,
+    subject: &str,
+    report: &str,
+    results: &[RepoResult],
+    exit_code: i32,
+) -> Result<()> {
+    let payload = webhook_payload(webhook.kind, subject, report, results, exit_code);
+    let mut body = tempfile::NamedTempFile::new().context("failed to allocate webhook body")?;
+    body.write_all(payload.to_string().as_bytes())
+        .context("failed writing webhook body")?;
+
+    // Matrix sends are idempotent PUTs keyed by a client transaction id.
+    let (method, url) = match webhook.kind {
+        WebhookKind::Matrix => (
+            "PUT",
+            format!(
+                "{}/shephard-{}",
+                webhook.url.trim_end_matches('/'),
+                Utc::now().timestamp_millis()
+            ),
+        ),
+        WebhookKind::Generic | WebhookKind::Slack | WebhookKind::Discord => {
+            ("POST", webhook.url.clone())
+        }
+    };
+    let mut curl_config = "header = \"Content-Type: application/json\"\n".to_string();
+    if let Some(command) = &webhook.token_command {
+        let header = format!("Authorization: Bearer {}", secret_from_command(command)?);
+        curl_config.push_str(&format!("header = {}\n", curl_quote(&header)));
+    }
+    let args = [
+        "--fail".to_string(),
+        "--request".to_string(),
+        method.to_string(),
+        "--data-binary".to_string(),
+        format!("@{}", body.path().display()),
+        "--url".to_string(),
+        url,
+    ];
+
+    curl(&args, &curl_config).with_context(|| format!("posting run report to {}", webhook.url))
+}
+
+/// Which healthcheck endpoint a ping goes to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HealthcheckPing {
+    Start,
+    Success,
+    Fail,
+}
+
+/// Pings a healthchecks.io-style URL, attaching `body` (the run report) for
+/// the check's event log. Pings give up quickly so a dead monitoring service
+/// never holds up a sync.
+pub fn ping_healthcheck(url: &str, ping: HealthcheckPing, body: &str) -> Result<()> {
+    let url = url.trim_end_matches('/');
+    let url = match ping {
+        HealthcheckPing::Start => format!("{url}/start"),
+        HealthcheckPing::Success => url.to_string(),
+        HealthcheckPing::Fail => format!("{url}/fail"),
+    };
+    let mut file = tempfile::NamedTempFile::new().context("failed to allocate ping body")?;
+    file.write_all(body.as_bytes())
+        .context("failed writing ping body")?;
+    let args = [
+        "--fail".to_string(),
+        "--max-time".to_string(),
+        "10".to_string(),
+        "--data-binary".to_string(),
+        format!("@{}", file.path().display()),
+        "--url".to_string(),
+        url.clone(),
+    ];
+
+    curl(&args, "").with_context(|| format!("pinging {url}"))
+}
+
+/// Builds the JSON body for a webhook of `kind`.
+pub fn webhook_payload(
+    kind: WebhookKind,
+    subject: &str,
+    report: &str,
+    results: &[RepoResult],
+    exit_code: i32,
+) -> Value {
+    let excerpt = match report.char_indices().nth(CHAT_REPORT_LIMIT) {
+        Some((cut, _)) => format!("{}\n…", &report[..cut]),
+        None => report.to_string(),
+    };
+    match kind {
+        WebhookKind::Generic => json!({
+            "subject": subject,
+            "exit_code": exit_code,
+            "report": report,
+            "results": results
+                .iter()
+                .map(|item| json!({
+                    "repo": item.repo,
+                    "status": item.status,
+                    "message": item.message,
+                }))
+                .collect::<Vec<_>>(),
+        }),
+        WebhookKind::Slack => json!({
+            "text": subject,
+            "blocks": [
+                {"type": "header", "text": {"type": "plain_text", "text": subject}},
+                {"type": "section", "text": {"type": "mrkdwn", "text": format!("```{excerpt}```")}},
+            ],
+        }),
+        WebhookKind::Discord => json!({
+            "embeds": [{
+                "title": subject,
+                "description": format!("```\n{excerpt}\n```"),
+                // Red when the run failed, green otherwise.
+                "color": if exit_code == 0 { 0x2e_b8_86 } else { 0xd9_3f_3f },
+            }],
+        }),
+        WebhookKind::Matrix => json!({
+            "msgtype": "m.text",
+            "body": format!("{subject}\n\n{report}"),
+            "format": "org.matrix.custom.html",
+            "formatted_body": format!(
+                "<strong>{}</strong><pre>{}</pre>",
+                html_escape(subject),
+                html_escape(&excerpt)
+            ),
+        }),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Quotes a value for a curl config file.
+fn curl_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs a `*_command` setting and returns its trimmed stdout.
+fn secret_from_command(command: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .output()
+        .with_context(|| format!("failed running `{command}`"))?;
+    if !output.status.success() {
+        bail!(
+            "`{command}` failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Runs curl with `args`, passing `config` (credentials, headers) on stdin so
+/// secrets never appear in the process list.
+fn curl(args: &[String], config: &str) -> Result<()> {
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--config", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed running curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(config.as_bytes())
+            .context("failed passing configuration to curl")?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("failed waiting for curl")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn chat_payloads_carry_subject_and_report() {
+        let report = "Processed 1 repos: 0 success, 0 no-op, 0 busy, 0 skipped, 1 failed\n";
+        let payloads = [
+            WebhookKind::Slack,
+            WebhookKind::Discord,
+            WebhookKind::Matrix,
+        ]
+        .map(|kind| webhook_payload(kind, "shephard on box: 1 failed", report, &[], 1));
+
+        assert_eq!(
+            payloads,
+            [
+                json!({
+                    "text": "shephard on box: 1 failed",
+                    "blocks": [
+                        {"type": "header", "text": {"type": "plain_text", "text": "shephard on box: 1 failed"}},
+                        {"type": "section", "text": {"type": "mrkdwn", "text": format!("```{report}```")}},
+                    ],
+                }),
+                json!({
+                    "embeds": [{
+                        "title": "shephard on box: 1 failed",
+                        "description": format!("```\n{report}\n```"),
+                        "color": 0xd9_3f_3f,
+                    }],
+                }),
+                json!({
+                    "msgtype": "m.text",
+                    "body": format!("shephard on box: 1 failed\n\n{report}"),
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": format!("<strong>shephard on box: 1 failed</strong><pre>{report}</pre>"),
+                }),
+            ]
+        );
+    }
+}
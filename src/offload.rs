@@ -0,0 +1,146 @@
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::{self, LastSnapshot, SideChannelBackend, SideChannelConfig};
+use crate::git::{self, SideChannelSyncResult};
+use crate::logging;
+
+/// Ref naming the snapshot inside each uploaded bundle.
+pub const SNAPSHOT_REF: &str = "refs/shephard/snapshot";
+/// Last snapshot that reached the destination, so unchanged trees are not
+/// uploaded again.
+const OFFLOADED_REF: &str = "refs/shephard/offloaded";
+
+/// Snapshots the working tree into a git bundle and stores it at the
+/// side-channel `destination` with rclone or restic, keyed by [`bundle_key`]
+/// and host. The bundle holds the snapshot and any local commits it sits on,
+/// with the upstream tip as its prerequisite.
+pub fn offload_snapshot(
+    repo: &Path,
+    side: &SideChannelConfig,
+    include_untracked: bool,
+    excludes: &[&str],
+    force_include: &[String],
+    message: &str,
+) -> Result<SideChannelSyncResult> {
+    let Some(destination) = &side.destination else {
+        bail!(
+            "side_channel.destination is required for the {} backend",
+            side.backend
+        );
+    };
+    let Some(tree) = git::snapshot_tree(
+        repo,
+        include_untracked,
+        side.snapshot_ignored,
+        excludes,
+        force_include,
+    )?
+    else {
+        return Ok(SideChannelSyncResult::NoChanges);
+    };
+    let offloaded = git::rev_parse_optional(repo, &format!("{OFFLOADED_REF}^{{tree}}"))?;
+    if offloaded.as_deref() == Some(tree.as_str()) {
+        return Ok(SideChannelSyncResult::NoChanges);
+    }
+    let previous = offloaded.unwrap_or_else(|| "HEAD".to_string());
+    if git::below_snapshot_threshold(repo, side, &previous, &tree)? {
+        return Ok(SideChannelSyncResult::BelowThreshold);
+    }
+
+    let stats = git::snapshot_stats(repo, &tree)?;
+    let head = git::rev_parse(repo, "HEAD")?.trim().to_string();
+    let snapshot = git::commit_tree(repo, &tree, Some(&head), message)?;
+    git::update_ref(repo, SNAPSHOT_REF, &snapshot)?;
+
+    let staging = tempfile::tempdir().context("failed to create bundle staging directory")?;
+    let bundle = staging.path().join("snapshot.bundle");
+    let mut revs = vec![SNAPSHOT_REF.to_string()];
+    if let Some(upstream) = git::rev_parse_optional(repo, "@{upstream}")? {
+        revs.push(format!("^{upstream}"));
+    }
+    git::create_bundle(repo, &bundle, &revs)?;
+
+    let host = hostname::get()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let key = bundle_key(repo);
+    match side.backend {
+        SideChannelBackend::Rclone => {
+            let target = format!("{}/{key}/{host}.bundle", destination.trim_end_matches('/'));
+            run(
+                Command::new("rclone")
+                    .arg("copyto")
+                    .arg(&bundle)
+                    .arg(&target),
+                "rclone",
+            )?;
+        }
+        SideChannelBackend::Restic => {
+            let file = File::open(&bundle).context("failed to open snapshot bundle")?;
+            run(
+                Command::new("restic")
+                    .args(["--repo", destination, "backup", "--quiet", "--host", &host])
+                    .args(["--tag", "shephard", "--stdin", "--stdin-filename"])
+                    .arg(format!("{key}.bundle"))
+                    .stdin(Stdio::from(file)),
+                "restic",
+            )?;
+        }
+        SideChannelBackend::Git => bail!("the git backend pushes to a remote, not a destination"),
+    }
+
+    git::update_ref(repo, OFFLOADED_REF, &snapshot)?;
+    Ok(SideChannelSyncResult::Pushed {
+        stats,
+        snapshot: LastSnapshot {
+            target: format!("{} {destination}", side.backend),
+            tree,
+            tip: None,
+        },
+    })
+}
+
+/// Names `repo`'s bundles: its directory name, for people browsing the
+/// destination, then a hash of its canonical path, so two checkouts sharing a
+/// directory name (`~/work/api` and `~/oss/api`) never overwrite each other.
+pub fn bundle_key(repo: &Path) -> String {
+    // FNV-1a, which unlike `DefaultHasher` is the same on every build.
+    let hash = config::canonical_repo_key(repo)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{}-{hash:016x}", git::repo_name(repo))
+}
+
+fn run(command: &mut Command, program: &str) -> Result<()> {
+    let output = logging::output(command).with_context(|| format!("failed running {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_keys_tell_apart_repositories_with_one_name() {
+        let work = bundle_key(Path::new("/nonexistent/work/api"));
+        let oss = bundle_key(Path::new("/nonexistent/oss/api"));
+
+        assert!(work.starts_with("api-") && oss.starts_with("api-"));
+        assert_ne!(work, oss);
+        assert_eq!(work, bundle_key(Path::new("/nonexistent/work/api")));
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::ResolvedRepositoryConfig;
+
+/// Prints the paths of `repos` one per line. With a picker command such as
+/// `fzf --multi`, the paths are piped through it instead and only the chosen
+/// ones are printed, ready for `run --repos-from -`. Returns 1 when the picker
+/// chose nothing.
+pub fn run(repos: &[ResolvedRepositoryConfig], picker: Option<&str>) -> Result<i32> {
+    let list = repos
+        .iter()
+        .map(|repo| format!("{}\n", repo.path.display()))
+        .collect::<String>();
+    let Some(picker) = picker else {
+        print!("{list}");
+        return Ok(0);
+    };
+
+    let chosen = choose(picker, &list)?;
+    print!("{chosen}");
+    Ok(i32::from(chosen.is_empty()))
+}
+
+/// Runs `picker` through `sh -c` with `list` on its stdin and returns what it
+/// printed. Pickers draw on the terminal themselves, so stderr is passed
+/// through; exiting non-zero without output (fzf's Escape) means nothing was
+/// chosen.
+pub fn choose(picker: &str, list: &str) -> Result<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", picker])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("failed running picker `{picker}`"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A picker may exit before reading everything, e.g. `head -n 1`.
+        let _ = stdin.write_all(list.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("failed waiting for picker `{picker}`"))?;
+    let chosen = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.status.success() && !chosen.is_empty() {
+        bail!("picker `{picker}` failed ({})", output.status);
+    }
+    Ok(chosen)
+}
+
+/// Reads repository paths, one per line, from `path` (`-` for stdin),
+/// skipping blank lines.
+pub fn read_repo_list(path: &Path) -> Result<Vec<PathBuf>> {
+    let raw = if path == Path::new("-") {
+        let mut raw = String::new();
+        io::stdin()
+            .read_to_string(&mut raw)
+            .context("failed reading repository list from stdin")?;
+        raw
+    } else {
+        fs::read_to_string(path)
+            .with_context(|| format!("failed reading repository list {}", path.display()))?
+    };
+    Ok(raw
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn choose_returns_picker_output_and_nothing_on_abort() {
+        let list = "/srv/a\n/srv/b\n/srv/c\n";
+
+        assert_eq!(
+            (
+                choose("grep -v /srv/b", list).expect("picker should run"),
+                choose("exit 130", list).expect("an aborted picker chooses nothing"),
+            ),
+            ("/srv/a\n/srv/c\n".to_string(), String::new())
+        );
+    }
+
+    #[test]
+    fn read_repo_list_skips_blank_lines() {
+        let temp = tempfile::tempdir().expect("tempdir should work");
+        let list = temp.path().join("repos.txt");
+        fs::write(&list, "/srv/a\n\n  /srv/with space  \n").expect("list should be written");
+
+        assert_eq!(
+            read_repo_list(&list).expect("list should be read"),
+            vec![PathBuf::from("/srv/a"), PathBuf::from("/srv/with space")]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ResolvedRunConfig, RunMode, UntrackedPolicy};
+
+/// What `shephard run` would do to each repository, written by
+/// `run --write-plan` for review and executed by `run --plan-file`. Entries can
+/// be edited or deleted by hand before the plan is executed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    pub created_at: i64,
+    pub host: String,
+    pub repos: Vec<PlanRepo>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PlanRepo {
+    /// Canonical repository path; must be a configured or discovered repository
+    /// when the plan runs.
+    pub path: PathBuf,
+    pub mode: RunMode,
+    /// Push upstream (or run `commands.push`) after pulling.
+    pub push: bool,
+    pub include_untracked: bool,
+    /// Snapshot to the side channel instead of committing on the branch.
+    pub side_channel: bool,
+}
+
+impl Plan {
+    /// Captures the decisions already resolved for a run's targets, including
+    /// answers to `include_untracked = "ask"`.
+    pub fn from_targets(targets: &[(PathBuf, ResolvedRunConfig)]) -> Self {
+        Plan {
+            created_at: Utc::now().timestamp(),
+            host: hostname::get()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            repos: targets
+                .iter()
+                .map(|(path, cfg)| PlanRepo {
+                    path: path.clone(),
+                    mode: cfg.mode,
+                    push: cfg.push_enabled,
+                    include_untracked: match cfg.include_untracked {
+                        UntrackedPolicy::Include => true,
+                        UntrackedPolicy::Exclude | UntrackedPolicy::Ask => false,
+                    },
+                    side_channel: cfg.side_channel.enabled,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("failed reading plan {}", path.display()))?;
+        serde_json::from_str(&raw)
+            .with_context(|| format!("failed parsing plan {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let raw = serde_json::to_string_pretty(self).context("failed serializing plan")?;
+        fs::write(path, format!("{raw}\n"))
+            .with_context(|| format!("failed writing plan {}", path.display()))
+    }
+
+    /// The plan entry for `repo`, if the plan still lists it.
+    pub fn entry(&self, repo: &Path) -> Option<&PlanRepo> {
+        let key = config::canonical_repo_key(repo);
+        self.repos
+            .iter()
+            .find(|entry| config::canonical_repo_key(&entry.path) == key)
+    }
+}
+
+impl PlanRepo {
+    /// Overrides the config-derived decisions for this repository with the
+    /// plan's, leaving everything else (remotes, templates, excludes) to config.
+    pub fn apply(&self, cfg: &mut ResolvedRunConfig) {
+        cfg.mode = self.mode;
+        cfg.push_enabled = self.push;
+        cfg.include_untracked = if self.include_untracked {
+            UntrackedPolicy::Include
+        } else {
+            UntrackedPolicy::Exclude
+        };
+        cfg.side_channel.enabled = self.side_channel;
+    }
+}
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::config::GitAuth;
+use crate::git;
+use crate::remote;
+
+/// The remote a repository's current branch tracks and the host it lives on.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Upstream {
+    pub remote_name: String,
+    pub host: String,
+}
+
+/// Looks up each repository's upstream host, after `rewrites`, so remotes
+/// not yet migrated count towards the host they are moving to. Repositories
+/// without an upstream, or whose upstream is on this machine, are left out.
+pub fn upstream_hosts(
+    repos: &[PathBuf],
+    rewrites: &BTreeMap<String, String>,
+) -> BTreeMap<PathBuf, Upstream> {
+    let mut upstreams = BTreeMap::new();
+    for repo in repos {
+        let Ok(Some(remote_name)) = git::upstream_remote(repo) else {
+            continue;
+        };
+        let Ok(url) = git::remote_url(repo, &remote_name) else {
+            continue;
+        };
+        let url = remote::rewrite_url(rewrites, &url).unwrap_or(url);
+        if let Some(host) = remote::host(&remote::parse_remote_url(&url)) {
+            upstreams.insert(repo.clone(), Upstream { remote_name, host });
+        }
+    }
+    upstreams
+}
+
+/// Probes each distinct host once, in parallel, through the first repository
+/// using it and with that repository's `auth`, and returns the hosts that did
+/// not answer with the reason.
+pub fn unreachable_hosts(
+    upstreams: &BTreeMap<PathBuf, Upstream>,
+    timeout: Duration,
+    auth: impl Fn(&Path) -> GitAuth,
+) -> BTreeMap<String, String> {
+    let mut by_host = BTreeMap::new();
+    for (repo, upstream) in upstreams {
+        by_host
+            .entry(upstream.host.as_str())
+            .or_insert_with(|| (repo, upstream.remote_name.as_str(), auth(repo)));
+    }
+
+    thread::scope(|scope| {
+        let probes = by_host
+            .into_iter()
+            .map(|(host, (repo, remote_name, auth))| {
+                (
+                    host,
+                    scope.spawn(move || git::probe_remote(repo, remote_name, timeout, &auth)),
+                )
+            })
+            .collect::<Vec<_>>();
+        probes
+            .into_iter()
+            .filter_map(|(host, probe)| match probe.join() {
+                Ok(Ok(())) => {
+                    debug!(host, "upstream host reachable");
+                    None
+                }
+                Ok(Err(err)) => {
+                    let reason = format!("{err:#}");
+                    debug!(host, reason, "upstream host unreachable");
+                    Some((host.to_string(), reason))
+                }
+                Err(_) => Some((host.to_string(), "probe panicked".to_string())),
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::config::UntrackedPolicy;
+use crate::git;
+
+/// How many untracked paths to list before summarizing the rest.
+const UNTRACKED_PREVIEW_LIMIT: usize = 20;
+
+/// Whether this run may stop and ask questions on the terminal.
+pub fn is_interactive(non_interactive: bool) -> bool {
+    !non_interactive && io::stdin().is_terminal() && io::stderr().is_terminal()
+}
+
+/// Lists the untracked files in `repo` and asks whether this run should
+/// include them. Anything but an explicit yes keeps the run tracked-only.
+pub fn ask_include_untracked(repo: &Path) -> Result<UntrackedPolicy> {
+    let files = git::untracked_files(repo)?;
+    if files.is_empty() {
+        return Ok(UntrackedPolicy::Exclude);
+    }
+
+    let mut stderr = io::stderr().lock();
+    writeln!(stderr, "Untracked files in {}:", repo.display())?;
+    for file in files.iter().take(UNTRACKED_PREVIEW_LIMIT) {
+        writeln!(stderr, "  {file}")?;
+    }
+    if files.len() > UNTRACKED_PREVIEW_LIMIT {
+        writeln!(
+            stderr,
+            "  ... and {} more",
+            files.len() - UNTRACKED_PREVIEW_LIMIT
+        )?;
+    }
+    drop(stderr);
+
+    Ok(
+        if confirm(&format!("Include {} untracked files?", files.len()))? {
+            UntrackedPolicy::Include
+        } else {
+            UntrackedPolicy::Exclude
+        },
+    )
+}
+
+/// Asks a yes/no `question` on the terminal. Anything but an explicit yes is no.
+pub fn confirm(question: &str) -> Result<bool> {
+    Ok(matches!(
+        ask(&format!("{question} [y/N]"))?.as_str(),
+        "y" | "yes"
+    ))
+}
+
+/// Asks `question` on the terminal and returns the answer trimmed and
+/// lowercased; end of input reads as an empty answer.
+pub fn ask(question: &str) -> Result<String> {
+    Ok(read_answer(question)?.to_ascii_lowercase())
+}
+
+/// Asks `question` on the terminal, showing `default`, and returns the answer
+/// trimmed with its case kept, or `default` when it is empty.
+pub fn ask_or(question: &str, default: &str) -> Result<String> {
+    let answer = read_answer(&format!("{question} [{default}]"))?;
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+fn read_answer(question: &str) -> Result<String> {
+    let mut stderr = io::stderr().lock();
+    write!(stderr, "{question} ")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .context("failed reading answer from stdin")?;
+    Ok(answer.trim().to_string())
+}
@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::cli::PruneRemotesArgs;
+use crate::config::{PruneRemotesPolicy, ResolvedRepositoryConfig};
+use crate::git;
+use crate::prompt;
+use crate::remote;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RemoteAction {
+    Remove { remote: String, reason: String },
+    Rename { from: String, to: String },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RepoPlan {
+    pub repo: PathBuf,
+    pub actions: Vec<RemoteAction>,
+}
+
+/// Prints what `[prune_remotes]` would change in every selected repository,
+/// then applies it after confirmation (or straight away with `--yes`).
+pub fn run(
+    args: &PruneRemotesArgs,
+    policy: &PruneRemotesPolicy,
+    repos: &[ResolvedRepositoryConfig],
+) -> Result<i32> {
+    let mut failed = false;
+    let mut plans = Vec::new();
+    for repo in repos {
+        match plan_repo(&repo.path, policy) {
+            Ok(actions) if actions.is_empty() => {}
+            Ok(actions) => plans.push(RepoPlan {
+                repo: repo.path.clone(),
+                actions,
+            }),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+
+    if plans.is_empty() {
+        println!("No remotes to prune.");
+        return Ok(i32::from(failed));
+    }
+    for plan in &plans {
+        println!("{}", plan.repo.display());
+        for action in &plan.actions {
+            match action {
+                RemoteAction::Remove { remote, reason } => {
+                    println!("  remove {remote} ({reason})");
+                }
+                RemoteAction::Rename { from, to } => println!("  rename {from} -> {to}"),
+            }
+        }
+    }
+
+    if !args.yes {
+        if !prompt::is_interactive(false) {
+            println!("Re-run with --yes to apply this plan.");
+            return Ok(i32::from(failed));
+        }
+        if !prompt::confirm("Apply these changes?")? {
+            return Ok(i32::from(failed));
+        }
+    }
+
+    for plan in &plans {
+        for action in &plan.actions {
+            let applied = match action {
+                RemoteAction::Remove { remote, .. } => git::remove_remote(&plan.repo, remote),
+                RemoteAction::Rename { from, to } => git::rename_remote(&plan.repo, from, to),
+            };
+            if let Err(err) = applied {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", plan.repo.display());
+            }
+        }
+    }
+    Ok(i32::from(failed))
+}
+
+/// Works out which remotes of `repo` the policy removes or renames. Removal
+/// wins over renaming, and a rename onto an existing remote name becomes a
+/// removal of the old one.
+pub fn plan_repo(repo: &Path, policy: &PruneRemotesPolicy) -> Result<Vec<RemoteAction>> {
+    let remotes = git::remotes(repo)?;
+    let mut actions = Vec::new();
+    for (name, url) in &remotes {
+        let dead_host = remote::host(&remote::parse_remote_url(url)).filter(|host| {
+            policy.dead_hosts.iter().any(|dead| {
+                host == dead || host.split_once(':').is_some_and(|(bare, _)| bare == dead)
+            })
+        });
+        if let Some(host) = dead_host {
+            actions.push(RemoteAction::Remove {
+                remote: name.clone(),
+                reason: format!("{host} is listed in dead_hosts"),
+            });
+        } else if policy.remove.contains(name) {
+            actions.push(RemoteAction::Remove {
+                remote: name.clone(),
+                reason: "listed in remove".to_string(),
+            });
+        } else if let Some(to) = policy.renames.get(name) {
+            if remotes.iter().any(|(existing, _)| existing == to) {
+                actions.push(RemoteAction::Remove {
+                    remote: name.clone(),
+                    reason: format!("renamed to {to}, which already exists"),
+                });
+            } else {
+                actions.push(RemoteAction::Rename {
+                    from: name.clone(),
+                    to: to.clone(),
+                });
+            }
+        }
+    }
+    Ok(actions)
+}
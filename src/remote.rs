@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Where a git remote URL points, as far as shephard can act on it directly.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum RemoteLocation {
+    /// A repository on this machine (plain path or `file://` URL).
+    Local(PathBuf),
+    /// A repository reachable with `ssh` (`ssh://` or scp-like `host:path`).
+    Ssh {
+        destination: String,
+        port: Option<u16>,
+        path: String,
+    },
+    /// Anything else (https, git://, ...), only reachable through the server.
+    Other(String),
+}
+
+pub fn parse_remote_url(url: &str) -> RemoteLocation {
+    if let Some(path) = url.strip_prefix("file://") {
+        return RemoteLocation::Local(PathBuf::from(path));
+    }
+
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => match port.parse::<u16>() {
+                Ok(port) => (destination, Some(port)),
+                Err(_) => (authority, None),
+            },
+            None => (authority, None),
+        };
+        // `ssh://host/~/repo.git` is relative to the remote home directory.
+        let path = match path.strip_prefix('~') {
+            Some(home_relative) => format!("~{home_relative}"),
+            None => format!("/{path}"),
+        };
+        return RemoteLocation::Ssh {
+            destination: destination.to_string(),
+            port,
+            path,
+        };
+    }
+
+    if url.contains("://") {
+        return RemoteLocation::Other(url.to_string());
+    }
+
+    // scp-like syntax is only recognized when a colon comes before any slash,
+    // matching git's own rule for telling it apart from local paths.
+    match url.split_once(':') {
+        Some((destination, path)) if !destination.is_empty() && !destination.contains('/') => {
+            RemoteLocation::Ssh {
+                destination: destination.to_string(),
+                port: None,
+                path: path.to_string(),
+            }
+        }
+        Some(_) | None => RemoteLocation::Local(PathBuf::from(url)),
+    }
+}
+
+/// The host (with any explicit port) a remote connects to, or `None` for
+/// repositories on this machine.
+pub fn host(location: &RemoteLocation) -> Option<String> {
+    match location {
+        RemoteLocation::Local(_) => None,
+        RemoteLocation::Ssh {
+            destination, port, ..
+        } => {
+            let host = destination
+                .rsplit_once('@')
+                .map_or(destination.as_str(), |(_, host)| host);
+            Some(match port {
+                Some(port) => format!("{host}:{port}"),
+                None => host.to_string(),
+            })
+        }
+        RemoteLocation::Other(url) => {
+            let rest = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+            let authority = rest.split('/').next().unwrap_or_default();
+            let host = authority
+                .rsplit_once('@')
+                .map_or(authority, |(_, host)| host);
+            Some(host.to_string())
+        }
+    }
+}
+
+/// `url` with the longest `rewrites` prefix it starts with replaced, as git
+/// does for `url.<base>.insteadOf`, or `None` when no prefix matches.
+pub fn rewrite_url(rewrites: &BTreeMap<String, String>, url: &str) -> Option<String> {
+    rewrites
+        .iter()
+        .filter(|(from, _)| url.starts_with(from.as_str()))
+        .max_by_key(|(from, _)| from.len())
+        .map(|(from, to)| format!("{to}{}", &url[from.len()..]))
+}
+
+/// Quotes `path` for a POSIX remote shell, leaving a leading `~/` unquoted so it
+/// still expands to the remote home directory.
+pub fn shell_quote_path(path: &str) -> String {
+    let quote = |value: &str| format!("'{}'", value.replace('\'', r"'\''"));
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", quote(rest)),
+        None => quote(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn url_rewrites_replace_the_longest_matching_prefix() {
+        let rewrites = BTreeMap::from([
+            ("git@old-git.corp:".to_string(), "git@git.corp:".to_string()),
+            (
+                "git@old-git.corp:team/".to_string(),
+                "git@team.git.corp:".to_string(),
+            ),
+        ]);
+
+        assert_eq!(
+            [
+                "git@old-git.corp:you/repo.git",
+                "git@old-git.corp:team/repo.git",
+                "git@git.corp:you/repo.git",
+            ]
+            .map(|url| rewrite_url(&rewrites, url)),
+            [
+                Some("git@git.corp:you/repo.git".to_string()),
+                Some("git@team.git.corp:repo.git".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_common_remote_url_forms() {
+        let parsed = [
+            "/srv/git/repo.git",
+            "file:///srv/git/repo.git",
+            "ssh://git@nas.local:2222/srv/git/repo.git",
+            "ssh://nas.local/~/repo.git",
+            "git@github.com:you/repo.git",
+            "https://github.com/you/repo.git",
+        ]
+        .map(parse_remote_url);
+
+        assert_eq!(
+            parsed,
+            [
+                RemoteLocation::Local(PathBuf::from("/srv/git/repo.git")),
+                RemoteLocation::Local(PathBuf::from("/srv/git/repo.git")),
+                RemoteLocation::Ssh {
+                    destination: "git@nas.local".to_string(),
+                    port: Some(2222),
+                    path: "/srv/git/repo.git".to_string(),
+                },
+                RemoteLocation::Ssh {
+                    destination: "nas.local".to_string(),
+                    port: None,
+                    path: "~/repo.git".to_string(),
+                },
+                RemoteLocation::Ssh {
+                    destination: "git@github.com".to_string(),
+                    port: None,
+                    path: "you/repo.git".to_string(),
+                },
+                RemoteLocation::Other("https://github.com/you/repo.git".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn host_ignores_users_and_keeps_ports() {
+        let hosts = [
+            "/srv/git/repo.git",
+            "ssh://git@nas.local:2222/srv/git/repo.git",
+            "git@github.com:you/repo.git",
+            "https://token@git.example.com:8443/you/repo.git",
+        ]
+        .map(|url| host(&parse_remote_url(url)));
+
+        assert_eq!(
+            hosts,
+            [
+                None,
+                Some("nas.local:2222".to_string()),
+                Some("github.com".to_string()),
+                Some("git.example.com:8443".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shell_quote_keeps_home_expansion() {
+        assert_eq!(
+            [
+                shell_quote_path("~/backups/it's.git"),
+                shell_quote_path("/srv/repo.git"),
+            ],
+            [
+                r"~/'backups/it'\''s.git'".to_string(),
+                "'/srv/repo.git'".to_string(),
+            ]
+        );
+    }
+}
@@ -1,8 +1,19 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::config::{ExitCodePolicy, OutputFormat};
+use crate::display::PathDisplay;
+use crate::history::RunMeta;
+use crate::state::RunDiff;
 use crate::workflow::{RepoResult, RepoStatus};
 
 pub struct Summary {
     pub success: usize,
     pub no_op: usize,
+    pub busy: usize,
+    pub skipped: usize,
+    pub environment: usize,
+    pub needs_apply: usize,
     pub failed: usize,
 }
 
@@ -10,6 +21,10 @@ pub fn summarize(results: &[RepoResult]) -> Summary {
     let mut summary = Summary {
         success: 0,
         no_op: 0,
+        busy: 0,
+        skipped: 0,
+        environment: 0,
+        needs_apply: 0,
         failed: 0,
     };
 
@@ -17,6 +32,10 @@ pub fn summarize(results: &[RepoResult]) -> Summary {
         match item.status {
             RepoStatus::Success => summary.success += 1,
             RepoStatus::NoOp => summary.no_op += 1,
+            RepoStatus::Busy => summary.busy += 1,
+            RepoStatus::Skipped => summary.skipped += 1,
+            RepoStatus::Environment => summary.environment += 1,
+            RepoStatus::NeedsApply => summary.needs_apply += 1,
             RepoStatus::Failed => summary.failed += 1,
         }
     }
@@ -24,33 +43,500 @@ pub fn summarize(results: &[RepoResult]) -> Summary {
     summary
 }
 
-pub fn print_run_summary(results: &[RepoResult]) {
-    let summary = summarize(results);
+/// What a run report shows, ready for any [`Renderer`].
+pub struct RunReport<'a> {
+    pub results: &'a [RepoResult],
+    pub paths: &'a PathDisplay,
+    /// Per-host counts from [`host_summary`].
+    pub hosts: Option<String>,
+    /// Per-root outcomes from [`root_summary`], when the run spans several roots.
+    pub roots: Option<String>,
+    /// What changed since the previous run, when state could be read.
+    pub diff: Option<RunDiff>,
+    /// Versions, host and start time of the run.
+    pub meta: Option<RunMeta>,
+}
+
+/// Turns a run report into the text printed for one output format.
+pub trait Renderer {
+    fn render(&self, report: &RunReport) -> String;
+}
+
+/// The counts line, one `[STATE] repo :: message` line per repository, then
+/// the per-host, per-root and since-last-run lines and what produced the run.
+pub struct Plain;
+
+/// [`Plain`] with status labels colored for terminals.
+pub struct Color;
+
+/// [`Plain`] without successful and no-op repositories or the per-host,
+/// per-root and run lines.
+pub struct Quiet;
+
+/// One JSON document with absolute repository paths, for scripts.
+pub struct Json;
+
+/// A Markdown table, for pasting into issues and chat.
+pub struct Markdown;
+
+/// The renderer for `format`.
+pub fn renderer(format: OutputFormat) -> Box<dyn Renderer> {
+    match format {
+        OutputFormat::Plain => Box::new(Plain),
+        OutputFormat::Color => Box::new(Color),
+        OutputFormat::Quiet => Box::new(Quiet),
+        OutputFormat::Json => Box::new(Json),
+        OutputFormat::Markdown => Box::new(Markdown),
+    }
+}
+
+impl Renderer for Plain {
+    fn render(&self, report: &RunReport) -> String {
+        render_text(report, false, |status| status_label(status).to_string())
+    }
+}
+
+impl Renderer for Color {
+    fn render(&self, report: &RunReport) -> String {
+        render_text(report, false, |status| {
+            let code = match status {
+                RepoStatus::Success => "32",
+                RepoStatus::NoOp => "2",
+                RepoStatus::Busy | RepoStatus::Skipped | RepoStatus::NeedsApply => "33",
+                RepoStatus::Environment | RepoStatus::Failed => "31",
+            };
+            format!("\x1b[{code}m{}\x1b[0m", status_label(status))
+        })
+    }
+}
+
+impl Renderer for Quiet {
+    fn render(&self, report: &RunReport) -> String {
+        render_text(report, true, |status| status_label(status).to_string())
+    }
+}
+
+impl Renderer for Json {
+    fn render(&self, report: &RunReport) -> String {
+        let summary = summarize(report.results);
+        let diff = report.diff.clone().unwrap_or_default();
+        let document = serde_json::json!({
+            "summary": {
+                "processed": report.results.len(),
+                "success": summary.success,
+                "no_op": summary.no_op,
+                "busy": summary.busy,
+                "skipped": summary.skipped,
+                "environment": summary.environment,
+                "needs_apply": summary.needs_apply,
+                "failed": summary.failed,
+            },
+            "repos": report.results.iter().map(|item| serde_json::json!({
+                "repo": item.repo,
+                "name": report.paths.show(&item.repo),
+                "status": item.status,
+                "message": item.message,
+                "excluded_untracked": item.excluded_untracked,
+                "phases": item.phases,
+            })).collect::<Vec<_>>(),
+            "run": report.meta,
+            "hosts": report.hosts,
+            "roots": report.roots,
+            "new_failures": diff.new_failures,
+            "recovered": diff.recovered,
+        });
+        format!(
+            "{}\n",
+            serde_json::to_string_pretty(&document).unwrap_or_default()
+        )
+    }
+}
+
+impl Renderer for Markdown {
+    fn render(&self, report: &RunReport) -> String {
+        let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+        let mut out = format!("**{}**\n\n", summary_line(report.results));
+        out.push_str("| Status | Repository | Result |\n| --- | --- | --- |\n");
+        for item in report.results {
+            out.push_str(&format!(
+                "| {} | `{}` | {} |\n",
+                status_label(item.status),
+                report.paths.show(&item.repo),
+                cell(&item.message)
+            ));
+        }
+        let mut notes = Vec::new();
+        if let Some(hosts) = &report.hosts {
+            notes.push(format!("- By host: {hosts}\n"));
+        }
+        if let Some(roots) = &report.roots {
+            notes.push(format!("- By root: {roots}\n"));
+        }
+        if let Some(meta) = &report.meta {
+            notes.push(format!("- Run: {}\n", meta.describe()));
+        }
+        if let Some(diff) = &report.diff {
+            notes.extend(
+                render_run_diff(diff, report.paths)
+                    .lines()
+                    .map(|line| format!("- {line}\n")),
+            );
+        }
+        if !notes.is_empty() {
+            out.push('\n');
+            out.push_str(&notes.concat());
+        }
+        out
+    }
+}
+
+/// The line-per-repository text shared by [`Plain`], [`Color`] and [`Quiet`];
+/// `label` renders each status.
+fn render_text(report: &RunReport, quiet: bool, label: impl Fn(RepoStatus) -> String) -> String {
+    let mut out = format!("{}\n", summary_line(report.results));
+    for item in report.results {
+        if quiet && matches!(item.status, RepoStatus::Success | RepoStatus::NoOp) {
+            continue;
+        }
+        out.push_str(&format!(
+            "[{}] {} :: {}\n",
+            label(item.status),
+            report.paths.show(&item.repo),
+            item.message
+        ));
+    }
+    if !quiet {
+        if let Some(hosts) = &report.hosts {
+            out.push_str(&format!("By host: {hosts}\n"));
+        }
+        if let Some(roots) = &report.roots {
+            out.push_str(&format!("By root: {roots}\n"));
+        }
+    }
+    if let Some(diff) = &report.diff {
+        out.push_str(&render_run_diff(diff, report.paths));
+    }
+    if !quiet && let Some(meta) = &report.meta {
+        out.push_str(&format!("Run: {}\n", meta.describe()));
+    }
+    out
+}
 
-    println!(
-        "Processed {} repos: {} success, {} no-op, {} failed",
+fn summary_line(results: &[RepoResult]) -> String {
+    let summary = summarize(results);
+    let mut line = format!(
+        "Processed {} repos: {} success, {} no-op, {} busy, {} skipped, {} failed",
         results.len(),
         summary.success,
         summary.no_op,
+        summary.busy,
+        summary.skipped,
         summary.failed
     );
+    // Kept off the line in healthy environments, so existing parsers of the
+    // summary line keep working.
+    if summary.environment > 0 {
+        line.push_str(&format!(", {} environment problems", summary.environment));
+    }
+    if summary.needs_apply > 0 {
+        line.push_str(&format!(", {} need apply", summary.needs_apply));
+    }
+    line
+}
+
+pub fn status_label(status: RepoStatus) -> &'static str {
+    match status {
+        RepoStatus::Success => "OK",
+        RepoStatus::NoOp => "NOOP",
+        RepoStatus::Busy => "BUSY",
+        RepoStatus::Skipped => "SKIP",
+        RepoStatus::Environment => "ENV",
+        RepoStatus::NeedsApply => "APPLY",
+        RepoStatus::Failed => "FAIL",
+    }
+}
+
+/// Renders which repositories started failing or recovered since the previous
+/// run, so regressions stand out from long-standing failures.
+pub fn render_run_diff(diff: &RunDiff, paths: &PathDisplay) -> String {
+    let mut out = String::new();
+    for (label, repos) in [
+        ("New failures since last run", &diff.new_failures),
+        ("Recovered since last run", &diff.recovered),
+    ] {
+        if repos.is_empty() {
+            continue;
+        }
+        let repos = repos
+            .iter()
+            .map(|repo| paths.show(repo))
+            .collect::<Vec<_>>();
+        out.push_str(&format!("{label}: {}\n", repos.join(", ")));
+    }
+    out
+}
+
+/// Renders per-host counts such as `github.com: 20 ok; nas.local: 5 skipped
+/// (unreachable)`, so a single host's outage reads as one problem. `hosts`
+/// maps repositories to their upstream host; the rest group under `local`.
+pub fn host_summary(
+    results: &[RepoResult],
+    hosts: &BTreeMap<PathBuf, String>,
+    unreachable: &BTreeMap<String, String>,
+) -> String {
+    let mut by_host: BTreeMap<&str, Vec<RepoResult>> = BTreeMap::new();
     for item in results {
-        let state = match item.status {
-            RepoStatus::Success => "OK",
-            RepoStatus::NoOp => "NOOP",
-            RepoStatus::Failed => "FAIL",
-        };
-        println!("[{state}] {} :: {}", item.repo.display(), item.message);
+        let host = hosts.get(&item.repo).map_or("local", String::as_str);
+        by_host.entry(host).or_default().push(item.clone());
+    }
+
+    by_host
+        .into_iter()
+        .map(|(host, results)| {
+            let summary = summarize(&results);
+            let counts = [
+                (summary.success, "ok"),
+                (summary.no_op, "no-op"),
+                (summary.busy, "busy"),
+                (summary.skipped, "skipped"),
+                (summary.environment, "environment"),
+                (summary.needs_apply, "need apply"),
+                (summary.failed, "failed"),
+            ]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+            if unreachable.contains_key(host) {
+                format!("{host}: {counts} (unreachable)")
+            } else {
+                format!("{host}: {counts}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Renders per-root outcomes such as `personal: 2 failed; work: all ok`, so
+/// each workspace reads at a glance. `roots` maps repositories to the name of
+/// the workspace root containing them; the rest group under `other`.
+pub fn root_summary(results: &[RepoResult], roots: &BTreeMap<PathBuf, String>) -> String {
+    let mut by_root: BTreeMap<&str, Vec<RepoResult>> = BTreeMap::new();
+    for item in results {
+        let root = roots.get(&item.repo).map_or("other", String::as_str);
+        by_root.entry(root).or_default().push(item.clone());
     }
+
+    by_root
+        .into_iter()
+        .map(|(root, results)| {
+            let summary = summarize(&results);
+            let problems = [
+                (summary.busy, "busy"),
+                (summary.skipped, "skipped"),
+                (summary.environment, "environment"),
+                (summary.needs_apply, "need apply"),
+                (summary.failed, "failed"),
+            ]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, label)| format!("{count} {label}"))
+            .collect::<Vec<_>>();
+            if problems.is_empty() {
+                format!("{root}: all ok")
+            } else {
+                format!("{root}: {}", problems.join(", "))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Phrases git and its transports print when credentials are rejected.
+const AUTH_FAILURE_MARKERS: &[&str] = &[
+    "Authentication failed",
+    "Permission denied (publickey",
+    "could not read Username",
+    "could not read Password",
+    "terminal prompts disabled",
+    "The requested URL returned error: 403",
+];
+
+/// Whether `item` failed because credentials were rejected or could not be
+/// asked for.
+pub fn is_auth_failure(item: &RepoResult) -> bool {
+    item.status == RepoStatus::Failed
+        && AUTH_FAILURE_MARKERS
+            .iter()
+            .any(|marker| item.message.contains(marker))
+}
+
+pub fn exit_code(results: &[RepoResult], policy: ExitCodePolicy) -> i32 {
+    let fails = |item: &RepoResult| match (item.status, policy) {
+        (RepoStatus::Success | RepoStatus::NoOp, _) => false,
+        (RepoStatus::Skipped | RepoStatus::NeedsApply, ExitCodePolicy::Strict) => true,
+        (
+            RepoStatus::Skipped | RepoStatus::NeedsApply,
+            ExitCodePolicy::Standard | ExitCodePolicy::Lenient,
+        ) => false,
+        (RepoStatus::Busy, ExitCodePolicy::Standard | ExitCodePolicy::Strict) => true,
+        (RepoStatus::Busy, ExitCodePolicy::Lenient) => false,
+        (RepoStatus::Failed, ExitCodePolicy::Standard | ExitCodePolicy::Strict) => true,
+        (RepoStatus::Failed, ExitCodePolicy::Lenient) => !is_auth_failure(item),
+        (RepoStatus::Environment, ExitCodePolicy::Standard | ExitCodePolicy::Strict) => true,
+        (RepoStatus::Environment, ExitCodePolicy::Lenient) => false,
+    };
+    if results.iter().any(fails) { 1 } else { 0 }
 }
 
-pub fn exit_code(results: &[RepoResult]) -> i32 {
-    if results
-        .iter()
-        .any(|r| matches!(r.status, RepoStatus::Failed))
-    {
-        1
-    } else {
-        0
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn host_summary_groups_counts_by_host() {
+        let results = [
+            result("/tmp/a", RepoStatus::Success),
+            result("/tmp/b", RepoStatus::NoOp),
+            result("/tmp/c", RepoStatus::Skipped),
+            result("/tmp/d", RepoStatus::Skipped),
+            result("/tmp/e", RepoStatus::Failed),
+        ];
+        let hosts = BTreeMap::from([
+            (PathBuf::from("/tmp/a"), "github.com".to_string()),
+            (PathBuf::from("/tmp/b"), "github.com".to_string()),
+            (PathBuf::from("/tmp/c"), "nas.local".to_string()),
+            (PathBuf::from("/tmp/d"), "nas.local".to_string()),
+        ]);
+        let unreachable =
+            BTreeMap::from([("nas.local".to_string(), "no response within 5s".to_string())]);
+
+        assert_eq!(
+            host_summary(&results, &hosts, &unreachable),
+            "github.com: 1 ok, 1 no-op; local: 1 failed; nas.local: 2 skipped (unreachable)"
+        );
+    }
+
+    #[test]
+    fn root_summary_reports_problems_per_root() {
+        let results = [
+            result("/home/me/work/a", RepoStatus::Success),
+            result("/home/me/work/b", RepoStatus::NoOp),
+            result("/home/me/personal/c", RepoStatus::Failed),
+            result("/home/me/personal/d", RepoStatus::Failed),
+            result("/srv/e", RepoStatus::Skipped),
+        ];
+        let roots = BTreeMap::from([
+            (PathBuf::from("/home/me/work/a"), "work".to_string()),
+            (PathBuf::from("/home/me/work/b"), "work".to_string()),
+            (PathBuf::from("/home/me/personal/c"), "personal".to_string()),
+            (PathBuf::from("/home/me/personal/d"), "personal".to_string()),
+        ]);
+
+        assert_eq!(
+            root_summary(&results, &roots),
+            "other: 1 skipped; personal: 2 failed; work: all ok"
+        );
+    }
+
+    #[test]
+    fn exit_code_policies_differ_on_skipped_busy_and_auth_failures() {
+        let skipped = [result("/tmp/a", RepoStatus::Skipped)];
+        let busy = [result("/tmp/a", RepoStatus::Busy)];
+        let auth = [RepoResult {
+            message: "pull failed: fatal: Authentication failed for 'https://host/repo.git/'"
+                .to_string(),
+            ..result("/tmp/a", RepoStatus::Failed)
+        }];
+        let failed = [result("/tmp/a", RepoStatus::Failed)];
+
+        let codes = [
+            ExitCodePolicy::Standard,
+            ExitCodePolicy::Strict,
+            ExitCodePolicy::Lenient,
+        ]
+        .map(|policy| [&skipped, &busy, &auth, &failed].map(|results| exit_code(results, policy)));
+
+        assert_eq!(codes, [[0, 1, 1, 1], [1, 1, 1, 1], [0, 0, 0, 1]]);
+    }
+
+    #[test]
+    fn renderers_share_one_report() {
+        let results = [
+            RepoResult {
+                message: "pull ok, push ok".to_string(),
+                ..result("/tmp/a", RepoStatus::Success)
+            },
+            RepoResult {
+                message: "merge conflict | retry".to_string(),
+                ..result("/tmp/b", RepoStatus::Failed)
+            },
+        ];
+        let paths = PathDisplay::default();
+        let report = RunReport {
+            results: &results,
+            paths: &paths,
+            hosts: Some("local: 1 ok, 1 failed".to_string()),
+            roots: None,
+            diff: Some(RunDiff {
+                new_failures: vec![PathBuf::from("/tmp/b")],
+                recovered: Vec::new(),
+            }),
+            meta: None,
+        };
+        let render = |format: OutputFormat| renderer(format).render(&report);
+
+        assert_eq!(
+            render(OutputFormat::Plain),
+            "Processed 2 repos: 1 success, 0 no-op, 0 busy, 0 skipped, 1 failed\n\
+             [OK] /tmp/a :: pull ok, push ok\n\
+             [FAIL] /tmp/b :: merge conflict | retry\n\
+             By host: local: 1 ok, 1 failed\n\
+             New failures since last run: /tmp/b\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Quiet),
+            "Processed 2 repos: 1 success, 0 no-op, 0 busy, 0 skipped, 1 failed\n\
+             [FAIL] /tmp/b :: merge conflict | retry\n\
+             New failures since last run: /tmp/b\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Markdown),
+            "**Processed 2 repos: 1 success, 0 no-op, 0 busy, 0 skipped, 1 failed**\n\n\
+             | Status | Repository | Result |\n\
+             | --- | --- | --- |\n\
+             | OK | `/tmp/a` | pull ok, push ok |\n\
+             | FAIL | `/tmp/b` | merge conflict \\| retry |\n\n\
+             - By host: local: 1 ok, 1 failed\n\
+             - New failures since last run: /tmp/b\n"
+        );
+        assert!(render(OutputFormat::Color).contains("[\x1b[31mFAIL\x1b[0m] /tmp/b"));
+        let json: serde_json::Value =
+            serde_json::from_str(&render(OutputFormat::Json)).expect("json should parse");
+        assert_eq!(
+            (
+                json["summary"]["failed"].clone(),
+                json["repos"][1]["status"].clone(),
+                json["new_failures"].clone(),
+            ),
+            (
+                serde_json::json!(1),
+                serde_json::json!("failed"),
+                serde_json::json!(["/tmp/b"]),
+            )
+        );
+    }
+
+    fn result(path: &str, status: RepoStatus) -> RepoResult {
+        RepoResult {
+            repo: PathBuf::from(path),
+            status,
+            message: String::new(),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
+        }
     }
 }
@@ -0,0 +1,85 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+use crate::git::{self, ConflictSide};
+
+/// Walks through the `conflicted` paths an apply left in `repo`, asking for
+/// each whether to keep ours (the branch), take theirs (the side channel),
+/// edit it, or skip it. Once nothing is left conflicted the merge is
+/// committed. Returns whether it was.
+pub fn resolve(
+    repo: &Path,
+    conflicted: &[String],
+    mut ask: impl FnMut(&str) -> Result<String>,
+) -> Result<bool> {
+    let mut skipped = Vec::new();
+    for path in conflicted {
+        loop {
+            let answer = ask(&format!(
+                "{path}: keep [o]urs, take [t]heirs (side channel), [e]dit, or [s]kip?"
+            ))?;
+            match answer.as_str() {
+                "o" | "ours" => git::take_conflict_side(repo, path, ConflictSide::Ours)?,
+                "t" | "theirs" => git::take_conflict_side(repo, path, ConflictSide::Theirs)?,
+                "e" | "edit" => {
+                    edit(repo, path)?;
+                    if has_conflict_markers(&repo.join(path)) {
+                        eprintln!("{path} still has conflict markers");
+                        continue;
+                    }
+                    git::mark_resolved(repo, path)?;
+                }
+                // End of input reads as an empty answer, which skips.
+                "s" | "skip" | "" => skipped.push(path.clone()),
+                other => {
+                    eprintln!("Unknown choice {other:?}");
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+
+    if !skipped.is_empty() {
+        println!(
+            "Left {} conflicted in {}: {}; resolve them and commit",
+            skipped.len(),
+            repo.display(),
+            skipped.join(", ")
+        );
+        return Ok(false);
+    }
+    git::conclude_merge(repo)
+        .with_context(|| format!("failed to commit the resolved merge in {}", repo.display()))?;
+    Ok(true)
+}
+
+/// Opens `path` in `$VISUAL`, else `$EDITOR`, else `vi`, and waits for it.
+fn edit(repo: &Path, path: &str) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|key| std::env::var(key).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // Through the shell, so editors configured with arguments such as
+    // `code --wait` work.
+    let status = Command::new("sh")
+        .args(["-c", &format!("{editor} \"$1\""), "sh", path])
+        .current_dir(repo)
+        .status()
+        .with_context(|| format!("failed to start {editor}"))?;
+    if !status.success() {
+        bail!("{editor} exited with {status}");
+    }
+    Ok(())
+}
+
+fn has_conflict_markers(file: &Path) -> bool {
+    fs::read_to_string(file).is_ok_and(|text| {
+        text.lines()
+            .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> "))
+    })
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::cli::SelfUpdateArgs;
+
+/// The GitHub API endpoint for the newest published release.
+pub const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/shetaye/shephard/releases/latest";
+
+/// The release asset listing `sha256sum` lines for every binary.
+pub const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    #[serde(default)]
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    fn asset(&self, name: &str) -> Result<&ReleaseAsset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .with_context(|| format!("release {} has no {name} asset", self.tag_name))
+    }
+}
+
+/// Checks the latest release and, unless `--check` was given, replaces the
+/// running binary with it. Returns 1 when `--check` finds a newer release.
+pub fn run(args: &SelfUpdateArgs) -> Result<i32> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release: Release = serde_json::from_slice(&fetch(LATEST_RELEASE_URL)?)
+        .context("failed parsing latest release")?;
+    let Some(latest) = parse_version(&release.tag_name) else {
+        bail!("latest release has unrecognized tag {}", release.tag_name);
+    };
+    let running = parse_version(current).context("failed parsing own version")?;
+    if latest <= running {
+        println!("shephard {current} is up to date");
+        return Ok(0);
+    }
+    if args.check {
+        println!(
+            "shephard {} is available (running {current})",
+            release.tag_name
+        );
+        return Ok(1);
+    }
+
+    let name = asset_name();
+    let binary = fetch(&release.asset(&name)?.browser_download_url)?;
+    let sums = fetch(&release.asset(CHECKSUMS_ASSET)?.browser_download_url)?;
+    let sums = String::from_utf8_lossy(&sums);
+    let Some(expected) = checksum_for(&sums, &name) else {
+        bail!("{CHECKSUMS_ASSET} has no entry for {name}");
+    };
+
+    let exe = std::env::current_exe().context("failed locating running binary")?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    replace_binary(&exe, &binary, expected)?;
+    println!(
+        "updated {} from {current} to {}",
+        exe.display(),
+        release.tag_name
+    );
+    Ok(0)
+}
+
+/// The release asset built for this platform, e.g. `shephard-x86_64-linux`.
+pub fn asset_name() -> String {
+    format!(
+        "shephard-{}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    )
+}
+
+/// Parses `1.2.3` or `v1.2.3`, ignoring any pre-release or build suffix.
+pub fn parse_version(tag: &str) -> Option<(u64, u64, u64)> {
+    let core = tag
+        .trim()
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()?;
+    let mut parts = core.split('.').map(|part| part.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(version)
+}
+
+/// Finds `name`'s digest in `sha256sum` output, accepting binary-mode (`*`)
+/// entries.
+pub fn checksum_for<'a>(sums: &'a str, name: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (digest, file) = line.split_once(char::is_whitespace)?;
+        let file = file.trim_start();
+        let file = file.strip_prefix('*').unwrap_or(file);
+        (file == name).then_some(digest)
+    })
+}
+
+/// The lowercase hex SHA-256 digest of `bytes`.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Checks `binary` against `expected`, writes it next to `exe`, and renames
+/// it over `exe` so the swap is atomic. The digest comes from the same
+/// release as the binary, so this catches a corrupted download but not a
+/// tampered release.
+fn replace_binary(exe: &Path, binary: &[u8], expected: &str) -> Result<()> {
+    let actual = sha256_hex(binary);
+    if !actual.eq_ignore_ascii_case(expected) {
+        bail!("checksum mismatch for downloaded binary: expected {expected}, got {actual}");
+    }
+
+    let dir = exe
+        .parent()
+        .context("running binary has no parent directory")?;
+    let mut staged = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed staging update in {}", dir.display()))?;
+    staged
+        .write_all(binary)
+        .context("failed writing downloaded binary")?;
+
+    fs::set_permissions(staged.path(), fs::Permissions::from_mode(0o755))
+        .context("failed marking downloaded binary executable")?;
+    staged
+        .persist(exe)
+        .with_context(|| format!("failed replacing {}", exe.display()))?;
+    Ok(())
+}
+
+/// Downloads `url` with curl, following redirects to release storage.
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--header", "Accept: application/vnd.github+json"])
+        .args([
+            "--user-agent",
+            concat!("shephard/", env!("CARGO_PKG_VERSION")),
+        ])
+        .arg(url)
+        .output()
+        .context("failed running curl")?;
+    if !output.status.success() {
+        bail!(
+            "fetching {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn release_tags_and_checksums_parse() {
+        let sums = "\
+0123abcd  shephard-aarch64-linux
+4567ef01 *shephard-x86_64-linux
+";
+
+        assert_eq!(
+            ["v0.2.0", "0.1.10", "v1.0.0-rc.1", "v1.2", "nightly"].map(parse_version),
+            [
+                Some((0, 2, 0)),
+                Some((0, 1, 10)),
+                Some((1, 0, 0)),
+                None,
+                None
+            ]
+        );
+        assert!(parse_version("v0.1.10") > parse_version("v0.1.9"));
+        assert_eq!(
+            [
+                "shephard-x86_64-linux",
+                "shephard-aarch64-linux",
+                "shephard-x86_64-macos"
+            ]
+            .map(|name| checksum_for(sums, name)),
+            [Some("4567ef01"), Some("0123abcd"), None]
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
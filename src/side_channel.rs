@@ -0,0 +1,226 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::{self, ResolvedConfig, ResolvedRepositoryConfig, SideChannelBackend};
+use crate::git;
+use crate::remote::{self, RemoteLocation};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProvisionOutcome {
+    /// The bare repository exists now (it may have existed before).
+    Ready,
+    /// The remote is over ssh and `--ssh` was not given.
+    NeedsSsh,
+    /// The remote is only reachable through its hosting service.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum GcOutcome {
+    Pruned,
+    /// The remote is only reachable through its hosting service, which runs
+    /// its own housekeeping.
+    Unsupported,
+}
+
+/// Creates the bare side-channel repository of every selected repository with
+/// side-channel enabled when it does not exist yet, then seeds the side branch
+/// from `HEAD`. Ssh remotes are only touched when `allow_ssh` is set.
+pub fn provision(
+    config: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+    allow_ssh: bool,
+) -> i32 {
+    let mut failed = false;
+
+    for repo in repos {
+        let side = config::resolve_repo_side_channel(config, repo);
+        if !side.enabled {
+            continue;
+        }
+        match side.backend {
+            SideChannelBackend::Git => {}
+            SideChannelBackend::Rclone | SideChannelBackend::Restic => {
+                println!(
+                    "[SKIP] {} :: snapshots are stored with {}, not a git remote",
+                    repo.path.display(),
+                    side.backend
+                );
+                continue;
+            }
+        }
+
+        let provisioned = git::ensure_side_channel_remote(&repo.path, &side).and_then(|url| {
+            let outcome = provision_remote(&repo.path, &url, allow_ssh)?;
+            Ok((url, outcome))
+        });
+        match provisioned {
+            Ok((url, ProvisionOutcome::Ready)) => match git::seed_side_branch(
+                &repo.path,
+                &side,
+                &config::resolve_git_auth(config, &repo.path),
+            ) {
+                Ok(true) => println!(
+                    "[OK] {} :: {url} ready, seeded {}",
+                    repo.path.display(),
+                    side.branch_name
+                ),
+                Ok(false) => println!("[OK] {} :: {url} ready", repo.path.display()),
+                Err(err) => {
+                    failed = true;
+                    println!(
+                        "[FAIL] {} :: seeding {} failed: {err:#}",
+                        repo.path.display(),
+                        side.branch_name
+                    );
+                }
+            },
+            Ok((url, ProvisionOutcome::NeedsSsh)) => println!(
+                "[SKIP] {} :: {url} is an ssh remote; pass --ssh to create it",
+                repo.path.display()
+            ),
+            Ok((url, ProvisionOutcome::Unsupported)) => println!(
+                "[SKIP] {} :: {url} is not file or ssh; create it with the hosting service",
+                repo.path.display()
+            ),
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {} :: {err:#}", repo.path.display());
+            }
+        }
+    }
+
+    i32::from(failed)
+}
+
+/// Runs `git init --bare` for a remote that does not exist yet, locally for
+/// file remotes and through `ssh` for ssh remotes. `repo` anchors relative paths.
+pub fn provision_remote(repo: &Path, url: &str, allow_ssh: bool) -> Result<ProvisionOutcome> {
+    match remote::parse_remote_url(url) {
+        RemoteLocation::Local(path) => {
+            if !repo.join(&path).exists() {
+                git::init_bare(repo, &path)?;
+            }
+            Ok(ProvisionOutcome::Ready)
+        }
+        RemoteLocation::Ssh { .. } if !allow_ssh => Ok(ProvisionOutcome::NeedsSsh),
+        RemoteLocation::Ssh {
+            destination,
+            port,
+            path,
+        } => {
+            let path = remote::shell_quote_path(&path);
+            ssh_exec(
+                &destination,
+                port,
+                &format!("test -d {path} || git init --bare --quiet {path}"),
+            )?;
+            Ok(ProvisionOutcome::Ready)
+        }
+        RemoteLocation::Other(_) => Ok(ProvisionOutcome::Unsupported),
+    }
+}
+
+/// Prunes unreachable objects on the side-channel remote of every selected
+/// repository with side-channel enabled. Each remote URL is handled once.
+pub fn gc(config: &ResolvedConfig, repos: &[ResolvedRepositoryConfig]) -> i32 {
+    let mut seen = BTreeSet::new();
+    let mut failed = false;
+
+    for repo in repos {
+        let side = config::resolve_repo_side_channel(config, repo);
+        if !side.enabled {
+            continue;
+        }
+        match side.backend {
+            SideChannelBackend::Git => {}
+            SideChannelBackend::Rclone | SideChannelBackend::Restic => {
+                println!(
+                    "[SKIP] {} :: snapshots are stored with {}, not a git remote",
+                    repo.path.display(),
+                    side.backend
+                );
+                continue;
+            }
+        }
+
+        let url = match git::remote_url(&repo.path, &side.remote_name) {
+            Ok(url) => url,
+            Err(err) => {
+                failed = true;
+                println!(
+                    "[FAIL] {} :: missing side-channel remote '{}': {err:#}",
+                    repo.path.display(),
+                    side.remote_name
+                );
+                continue;
+            }
+        };
+        if !seen.insert(url.clone()) {
+            continue;
+        }
+
+        match gc_remote(&repo.path, &url) {
+            Ok(GcOutcome::Pruned) => println!("[OK] {url} :: pruned unreachable objects"),
+            Ok(GcOutcome::Unsupported) => {
+                println!("[SKIP] {url} :: not file or ssh; run housekeeping on the server")
+            }
+            Err(err) => {
+                failed = true;
+                println!("[FAIL] {url} :: {err:#}");
+            }
+        }
+    }
+
+    i32::from(failed)
+}
+
+/// Runs `git gc --prune=now` against a remote repository, locally for file
+/// remotes and through `ssh` for ssh remotes. `repo` anchors relative paths.
+pub fn gc_remote(repo: &Path, url: &str) -> Result<GcOutcome> {
+    match remote::parse_remote_url(url) {
+        RemoteLocation::Local(path) => {
+            git::gc_prune_now(&repo.join(path))?;
+            Ok(GcOutcome::Pruned)
+        }
+        RemoteLocation::Ssh {
+            destination,
+            port,
+            path,
+        } => {
+            ssh_exec(
+                &destination,
+                port,
+                &format!(
+                    "git --git-dir {} gc --prune=now --quiet",
+                    remote::shell_quote_path(&path)
+                ),
+            )?;
+            Ok(GcOutcome::Pruned)
+        }
+        RemoteLocation::Other(_) => Ok(GcOutcome::Unsupported),
+    }
+}
+
+fn ssh_exec(destination: &str, port: Option<u16>, remote_command: &str) -> Result<()> {
+    let mut cmd = Command::new("ssh");
+    if let Some(port) = port {
+        cmd.arg("-p").arg(port.to_string());
+    }
+    let output = cmd
+        .arg(destination)
+        .arg(remote_command)
+        .output()
+        .with_context(|| format!("failed running ssh {destination}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "remote command `{remote_command}` failed on {destination}: {}",
+            stderr.trim()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,742 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, LastSnapshot, ResolvedRepositoryConfig};
+use crate::git;
+use crate::workflow::{RepoResult, RepoStatus};
+
+/// Persistent data shephard keeps between runs, keyed by [`RepoKeys`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub repos: BTreeMap<String, RepoState>,
+    /// The last `apply` that moved each repository's `HEAD`, for
+    /// `apply --undo`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub applies: BTreeMap<String, ApplyRecord>,
+    /// Root commit of each repository by canonical path, so resolving
+    /// [`RepoKeys`] does not walk every repository's history on each run.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub root_commits: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RepoState {
+    pub last_status: RepoStatus,
+    pub last_message: String,
+    /// Unix seconds of the last run that processed this repository.
+    pub last_run_at: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded_untracked: Vec<String>,
+    /// The last side-channel snapshot stored, kept across runs that stored
+    /// none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_snapshot: Option<LastSnapshot>,
+    /// Canonical path of the repository that last wrote this record, which
+    /// tells clones sharing a root commit which of them holds the `root:` key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// An `apply` that moved a repository's `HEAD`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ApplyRecord {
+    /// `HEAD` before the apply, which `apply --undo` resets to.
+    pub before: String,
+    /// `HEAD` right after the apply.
+    pub after: String,
+    /// The side-channel tip that was applied.
+    pub side_tip: String,
+    pub method: String,
+    /// Unix seconds of the apply.
+    pub applied_at: i64,
+}
+
+/// Repositories whose outcome changed since the run recorded in state.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RunDiff {
+    /// Failed now, but not on their previous run (or never run before).
+    pub new_failures: Vec<PathBuf>,
+    /// Failed on their previous run and succeeded or had nothing to do now.
+    pub recovered: Vec<PathBuf>,
+}
+
+/// State keys of known repositories, by canonical path. Repositories are
+/// keyed by `id:<id>` when configured with an `id`, else by `root:<hash>` of
+/// their root commit, so records survive moves, symlinked homes, and mount
+/// points. Repositories without commits are keyed by path, as are clones of
+/// one project sharing a root commit, except the clone that already holds the
+/// `root:` record, which keeps it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RepoKeys {
+    keys: BTreeMap<String, String>,
+    /// Root commits looked up rather than found in the state's cache.
+    found_roots: BTreeMap<String, String>,
+}
+
+impl RepoKeys {
+    /// Resolves keys against the state in `store`, saving the root commits it
+    /// had to look up. The state only speeds this up, so failing to read or
+    /// write it is not an error.
+    pub fn load(repos: &[ResolvedRepositoryConfig], store: &StateStore) -> Self {
+        let keys = Self::resolve(repos, &store.read().unwrap_or_default());
+        if !keys.found_roots.is_empty()
+            && let Err(err) = store.update(|saved| {
+                saved.root_commits.extend(keys.found_roots.clone());
+            })
+        {
+            tracing::debug!("failed caching root commits: {err:#}");
+        }
+        keys
+    }
+
+    /// Resolves keys, reading root commits from `saved`'s cache where it has
+    /// them and `saved`'s records to keep `root:` keys with their holders.
+    pub fn resolve(repos: &[ResolvedRepositoryConfig], saved: &State) -> Self {
+        let mut found_roots = BTreeMap::new();
+        let roots = repos
+            .iter()
+            .map(|repo| {
+                let root = match &repo.id {
+                    Some(_) => None,
+                    None => {
+                        let path_key = config::canonical_repo_key(&repo.path);
+                        match saved.root_commits.get(&path_key) {
+                            Some(root) => Some(root.clone()),
+                            None => {
+                                let root = git::root_commit(&repo.path).ok().flatten();
+                                if let Some(root) = &root {
+                                    found_roots.insert(path_key, root.clone());
+                                }
+                                root
+                            }
+                        }
+                    }
+                };
+                (repo, root)
+            })
+            .collect::<Vec<_>>();
+        let mut root_counts = BTreeMap::<&str, usize>::new();
+        for (_, root) in &roots {
+            if let Some(root) = root {
+                *root_counts.entry(root.as_str()).or_default() += 1;
+            }
+        }
+
+        let keys = roots
+            .iter()
+            .map(|(repo, root)| {
+                let path_key = config::canonical_repo_key(&repo.path);
+                let key = match (&repo.id, root) {
+                    (Some(id), _) => format!("id:{id}"),
+                    (None, Some(root)) => {
+                        let root_key = format!("root:{root}");
+                        let holder = saved
+                            .repos
+                            .get(&root_key)
+                            .and_then(|record| record.path.as_deref());
+                        if root_counts[root.as_str()] == 1 || holder == Some(path_key.as_str()) {
+                            root_key
+                        } else {
+                            path_key.clone()
+                        }
+                    }
+                    (None, None) => path_key.clone(),
+                };
+                (path_key, key)
+            })
+            .collect();
+        Self { keys, found_roots }
+    }
+
+    /// The state key for `repo`, falling back to its canonical path.
+    pub fn key(&self, repo: &Path) -> String {
+        let path_key = config::canonical_repo_key(repo);
+        self.keys.get(&path_key).cloned().unwrap_or(path_key)
+    }
+
+    /// Every key in use, including the path keys older records were written under.
+    pub fn all_keys(&self) -> BTreeSet<String> {
+        self.keys
+            .iter()
+            .flat_map(|(path_key, key)| [path_key.clone(), key.clone()])
+            .collect()
+    }
+}
+
+/// Why [`prune`] dropped a repository record.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PruneReason {
+    Unconfigured,
+    Expired,
+}
+
+pub fn state_path() -> Result<PathBuf> {
+    let base = dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .context("unable to resolve XDG state directory")?;
+    Ok(base.join("shephard").join("state.json"))
+}
+
+/// The state file, shared by every shephard process on the machine. Reads
+/// take a shared advisory lock and [`StateStore::update`] an exclusive one
+/// around re-reading, changing and rewriting the file, so concurrent runs
+/// each apply their change to the latest records instead of overwriting one
+/// another's.
+#[derive(Debug, Clone)]
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    /// The store at [`state_path`].
+    pub fn open() -> Result<Self> {
+        Ok(Self::at(state_path()?))
+    }
+
+    pub fn at(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn read(&self) -> Result<State> {
+        let lock = self.lock_file()?;
+        lock.lock_shared()
+            .with_context(|| format!("failed locking state file at {}", self.path.display()))?;
+        self.read_unlocked()
+    }
+
+    /// Applies `change` to the current state and saves the result, holding
+    /// the lock throughout. Returns what `change` returned.
+    pub fn update<T>(&self, change: impl FnOnce(&mut State) -> T) -> Result<T> {
+        let lock = self.lock_file()?;
+        lock.lock()
+            .with_context(|| format!("failed locking state file at {}", self.path.display()))?;
+        let mut state = self.read_unlocked()?;
+        let changed = change(&mut state);
+        self.write_unlocked(&state)?;
+        Ok(changed)
+    }
+
+    /// Opens the lock file next to the state file; the lock is released when
+    /// it is dropped.
+    fn lock_file(&self) -> Result<fs::File> {
+        let dir = self.dir()?;
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed creating state directory {}", dir.display()))?;
+        let path = self.path.with_extension("lock");
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("failed opening state lock {}", path.display()))
+    }
+
+    fn dir(&self) -> Result<&Path> {
+        self.path
+            .parent()
+            .context("unable to determine parent directory for state file")
+    }
+
+    fn read_unlocked(&self) -> Result<State> {
+        if !self.path.exists() {
+            return Ok(State::default());
+        }
+
+        let raw = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed reading state file at {}", self.path.display()))?;
+        serde_json::from_str(&raw)
+            .with_context(|| format!("failed parsing state file at {}", self.path.display()))
+    }
+
+    fn write_unlocked(&self, state: &State) -> Result<()> {
+        let dir = self.dir()?;
+        // Write to a sibling temp file and rename so an interrupted run never
+        // leaves a truncated state file behind.
+        let mut file = tempfile::NamedTempFile::new_in(dir)
+            .with_context(|| format!("failed creating temp state file in {}", dir.display()))?;
+        let raw = serde_json::to_string_pretty(state).context("failed serializing state")?;
+        file.write_all(raw.as_bytes())
+            .context("failed writing temp state file")?;
+        file.persist(&self.path)
+            .with_context(|| format!("failed writing state file at {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+impl State {
+    /// The record for `repo`, also finding records written under its path
+    /// before it had an identity key.
+    pub fn lookup(&self, keys: &RepoKeys, repo: &Path) -> Option<&RepoState> {
+        self.repos
+            .get(&keys.key(repo))
+            .or_else(|| self.repos.get(&config::canonical_repo_key(repo)))
+    }
+
+    pub fn record_run(&mut self, keys: &RepoKeys, results: &[RepoResult], run_at: i64) {
+        for result in results {
+            let key = keys.key(&result.repo);
+            let path_key = config::canonical_repo_key(&result.repo);
+            let last_snapshot = result.snapshot.clone().or_else(|| {
+                self.lookup(keys, &result.repo)
+                    .and_then(|saved| saved.last_snapshot.clone())
+            });
+            if key != path_key {
+                self.repos.remove(&path_key);
+            }
+            self.repos.insert(
+                key,
+                RepoState {
+                    last_status: result.status,
+                    last_message: result.message.clone(),
+                    last_run_at: run_at,
+                    excluded_untracked: result.excluded_untracked.clone(),
+                    last_snapshot,
+                    path: Some(path_key),
+                },
+            );
+        }
+    }
+
+    /// The last recorded apply in `repo`.
+    pub fn last_apply(&self, keys: &RepoKeys, repo: &Path) -> Option<&ApplyRecord> {
+        self.applies.get(&keys.key(repo))
+    }
+
+    /// Remembers `record` as `repo`'s last apply, replacing the previous one.
+    pub fn record_apply(&mut self, keys: &RepoKeys, repo: &Path, record: ApplyRecord) {
+        self.applies.insert(keys.key(repo), record);
+    }
+
+    /// Forgets `repo`'s last apply once it has been undone.
+    pub fn forget_apply(&mut self, keys: &RepoKeys, repo: &Path) {
+        self.applies.remove(&keys.key(repo));
+    }
+
+    /// Moves the record kept under `old_key` to `new_key`, replacing any
+    /// record already there. Returns whether there was a record to move.
+    pub fn rename_repo(&mut self, old_key: &str, new_key: &str) -> bool {
+        if let Some(applied) = self.applies.remove(old_key) {
+            self.applies.insert(new_key.to_string(), applied);
+        }
+        match self.repos.remove(old_key) {
+            Some(saved) => {
+                self.repos.insert(new_key.to_string(), saved);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Compares `results` with these records, matching repositories by state
+    /// key.
+    pub fn diff_run(&self, keys: &RepoKeys, results: &[RepoResult]) -> RunDiff {
+        let mut diff = RunDiff::default();
+        for result in results {
+            let previous_status = self
+                .lookup(keys, &result.repo)
+                .map(|saved| saved.last_status);
+            match (previous_status, result.status) {
+                (
+                    Some(RepoStatus::Failed | RepoStatus::Environment),
+                    RepoStatus::Success | RepoStatus::NoOp | RepoStatus::NeedsApply,
+                ) => {
+                    diff.recovered.push(result.repo.clone());
+                }
+                (
+                    Some(RepoStatus::Failed | RepoStatus::Environment),
+                    RepoStatus::Failed | RepoStatus::Environment,
+                ) => {}
+                (_, RepoStatus::Failed | RepoStatus::Environment) => {
+                    diff.new_failures.push(result.repo.clone());
+                }
+                (
+                    _,
+                    RepoStatus::Success
+                    | RepoStatus::NoOp
+                    | RepoStatus::Busy
+                    | RepoStatus::Skipped
+                    | RepoStatus::NeedsApply,
+                ) => {}
+            }
+        }
+        diff
+    }
+
+    /// Removes run and apply records for repositories outside `known` (when
+    /// given) and those older than `retention_days` before `now`: by last run
+    /// for run records, by when the apply happened for apply records. A
+    /// retention of zero keeps records regardless of age. Each pruned key is
+    /// reported once, sorted.
+    pub fn prune(
+        &mut self,
+        known: Option<&BTreeSet<String>>,
+        retention_days: u64,
+        now: i64,
+    ) -> Vec<(String, PruneReason)> {
+        let cutoff = (retention_days > 0).then(|| now - retention_days as i64 * 24 * 60 * 60);
+        let reason = |key: &String, at: i64| {
+            if known.is_some_and(|known| !known.contains(key)) {
+                Some(PruneReason::Unconfigured)
+            } else if cutoff.is_some_and(|cutoff| at < cutoff) {
+                Some(PruneReason::Expired)
+            } else {
+                None
+            }
+        };
+        let mut pruned = BTreeMap::new();
+        self.repos
+            .retain(|key, saved| match reason(key, saved.last_run_at) {
+                Some(reason) => {
+                    pruned.insert(key.clone(), reason);
+                    false
+                }
+                None => true,
+            });
+        self.applies
+            .retain(|key, record| match reason(key, record.applied_at) {
+                Some(reason) => {
+                    pruned.entry(key.clone()).or_insert(reason);
+                    false
+                }
+                None => true,
+            });
+        if let Some(known) = known {
+            self.root_commits
+                .retain(|path_key, _| known.contains(path_key));
+        }
+        pruned.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::thread;
+
+    #[test]
+    fn prune_drops_unconfigured_and_expired_records() {
+        let day = 24 * 60 * 60;
+        let saved = |last_run_at: i64| RepoState {
+            last_status: RepoStatus::Success,
+            last_message: "pull ok".to_string(),
+            last_run_at,
+            excluded_untracked: Vec::new(),
+            last_snapshot: None,
+            path: None,
+        };
+        let applied = |applied_at: i64| ApplyRecord {
+            before: "before".to_string(),
+            after: "after".to_string(),
+            side_tip: "tip".to_string(),
+            method: "ff".to_string(),
+            applied_at,
+        };
+        let mut state = State {
+            repos: BTreeMap::from([
+                ("/tmp/fresh".to_string(), saved(100 * day)),
+                ("/tmp/stale".to_string(), saved(5 * day)),
+                ("/tmp/removed".to_string(), saved(100 * day)),
+            ]),
+            applies: BTreeMap::from([
+                ("/tmp/fresh".to_string(), applied(99 * day)),
+                ("/tmp/old-apply".to_string(), applied(5 * day)),
+                ("/tmp/removed".to_string(), applied(100 * day)),
+                ("/tmp/gone".to_string(), applied(100 * day)),
+            ]),
+            root_commits: BTreeMap::from([
+                ("/tmp/fresh".to_string(), "abc123".to_string()),
+                ("/tmp/removed".to_string(), "def456".to_string()),
+            ]),
+        };
+        let known = BTreeSet::from([
+            "/tmp/fresh".to_string(),
+            "/tmp/stale".to_string(),
+            "/tmp/old-apply".to_string(),
+        ]);
+
+        let pruned = state.prune(Some(&known), 30, 100 * day);
+
+        assert_eq!(
+            (
+                pruned,
+                state.repos.into_keys().collect::<Vec<_>>(),
+                state.applies.into_keys().collect::<Vec<_>>(),
+                state.root_commits.into_keys().collect::<Vec<_>>()
+            ),
+            (
+                vec![
+                    ("/tmp/gone".to_string(), PruneReason::Unconfigured),
+                    ("/tmp/old-apply".to_string(), PruneReason::Expired),
+                    ("/tmp/removed".to_string(), PruneReason::Unconfigured),
+                    ("/tmp/stale".to_string(), PruneReason::Expired),
+                ],
+                vec!["/tmp/fresh".to_string()],
+                vec!["/tmp/fresh".to_string()],
+                vec!["/tmp/fresh".to_string()],
+            )
+        );
+    }
+
+    #[test]
+    fn clones_sharing_a_root_leave_the_root_key_with_its_holder() {
+        let repos = config::merge_discovered_repositories(
+            &[],
+            &[
+                PathBuf::from("/tmp/shephard-keys-first"),
+                PathBuf::from("/tmp/shephard-keys-clone"),
+            ],
+            &[],
+        );
+        let first = repos
+            .iter()
+            .filter(|repo| repo.path.ends_with("shephard-keys-first"))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut saved = State {
+            root_commits: BTreeMap::from([
+                ("/tmp/shephard-keys-first".to_string(), "abc123".to_string()),
+                ("/tmp/shephard-keys-clone".to_string(), "abc123".to_string()),
+            ]),
+            ..State::default()
+        };
+        saved.record_run(
+            &RepoKeys::resolve(&first, &saved),
+            &[RepoResult {
+                repo: PathBuf::from("/tmp/shephard-keys-first"),
+                status: RepoStatus::Success,
+                message: "pull ok".to_string(),
+                excluded_untracked: Vec::new(),
+                phases: Vec::new(),
+                snapshot: None,
+            }],
+            10,
+        );
+
+        let keys = RepoKeys::resolve(&repos, &saved);
+        assert_eq!(
+            (
+                keys.key(Path::new("/tmp/shephard-keys-first")),
+                keys.key(Path::new("/tmp/shephard-keys-clone")),
+                keys.found_roots,
+            ),
+            (
+                "root:abc123".to_string(),
+                "/tmp/shephard-keys-clone".to_string(),
+                BTreeMap::new(),
+            )
+        );
+    }
+
+    #[test]
+    fn record_run_replaces_previous_entries() {
+        let mut state = State::default();
+        let first = RepoResult {
+            repo: PathBuf::from("/tmp/shephard-state-repo"),
+            status: RepoStatus::Failed,
+            message: "pull failed".to_string(),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
+        };
+        let second = RepoResult {
+            status: RepoStatus::Success,
+            message: "pull ok".to_string(),
+            excluded_untracked: vec!["notes.txt".to_string()],
+            ..first.clone()
+        };
+
+        state.record_run(&RepoKeys::default(), &[first], 10);
+        state.record_run(&RepoKeys::default(), &[second], 20);
+
+        assert_eq!(
+            state,
+            State {
+                repos: BTreeMap::from([(
+                    "/tmp/shephard-state-repo".to_string(),
+                    RepoState {
+                        last_status: RepoStatus::Success,
+                        last_message: "pull ok".to_string(),
+                        last_run_at: 20,
+                        excluded_untracked: vec!["notes.txt".to_string()],
+                        last_snapshot: None,
+                        path: Some("/tmp/shephard-state-repo".to_string()),
+                    },
+                )]),
+                applies: BTreeMap::new(),
+                root_commits: BTreeMap::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn record_run_keeps_the_last_snapshot_until_a_new_snapshot() {
+        let mut state = State::default();
+        let snapshot = |tree: &str| LastSnapshot {
+            target: "git shephard /srv/side.git refs/heads/shephard/sync".to_string(),
+            tree: tree.to_string(),
+            tip: Some(format!("{tree}-tip")),
+        };
+        let result = |snapshot: Option<LastSnapshot>| RepoResult {
+            repo: PathBuf::from("/tmp/shephard-snapshot"),
+            status: RepoStatus::NoOp,
+            message: String::new(),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot,
+        };
+        let last_snapshot = |state: &State| {
+            state
+                .lookup(&RepoKeys::default(), Path::new("/tmp/shephard-snapshot"))
+                .and_then(|saved| saved.last_snapshot.clone())
+        };
+
+        state.record_run(
+            &RepoKeys::default(),
+            &[result(Some(snapshot("tree-a")))],
+            10,
+        );
+        state.record_run(&RepoKeys::default(), &[result(None)], 20);
+        let kept = last_snapshot(&state);
+        state.record_run(
+            &RepoKeys::default(),
+            &[result(Some(snapshot("tree-b")))],
+            30,
+        );
+
+        assert_eq!(
+            (kept, last_snapshot(&state)),
+            (Some(snapshot("tree-a")), Some(snapshot("tree-b")))
+        );
+    }
+
+    #[test]
+    fn record_run_moves_path_keyed_records_to_identity_keys() {
+        let keys = RepoKeys {
+            keys: BTreeMap::from([(
+                "/tmp/shephard-identity".to_string(),
+                "root:abc123".to_string(),
+            )]),
+            found_roots: BTreeMap::new(),
+        };
+        let saved = RepoState {
+            last_status: RepoStatus::Failed,
+            last_message: "pull failed".to_string(),
+            last_run_at: 10,
+            excluded_untracked: Vec::new(),
+            last_snapshot: None,
+            path: None,
+        };
+        let mut state = State {
+            repos: BTreeMap::from([("/tmp/shephard-identity".to_string(), saved.clone())]),
+            applies: BTreeMap::new(),
+            root_commits: BTreeMap::new(),
+        };
+        let repo = Path::new("/tmp/shephard-identity");
+
+        let found = state.lookup(&keys, repo).cloned();
+        state.record_run(
+            &keys,
+            &[RepoResult {
+                repo: repo.to_path_buf(),
+                status: RepoStatus::Success,
+                message: "pull ok".to_string(),
+                excluded_untracked: Vec::new(),
+                phases: Vec::new(),
+                snapshot: None,
+            }],
+            20,
+        );
+
+        assert_eq!(
+            (found, state.repos.into_keys().collect::<Vec<_>>()),
+            (Some(saved), vec!["root:abc123".to_string()])
+        );
+    }
+
+    #[test]
+    fn concurrent_updates_keep_every_writers_records() {
+        let dir = tempfile::tempdir().expect("tempdir should be created");
+        let store = StateStore::at(dir.path().join("state.json"));
+
+        thread::scope(|scope| {
+            for run in 0..8 {
+                let store = &store;
+                scope.spawn(move || {
+                    store
+                        .update(|state| {
+                            state.record_run(
+                                &RepoKeys::default(),
+                                &[RepoResult {
+                                    repo: PathBuf::from(format!("/tmp/concurrent-{run}")),
+                                    status: RepoStatus::Success,
+                                    message: "pull ok".to_string(),
+                                    excluded_untracked: Vec::new(),
+                                    phases: Vec::new(),
+                                    snapshot: None,
+                                }],
+                                run,
+                            );
+                        })
+                        .expect("update should succeed");
+                });
+            }
+        });
+
+        assert_eq!(store.read().expect("read should succeed").repos.len(), 8);
+    }
+
+    #[test]
+    fn diff_run_reports_new_failures_and_recoveries() {
+        let saved = |last_status: RepoStatus| RepoState {
+            last_status,
+            last_message: String::new(),
+            last_run_at: 10,
+            excluded_untracked: Vec::new(),
+            last_snapshot: None,
+            path: None,
+        };
+        let previous = State {
+            repos: BTreeMap::from([
+                ("/tmp/still-failing".to_string(), saved(RepoStatus::Failed)),
+                ("/tmp/recovered".to_string(), saved(RepoStatus::Failed)),
+                ("/tmp/regressed".to_string(), saved(RepoStatus::Success)),
+                ("/tmp/still-busy".to_string(), saved(RepoStatus::Failed)),
+            ]),
+            applies: BTreeMap::new(),
+            root_commits: BTreeMap::new(),
+        };
+        let result = |path: &str, status: RepoStatus| RepoResult {
+            repo: PathBuf::from(path),
+            status,
+            message: String::new(),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
+        };
+        let results = [
+            result("/tmp/still-failing", RepoStatus::Failed),
+            result("/tmp/recovered", RepoStatus::NoOp),
+            result("/tmp/regressed", RepoStatus::Failed),
+            result("/tmp/still-busy", RepoStatus::Busy),
+            result("/tmp/first-run", RepoStatus::Failed),
+        ];
+
+        assert_eq!(
+            previous.diff_run(&RepoKeys::default(), &results),
+            RunDiff {
+                new_failures: vec![
+                    PathBuf::from("/tmp/regressed"),
+                    PathBuf::from("/tmp/first-run"),
+                ],
+                recovered: vec![PathBuf::from("/tmp/recovered")],
+            }
+        );
+    }
+}
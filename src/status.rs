@@ -0,0 +1,232 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{Local, TimeZone};
+
+use crate::cli::StatusArgs;
+use crate::config::{
+    self, ResolvedConfig, ResolvedRepositoryConfig, SideChannelBackend, SideChannelConfig,
+};
+use crate::discovery;
+use crate::display::PathDisplay;
+use crate::git;
+use crate::git_backend::{self, GitBackend};
+use crate::state::{RepoKeys, RepoState, StateStore};
+use crate::workflow::RepoStatus;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Health {
+    Ok,
+    Attention,
+    Failed,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RepoHealth {
+    pub repo: PathBuf,
+    pub health: Health,
+    pub details: Vec<String>,
+}
+
+pub fn run(
+    args: &StatusArgs,
+    config: &ResolvedConfig,
+    repos: &[ResolvedRepositoryConfig],
+    keys: &RepoKeys,
+    paths: &PathDisplay,
+) -> Result<i32> {
+    let state = StateStore::open()?.read()?;
+    let backend = git_backend::for_config(config.git_backend);
+    let checks = repos
+        .iter()
+        .map(|repo| {
+            let side = config::resolve_repo_side_channel(config, repo);
+            let saved = state.lookup(keys, &repo.path);
+            check_repo(&repo.path, &side, saved, args.refresh, backend.as_ref())
+        })
+        .collect::<Vec<_>>();
+
+    if args.short {
+        println!("{}", short_line(&checks));
+        return Ok(0);
+    }
+
+    for item in &checks {
+        let label = match item.health {
+            Health::Ok => "OK",
+            Health::Attention => "WARN",
+            Health::Failed => "FAIL",
+        };
+        println!(
+            "[{label}] {} :: {}",
+            paths.show(&item.repo),
+            item.details.join(", ")
+        );
+    }
+    Ok(0)
+}
+
+/// Renders counts as `12✓ 2✗ 3⚠` for status bars.
+pub fn short_line(checks: &[RepoHealth]) -> String {
+    let count = |health: Health| checks.iter().filter(|item| item.health == health).count();
+    format!(
+        "{}✓ {}✗ {}⚠",
+        count(Health::Ok),
+        count(Health::Failed),
+        count(Health::Attention)
+    )
+}
+
+fn check_repo(
+    repo: &Path,
+    side: &SideChannelConfig,
+    saved: Option<&RepoState>,
+    refresh: bool,
+    backend: &dyn GitBackend,
+) -> RepoHealth {
+    let mut health = Health::Ok;
+    let mut details = Vec::new();
+    let mut flag = |level: Health, detail: String| {
+        if level == Health::Failed || (level == Health::Attention && health == Health::Ok) {
+            health = level;
+        }
+        details.push(detail);
+    };
+
+    if !discovery::is_git_repository(repo) {
+        flag(Health::Failed, "not a git repository".to_string());
+        return RepoHealth {
+            repo: repo.to_path_buf(),
+            health,
+            details,
+        };
+    }
+
+    if refresh && let Err(err) = git::fetch(repo) {
+        flag(Health::Failed, format!("fetch failed: {err:#}"));
+    }
+
+    match backend.current_branch(repo) {
+        Ok(Some(branch)) => flag(Health::Ok, format!("on {branch}")),
+        Ok(None) => flag(Health::Attention, "detached HEAD".to_string()),
+        Err(err) => flag(Health::Failed, format!("branch check failed: {err:#}")),
+    }
+
+    match backend.changed_path_count(repo) {
+        Ok(0) => flag(Health::Ok, "clean".to_string()),
+        Ok(count) => flag(Health::Attention, format!("{count} changed")),
+        Err(err) => flag(Health::Failed, format!("status check failed: {err:#}")),
+    }
+
+    match backend.upstream_ahead_behind(repo) {
+        Ok(Some((0, 0))) => flag(Health::Ok, "up to date".to_string()),
+        Ok(Some((ahead, behind))) => {
+            flag(Health::Attention, format!("ahead {ahead}, behind {behind}"))
+        }
+        Ok(None) => flag(Health::Attention, "no upstream".to_string()),
+        Err(err) => flag(Health::Failed, format!("upstream check failed: {err:#}")),
+    }
+
+    let uses_remote = match side.backend {
+        SideChannelBackend::Git => true,
+        SideChannelBackend::Rclone | SideChannelBackend::Restic => false,
+    };
+    if side.enabled && !uses_remote {
+        flag(
+            Health::Ok,
+            format!("side channel offloaded with {}", side.backend),
+        );
+    }
+    if side.enabled && uses_remote {
+        // Snapshot commits that never made it upstream are "floating" work that
+        // has not been applied anywhere yet.
+        match git::side_channel_divergence(repo, side) {
+            Ok(Some((0, _))) => flag(Health::Ok, "side channel applied".to_string()),
+            Ok(Some((side_only, upstream_only))) => flag(
+                Health::Attention,
+                format!(
+                    "side channel {side_only} not upstream, {upstream_only} upstream not in side channel"
+                ),
+            ),
+            Ok(None) => flag(Health::Ok, "no side-channel tip".to_string()),
+            Err(err) => flag(
+                Health::Failed,
+                format!("side-channel check failed: {err:#}"),
+            ),
+        }
+    }
+
+    match saved {
+        None => flag(Health::Attention, "never synced".to_string()),
+        Some(saved) => {
+            let when = Local
+                .timestamp_opt(saved.last_run_at, 0)
+                .single()
+                .map(|at| at.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| saved.last_run_at.to_string());
+            match saved.last_status {
+                RepoStatus::Success | RepoStatus::NoOp => {
+                    flag(Health::Ok, format!("last sync ok {when}"));
+                }
+                RepoStatus::Busy => {
+                    flag(
+                        Health::Attention,
+                        format!("last sync skipped {when}: repository busy"),
+                    );
+                }
+                RepoStatus::Skipped => {
+                    flag(Health::Ok, format!("last sync skipped {when}"));
+                }
+                RepoStatus::NeedsApply => {
+                    flag(
+                        Health::Attention,
+                        format!("last sync ok {when}, side-channel changes need apply"),
+                    );
+                }
+                RepoStatus::Environment => {
+                    let reason = saved.last_message.lines().next().unwrap_or_default();
+                    flag(
+                        Health::Failed,
+                        format!("last sync failed {when} (environment): {reason}"),
+                    );
+                }
+                RepoStatus::Failed => {
+                    let reason = saved.last_message.lines().next().unwrap_or_default();
+                    flag(Health::Failed, format!("last sync failed {when}: {reason}"));
+                }
+            }
+        }
+    }
+
+    RepoHealth {
+        repo: repo.to_path_buf(),
+        health,
+        details,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn short_line_counts_each_health_bucket() {
+        let checks = [
+            health("/tmp/a", Health::Ok),
+            health("/tmp/b", Health::Ok),
+            health("/tmp/c", Health::Failed),
+            health("/tmp/d", Health::Attention),
+        ];
+
+        assert_eq!(short_line(&checks), "2✓ 1✗ 1⚠");
+    }
+
+    fn health(path: &str, health: Health) -> RepoHealth {
+        RepoHealth {
+            repo: PathBuf::from(path),
+            health,
+            details: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::git;
+use crate::git_hooks;
+
+/// Longest wait for filesystem events when nothing is pending.
+const IDLE_WAIT: Duration = Duration::from_secs(3600);
+
+/// Changed path recorded when the hooks from `shephard hook install` touch a
+/// repository's marker, i.e. after a commit, checkout or merge. No working-tree
+/// file can have this path.
+const HOOK_EVENT: &str = ".git/shephard-changed";
+
+/// Watches `repos` for working-tree changes and calls `sync` with the
+/// repositories whose changes have settled for `debounce`, until the process
+/// is stopped. Changes inside `.git` and to ignored files are not counted,
+/// except the marker installed hooks write on commits and checkouts. Changes
+/// saved while a sync runs are kept for the next one, except those that leave
+/// nothing different from `HEAD`, as the sync's own writes do.
+pub fn watch(
+    repos: &[PathBuf],
+    debounce: Duration,
+    mut sync: impl FnMut(&[PathBuf]) -> Result<()>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx).context("failed starting file watcher")?;
+    let mut watched = Vec::new();
+    let mut markers = Vec::new();
+    for repo in repos {
+        let root = repo.canonicalize().unwrap_or_else(|_| repo.clone());
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("failed watching {}", root.display()))?;
+        if let Ok(marker) = git_hooks::changed_marker(repo)
+            && let (Some(dir), Some(name)) = (marker.parent(), marker.file_name())
+            && let Ok(dir) = dir.canonicalize()
+        {
+            // Linked worktrees keep their git directory outside the tree.
+            if !dir.starts_with(&root) {
+                watcher
+                    .watch(&dir, RecursiveMode::NonRecursive)
+                    .with_context(|| format!("failed watching {}", dir.display()))?;
+            }
+            markers.push((dir.join(name), repo.clone()));
+        }
+        watched.push((root, repo.clone()));
+    }
+
+    let mut pending = Debouncer::new(debounce);
+    loop {
+        let wait = pending.next_due().map_or(IDLE_WAIT, |due| {
+            due.saturating_duration_since(Instant::now())
+        });
+        match rx.recv_timeout(wait) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+                for path in &event.paths {
+                    if let Some((repo, relative)) = event_repo(&watched, &markers, path) {
+                        pending.record(repo, relative, Instant::now());
+                    }
+                }
+            }
+            Ok(Err(err)) => eprintln!("Warning: file watcher error: {err}"),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                anyhow::bail!("file watcher stopped unexpectedly")
+            }
+        }
+
+        let ready = pending
+            .take_due(Instant::now())
+            .into_iter()
+            .filter(|(repo, paths)| {
+                // Build output and editor swap files are usually ignored; a
+                // burst of only those is not worth a sync.
+                paths.contains(HOOK_EVENT)
+                    || git::unignored_paths(repo, &paths.iter().cloned().collect::<Vec<_>>())
+                        .map_or(true, |paths| !paths.is_empty())
+            })
+            .map(|(repo, _)| repo)
+            .collect::<Vec<_>>();
+        if ready.is_empty() {
+            continue;
+        }
+        if let Err(err) = sync(&ready) {
+            eprintln!("Warning: sync failed: {err:#}");
+        }
+        let during = rx
+            .try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| !matches!(event.kind, EventKind::Access(_)))
+            .flat_map(|event| event.paths)
+            .filter_map(|path| event_repo(&watched, &markers, &path))
+            .collect::<Vec<_>>();
+        pending.record_after_sync(&ready, during, Instant::now(), |repo, paths| {
+            git::changed_paths_among(repo, paths).unwrap_or_else(|_| paths.to_vec())
+        });
+    }
+}
+
+/// The repository an event at `path` counts for: the one whose hook marker it
+/// is, recorded as [`HOOK_EVENT`], else as [`owning_repo`] finds it.
+fn event_repo(
+    watched: &[(PathBuf, PathBuf)],
+    markers: &[(PathBuf, PathBuf)],
+    path: &Path,
+) -> Option<(PathBuf, String)> {
+    match markers.iter().find(|(marker, _)| marker == path) {
+        Some((_, repo)) => Some((repo.clone(), HOOK_EVENT.to_string())),
+        None => owning_repo(watched, path),
+    }
+}
+
+/// The watched repository containing `path` and the path relative to it, or
+/// `None` for paths inside a git directory or outside every repository.
+fn owning_repo(watched: &[(PathBuf, PathBuf)], path: &Path) -> Option<(PathBuf, String)> {
+    let (relative, repo) = watched
+        .iter()
+        .filter_map(|(root, repo)| Some((path.strip_prefix(root).ok()?, repo)))
+        .min_by_key(|(relative, _)| relative.components().count())?;
+    if relative
+        .components()
+        .any(|component| component == Component::Normal(".git".as_ref()))
+    {
+        return None;
+    }
+    Some((repo.clone(), relative.to_string_lossy().to_string()))
+}
+
+/// Changed paths per repository, released once a repository has seen no
+/// change for the debounce interval.
+struct Debouncer {
+    debounce: Duration,
+    pending: BTreeMap<PathBuf, (Instant, BTreeSet<String>)>,
+}
+
+impl Debouncer {
+    fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn record(&mut self, repo: PathBuf, relative: String, now: Instant) {
+        let entry = self
+            .pending
+            .entry(repo)
+            .or_insert_with(|| (now, BTreeSet::new()));
+        entry.0 = now;
+        if !relative.is_empty() {
+            entry.1.insert(relative);
+        }
+    }
+
+    /// Records `events` that arrived while the `synced` repositories were
+    /// syncing. In those, only paths `still_changed` reports as differing from
+    /// `HEAD` are kept, so files the sync wrote itself do not start another
+    /// sync while edits saved meanwhile do. Hook markers are dropped there
+    /// too: the sync's own commits fire the hooks.
+    fn record_after_sync(
+        &mut self,
+        synced: &[PathBuf],
+        events: Vec<(PathBuf, String)>,
+        now: Instant,
+        still_changed: impl Fn(&Path, &[String]) -> Vec<String>,
+    ) {
+        let mut by_repo = BTreeMap::<PathBuf, Vec<String>>::new();
+        for (repo, relative) in events {
+            by_repo.entry(repo).or_default().push(relative);
+        }
+        for (repo, mut paths) in by_repo {
+            if synced.contains(&repo) {
+                paths.retain(|path| !path.is_empty() && path != HOOK_EVENT);
+                paths = still_changed(&repo, &paths);
+            }
+            for relative in paths {
+                self.record(repo.clone(), relative, now);
+            }
+        }
+    }
+
+    /// When the earliest pending repository settles.
+    fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|(last, _)| *last + self.debounce)
+            .min()
+    }
+
+    /// Removes and returns the repositories that have settled by `now`, with
+    /// their changed paths.
+    fn take_due(&mut self, now: Instant) -> Vec<(PathBuf, BTreeSet<String>)> {
+        let due = self
+            .pending
+            .iter()
+            .filter(|(_, (last, _))| now.duration_since(*last) >= self.debounce)
+            .map(|(repo, _)| repo.clone())
+            .collect::<Vec<_>>();
+        due.into_iter()
+            .filter_map(|repo| {
+                let (_, paths) = self.pending.remove(&repo)?;
+                Some((repo, paths))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn events_map_to_the_innermost_repo_and_skip_git_directories() {
+        let watched = [
+            (PathBuf::from("/srv/outer"), PathBuf::from("~/outer")),
+            (PathBuf::from("/srv/outer/inner"), PathBuf::from("~/inner")),
+        ];
+
+        assert_eq!(
+            [
+                "/srv/outer/a.txt",
+                "/srv/outer/inner/src/b.rs",
+                "/srv/outer/inner/.git/index",
+                "/srv/elsewhere/c.txt",
+            ]
+            .map(|path| owning_repo(&watched, Path::new(path))),
+            [
+                Some((PathBuf::from("~/outer"), "a.txt".to_string())),
+                Some((PathBuf::from("~/inner"), "src/b.rs".to_string())),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn hook_markers_count_as_changes_except_during_their_repos_sync() {
+        let now = Instant::now();
+        let watched = [(PathBuf::from("/srv/a"), PathBuf::from("~/a"))];
+        let markers = [(
+            PathBuf::from("/srv/a/.git/shephard-changed"),
+            PathBuf::from("~/a"),
+        )];
+        let marker = event_repo(
+            &watched,
+            &markers,
+            Path::new("/srv/a/.git/shephard-changed"),
+        )
+        .expect("the marker belongs to ~/a");
+        assert_eq!(
+            (
+                marker.clone(),
+                event_repo(&watched, &markers, Path::new("/srv/a/.git/index")),
+            ),
+            ((PathBuf::from("~/a"), HOOK_EVENT.to_string()), None)
+        );
+
+        let mut pending = Debouncer::new(Duration::from_secs(5));
+        pending.record_after_sync(
+            &[PathBuf::from("~/a")],
+            vec![marker.clone()],
+            now,
+            |_, paths| paths.to_vec(),
+        );
+        assert_eq!(pending.next_due(), None);
+        pending.record_after_sync(&[], vec![marker], now, |_, paths| paths.to_vec());
+        assert_eq!(
+            pending.take_due(now + Duration::from_secs(5)),
+            vec![(
+                PathBuf::from("~/a"),
+                BTreeSet::from([HOOK_EVENT.to_string()])
+            )]
+        );
+    }
+
+    #[test]
+    fn edits_saved_during_a_sync_are_kept_and_its_own_writes_dropped() {
+        let now = Instant::now();
+        let mut pending = Debouncer::new(Duration::from_secs(5));
+        let event = |repo: &str, path: &str| (PathBuf::from(repo), path.to_string());
+
+        pending.record_after_sync(
+            &[PathBuf::from("/srv/a")],
+            vec![
+                event("/srv/a", "pulled.txt"),
+                event("/srv/a", "edited.txt"),
+                event("/srv/b", "elsewhere.txt"),
+            ],
+            now,
+            |_, paths| {
+                paths
+                    .iter()
+                    .filter(|path| *path == "edited.txt")
+                    .cloned()
+                    .collect()
+            },
+        );
+
+        assert_eq!(
+            pending.take_due(now + Duration::from_secs(5)),
+            vec![
+                (
+                    PathBuf::from("/srv/a"),
+                    BTreeSet::from(["edited.txt".to_string()])
+                ),
+                (
+                    PathBuf::from("/srv/b"),
+                    BTreeSet::from(["elsewhere.txt".to_string()])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn repos_are_released_once_their_changes_settle() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut pending = Debouncer::new(5 * second);
+        pending.record(PathBuf::from("/srv/a"), "one".to_string(), start);
+        pending.record(PathBuf::from("/srv/b"), "two".to_string(), start + second);
+        pending.record(
+            PathBuf::from("/srv/a"),
+            "three".to_string(),
+            start + 3 * second,
+        );
+
+        let early = pending.take_due(start + 5 * second);
+        let next = pending.next_due();
+        let settled = pending.take_due(start + 8 * second);
+
+        assert_eq!(
+            (early, next, settled, pending.next_due()),
+            (
+                Vec::new(),
+                Some(start + 6 * second),
+                vec![
+                    (
+                        PathBuf::from("/srv/a"),
+                        BTreeSet::from(["one".to_string(), "three".to_string()])
+                    ),
+                    (PathBuf::from("/srv/b"), BTreeSet::from(["two".to_string()])),
+                ],
+                None,
+            )
+        );
+    }
+}
@@ -1,12 +1,38 @@
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::config::{FailurePolicy, ResolvedRunConfig};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::backend::{self, SnapshotRequest};
+use crate::config::{
+    FailurePolicy, JjPolicy, LastSnapshot, RepoCommands, ResolvedRunConfig, RunMode,
+    SideChannelBackend, UntrackedPolicy,
+};
+use crate::discovery;
 use crate::git;
+use crate::journal::{Journal, Phase};
+use crate::logging;
+use crate::report;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RepoStatus {
     Success,
     NoOp,
+    /// Another process held the repository's index lock for the whole timeout.
+    Busy,
+    /// Left alone by policy, e.g. a colocated jj repository.
+    Skipped,
+    /// Failed because the process environment lacks what git needs, such as
+    /// an ssh agent under cron.
+    Environment,
+    /// Synced, but the side channel holds work from another machine that
+    /// could not be fast-forwarded in, so `shephard apply` is needed here.
+    NeedsApply,
     Failed,
 }
 
@@ -15,6 +41,58 @@ pub struct RepoResult {
     pub repo: PathBuf,
     pub status: RepoStatus,
     pub message: String,
+    /// Untracked files this run left out because of `include_untracked`,
+    /// capped at a short preview.
+    pub excluded_untracked: Vec<String>,
+    /// How each [`RunPhase`] went, in order; empty when the repository was
+    /// left alone before its pipeline started.
+    pub phases: Vec<PhaseResult>,
+    /// The snapshot this run stored, kept in state so an unchanged tree is
+    /// not snapshot to the same place again.
+    pub snapshot: Option<LastSnapshot>,
+}
+
+/// The phases of a repository's sync, in the order they run. `[phases]`
+/// turns each but `Post` off; pull-only runs stop after `Pull`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunPhase {
+    /// Side-channel remote setup and fetch.
+    Preflight,
+    /// Upstream pull, then side-channel catch-up.
+    Pull,
+    /// Stage and commit, or take and store a side-channel snapshot. Its
+    /// conflict guard runs first, before the pull.
+    Commit,
+    /// Upstream push, or `commands.push`.
+    Push,
+    /// Notes on the result, such as untracked files left out.
+    Post,
+}
+
+impl RunPhase {
+    pub const ALL: [RunPhase; 5] = [
+        RunPhase::Preflight,
+        RunPhase::Pull,
+        RunPhase::Commit,
+        RunPhase::Push,
+        RunPhase::Post,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PhaseStatus {
+    Ok,
+    /// Turned off, not needed for this repository, or never reached.
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PhaseResult {
+    pub phase: RunPhase,
+    pub status: PhaseStatus,
 }
 
 pub fn run(repos: &[PathBuf], cfg: &ResolvedRunConfig) -> Vec<RepoResult> {
@@ -49,110 +127,734 @@ pub fn run_with_repo_configs(repos: &[(PathBuf, ResolvedRunConfig)]) -> Vec<Repo
     results
 }
 
+/// How many excluded untracked paths a result names before summarizing.
+const UNTRACKED_PREVIEW_LIMIT: usize = 5;
+
 fn run_repo(repo: &Path, cfg: &ResolvedRunConfig) -> RepoResult {
-    if let Err(err) = git::pull_ff_only(repo) {
+    // jj snapshots the working copy on its own, so git-level staging would
+    // fight it; such repositories sync through jj commands or not at all.
+    let jj_cfg;
+    let cfg = if discovery::is_colocated_jj(repo) {
+        match cfg.colocated_jj {
+            JjPolicy::Skip => {
+                return RepoResult {
+                    repo: repo.to_path_buf(),
+                    status: RepoStatus::Skipped,
+                    message: "skipped, colocated jj repository (set colocated_jj = \"commands\" to sync it with jj)".to_string(),
+                    excluded_untracked: Vec::new(),
+                    phases: Vec::new(),
+                    snapshot: None,
+                };
+            }
+            JjPolicy::Commands => {
+                jj_cfg = ResolvedRunConfig {
+                    commands: RepoCommands {
+                        pull: Some(
+                            cfg.commands
+                                .pull
+                                .clone()
+                                .unwrap_or_else(|| "jj git fetch".to_string()),
+                        ),
+                        push: Some(
+                            cfg.commands
+                                .push
+                                .clone()
+                                .unwrap_or_else(|| "jj git push".to_string()),
+                        ),
+                    },
+                    ..cfg.clone()
+                };
+                &jj_cfg
+            }
+        }
+    } else {
+        cfg
+    };
+
+    // Wait out short-lived index locks (an IDE refreshing status, another
+    // shephard run) instead of failing with git's raw lock error.
+    if let Ok(lock) = git::index_lock_path(repo) {
+        let deadline = Instant::now() + Duration::from_secs(cfg.lock_timeout_secs);
+        while lock.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(250));
+        }
+        if lock.exists() {
+            return RepoResult {
+                repo: repo.to_path_buf(),
+                status: RepoStatus::Busy,
+                message: format!(
+                    "skipped, {} still locked after {}s",
+                    lock.display(),
+                    cfg.lock_timeout_secs
+                ),
+                excluded_untracked: Vec::new(),
+                phases: Vec::new(),
+                snapshot: None,
+            };
+        }
+    }
+
+    let repo_env = repo.to_string_lossy();
+    if let Some(command) = &cfg.hooks.pre_repo
+        && let Err(err) = run_shell(repo, command, &[("SHEPHARD_REPO", &repo_env)])
+    {
         return RepoResult {
             repo: repo.to_path_buf(),
             status: RepoStatus::Failed,
-            message: format!("pull failed: {err:#}"),
+            message: format!("pre_repo hook failed: {err:#}"),
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
         };
     }
 
-    if !cfg.push_enabled {
-        return RepoResult {
-            repo: repo.to_path_buf(),
-            status: RepoStatus::Success,
-            message: "pull ok".to_string(),
+    let mut journal = Journal::open(cfg.journal_dir.as_deref(), repo);
+    let result = Pipeline {
+        repo,
+        cfg,
+        journal: &mut journal,
+        phases: Vec::new(),
+    }
+    .run();
+    journal.finish();
+    info!(
+        repo = %repo.display(),
+        status = report::status_label(result.status),
+        "{}",
+        result.message
+    );
+
+    // The sync already happened, so a failing post hook only warns.
+    if let Some(command) = &cfg.hooks.post_repo
+        && let Err(err) = run_shell(
+            repo,
+            command,
+            &[
+                ("SHEPHARD_REPO", &repo_env),
+                ("SHEPHARD_STATUS", report::status_label(result.status)),
+                ("SHEPHARD_MESSAGE", &result.message),
+            ],
+        )
+    {
+        eprintln!("Warning: post_repo hook failed: {err:#}");
+    }
+    result
+}
+
+/// One repository's sync as its [`RunPhase`]s. Each phase either hands what
+/// it found to the next or stops the run with the repository's result, as the
+/// `Err` of its `Result`.
+struct Pipeline<'a> {
+    repo: &'a Path,
+    cfg: &'a ResolvedRunConfig,
+    journal: &'a mut Journal,
+    /// Phases that ran, in order.
+    phases: Vec<PhaseResult>,
+}
+
+/// What the commit phase left for the push phase.
+enum Committed {
+    Changes,
+    LockfileOnly,
+    NoChanges,
+    /// The phase was off, or `commands.push` does the committing.
+    Skipped,
+}
+
+impl Pipeline<'_> {
+    fn run(mut self) -> RepoResult {
+        let mut result = match self.sync() {
+            Ok(result) => result,
+            Err(result) => *result,
         };
+        self.post(&mut result);
+        result.phases = RunPhase::ALL
+            .into_iter()
+            .map(|phase| {
+                self.phases
+                    .iter()
+                    .find(|done| done.phase == phase)
+                    .copied()
+                    .unwrap_or(PhaseResult {
+                        phase,
+                        status: PhaseStatus::Skipped,
+                    })
+            })
+            .collect();
+        result
     }
 
-    if cfg.side_channel.enabled {
-        if let Err(err) = git::side_channel_preflight(repo, &cfg.side_channel) {
-            return RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::Failed,
-                message: format!("side-channel setup failed: {err:#}"),
+    /// Whether `phase` runs: on in `[phases]`, and for everything past the
+    /// pull, a push run in a repository that is not `read_only`.
+    fn runs(&self, phase: RunPhase) -> bool {
+        let phases = self.cfg.phases;
+        let syncs = !self.cfg.read_only
+            && match self.cfg.mode {
+                RunMode::SyncAll => true,
+                RunMode::PullOnly => false,
             };
+        match phase {
+            RunPhase::Preflight => phases.preflight && phases.commit && syncs,
+            RunPhase::Pull => phases.pull,
+            RunPhase::Commit => phases.commit && syncs,
+            RunPhase::Push => phases.push && syncs,
+            RunPhase::Post => true,
         }
+    }
 
-        // Side-channel mode bypasses local commit/push so branch history remains
-        // clean; commits are synthesized and pushed to the configured side branch.
-        let message = git::generate_commit_message(&cfg.commit_template, cfg.include_untracked);
-        return match git::side_channel_sync(
-            repo,
-            &cfg.side_channel,
-            cfg.include_untracked,
-            &message,
-        ) {
-            Ok(git::SideChannelSyncResult::Pushed) => RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::Success,
-                message: "pull ok, side-channel commit pushed".to_string(),
-            },
-            Ok(git::SideChannelSyncResult::NoChanges) => RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::NoOp,
-                message: "pull ok, no local changes to commit".to_string(),
-            },
-            Err(err) => RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::Failed,
-                message: format!("side-channel sync failed: {err:#}"),
-            },
+    fn record(&mut self, phase: RunPhase, status: PhaseStatus) {
+        self.phases.push(PhaseResult { phase, status });
+    }
+
+    fn result(&self, status: RepoStatus, message: String) -> RepoResult {
+        RepoResult {
+            repo: self.repo.to_path_buf(),
+            status,
+            message,
+            excluded_untracked: Vec::new(),
+            phases: Vec::new(),
+            snapshot: None,
+        }
+    }
+
+    /// The message for this repository's commit or snapshot. Changed paths
+    /// are only listed when the template or a trailer asks for them, from the
+    /// index when `staged`, else from the working tree; a failure to list
+    /// them leaves the placeholder empty.
+    fn commit_message(&self, include_untracked: bool, staged: bool) -> String {
+        let wants_paths = std::iter::once(&self.cfg.commit_template)
+            .chain(&self.cfg.commit_body_template)
+            .chain(self.cfg.commit_trailers.values())
+            .any(|text| {
+                text.contains(git::CHANGED_PATHS_PLACEHOLDER)
+                    || text.contains(git::CHANGED_FILES_PLACEHOLDER)
+            });
+        let changed = if !wants_paths {
+            Vec::new()
+        } else if staged {
+            git::staged_paths(self.repo).unwrap_or_default()
+        } else {
+            git::working_tree_changes(self.repo, include_untracked).unwrap_or_default()
         };
+        git::generate_commit_message(
+            &self.cfg.commit_template,
+            self.cfg.commit_body_template.as_deref(),
+            self.cfg.commit_wrap_width,
+            &self.cfg.commit_timestamp,
+            &self.cfg.commit_trailers,
+            include_untracked,
+            &changed,
+        )
     }
 
-    if let Err(err) = git::stage_changes(repo, cfg.include_untracked) {
-        return RepoResult {
-            repo: repo.to_path_buf(),
-            status: RepoStatus::Failed,
-            message: format!("stage failed: {err:#}"),
+    /// Records `phase` as failed and builds the result that stops the run.
+    fn fail(&mut self, phase: RunPhase, status: RepoStatus, message: String) -> Box<RepoResult> {
+        self.record(phase, PhaseStatus::Failed);
+        Box::new(self.result(status, message))
+    }
+
+    fn sync(&mut self) -> Result<RepoResult, Box<RepoResult>> {
+        // `Ask` is answered before the run starts; a repository that still says
+        // `Ask` here had nobody to ask, so it stays tracked-only.
+        let include_untracked = match self.cfg.include_untracked {
+            UntrackedPolicy::Include => true,
+            UntrackedPolicy::Exclude | UntrackedPolicy::Ask => false,
+        };
+        let mut excludes = if self.cfg.builtin_excludes {
+            git::BUILTIN_EXCLUDES.to_vec()
+        } else {
+            Vec::new()
+        };
+        excludes.extend(self.cfg.excludes.iter().map(String::as_str));
+        let excludes = excludes.as_slice();
+
+        // Not part of preflight: turning that off must never let markers
+        // through.
+        if self.runs(RunPhase::Commit) {
+            self.guard_conflicts()?;
+        }
+        if self.runs(RunPhase::Preflight) {
+            self.preflight()?;
+        }
+        let (pulled, catch_up) = if self.runs(RunPhase::Pull) {
+            self.pull(include_untracked, excludes)?
+        } else {
+            ("pull skipped".to_string(), CatchUp::UpToDate)
+        };
+        if self.cfg.read_only {
+            return Ok(self.result(RepoStatus::Success, format!("{pulled}, read-only")));
+        }
+        if !self.runs(RunPhase::Commit) && !self.runs(RunPhase::Push) {
+            return Ok(self.result(RepoStatus::Success, pulled));
+        }
+
+        if self.cfg.side_channel.enabled {
+            return self.snapshot(&pulled, catch_up, include_untracked, excludes);
+        }
+
+        // With upstream push disabled and no side channel there is nowhere to
+        // send local work, so leave it uncommitted rather than piling up local
+        // commits.
+        if !self.cfg.push_enabled {
+            return Ok(self.result(
+                RepoStatus::Success,
+                format!("{pulled}, upstream push disabled"),
+            ));
+        }
+
+        let committed = if self.runs(RunPhase::Commit) {
+            self.commit(include_untracked, excludes)?
+        } else {
+            Committed::Skipped
         };
+
+        if !self.runs(RunPhase::Push) {
+            return Ok(match committed {
+                Committed::Changes => self.result(
+                    RepoStatus::Success,
+                    format!("{pulled}, committed, push skipped"),
+                ),
+                Committed::LockfileOnly => self.result(
+                    RepoStatus::NoOp,
+                    format!("{pulled}, only lockfile changes, not committed"),
+                ),
+                Committed::NoChanges => self.result(
+                    RepoStatus::NoOp,
+                    format!("{pulled}, no local changes to commit"),
+                ),
+                Committed::Skipped => {
+                    self.result(RepoStatus::Success, format!("{pulled}, push skipped"))
+                }
+            });
+        }
+        self.push(&pulled, committed)
     }
 
-    let has_changes = match git::has_staged_changes(repo) {
-        Ok(value) => value,
-        Err(err) => {
-            return RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::Failed,
-                message: format!("failed to inspect staged diff: {err:#}"),
+    /// Keeps half-resolved conflicts away from commits and snapshots: they
+    /// would otherwise be snapshot or committed as they stand, markers and
+    /// all, so such repositories wait for a human. Failures count against the
+    /// commit phase, which is what they stop.
+    fn guard_conflicts(&mut self) -> Result<(), Box<RepoResult>> {
+        match unresolved_conflicts(self.repo) {
+            Ok(None) => Ok(()),
+            Ok(Some(problem)) => Err(self.fail(
+                RunPhase::Commit,
+                RepoStatus::Skipped,
+                format!("skipped, {problem}; resolve them before syncing"),
+            )),
+            Err(err) => Err(self.fail(
+                RunPhase::Commit,
+                RepoStatus::Failed,
+                format!("failed to inspect working tree: {err:#}"),
+            )),
+        }
+    }
+
+    /// Readies the side-channel remote.
+    fn preflight(&mut self) -> Result<(), Box<RepoResult>> {
+        if self.cfg.side_channel.enabled {
+            let backend = backend::for_config(&self.cfg.side_channel);
+            if let Err(err) = backend.preflight(self.repo, &self.cfg.auth) {
+                return Err(self.fail(
+                    RunPhase::Preflight,
+                    RepoStatus::Failed,
+                    format!("side-channel setup failed: {err:#}"),
+                ));
+            }
+        }
+        self.record(RunPhase::Preflight, PhaseStatus::Ok);
+        Ok(())
+    }
+
+    /// Pulls upstream, then catches up with side-channel work from other
+    /// machines. Returns how the pull reads in the result message.
+    fn pull(
+        &mut self,
+        include_untracked: bool,
+        excludes: &[&str],
+    ) -> Result<(String, CatchUp), Box<RepoResult>> {
+        self.journal.record(Phase::Pull);
+        let pulled = match &self.cfg.commands.pull {
+            Some(command) => run_command(self.repo, command),
+            None => git::pull_ff_only(self.repo, &self.cfg.auth),
+        };
+        if let Err(err) = pulled {
+            return Err(self.fail(
+                RunPhase::Pull,
+                RepoStatus::Failed,
+                format!("pull failed: {err:#}"),
+            ));
+        }
+
+        // Work snapshotted on another machine is fast-forwarded in before this
+        // snapshot when `auto_apply` is on and nothing local could be lost;
+        // otherwise it waits for `shephard apply` and the result says so.
+        let pulls_side_tip = match self.cfg.side_channel.backend {
+            SideChannelBackend::Git => true,
+            SideChannelBackend::Rclone | SideChannelBackend::Restic => false,
+        };
+        let catch_up =
+            if self.cfg.side_channel.enabled && pulls_side_tip && self.runs(RunPhase::Commit) {
+                match catch_up_side_channel(
+                    self.repo,
+                    self.cfg,
+                    include_untracked,
+                    excludes,
+                    self.journal,
+                ) {
+                    Ok(outcome) => outcome,
+                    Err(err) if self.cfg.side_channel.auto_apply => {
+                        return Err(self.fail(
+                            RunPhase::Pull,
+                            RepoStatus::Failed,
+                            format!("side-channel auto-apply failed: {err:#}"),
+                        ));
+                    }
+                    // Only a hint without auto-apply, so never worth failing for.
+                    Err(_) => CatchUp::UpToDate,
+                }
+            } else {
+                CatchUp::UpToDate
             };
+        self.record(RunPhase::Pull, PhaseStatus::Ok);
+        let pulled = match &catch_up {
+            CatchUp::Applied(count) => {
+                format!("pull ok, applied {count} side-channel commits")
+            }
+            CatchUp::UpToDate | CatchUp::Pending(..) => "pull ok".to_string(),
+        };
+        Ok((pulled, catch_up))
+    }
+
+    /// Whether every local change is to a lockfile. Lockfiles refreshed by a
+    /// build are not worth a commit on their own; they ride along with the
+    /// next real change instead.
+    fn lockfile_only(
+        &mut self,
+        include_untracked: bool,
+        excludes: &[&str],
+    ) -> Result<bool, Box<RepoResult>> {
+        if self.cfg.lockfiles.is_empty() {
+            return Ok(false);
         }
-    };
+        git::only_changes_match(self.repo, include_untracked, &self.cfg.lockfiles, excludes)
+            .map_err(|err| {
+                self.fail(
+                    RunPhase::Commit,
+                    RepoStatus::Failed,
+                    format!("failed to inspect working tree: {err:#}"),
+                )
+            })
+    }
 
-    if has_changes {
-        let message = git::generate_commit_message(&cfg.commit_template, cfg.include_untracked);
-        if let Err(err) = git::commit(repo, &message) {
-            return RepoResult {
-                repo: repo.to_path_buf(),
-                status: RepoStatus::Failed,
-                message: format!("commit failed: {err:#}"),
+    /// The commit phase in side-channel mode, which ends the run: the snapshot
+    /// goes to the configured backend and nothing is pushed upstream.
+    fn snapshot(
+        &mut self,
+        pulled: &str,
+        catch_up: CatchUp,
+        include_untracked: bool,
+        excludes: &[&str],
+    ) -> Result<RepoResult, Box<RepoResult>> {
+        if !self.runs(RunPhase::Commit) {
+            return Ok(self.result(RepoStatus::Success, pulled.to_string()));
+        }
+
+        let mut result = if self.lockfile_only(include_untracked, excludes)? {
+            self.result(
+                RepoStatus::NoOp,
+                format!("{pulled}, only lockfile changes, no snapshot"),
+            )
+        } else {
+            // Side-channel mode bypasses local commit/push so branch history
+            // remains clean; snapshots are synthesized and handed to the
+            // configured backend.
+            let backend = backend::for_config(&self.cfg.side_channel);
+            let message = self.commit_message(include_untracked, false);
+            self.journal.record(Phase::Snapshot);
+            let request = SnapshotRequest {
+                include_untracked,
+                excludes,
+                force_include: &self.cfg.force_include,
+                message: &message,
+                auth: &self.cfg.auth,
+                last_snapshot: self.cfg.last_snapshot.as_ref(),
             };
+            match backend.store(self.repo, &request) {
+                Ok(git::SideChannelSyncResult::Pushed { stats, snapshot }) => RepoResult {
+                    snapshot: Some(snapshot),
+                    ..self.result(
+                        RepoStatus::Success,
+                        format!("{pulled}, {}: {stats}", backend.stored_label()),
+                    )
+                },
+                Ok(git::SideChannelSyncResult::Unchanged) => self.result(
+                    RepoStatus::NoOp,
+                    format!("{pulled}, working tree unchanged since the last snapshot"),
+                ),
+                Ok(git::SideChannelSyncResult::BelowThreshold) => self.result(
+                    RepoStatus::NoOp,
+                    format!("{pulled}, changes below snapshot threshold"),
+                ),
+                Ok(git::SideChannelSyncResult::NoChanges) => self.result(
+                    RepoStatus::NoOp,
+                    format!("{pulled}, no local changes to commit"),
+                ),
+                Err(err) => {
+                    return Err(self.fail(
+                        RunPhase::Commit,
+                        RepoStatus::Failed,
+                        format!("side-channel sync failed: {err:#}"),
+                    ));
+                }
+            }
+        };
+        self.record(RunPhase::Commit, PhaseStatus::Ok);
+
+        match catch_up {
+            CatchUp::UpToDate => {}
+            CatchUp::Applied(_) => result.status = RepoStatus::Success,
+            CatchUp::Pending(count, reason) => {
+                let reason = match reason {
+                    Some(reason) => format!(", not auto-applied ({reason})"),
+                    None => String::new(),
+                };
+                result.status = RepoStatus::NeedsApply;
+                result.message = format!(
+                    "{}; {count} side-channel commits to apply{reason}, run `shephard apply`",
+                    result.message
+                );
+            }
         }
+        Ok(result)
     }
 
-    let push_result = git::push(repo);
+    /// Stages and commits local changes upstream-style. `commands.push`
+    /// replaces this phase along with the push.
+    fn commit(
+        &mut self,
+        include_untracked: bool,
+        excludes: &[&str],
+    ) -> Result<Committed, Box<RepoResult>> {
+        if self.cfg.commands.push.is_some() {
+            return Ok(Committed::Skipped);
+        }
+        if self.lockfile_only(include_untracked, excludes)? {
+            self.record(RunPhase::Commit, PhaseStatus::Ok);
+            return Ok(Committed::LockfileOnly);
+        }
 
-    if let Err(err) = push_result {
-        return RepoResult {
-            repo: repo.to_path_buf(),
-            status: RepoStatus::Failed,
-            message: format!("push failed: {err:#}"),
+        self.journal.record(Phase::Stage);
+        if let Err(err) = git::stage_changes(self.repo, include_untracked, excludes) {
+            return Err(self.fail(
+                RunPhase::Commit,
+                RepoStatus::Failed,
+                format!("stage failed: {err:#}"),
+            ));
+        }
+        let has_changes = match git::has_staged_changes(self.repo) {
+            Ok(value) => value,
+            Err(err) => {
+                return Err(self.fail(
+                    RunPhase::Commit,
+                    RepoStatus::Failed,
+                    format!("failed to inspect staged diff: {err:#}"),
+                ));
+            }
         };
+        if !has_changes {
+            self.record(RunPhase::Commit, PhaseStatus::Ok);
+            return Ok(Committed::NoChanges);
+        }
+
+        let message = self.commit_message(include_untracked, true);
+        self.journal.record(Phase::Commit);
+        if let Err(err) = git::commit(self.repo, &message) {
+            return Err(self.fail(
+                RunPhase::Commit,
+                RepoStatus::Failed,
+                format!("commit failed: {err:#}"),
+            ));
+        }
+        self.record(RunPhase::Commit, PhaseStatus::Ok);
+        Ok(Committed::Changes)
     }
 
-    if has_changes {
-        RepoResult {
-            repo: repo.to_path_buf(),
-            status: RepoStatus::Success,
-            message: "pull ok, committed, pushed".to_string(),
+    /// Pushes upstream, or runs `commands.push` instead.
+    fn push(&mut self, pulled: &str, committed: Committed) -> Result<RepoResult, Box<RepoResult>> {
+        self.journal.record(Phase::Push);
+        if let Some(command) = &self.cfg.commands.push {
+            if let Err(err) = run_command(self.repo, command) {
+                return Err(self.fail(
+                    RunPhase::Push,
+                    RepoStatus::Failed,
+                    format!("push failed: {err:#}"),
+                ));
+            }
+            self.record(RunPhase::Push, PhaseStatus::Ok);
+            return Ok(self.result(RepoStatus::Success, format!("{pulled}, push command ok")));
         }
-    } else {
-        RepoResult {
-            repo: repo.to_path_buf(),
-            status: RepoStatus::NoOp,
-            message: "pull ok, no local changes to commit".to_string(),
+
+        if let Err(err) = git::push(self.repo, &self.cfg.auth) {
+            return Err(self.fail(
+                RunPhase::Push,
+                RepoStatus::Failed,
+                format!("push failed: {err:#}"),
+            ));
         }
+        self.record(RunPhase::Push, PhaseStatus::Ok);
+        Ok(match committed {
+            Committed::Changes => {
+                self.result(RepoStatus::Success, format!("{pulled}, committed, pushed"))
+            }
+            Committed::LockfileOnly => self.result(
+                RepoStatus::NoOp,
+                format!("{pulled}, only lockfile changes, not committed"),
+            ),
+            Committed::NoChanges => self.result(
+                RepoStatus::NoOp,
+                format!("{pulled}, no local changes to commit"),
+            ),
+            Committed::Skipped => self.result(
+                RepoStatus::Success,
+                format!("{pulled}, commit skipped, push ok"),
+            ),
+        })
+    }
+
+    /// Excluded untracked files are otherwise invisible, so name a few of them
+    /// whenever a sync ran without them.
+    fn post(&mut self, result: &mut RepoResult) {
+        self.record(RunPhase::Post, PhaseStatus::Ok);
+        if result.status == RepoStatus::Failed
+            || !self.runs(RunPhase::Commit)
+            || self.cfg.include_untracked == UntrackedPolicy::Include
+            || self.cfg.commands.push.is_some()
+        {
+            return;
+        }
+        let Ok(files) = git::untracked_files(self.repo) else {
+            return;
+        };
+        if files.is_empty() {
+            return;
+        }
+        let preview = files
+            .iter()
+            .take(UNTRACKED_PREVIEW_LIMIT)
+            .cloned()
+            .collect::<Vec<_>>();
+        let more = match files.len() - preview.len() {
+            0 => String::new(),
+            hidden => format!(", +{hidden} more"),
+        };
+        result.message = format!(
+            "{}; {} untracked not included: {}{more}",
+            result.message,
+            files.len(),
+            preview.join(", ")
+        );
+        result.excluded_untracked = preview;
+    }
+}
+
+/// Describes unmerged paths or leftover conflict markers in the working tree,
+/// if there are any.
+fn unresolved_conflicts(repo: &Path) -> Result<Option<String>> {
+    let unmerged = git::unmerged_paths(repo)?;
+    if !unmerged.is_empty() {
+        return Ok(Some(format!("unmerged paths: {}", unmerged.join(", "))));
+    }
+    let marked = git::conflict_marker_paths(repo)?;
+    if !marked.is_empty() {
+        return Ok(Some(format!(
+            "leftover conflict markers in {}",
+            marked.join(", ")
+        )));
+    }
+    Ok(None)
+}
+
+/// Where this checkout stands against side-channel work from other machines.
+enum CatchUp {
+    /// Nothing on the side channel is missing locally.
+    UpToDate,
+    /// Fast-forwarded onto this many side-channel commits.
+    Applied(usize),
+    /// This many side-channel commits carry work missing locally; with a
+    /// reason, `auto_apply` was on but could not fast-forward safely.
+    Pending(usize, Option<&'static str>),
+}
+
+/// Counts side-channel commits carrying work this checkout lacks and, with
+/// `auto_apply`, fast-forwards `HEAD` onto them when the working tree is
+/// clean and no local commit would be left behind.
+fn catch_up_side_channel(
+    repo: &Path,
+    cfg: &ResolvedRunConfig,
+    include_untracked: bool,
+    excludes: &[&str],
+    journal: &mut Journal,
+) -> Result<CatchUp> {
+    let side = &cfg.side_channel;
+    let tip = git::side_channel_tracking_ref(side);
+    if git::rev_parse_optional(repo, &tip)?.is_none() || git::is_ancestor(repo, &tip, "HEAD")? {
+        return Ok(CatchUp::UpToDate);
+    }
+    let local_tree = match git::snapshot_tree(
+        repo,
+        include_untracked,
+        side.snapshot_ignored,
+        excludes,
+        &cfg.force_include,
+    )? {
+        Some(tree) => tree,
+        None => git::rev_parse(repo, "HEAD^{tree}")?.trim().to_string(),
+    };
+    let pending = git::side_channel_pending(repo, side, &local_tree, &cfg.auth)?;
+    if pending == 0 {
+        return Ok(CatchUp::UpToDate);
+    }
+    if !side.auto_apply {
+        return Ok(CatchUp::Pending(pending, None));
+    }
+    if git::changed_path_count(repo)? > 0 {
+        return Ok(CatchUp::Pending(pending, Some("working tree has changes")));
+    }
+    if !git::is_ancestor(repo, "HEAD", &tip)? {
+        return Ok(CatchUp::Pending(
+            pending,
+            Some("branch has commits the side channel lacks"),
+        ));
+    }
+
+    journal.record(Phase::Apply);
+    git::merge_side_channel_ff(repo, side)?;
+    Ok(CatchUp::Applied(pending))
+}
+
+/// Runs a repository's `commands.pull` or `commands.push` with `sh -c`.
+fn run_command(repo: &Path, command: &str) -> Result<()> {
+    run_shell(repo, command, &[])
+}
+
+/// Runs `command` with `sh -c` in `dir`, with `env` added to the environment,
+/// as commands and hooks run.
+pub fn run_shell(dir: &Path, command: &str, env: &[(&str, &str)]) -> Result<()> {
+    let output = logging::output(
+        Command::new("sh")
+            .args(["-c", command])
+            .current_dir(dir)
+            .envs(env.iter().copied()),
+    )
+    .with_context(|| format!("failed running `{command}` in {}", dir.display()))?;
+    if !output.status.success() {
+        bail!(
+            "`{command}` failed in {} ({}): {}",
+            dir.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
     }
+    Ok(())
 }